@@ -31,6 +31,52 @@ function parseRetryAfterMs(headers, fallbackMs = 0) {
   return fallbackMs;
 }
 
+function retryDelayMs(permit) {
+  const guidance = permit.retry;
+  if (guidance && Number.isFinite(guidance.jitter_min_ms) && Number.isFinite(guidance.jitter_max_ms)) {
+    const spread = Math.max(0, guidance.jitter_max_ms - guidance.jitter_min_ms);
+    return Math.max(guidance.jitter_min_ms + Math.random() * spread, 10);
+  }
+  return Math.max(permit.retry_after_ms ?? 50, 10);
+}
+
+/**
+ * How long to hold a granted call until its `not_before_unix_ms`, measured
+ * against the orchestrator's clock. Paced grants (reactions) start slightly
+ * in the future; everything else starts at once.
+ */
+function startDelayMs(permit) {
+  if (!Number.isFinite(permit.not_before_unix_ms)) {
+    return 0;
+  }
+  const serverNow = Number.isFinite(permit.server_unix_ms) ? permit.server_unix_ms : Date.now();
+  return Math.max(0, permit.not_before_unix_ms - serverNow);
+}
+
+/**
+ * Parses one server-sent event frame into `{ event, data }`, decoding `data`
+ * as JSON. Returns `null` for comments and keep-alives.
+ */
+function parseSseFrame(frame) {
+  let event = "message";
+  const data = [];
+  for (const line of frame.split("\n")) {
+    if (line.startsWith("event:")) {
+      event = line.slice(6).trim();
+    } else if (line.startsWith("data:")) {
+      data.push(line.slice(5).replace(/^ /, ""));
+    }
+  }
+  if (data.length === 0) {
+    return null;
+  }
+  try {
+    return { event, data: JSON.parse(data.join("\n")) };
+  } catch (_error) {
+    return null;
+  }
+}
+
 export class DmboClient {
   constructor(options = {}) {
     this.orchestratorUrl = options.orchestratorUrl ?? DEFAULT_ORCHESTRATOR_URL;
@@ -52,6 +98,10 @@ export class DmboClient {
       reportErrors: 0,
     };
     this.lastPermitSource = "orchestrator";
+    this.policy = null;
+    // Blocked guards and buckets from `subscribeSignals`, by key, to when
+    // they are expected to clear.
+    this.blocks = new Map();
   }
 
   async withPermit(requestMeta, execute) {
@@ -60,17 +110,31 @@ export class DmboClient {
       group_id: requestMeta.groupId ?? this.groupId,
       discord_identity: requestMeta.discordIdentity ?? this.discordIdentity,
       method: requestMeta.method ?? "POST",
-      route: requestMeta.route ?? "/unknown",
+      route: requestMeta.route ?? requestMeta.path ?? "/unknown",
+      // The raw path, when given, is normalized by the orchestrator so every
+      // client keys the same bucket.
+      path: requestMeta.path ?? null,
+      // Slots to reserve for a call that fans out into several requests.
+      cost: requestMeta.cost ?? null,
       major_parameter: String(requestMeta.majorParameter ?? "unknown"),
       priority: requestMeta.priority ?? "normal",
       max_wait_ms: requestMeta.maxWaitMs ?? 2000,
+      // Held open until granted, woken by the queue rather than rechecking.
+      long_poll: requestMeta.longPoll ?? false,
       request_id: requestMeta.requestId ?? randomUUID(),
+      feature: requestMeta.feature ?? null,
     };
 
     const maxRetries = requestMeta.maxRetries ?? 100;
     let retryCount = 0;
 
     while (retryCount < maxRetries) {
+      const blockedMs = this.blockedMs(permitRequest);
+      if (blockedMs > 0) {
+        await sleep(blockedMs);
+      }
+      permitRequest.attempt = retryCount + 1;
+      permitRequest.client_unix_ms = Date.now();
       const permit = await this.requestToken(permitRequest);
       if (permit.source === "fallback") {
         await this.localLimiter.acquire(
@@ -105,6 +169,10 @@ export class DmboClient {
       if (permit.granted) {
         this.stats.orchestratorGrants += 1;
         this.lastPermitSource = "orchestrator";
+        const delayMs = startDelayMs(permit);
+        if (delayMs > 0) {
+          await sleep(delayMs);
+        }
         let result;
         let executeError;
         try {
@@ -122,8 +190,13 @@ export class DmboClient {
 
       this.stats.orchestratorDenials += 1;
       retryCount += 1;
+      if (permit.retry?.give_up) {
+        throw new Error(
+          `Orchestrator advised giving up after ${retryCount} attempts (${permit.reason ?? "denied"})`,
+        );
+      }
       if (retryCount < maxRetries) {
-        await sleep(Math.max(permit.retry_after_ms ?? 50, 10));
+        await sleep(retryDelayMs(permit));
       }
     }
 
@@ -141,6 +214,12 @@ export class DmboClient {
         body: JSON.stringify(payload),
         signal: controller.signal,
       });
+      if (response.status === 429) {
+        // Denials arrive as application/problem+json with the permit fields
+        // carried as extension members.
+        const body = await response.json();
+        return { ...body, granted: false, source: "orchestrator" };
+      }
       if (!response.ok) {
         return {
           granted: true,
@@ -163,6 +242,160 @@ export class DmboClient {
     }
   }
 
+  /**
+   * Asks for several permits in one call (`POST /request_tokens`), e.g. to
+   * send one message to many channels. Returns one permit per payload, in
+   * order; none of them waits server-side. Fails open like `requestToken`.
+   */
+  async requestTokens(payloads) {
+    const fallback = (reason) =>
+      payloads.map(() => ({ granted: true, source: "fallback", reason, lease_id: null }));
+    const controller = new AbortController();
+    const timeout = setTimeout(() => controller.abort(), this.timeoutMs);
+    try {
+      const response = await fetch(`${this.orchestratorUrl}/request_tokens`, {
+        method: "POST",
+        headers: { "content-type": "application/json" },
+        body: JSON.stringify({ requests: payloads }),
+        signal: controller.signal,
+      });
+      if (!response.ok) {
+        return fallback(`orchestrator_http_${response.status}`);
+      }
+      const body = await response.json();
+      return body.results.map((permit) => ({ ...permit, source: "orchestrator" }));
+    } catch (_error) {
+      return fallback("orchestrator_down");
+    } finally {
+      clearTimeout(timeout);
+    }
+  }
+
+  /**
+   * Fetches the orchestrator's effective policy (retry floor, max wait cap,
+   * protocol version) for this client. Returns `null` when unreachable so
+   * callers can keep their local defaults.
+   */
+  async fetchPolicy() {
+    const params = new URLSearchParams({
+      client_id: this.clientId,
+      discord_identity: this.discordIdentity,
+    });
+    const controller = new AbortController();
+    const timeout = setTimeout(() => controller.abort(), this.timeoutMs);
+    try {
+      const response = await fetch(`${this.orchestratorUrl}/policy?${params}`, {
+        signal: controller.signal,
+      });
+      if (!response.ok) {
+        return null;
+      }
+      this.policy = await response.json();
+      return this.policy;
+    } catch (_error) {
+      return null;
+    } finally {
+      clearTimeout(timeout);
+    }
+  }
+
+  /**
+   * Subscribes to the orchestrator's maintenance event stream (`GET /events`)
+   * and calls `onEvent` with each parsed event: the currently `active` and
+   * `upcoming` windows first, then `warning`, `started` and `ended`
+   * transitions. Reconnects after `reconnectMs` when the stream drops.
+   * Returns a function that closes the subscription.
+   */
+  subscribeMaintenance(onEvent, { reconnectMs = 5000 } = {}) {
+    return this.#followEvents((name, data) => {
+      if (name === "maintenance") {
+        onEvent(data);
+      }
+    }, reconnectMs);
+  }
+
+  /**
+   * Follows guardrail and bucket events so `withPermit` holds calls on a
+   * blocked group or route bucket until it is expected to clear, instead of
+   * spending a permit request to learn about it. Bucket blocks only match
+   * calls whose `route` is the template the orchestrator reports. Returns an
+   * unsubscribe function.
+   */
+  subscribeSignals({ reconnectMs = 5000, onEvent = () => {} } = {}) {
+    return this.#followEvents((name, data) => {
+      if (name !== "guardrail" && name !== "bucket") {
+        return;
+      }
+      const key =
+        name === "guardrail"
+          ? `guard:${data.group_id}`
+          : `bucket:${data.discord_identity}:${data.method}:${data.route}:${data.major_parameter}`;
+      if (Number.isFinite(data.until_unix_ms)) {
+        this.blocks.set(key, data.until_unix_ms);
+      } else {
+        this.blocks.delete(key);
+      }
+      onEvent(name, data);
+    }, reconnectMs);
+  }
+
+  /** Milliseconds a permit request should hold for known blocks. */
+  blockedMs(permitRequest) {
+    const now = Date.now();
+    const until = Math.max(
+      this.blocks.get(`guard:${permitRequest.group_id}`) ?? 0,
+      this.blocks.get(
+        `bucket:${permitRequest.discord_identity}:${permitRequest.method}:${permitRequest.route}:${permitRequest.major_parameter}`,
+      ) ?? 0,
+    );
+    return Math.max(0, until - now);
+  }
+
+  #followEvents(onEvent, reconnectMs) {
+    const controller = new AbortController();
+    const run = async () => {
+      while (!controller.signal.aborted) {
+        try {
+          const response = await fetch(`${this.orchestratorUrl}/events`, {
+            headers: { accept: "text/event-stream" },
+            signal: controller.signal,
+          });
+          if (response.ok && response.body) {
+            const decoder = new TextDecoder();
+            let buffer = "";
+            for await (const chunk of response.body) {
+              buffer += decoder.decode(chunk, { stream: true });
+              const frames = buffer.split("\n\n");
+              buffer = frames.pop();
+              for (const event of frames.map(parseSseFrame)) {
+                if (event) {
+                  onEvent(event.event, event.data);
+                }
+              }
+            }
+          }
+        } catch (_error) {
+          // Dropped or refused; retry below unless unsubscribed.
+        }
+        if (!controller.signal.aborted) {
+          await new Promise((resolve) => {
+            const timer = setTimeout(resolve, reconnectMs);
+            controller.signal.addEventListener(
+              "abort",
+              () => {
+                clearTimeout(timer);
+                resolve();
+              },
+              { once: true },
+            );
+          });
+        }
+      }
+    };
+    run();
+    return () => controller.abort();
+  }
+
   async reportResult(payload) {
     try {
       await fetch(`${this.orchestratorUrl}/report_result`, {
@@ -178,6 +411,7 @@ export class DmboClient {
   #buildReportPayload(request, result, leaseId = null, fallbackReason = null) {
     const statusCode = result?.statusCode ?? result?.status ?? 200;
     const headers = normalizeHeaders(result?.headers);
+    const contentType = String(headers["content-type"] ?? "");
     
     const parseHeaderNumber = (value) => {
       if (value == null) return null;
@@ -187,11 +421,13 @@ export class DmboClient {
     
     return {
       request_id: request.request_id,
+      client_id: request.client_id,
       lease_id: leaseId,
       discord_identity: request.discord_identity,
       group_id: request.group_id,
       method: request.method,
       route: request.route,
+      path: request.path ?? null,
       major_parameter: request.major_parameter,
       status_code: statusCode,
       x_ratelimit_bucket: headers["x-ratelimit-bucket"] ?? null,
@@ -199,14 +435,25 @@ export class DmboClient {
       x_ratelimit_remaining: parseHeaderNumber(headers["x-ratelimit-remaining"]),
       x_ratelimit_reset_after_s: parseHeaderNumber(headers["x-ratelimit-reset-after"]),
       x_ratelimit_scope: headers["x-ratelimit-scope"] ?? null,
+      x_ratelimit_global: String(headers["x-ratelimit-global"] ?? "").toLowerCase() === "true",
       retry_after_ms: parseRetryAfterMs(
         headers,
         headers["x-ratelimit-reset-after"]
           ? Math.ceil(Number(headers["x-ratelimit-reset-after"]) * 1000)
           : 0,
       ),
+      body_retry_after_s: parseHeaderNumber(result?.body?.retry_after),
+      // Discord answers its own 429s with JSON and rate-limit headers;
+      // Cloudflare with an HTML page and neither. A 429 whose content type
+      // is unknown is not enough to call it a ban.
+      cloudflare:
+        statusCode === 429 &&
+        contentType !== "" &&
+        !contentType.includes("json") &&
+        !Object.keys(headers).some((name) => name.startsWith("x-ratelimit-")),
       fallback_reason: fallbackReason,
       observed_at_unix_ms: Date.now(),
+      feature: request.feature ?? null,
     };
   }
 
@@ -253,6 +500,7 @@ export function attachDiscordJsRestTelemetry(rest, dmboClient, defaults = {}) {
   const onRateLimited = (data) => {
     dmboClient.reportResult({
       request_id: randomUUID(),
+      client_id: dmboClient.clientId,
       lease_id: null,
       discord_identity: discordIdentity,
       group_id: groupId,
@@ -266,7 +514,9 @@ export function attachDiscordJsRestTelemetry(rest, dmboClient, defaults = {}) {
       x_ratelimit_reset_after_s:
         data?.retryAfter != null ? Number(data.retryAfter) / 1000 : null,
       x_ratelimit_scope: data?.scope ?? null,
+      x_ratelimit_global: data?.global === true,
       retry_after_ms: data?.retryAfter ?? null,
+      body_retry_after_s: data?.sublimitTimeout ? Number(data.sublimitTimeout) / 1000 : null,
       observed_at_unix_ms: Date.now(),
     });
   };
@@ -276,6 +526,7 @@ export function attachDiscordJsRestTelemetry(rest, dmboClient, defaults = {}) {
   const onInvalidRequestWarning = (warning) => {
     dmboClient.reportResult({
       request_id: randomUUID(),
+      client_id: dmboClient.clientId,
       lease_id: null,
       discord_identity: discordIdentity,
       group_id: groupId,
@@ -300,4 +551,4 @@ export function attachDiscordJsRestTelemetry(rest, dmboClient, defaults = {}) {
   };
 }
 
-export { LocalLimiter, normalizeHeaders, parseRetryAfterMs };
+export { LocalLimiter, normalizeHeaders, parseRetryAfterMs, parseSseFrame };
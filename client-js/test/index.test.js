@@ -1,6 +1,12 @@
 import test from "node:test";
 import assert from "node:assert/strict";
-import { DmboClient, normalizeHeaders, parseRetryAfterMs, attachDiscordJsRestTelemetry } from "../src/index.js";
+import {
+  DmboClient,
+  normalizeHeaders,
+  parseRetryAfterMs,
+  parseSseFrame,
+  attachDiscordJsRestTelemetry,
+} from "../src/index.js";
 
 test("normalizeHeaders - handles Headers object", () => {
   const headers = new Map([
@@ -130,6 +136,164 @@ test("DmboClient - buildReportPayload handles missing headers", () => {
   assert.equal(payload.x_ratelimit_bucket, null);
 });
 
+test("DmboClient - buildReportPayload carries client_id for usage attribution", () => {
+  const client = new DmboClient({ clientId: "bot-7" });
+  const payload = client._testBuildReportPayload(
+    { request_id: "r", client_id: "bot-7", discord_identity: "id", method: "GET", route: "/x" },
+    { statusCode: 200, headers: {} },
+  );
+
+  assert.equal(payload.client_id, "bot-7");
+});
+
+test("DmboClient - buildReportPayload flags Cloudflare 429s", () => {
+  const client = new DmboClient();
+  const request = { request_id: "r", discord_identity: "id", method: "GET", route: "/x" };
+
+  const cloudflare = client._testBuildReportPayload(request, {
+    statusCode: 429,
+    headers: { "content-type": "text/html" },
+  });
+  const discord = client._testBuildReportPayload(request, {
+    statusCode: 429,
+    headers: { "content-type": "application/json", "retry-after": "1" },
+  });
+  const untyped = client._testBuildReportPayload(request, { statusCode: 429, headers: {} });
+  const rateLimited = client._testBuildReportPayload(request, {
+    statusCode: 429,
+    headers: { "content-type": "text/plain", "x-ratelimit-scope": "user" },
+  });
+
+  assert.equal(cloudflare.cloudflare, true);
+  assert.equal(discord.cloudflare, false);
+  assert.equal(untyped.cloudflare, false);
+  assert.equal(rateLimited.cloudflare, false);
+});
+
+test("DmboClient - buildReportPayload carries the 429 body's retry_after", () => {
+  const client = new DmboClient();
+  const payload = client._testBuildReportPayload(
+    { request_id: "r", discord_identity: "id", method: "PATCH", route: "/channels/1" },
+    {
+      statusCode: 429,
+      headers: { "content-type": "application/json", "retry-after": "1" },
+      body: { message: "You are being rate limited.", retry_after: 297.5, global: false },
+    },
+  );
+
+  assert.equal(payload.body_retry_after_s, 297.5);
+  assert.equal(client._testBuildReportPayload({}, { statusCode: 200 }).body_retry_after_s, null);
+});
+
+test("DmboClient - buildReportPayload carries X-RateLimit-Global", () => {
+  const client = new DmboClient();
+  const request = { request_id: "r", discord_identity: "id", method: "GET", route: "/x" };
+
+  const global = client._testBuildReportPayload(request, {
+    statusCode: 429,
+    headers: { "x-ratelimit-global": "true", "content-type": "application/json" },
+  });
+  const route = client._testBuildReportPayload(request, { statusCode: 429, headers: {} });
+
+  assert.equal(global.x_ratelimit_global, true);
+  assert.equal(route.x_ratelimit_global, false);
+});
+
+test("DmboClient - withPermit tags permit and report with the feature", async () => {
+  const client = new DmboClient();
+  const sent = {};
+  client.requestToken = async (request) => {
+    sent.permit = { ...request };
+    return { granted: true, source: "orchestrator" };
+  };
+  client.reportResult = async (report) => {
+    sent.report = report;
+  };
+
+  await client.withPermit({ route: "/channels/1/messages", feature: "starboard" }, async () => ({
+    statusCode: 200,
+    headers: {},
+  }));
+
+  assert.equal(sent.permit.feature, "starboard");
+  assert.equal(sent.report.feature, "starboard");
+});
+
+test("DmboClient - withPermit asks for a long-poll wait when requested", async () => {
+  const client = new DmboClient();
+  const sent = [];
+  client.requestToken = async (request) => {
+    sent.push({ ...request });
+    return { granted: true, source: "orchestrator" };
+  };
+  client.reportResult = async () => {};
+
+  const execute = async () => ({ statusCode: 200, headers: {} });
+  await client.withPermit({ route: "/channels/1/messages", longPoll: true, maxWaitMs: 10000 }, execute);
+  await client.withPermit({ route: "/channels/1/messages" }, execute);
+
+  assert.equal(sent[0].long_poll, true);
+  assert.equal(sent[0].max_wait_ms, 10000);
+  assert.equal(sent[1].long_poll, false);
+});
+
+test("DmboClient - withPermit forwards the raw path for server-side normalization", async () => {
+  const client = new DmboClient();
+  const sent = {};
+  client.requestToken = async (request) => {
+    sent.permit = { ...request };
+    return { granted: true, source: "orchestrator" };
+  };
+  client.reportResult = async (report) => {
+    sent.report = report;
+  };
+
+  await client.withPermit({ path: "/api/v10/channels/123/messages/456" }, async () => ({
+    statusCode: 200,
+    headers: {},
+  }));
+
+  assert.equal(sent.permit.path, "/api/v10/channels/123/messages/456");
+  assert.equal(sent.report.path, "/api/v10/channels/123/messages/456");
+});
+
+test("DmboClient - withPermit sends the requested cost", async () => {
+  const client = new DmboClient();
+  let sent;
+  client.requestToken = async (request) => {
+    sent = { ...request };
+    return { granted: true, source: "orchestrator" };
+  };
+  client.reportResult = async () => {};
+
+  await client.withPermit({ route: "/guilds/:guild_id/bans", cost: 5 }, async () => ({
+    statusCode: 200,
+    headers: {},
+  }));
+
+  assert.equal(sent.cost, 5);
+});
+
+test("DmboClient - withPermit waits for a paced grant's not_before_unix_ms", async () => {
+  const client = new DmboClient();
+  client.requestToken = async () => ({
+    granted: true,
+    source: "orchestrator",
+    server_unix_ms: 1_000,
+    not_before_unix_ms: 1_080,
+  });
+  client.reportResult = async () => {};
+
+  const started = Date.now();
+  let executedAfterMs;
+  await client.withPermit({ route: "/channels/1/messages/2/reactions/x/@me", method: "PUT" }, async () => {
+    executedAfterMs = Date.now() - started;
+    return { statusCode: 204, headers: {} };
+  });
+
+  assert.ok(executedAfterMs >= 70, `executed after ${executedAfterMs}ms`);
+});
+
 test("DmboClient - withPermit respects maxRetries", async () => {
   const client = new DmboClient();
   
@@ -155,6 +319,213 @@ test("DmboClient - withPermit respects maxRetries", async () => {
   assert.equal(client.stats.orchestratorDenials, 3);
 });
 
+test("DmboClient - requestToken treats 429 problem responses as denials", async () => {
+  const client = new DmboClient({ orchestratorUrl: "http://orchestrator.test" });
+  const originalFetch = globalThis.fetch;
+  globalThis.fetch = async () =>
+    new Response(
+      JSON.stringify({
+        type: "urn:dmbo:problem:rate-limited",
+        title: "Permit denied",
+        status: 429,
+        granted: false,
+        not_before_unix_ms: 1739325600273,
+        retry_after_ms: 150,
+        reason: "global_bucket_exhausted",
+      }),
+      { status: 429, headers: { "content-type": "application/problem+json" } },
+    );
+  try {
+    const permit = await client.requestToken({ max_wait_ms: 0 });
+    assert.equal(permit.granted, false);
+    assert.equal(permit.source, "orchestrator");
+    assert.equal(permit.retry_after_ms, 150);
+    assert.equal(permit.reason, "global_bucket_exhausted");
+  } finally {
+    globalThis.fetch = originalFetch;
+  }
+});
+
+test("DmboClient - requestTokens returns one permit per payload in order", async () => {
+  const client = new DmboClient({ orchestratorUrl: "http://orchestrator.test" });
+  const originalFetch = globalThis.fetch;
+  let sent;
+  globalThis.fetch = async (url, init) => {
+    sent = { url, body: JSON.parse(init.body) };
+    return new Response(
+      JSON.stringify({
+        granted: 1,
+        results: [
+          { granted: true, lease_id: "lease-1" },
+          { granted: false, reason: "route_bucket_exhausted", retry_after_ms: 200 },
+        ],
+      }),
+      { status: 200, headers: { "content-type": "application/json" } },
+    );
+  };
+  try {
+    const permits = await client.requestTokens([
+      { route: "/channels/1/messages" },
+      { route: "/channels/2/messages" },
+    ]);
+    assert.equal(sent.url, "http://orchestrator.test/request_tokens");
+    assert.equal(sent.body.requests.length, 2);
+    assert.equal(permits[0].granted, true);
+    assert.equal(permits[0].source, "orchestrator");
+    assert.equal(permits[1].reason, "route_bucket_exhausted");
+  } finally {
+    globalThis.fetch = originalFetch;
+  }
+});
+
+test("DmboClient - requestTokens fails open per payload", async () => {
+  const client = new DmboClient({ orchestratorUrl: "http://orchestrator.test" });
+  const originalFetch = globalThis.fetch;
+  globalThis.fetch = async () => {
+    throw new Error("connection refused");
+  };
+  try {
+    const permits = await client.requestTokens([{}, {}, {}]);
+    assert.equal(permits.length, 3);
+    assert.ok(permits.every((permit) => permit.granted && permit.source === "fallback"));
+  } finally {
+    globalThis.fetch = originalFetch;
+  }
+});
+
+test("DmboClient - withPermit stops when the orchestrator advises giving up", async () => {
+  const client = new DmboClient();
+  const attempts = [];
+
+  client.requestToken = async (payload) => {
+    attempts.push(payload.attempt);
+    return {
+      granted: false,
+      retry_after_ms: 1,
+      reason: "route_bucket_exhausted",
+      retry: {
+        recommended_delay_ms: 1,
+        jitter_min_ms: 1,
+        jitter_max_ms: 2,
+        give_up_after_attempts: 2,
+        give_up: payload.attempt >= 2,
+      },
+      source: "orchestrator",
+    };
+  };
+
+  await assert.rejects(
+    async () => {
+      await client.withPermit({ route: "/test", maxRetries: 10 }, async () => ({ statusCode: 200 }));
+    },
+    { message: /advised giving up after 2 attempts/ }
+  );
+
+  assert.deepEqual(attempts, [1, 2]);
+});
+
+test("DmboClient - fetchPolicy stores policy and tolerates failures", async () => {
+  const client = new DmboClient({ orchestratorUrl: "http://orchestrator.test", clientId: "bot-1" });
+  const originalFetch = globalThis.fetch;
+  let requestedUrl = null;
+  globalThis.fetch = async (url) => {
+    requestedUrl = String(url);
+    return new Response(JSON.stringify({ protocol_version: 1, min_retry_ms: 50 }), {
+      status: 200,
+      headers: { "content-type": "application/json" },
+    });
+  };
+  try {
+    const policy = await client.fetchPolicy();
+    assert.equal(policy.protocol_version, 1);
+    assert.equal(client.policy.min_retry_ms, 50);
+    assert.match(requestedUrl, /\/policy\?client_id=bot-1/);
+
+    globalThis.fetch = async () => {
+      throw new Error("connection refused");
+    };
+    assert.equal(await client.fetchPolicy(), null);
+  } finally {
+    globalThis.fetch = originalFetch;
+  }
+});
+
+test("parseSseFrame - decodes maintenance events and skips keep-alives", () => {
+  const frame = 'event: maintenance\ndata: {"kind":"warning","window":"db-upgrade"}';
+  assert.deepEqual(parseSseFrame(frame), {
+    event: "maintenance",
+    data: { kind: "warning", window: "db-upgrade" },
+  });
+  assert.equal(parseSseFrame(":"), null);
+});
+
+test("DmboClient - subscribeMaintenance delivers streamed events", async () => {
+  const client = new DmboClient({ orchestratorUrl: "http://orchestrator.test" });
+  const originalFetch = globalThis.fetch;
+  const body = [
+    'event: maintenance\ndata: {"kind":"active","window":"a"}\n\n',
+    ':\n\nevent: maintenance\ndata: {"kind":"warning",',
+    '"window":"b"}\n\n',
+  ].join("");
+  globalThis.fetch = async (url) => {
+    assert.match(String(url), /\/events$/);
+    return new Response(body, { status: 200, headers: { "content-type": "text/event-stream" } });
+  };
+  const received = [];
+  const unsubscribe = client.subscribeMaintenance((event) => received.push(event), {
+    reconnectMs: 10_000,
+  });
+  try {
+    await new Promise((resolve) => setTimeout(resolve, 50));
+    assert.deepEqual(received, [
+      { kind: "active", window: "a" },
+      { kind: "warning", window: "b" },
+    ]);
+  } finally {
+    unsubscribe();
+    globalThis.fetch = originalFetch;
+  }
+});
+
+test("DmboClient - subscribeSignals tracks blocked guards and buckets", async () => {
+  const client = new DmboClient({ orchestratorUrl: "http://orchestrator.test", groupId: "home" });
+  const originalFetch = globalThis.fetch;
+  const until = Date.now() + 60_000;
+  const body = [
+    `event: guardrail\ndata: {"kind":"guardrail_activated","group_id":"home","until_unix_ms":${until},"unix_ms":1}\n\n`,
+    `event: bucket\ndata: {"kind":"bucket_exhausted","discord_identity":"bot","method":"POST","route":"/channels/:channel_id/messages","major_parameter":"1","until_unix_ms":${until},"unix_ms":1}\n\n`,
+    'event: guardrail\ndata: {"kind":"guardrail_released","group_id":"home","unix_ms":2}\n\n',
+    'event: maintenance\ndata: {"kind":"active","window":"a"}\n\n',
+  ].join("");
+  globalThis.fetch = async () =>
+    new Response(body, { status: 200, headers: { "content-type": "text/event-stream" } });
+  const seen = [];
+  const unsubscribe = client.subscribeSignals({
+    reconnectMs: 10_000,
+    onEvent: (name, data) => seen.push(`${name}:${data.kind}`),
+  });
+  try {
+    await new Promise((resolve) => setTimeout(resolve, 50));
+    assert.deepEqual(seen, [
+      "guardrail:guardrail_activated",
+      "bucket:bucket_exhausted",
+      "guardrail:guardrail_released",
+    ]);
+    const bucketCall = {
+      group_id: "home",
+      discord_identity: "bot",
+      method: "POST",
+      route: "/channels/:channel_id/messages",
+      major_parameter: "1",
+    };
+    assert.ok(client.blockedMs(bucketCall) > 50_000);
+    assert.equal(client.blockedMs({ ...bucketCall, major_parameter: "2" }), 0);
+  } finally {
+    unsubscribe();
+    globalThis.fetch = originalFetch;
+  }
+});
+
 test("DmboClient - withPermit handles execute errors", async () => {
   const client = new DmboClient();
   
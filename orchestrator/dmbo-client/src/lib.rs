@@ -0,0 +1,253 @@
+//! Async client for the dmbo orchestrator: typed `request_token` and
+//! `report_result` calls, [`Client::acquire`] to retry denials until a
+//! permit is granted, and a [`Permit`] guard that reports the call's outcome
+//! when it is dropped.
+//!
+//! Unlike the JavaScript client this one does not fail open: when the
+//! orchestrator cannot be reached the error is returned, and the caller
+//! decides whether to call Discord anyway.
+//!
+//! ```no_run
+//! use dmbo_client::{Client, ClientConfig, PermitRequest};
+//!
+//! # async fn send(http: reqwest::Client) -> Result<(), dmbo_client::Error> {
+//! let dmbo = Client::new(ClientConfig::default());
+//! let mut permit = dmbo
+//!     .acquire(PermitRequest::new("bot-1", "POST", "/channels/123/messages"))
+//!     .await?;
+//! let response = http
+//!     .post("https://discord.com/api/v10/channels/123/messages")
+//!     .send()
+//!     .await?;
+//! permit.record(response.status().as_u16(), response.headers());
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "middleware")]
+mod middleware;
+mod permit;
+#[cfg(feature = "serenity")]
+mod serenity;
+#[cfg(feature = "twilight")]
+mod twilight;
+mod wire;
+
+#[cfg(feature = "middleware")]
+pub use middleware::DiscordPermits;
+pub use permit::Permit;
+#[cfg(feature = "serenity")]
+pub use serenity::serenity_http;
+#[cfg(feature = "twilight")]
+pub use twilight::OrchestratorRatelimiter;
+pub use wire::{Decision, PermitRequest, Report};
+
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Orchestrator base URL, without a trailing slash.
+    pub base_url: String,
+    /// Sent on requests that name no `client_id`.
+    pub client_id: String,
+    /// Per call; permit requests also get their `max_wait_ms`.
+    pub timeout: Duration,
+    /// Permit requests [`Client::acquire`] makes before giving up.
+    pub max_attempts: u32,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "http://127.0.0.1:8787".to_string(),
+            client_id: format!("client-{}", std::process::id()),
+            timeout: Duration::from_secs(3),
+            max_attempts: 100,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The orchestrator could not be reached or its answer not read.
+    Http(reqwest::Error),
+    /// The orchestrator answered with an error status; `problem` is its
+    /// problem body, or `null` when it had none.
+    Status { status: u16, problem: Value },
+    /// Denied on every attempt, or told to give up.
+    Denied(Decision),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(error) => write!(f, "orchestrator request failed: {error}"),
+            Error::Status { status, problem } => match problem["detail"].as_str() {
+                Some(detail) => write!(f, "orchestrator answered {status}: {detail}"),
+                None => write!(f, "orchestrator answered {status}"),
+            },
+            Error::Denied(decision) => write!(f, "permit denied: {}", decision.reason_code),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+/// Cheap to clone; clones share one connection pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    config: Arc<ClientConfig>,
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Self {
+        Self::with_http(config, reqwest::Client::new())
+    }
+
+    /// Uses `http` for orchestrator calls, e.g. one trusting a private CA.
+    pub fn with_http(config: ClientConfig, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Asks for a permit once. Denials are decisions, not errors.
+    pub async fn request_token(&self, request: &PermitRequest) -> Result<Decision, Error> {
+        let mut request = request.clone();
+        if request.client_id.is_empty() {
+            request.client_id = self.config.client_id.clone();
+        }
+        let timeout = self.config.timeout.max(Duration::from_millis(
+            request.max_wait_ms.saturating_add(500),
+        ));
+        let response = self
+            .http
+            .post(self.url("/request_token"))
+            .timeout(timeout)
+            .json(&request)
+            .send()
+            .await?;
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        // Denials come as problem bodies carrying the decision's members;
+        // a strict-mode queue_full denial is a 503.
+        let decided = status.is_success()
+            || (matches!(
+                status,
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+            ) && body.get("granted").is_some());
+        if !decided {
+            return Err(Error::Status {
+                status: status.as_u16(),
+                problem: body,
+            });
+        }
+        serde_json::from_value(body).map_err(|_| Error::Status {
+            status: status.as_u16(),
+            problem: Value::Null,
+        })
+    }
+
+    /// Asks for a permit until it is granted, waiting out each denial's
+    /// retry hint, then holds the permit until its `not_before_unix_ms`.
+    /// Fails with [`Error::Denied`] once the server advises giving up or
+    /// `max_attempts` requests were denied.
+    pub async fn acquire(&self, mut request: PermitRequest) -> Result<Permit, Error> {
+        if request.client_id.is_empty() {
+            request.client_id = self.config.client_id.clone();
+        }
+        if request.request_id.is_empty() {
+            request.request_id = uuid::Uuid::new_v4().to_string();
+        }
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            request.attempt = attempt;
+            request.client_unix_ms = Some(unix_ms());
+            let decision = self.request_token(&request).await?;
+            if decision.granted {
+                tokio::time::sleep(Duration::from_millis(decision.start_delay_ms())).await;
+                return Ok(Permit::new(self.clone(), request, decision));
+            }
+            let give_up = decision.retry.as_ref().is_some_and(|retry| retry.give_up);
+            if give_up || attempt >= self.config.max_attempts {
+                return Err(Error::Denied(decision));
+            }
+            tokio::time::sleep(Duration::from_millis(decision.retry_delay_ms())).await;
+        }
+    }
+
+    pub async fn report_result(&self, report: &Report) -> Result<(), Error> {
+        let mut report = report.clone();
+        if report.client_id.is_empty() {
+            report.client_id = self.config.client_id.clone();
+        }
+        let response = self
+            .http
+            .post(self.url("/report_result"))
+            .timeout(self.config.timeout)
+            .json(&report)
+            .send()
+            .await?;
+        check(response).await
+    }
+
+    /// Hands back a granted permit whose call will not be made. A lease that
+    /// already ended is not an error.
+    pub async fn release_lease(&self, lease_id: &str) -> Result<(), Error> {
+        let response = self
+            .http
+            .post(self.url("/release_lease"))
+            .timeout(self.config.timeout)
+            .json(&json!({ "lease_id": lease_id }))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check(response).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.config.base_url.trim_end_matches('/'))
+    }
+}
+
+async fn check(response: reqwest::Response) -> Result<(), Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    Err(Error::Status {
+        status: status.as_u16(),
+        problem: response.json().await.unwrap_or(Value::Null),
+    })
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or_default()
+}
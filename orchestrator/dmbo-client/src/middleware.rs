@@ -0,0 +1,92 @@
+//! A `reqwest-middleware` layer that takes a permit for every Discord API
+//! call made through the client and reports its response.
+
+use crate::{Client, Error, PermitRequest};
+use async_trait::async_trait;
+use http::Extensions;
+use reqwest::{Request, Response, Url};
+use reqwest_middleware::{Middleware, Next};
+
+/// Brokers the Discord API calls of a `reqwest_middleware` client; other
+/// hosts pass through untouched.
+///
+/// ```no_run
+/// # use dmbo_client::{Client, ClientConfig, DiscordPermits};
+/// let http = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+///     .with(DiscordPermits::new(Client::new(ClientConfig::default()), "bot-1"))
+///     .build();
+/// ```
+///
+/// - Each call waits in [`Client::acquire`] for its permit. A call the
+///   orchestrator keeps denying fails with [`Error::Denied`] as a middleware
+///   error, without reaching Discord.
+/// - When the orchestrator cannot be reached, or rejects the permit request,
+///   the call goes to Discord unbrokered, like the JS client's fallback.
+/// - The response is reported in the background. A call that got no
+///   response releases its permit's lease.
+/// - A [`PermitRequest`] added with `with_extension` replaces the default one
+///   for that call, e.g. to set `priority` or `feature`; its method and route
+///   are always the request's.
+pub struct DiscordPermits {
+    client: Client,
+    discord_identity: String,
+}
+
+impl DiscordPermits {
+    /// `discord_identity` keys the bot's buckets, as in permit requests.
+    pub fn new(client: Client, discord_identity: impl Into<String>) -> Self {
+        Self {
+            client,
+            discord_identity: discord_identity.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for DiscordPermits {
+    async fn handle(
+        &self,
+        request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if !is_discord_api(request.url()) {
+            return next.run(request, extensions).await;
+        }
+        let mut permit_request = extensions
+            .get::<PermitRequest>()
+            .cloned()
+            .unwrap_or_else(|| PermitRequest::new(self.discord_identity.clone(), "", ""));
+        permit_request.method = request.method().as_str().to_string();
+        permit_request.route = request.url().to_string();
+        let mut permit = match self.client.acquire(permit_request).await {
+            Ok(permit) => permit,
+            Err(Error::Denied(decision)) => {
+                return Err(reqwest_middleware::Error::middleware(Error::Denied(
+                    decision,
+                )))
+            }
+            Err(_) => return next.run(request, extensions).await,
+        };
+        let response = next.run(request, extensions).await;
+        if let Ok(response) = &response {
+            permit.record(response.status().as_u16(), response.headers());
+        }
+        response
+    }
+}
+
+/// `discord.com` and its canary and PTB hosts (and the legacy
+/// `discordapp.com`), under `/api`.
+fn is_discord_api(url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let discord = ["discord.com", "discordapp.com"].iter().any(|domain| {
+        host == *domain
+            || host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.ends_with('.'))
+    });
+    discord && url.path().starts_with("/api/")
+}
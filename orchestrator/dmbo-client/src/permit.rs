@@ -0,0 +1,89 @@
+//! A granted permit held while its Discord call runs.
+
+use crate::{Client, Decision, Error, PermitRequest, Report};
+use reqwest::header::HeaderMap;
+
+/// A granted permit. Record the call's outcome with [`Permit::record`] (or
+/// send it at once with [`Permit::report`]); when the permit is dropped the
+/// recorded outcome is reported in the background. A permit dropped without
+/// an outcome releases its lease, as the call was never made.
+///
+/// Background reports need a Tokio runtime; without one the lease simply
+/// expires on the server.
+pub struct Permit {
+    client: Client,
+    request: PermitRequest,
+    decision: Decision,
+    outcome: Option<Report>,
+    settled: bool,
+}
+
+impl Permit {
+    pub(crate) fn new(client: Client, request: PermitRequest, decision: Decision) -> Self {
+        Self {
+            client,
+            request,
+            decision,
+            outcome: None,
+            settled: false,
+        }
+    }
+
+    pub fn decision(&self) -> &Decision {
+        &self.decision
+    }
+
+    pub fn request(&self) -> &PermitRequest {
+        &self.request
+    }
+
+    /// Remembers Discord's response to report when the permit is dropped.
+    pub fn record(&mut self, status_code: u16, headers: &HeaderMap) {
+        self.outcome = Some(self.report_for(status_code, headers));
+    }
+
+    /// Remembers a prepared report, e.g. one carrying `body_retry_after_s`.
+    pub fn record_report(&mut self, report: Report) {
+        self.outcome = Some(report);
+    }
+
+    /// Reports Discord's response now and waits for the orchestrator to
+    /// acknowledge it.
+    pub async fn report(mut self, status_code: u16, headers: &HeaderMap) -> Result<(), Error> {
+        let report = self.report_for(status_code, headers);
+        self.settled = true;
+        self.client.report_result(&report).await
+    }
+
+    fn report_for(&self, status_code: u16, headers: &HeaderMap) -> Report {
+        let mut report = Report::new(&self.request, status_code, headers);
+        report.lease_id = self.decision.lease_id.clone();
+        report
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let client = self.client.clone();
+        match self.outcome.take() {
+            Some(report) => {
+                runtime.spawn(async move {
+                    let _ = client.report_result(&report).await;
+                });
+            }
+            None => {
+                if let Some(lease_id) = self.decision.lease_id.clone() {
+                    runtime.spawn(async move {
+                        let _ = client.release_lease(&lease_id).await;
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! Serenity through the orchestrator's proxy mode. Serenity 0.12 has no hook
+//! in front of its REST calls that a permit could be taken in, but it can
+//! send them to a proxy instead of discord.com and skip its own ratelimiter.
+
+use crate::ClientConfig;
+use serenity::http::HttpBuilder;
+
+/// A serenity [`HttpBuilder`] whose REST calls go to the orchestrator at
+/// `config.base_url`, which must run with `DMBO_PROXY=true`:
+///
+/// ```no_run
+/// # use dmbo_client::{serenity_http, ClientConfig};
+/// let http = serenity_http(&ClientConfig::default(), "Bot-token").build();
+/// // serenity::Client::builder(token, intents) becomes
+/// // serenity::all::ClientBuilder::new_with_http(http, intents)
+/// ```
+///
+/// - Each call takes its permit, is forwarded to Discord and is reported by
+///   the orchestrator, so serenity's ratelimiter is disabled.
+/// - Calls are limited under the bot id in the token: serenity cannot send
+///   `X-Dmbo-Identity` or the other `X-Dmbo-*` headers.
+/// - The other settings of `config` do not apply: serenity makes the calls
+///   with its own HTTP client.
+pub fn serenity_http(config: &ClientConfig, token: &str) -> HttpBuilder {
+    HttpBuilder::new(token)
+        .proxy(config.base_url.trim_end_matches('/'))
+        .ratelimiter_disabled(true)
+}
@@ -0,0 +1,172 @@
+//! A twilight-http [`Ratelimiter`] that takes a permit from the orchestrator
+//! for every ticket and reports the headers twilight hands back.
+
+use crate::{Client, Error, PermitRequest, Report};
+use twilight_http_ratelimiting::{
+    headers::{RatelimitHeaders, RatelimitScope},
+    request::Path,
+    ticket, GetBucketFuture, GetTicketFuture, HasBucketFuture, IsGloballyLockedFuture, Ratelimiter,
+};
+
+/// Coordinates twilight's REST calls through the orchestrator:
+///
+/// ```no_run
+/// # use dmbo_client::{Client, ClientConfig, OrchestratorRatelimiter};
+/// let ratelimiter = OrchestratorRatelimiter::new(Client::new(ClientConfig::default()), "bot-1");
+/// // twilight_http::Client::builder().ratelimiter(Some(Box::new(ratelimiter)))
+/// ```
+///
+/// - A ticket is ready once [`Client::acquire`] is granted. A permit the
+///   orchestrator keeps denying fails the call; when the orchestrator cannot
+///   be reached the ticket is granted unbrokered.
+/// - Twilight only passes back rate-limit headers, not the status code. A
+///   response with `X-RateLimit-Scope` or a global limit is reported as a
+///   429 and any other as a 200, so 401 and 403 responses do not count
+///   toward the invalid-request guardrail.
+/// - A [`Path`] carries only its major parameter, so other ids in the route
+///   appear as `:id`. Routes whose path does not name the method are asked
+///   for as `GET` (method weights then treat them as reads).
+/// - Bucket state lives in the orchestrator: `bucket` and `has` report
+///   nothing locally and `globally_locked` is always false.
+#[derive(Debug)]
+pub struct OrchestratorRatelimiter {
+    client: Client,
+    discord_identity: String,
+}
+
+impl OrchestratorRatelimiter {
+    /// `discord_identity` keys the bot's buckets, as in permit requests.
+    pub fn new(client: Client, discord_identity: impl Into<String>) -> Self {
+        Self {
+            client,
+            discord_identity: discord_identity.into(),
+        }
+    }
+}
+
+impl Ratelimiter for OrchestratorRatelimiter {
+    fn bucket(&self, _path: &Path) -> GetBucketFuture {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn globally_locked(&self) -> IsGloballyLockedFuture {
+        Box::pin(async { Ok(false) })
+    }
+
+    fn has(&self, _path: &Path) -> HasBucketFuture {
+        Box::pin(async { Ok(false) })
+    }
+
+    fn ticket(&self, path: Path) -> GetTicketFuture {
+        let (method, route, major_parameter) = describe(&path);
+        let mut request = PermitRequest::new(self.discord_identity.clone(), method, route);
+        request.major_parameter = major_parameter;
+        let client = self.client.clone();
+        let (notifier, receiver) = ticket::channel();
+        tokio::spawn(async move {
+            let mut permit = match client.acquire(request).await {
+                Ok(permit) => Some(permit),
+                // Dropping the notifier fails the caller's ticket.
+                Err(Error::Denied(_)) => return,
+                Err(_) => None,
+            };
+            let Some(headers) = notifier.available() else {
+                return;
+            };
+            let Ok(Some(headers)) = headers.await else {
+                return;
+            };
+            if let Some(permit) = &mut permit {
+                let report = report_for(permit.request(), &headers);
+                permit.record_report(report);
+            }
+        });
+        Box::pin(async move { Ok(receiver) })
+    }
+}
+
+/// Method, route and major parameter of a twilight path. `Path` exposes
+/// its parts only through `Debug`, e.g. `ChannelsIdMessagesId(Delete, 123)`:
+/// the variant name spells the route and the arguments hold the method and
+/// the major parameter.
+fn describe(path: &Path) -> (String, String, String) {
+    let debug = format!("{path:?}");
+    let (name, args) = debug
+        .trim_end_matches(')')
+        .split_once('(')
+        .unwrap_or((debug.as_str(), ""));
+    let mut method = "GET".to_string();
+    let mut major_parameter = String::new();
+    for arg in args.split(", ").filter(|arg| !arg.is_empty()) {
+        if arg.parse::<u64>().is_ok() {
+            major_parameter = arg.to_string();
+        } else if ["Delete", "Get", "Patch", "Post", "Put"].contains(&arg) {
+            method = arg.to_ascii_uppercase();
+        }
+    }
+    let mut route = String::new();
+    let mut major_placed = false;
+    for word in camel_words(name) {
+        route.push('/');
+        if word == "Id" {
+            if !major_placed && !major_parameter.is_empty() {
+                route.push_str(&major_parameter);
+                major_placed = true;
+            } else {
+                route.push_str(":id");
+            }
+        } else {
+            route.push_str(&word.to_ascii_lowercase());
+        }
+    }
+    (method, route, major_parameter)
+}
+
+fn camel_words(name: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (index, ch) in name.char_indices().skip(1) {
+        if ch.is_ascii_uppercase() {
+            words.push(&name[start..index]);
+            start = index;
+        }
+    }
+    words.push(&name[start..]);
+    words
+}
+
+fn report_for(request: &PermitRequest, headers: &RatelimitHeaders) -> Report {
+    let mut report = Report::new(request, 200, &Default::default());
+    match headers {
+        RatelimitHeaders::Present(present) => {
+            report.x_ratelimit_bucket = present.bucket().map(str::to_string);
+            report.x_ratelimit_limit = Some(present.limit());
+            report.x_ratelimit_remaining = Some(present.remaining());
+            // `reset_after` is in milliseconds; only `GlobalLimited` counts
+            // seconds.
+            report.x_ratelimit_reset_after_s = Some(present.reset_after() as f64 / 1000.0);
+            // Discord only sends a scope with 429s.
+            if let Some(scope) = present.scope() {
+                report.status_code = 429;
+                report.x_ratelimit_scope = Some(scope_name(scope).to_string());
+                report.retry_after_ms = Some(present.reset_after());
+            }
+        }
+        RatelimitHeaders::GlobalLimited(global) => {
+            report.status_code = 429;
+            report.x_ratelimit_global = true;
+            report.x_ratelimit_scope = Some("global".to_string());
+            report.retry_after_ms = Some(global.retry_after().saturating_mul(1000));
+        }
+        _ => {}
+    }
+    report
+}
+
+fn scope_name(scope: RatelimitScope) -> &'static str {
+    match scope {
+        RatelimitScope::Global => "global",
+        RatelimitScope::Shared => "shared",
+        RatelimitScope::User => "user",
+    }
+}
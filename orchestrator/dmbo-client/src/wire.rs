@@ -0,0 +1,191 @@
+//! Bodies of `POST /request_token` and `POST /report_result`.
+
+use dmbo_core::{Reason, RetryGuidance, DEFAULT_GROUP_ID};
+use reqwest::header::{HeaderMap, CONTENT_TYPE, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+
+/// One Discord call to ask a permit for.
+#[derive(Debug, Clone, Serialize)]
+pub struct PermitRequest {
+    /// Filled from [`ClientConfig::client_id`](crate::ClientConfig) when empty.
+    pub client_id: String,
+    pub group_id: String,
+    pub discord_identity: String,
+    pub method: String,
+    /// Route template, concrete path, or full Discord request URL.
+    pub route: String,
+    /// Derived from a concrete `route` when empty, or `unknown` when the
+    /// route has none.
+    pub major_parameter: String,
+    /// `critical`, `high`, `normal` or `low`.
+    pub priority: String,
+    /// How long the orchestrator may hold the call waiting for capacity.
+    pub max_wait_ms: u64,
+    /// Wait until granted, woken by the queue, for up to the server's cap
+    /// when `max_wait_ms` is 0.
+    pub long_poll: bool,
+    /// Generated when empty; kept across the attempts of one call.
+    pub request_id: String,
+    /// Set by [`Client::acquire`](crate::Client::acquire) on each attempt.
+    pub attempt: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<u64>,
+    /// Size of the files an upload sends, for byte pacing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_bytes: Option<u64>,
+    /// Bot feature the call is made for; one of the server's `DMBO_FEATURES`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_unix_ms: Option<u64>,
+}
+
+impl PermitRequest {
+    pub fn new(
+        discord_identity: impl Into<String>,
+        method: impl Into<String>,
+        route: impl Into<String>,
+    ) -> Self {
+        Self {
+            client_id: String::new(),
+            group_id: DEFAULT_GROUP_ID.to_string(),
+            discord_identity: discord_identity.into(),
+            method: method.into(),
+            route: route.into(),
+            major_parameter: String::new(),
+            priority: "normal".to_string(),
+            max_wait_ms: 2000,
+            long_poll: false,
+            request_id: String::new(),
+            attempt: 0,
+            cost: None,
+            payload_bytes: None,
+            feature: None,
+            client_unix_ms: None,
+        }
+    }
+}
+
+/// The orchestrator's answer to a permit request, granted or denied.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Decision {
+    pub granted: bool,
+    /// Start the call no earlier than this, on the server's clock.
+    pub not_before_unix_ms: u64,
+    pub server_unix_ms: u64,
+    /// Canonical route template the request was keyed on.
+    pub route_template: String,
+    pub lease_id: Option<String>,
+    pub retry_after_ms: Option<u64>,
+    /// Wire code; see [`Decision::reason`].
+    #[serde(rename = "reason")]
+    pub reason_code: String,
+    pub reason_message: String,
+    pub retry: Option<RetryGuidance>,
+    /// Calls queued ahead of a denied one on its route bucket.
+    pub queue_position: Option<u64>,
+    pub estimated_wait_ms: Option<u64>,
+    /// `request_id` of the identical GET whose response to share.
+    pub coalesced_with: Option<String>,
+}
+
+impl Decision {
+    /// `None` for reasons added to the server after this client.
+    pub fn reason(&self) -> Option<Reason> {
+        Reason::from_code(&self.reason_code)
+    }
+
+    /// How long to hold a granted call until `not_before_unix_ms`.
+    pub fn start_delay_ms(&self) -> u64 {
+        self.not_before_unix_ms.saturating_sub(self.server_unix_ms)
+    }
+
+    /// How long to wait before asking again after a denial: somewhere in
+    /// the server's jitter range, or its plain retry hint.
+    pub fn retry_delay_ms(&self) -> u64 {
+        let delay_ms = match &self.retry {
+            Some(guidance) => {
+                let spread = guidance
+                    .jitter_max_ms
+                    .saturating_sub(guidance.jitter_min_ms);
+                guidance.jitter_min_ms + jitter(spread)
+            }
+            None => self.retry_after_ms.unwrap_or(50),
+        };
+        delay_ms.max(10)
+    }
+}
+
+/// Cheap spread in `0..=max`; retries only need to not line up.
+fn jitter(max: u64) -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| u64::from(now.subsec_nanos()))
+        .unwrap_or_default();
+    nanos % (max + 1)
+}
+
+/// The outcome of a permitted Discord call.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub request_id: String,
+    pub client_id: String,
+    pub lease_id: Option<String>,
+    pub discord_identity: String,
+    pub group_id: String,
+    pub method: String,
+    pub route: String,
+    pub major_parameter: String,
+    pub status_code: u16,
+    pub x_ratelimit_bucket: Option<String>,
+    pub x_ratelimit_limit: Option<u64>,
+    pub x_ratelimit_remaining: Option<u64>,
+    pub x_ratelimit_reset_after_s: Option<f64>,
+    pub x_ratelimit_scope: Option<String>,
+    pub x_ratelimit_global: bool,
+    pub retry_after_ms: Option<u64>,
+    /// `retry_after` from a 429's JSON body, in seconds.
+    pub body_retry_after_s: Option<f64>,
+    /// The 429 came from Cloudflare (no JSON body), not Discord.
+    pub cloudflare: bool,
+    pub feature: Option<String>,
+}
+
+impl Report {
+    /// A report of Discord's response to `request`, read from its status
+    /// and rate-limit headers.
+    pub fn new(request: &PermitRequest, status_code: u16, headers: &HeaderMap) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let number = |name: &str| header(name).and_then(|value| value.parse::<f64>().ok());
+        let is_json = header(CONTENT_TYPE.as_str()).is_some_and(|value| value.contains("json"));
+        Self {
+            request_id: request.request_id.clone(),
+            client_id: request.client_id.clone(),
+            lease_id: None,
+            discord_identity: request.discord_identity.clone(),
+            group_id: request.group_id.clone(),
+            method: request.method.clone(),
+            route: request.route.clone(),
+            major_parameter: request.major_parameter.clone(),
+            status_code,
+            x_ratelimit_bucket: header("x-ratelimit-bucket"),
+            x_ratelimit_limit: number("x-ratelimit-limit").map(|limit| limit as u64),
+            x_ratelimit_remaining: number("x-ratelimit-remaining").map(|left| left as u64),
+            x_ratelimit_reset_after_s: number("x-ratelimit-reset-after"),
+            x_ratelimit_scope: header("x-ratelimit-scope"),
+            x_ratelimit_global: header("x-ratelimit-global")
+                .is_some_and(|value| value.eq_ignore_ascii_case("true")),
+            retry_after_ms: number(RETRY_AFTER.as_str())
+                .map(|seconds| (seconds * 1000.0).ceil() as u64),
+            body_retry_after_s: None,
+            cloudflare: status_code == 429 && !is_json,
+            feature: request.feature.clone(),
+        }
+    }
+}
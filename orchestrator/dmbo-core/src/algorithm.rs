@@ -0,0 +1,147 @@
+//! Limiter algorithms and the inputs and results of one evaluation. The
+//! service runs them as Lua scripts against Redis; [`MemoryBackend`]
+//! implements the same arithmetic in process.
+//!
+//! [`MemoryBackend`]: crate::MemoryBackend
+
+use crate::Reason;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    /// Per-second counters: up to `limit` grants per wall-clock second.
+    FixedWindow,
+    /// Generic cell rate algorithm: a burst of `limit`, then one grant every
+    /// `1000 / limit` ms, without the doubled burst a fixed window allows
+    /// across a second boundary.
+    Gcra,
+    /// Refillable token bucket: holds `burst_percent` of the limit and
+    /// refills at `limit` tokens per window, so bursts and the sustained
+    /// rate are tuned separately.
+    TokenBucket,
+}
+
+impl Algorithm {
+    /// The configuration and wire name, e.g. `fixed_window`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::FixedWindow => "fixed_window",
+            Self::Gcra => "gcra",
+            Self::TokenBucket => "token_bucket",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "fixed_window" => Some(Self::FixedWindow),
+            "gcra" => Some(Self::Gcra),
+            "token_bucket" => Some(Self::TokenBucket),
+            _ => None,
+        }
+    }
+
+    /// Time until the route bucket is back to its full limit, for forecasts.
+    pub fn resets_in_ms(self, now_ms: u64, limit: u64, remaining: u64, window_ms: u64) -> u64 {
+        let window_ms = window_ms.max(1);
+        match self {
+            Self::FixedWindow => window_ms - now_ms % window_ms,
+            Self::Gcra | Self::TokenBucket => {
+                limit.saturating_sub(remaining) * window_ms / limit.max(1)
+            }
+        }
+    }
+}
+
+/// Inputs to one limiter evaluation.
+#[derive(Clone, Debug)]
+pub struct LimitInput {
+    pub group_id: String,
+    pub discord_identity: String,
+    /// Key suffix from [`bucket_id`](crate::keys::bucket_id).
+    pub bucket: String,
+    /// [`bucket_id`](crate::keys::bucket_id) of the literal request tuple,
+    /// which `bucket` may replace with a discovered Discord bucket;
+    /// sub-limit cooldowns are keyed on it.
+    pub route_bucket: String,
+    /// The resource the request acts on; shared-scope cooldowns are keyed
+    /// on it.
+    pub major_parameter: String,
+    pub global_limit: u64,
+    /// Skips the global limit (and a reported global cooldown) for routes
+    /// Discord does not count against it, such as interaction responses.
+    pub global_exempt: bool,
+    pub route_limit: u64,
+    /// Window the route limit counts over, normally 1000 ms; the global
+    /// limit is always per second.
+    pub route_window_ms: u64,
+    /// Units the request draws from both limits: its method weight times
+    /// any cost the caller asked for. Capped at each limit so a heavy
+    /// request is slowed rather than never granted.
+    pub cost: u64,
+    /// Token bucket capacity as a percentage of each limit; other
+    /// algorithms ignore it.
+    pub burst_percent: u64,
+    pub now_ms: u64,
+}
+
+/// Capacity of a token bucket refilling at `limit` per window, never below
+/// one token.
+pub fn token_bucket_capacity(limit: u64, burst_percent: u64) -> f64 {
+    (limit as f64 * burst_percent as f64 / 100.0).max(1.0)
+}
+
+/// Cost multipliers per HTTP method, so mutation-heavy bursts drain the
+/// limits faster than reads. Methods not listed cost 1.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MethodWeights(BTreeMap<String, u64>);
+
+impl MethodWeights {
+    /// Parses `METHOD=weight` pairs separated by commas, e.g.
+    /// `DELETE=3,PATCH=2`. Methods are case-insensitive; weights must be
+    /// 1 to 100.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut weights = BTreeMap::new();
+        for pair in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (method, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("{pair:?} is not METHOD=weight"))?;
+            let method = method.trim().to_ascii_uppercase();
+            let weight = weight
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|weight| (1..=100).contains(weight))
+                .ok_or_else(|| format!("weight for {method} must be 1 to 100"))?;
+            if method.is_empty() || weights.insert(method.clone(), weight).is_some() {
+                return Err(format!("{pair:?} repeats or omits its method"));
+            }
+        }
+        Ok(Self(weights))
+    }
+
+    pub fn cost(&self, method: &str) -> u64 {
+        self.0.get(method).copied().unwrap_or(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Result of one limiter evaluation.
+#[derive(Clone, Copy, Debug)]
+pub struct Evaluation {
+    pub granted: bool,
+    /// Wait before retrying a denial, at least the minimum retry delay.
+    pub retry_after_ms: u64,
+    pub reason: Reason,
+    /// Route limit and what is left of it after a grant; `0` on denials.
+    pub route_limit: u64,
+    pub route_remaining: u64,
+}
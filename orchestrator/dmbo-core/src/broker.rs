@@ -0,0 +1,297 @@
+//! The rate-limit broker: request normalization, limit evaluation, waiting
+//! for capacity and the invalid-request guardrail, over a pluggable
+//! [`Backend`]. The service's `issue_permit` makes the same decision with
+//! Redis and its service-only policies on top.
+
+use crate::{
+    keys::{bucket_id, counts_toward_invalid_limit, normalize_key_part},
+    routes, Algorithm, Evaluation, LimitInput, MemoryBackend, MethodWeights, Reason, RetryGuidance,
+    RetryPolicy,
+};
+use std::{
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Group used when a request names none, as in the service.
+pub const DEFAULT_GROUP_ID: &str = "homelab-ip";
+
+/// Where limiter state lives. Evaluations must be atomic per call: the
+/// guardrail check and both limit checks see one consistent state.
+pub trait Backend: Send + Sync {
+    type Error: Send;
+
+    /// Checks the group's guardrail, then the global and route limits, and
+    /// records the grant if both have room.
+    fn evaluate(
+        &self,
+        algorithm: Algorithm,
+        input: &LimitInput,
+        min_retry_ms: u64,
+    ) -> impl Future<Output = Result<Evaluation, Self::Error>> + Send;
+
+    /// Counts an invalid response for `group_id` (normalized) and, once the
+    /// count reaches `threshold`, (re)arms its guard for `cooldown_ms`.
+    /// Returns the count when this call tripped a guard that was not active.
+    fn record_invalid(
+        &self,
+        group_id: &str,
+        threshold: u64,
+        cooldown_ms: u64,
+        now_ms: u64,
+    ) -> impl Future<Output = Result<Option<u64>, Self::Error>> + Send;
+}
+
+/// Broker settings; the defaults match the service's.
+#[derive(Debug, Clone)]
+pub struct BrokerConfig {
+    /// Per-identity rate across all routes.
+    pub global_rps: u64,
+    /// Per-bucket rate.
+    pub route_rps: u64,
+    pub algorithm: Algorithm,
+    /// Token bucket capacity as a percentage of each limit.
+    pub burst_percent: u64,
+    pub method_weights: MethodWeights,
+    pub retry: RetryPolicy,
+    /// Invalid responses per 10 minutes that trip a group's guardrail.
+    pub invalid_threshold: u64,
+    /// How long a tripped guardrail denies the group's permits.
+    pub guardrail_cooldown_ms: u64,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self {
+            global_rps: 50,
+            route_rps: 5,
+            algorithm: Algorithm::FixedWindow,
+            burst_percent: 100,
+            method_weights: MethodWeights::default(),
+            retry: RetryPolicy::default(),
+            invalid_threshold: 8000,
+            guardrail_cooldown_ms: 30_000,
+        }
+    }
+}
+
+/// One Discord call to permit.
+#[derive(Debug, Clone)]
+pub struct PermitRequest {
+    pub group_id: String,
+    pub discord_identity: String,
+    pub method: String,
+    /// Route template, concrete path, or full Discord request URL.
+    pub route: String,
+    /// Derived from a concrete `route` when empty.
+    pub major_parameter: String,
+    /// Replace the configured limits for this request.
+    pub global_rps: Option<u64>,
+    pub route_rps: Option<u64>,
+}
+
+impl PermitRequest {
+    pub fn new(
+        discord_identity: impl Into<String>,
+        method: impl Into<String>,
+        route: impl Into<String>,
+    ) -> Self {
+        Self {
+            group_id: DEFAULT_GROUP_ID.to_string(),
+            discord_identity: discord_identity.into(),
+            method: method.into(),
+            route: route.into(),
+            major_parameter: String::new(),
+            global_rps: None,
+            route_rps: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Permit {
+    pub granted: bool,
+    pub reason: Reason,
+    /// Wait before retrying a denial; `0` when granted.
+    pub retry_after_ms: u64,
+    /// Time spent waiting for capacity before the decision.
+    pub waited_ms: u64,
+    /// Normalized route template the permit was evaluated on.
+    pub route_template: String,
+    /// Route limit and what is left of it after a grant; `0` on denials.
+    pub route_limit: u64,
+    pub route_remaining: u64,
+}
+
+/// The outcome of a Discord call, for the invalid-request guardrail.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub group_id: String,
+    pub status_code: u16,
+    /// `X-RateLimit-Scope` of a 429.
+    pub scope: Option<String>,
+}
+
+/// Decides permits for one process's Discord calls. Defaults to the
+/// in-memory backend; see [`RateLimitBroker::in_memory`].
+pub struct RateLimitBroker<B = MemoryBackend> {
+    config: BrokerConfig,
+    backend: B,
+    queue_depth: AtomicU64,
+}
+
+impl RateLimitBroker {
+    /// A broker whose state lives in this process only.
+    pub fn in_memory(config: BrokerConfig) -> Self {
+        Self::new(config, MemoryBackend::default())
+    }
+}
+
+impl<B: Backend> RateLimitBroker<B> {
+    pub fn new(config: BrokerConfig, backend: B) -> Self {
+        Self {
+            config,
+            backend,
+            queue_depth: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &BrokerConfig {
+        &self.config
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Calls currently waiting for capacity in [`acquire`](Self::acquire).
+    pub fn queue_depth(&self) -> u64 {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// Decides immediately, without waiting for capacity.
+    pub async fn try_acquire(&self, request: &PermitRequest) -> Result<Permit, B::Error> {
+        self.acquire(request, Duration::ZERO).await
+    }
+
+    /// Waits up to `max_wait` for capacity, like `request_token` with
+    /// `max_wait_ms`. A denial whose retry delay would overrun `max_wait` is
+    /// returned at once rather than after a pointless wait.
+    pub async fn acquire(
+        &self,
+        request: &PermitRequest,
+        max_wait: Duration,
+    ) -> Result<Permit, B::Error> {
+        let method = request.method.trim().to_ascii_uppercase();
+        let resolved = routes::normalize_route(&request.route);
+        let major_parameter = if request.major_parameter.trim().is_empty() {
+            resolved.major_parameter.unwrap_or_default()
+        } else {
+            request.major_parameter.clone()
+        };
+        let bucket = bucket_id(
+            &request.discord_identity,
+            &method,
+            &resolved.template,
+            &major_parameter,
+        );
+        let mut input = LimitInput {
+            group_id: normalize_key_part(&request.group_id),
+            discord_identity: request.discord_identity.clone(),
+            route_bucket: bucket.clone(),
+            bucket,
+            major_parameter: major_parameter.clone(),
+            global_limit: request.global_rps.unwrap_or(self.config.global_rps),
+            global_exempt: false,
+            route_limit: request.route_rps.unwrap_or(self.config.route_rps),
+            route_window_ms: 1000,
+            cost: self.config.method_weights.cost(&method),
+            burst_percent: self.config.burst_percent,
+            now_ms: 0,
+        };
+
+        let max_wait_ms = max_wait.as_millis() as u64;
+        let min_retry_ms = self.config.retry.min_retry_ms;
+        let started = unix_ms();
+        let deadline = started.saturating_add(max_wait_ms);
+        let mut waited_ms = 0_u64;
+        loop {
+            input.now_ms = unix_ms();
+            let evaluation = self
+                .backend
+                .evaluate(self.config.algorithm, &input, min_retry_ms)
+                .await?;
+            let retry_after_ms = if evaluation.granted {
+                0
+            } else {
+                evaluation.retry_after_ms.max(min_retry_ms)
+            };
+            let now = unix_ms();
+            let can_wait = !evaluation.granted
+                && max_wait_ms > 0
+                && now < deadline
+                && now.saturating_add(retry_after_ms) <= deadline
+                && waited_ms.saturating_add(retry_after_ms) <= max_wait_ms;
+            if can_wait {
+                let _queued = Queued::new(&self.queue_depth);
+                tokio::time::sleep(Duration::from_millis(retry_after_ms)).await;
+                waited_ms = waited_ms.saturating_add(retry_after_ms);
+                continue;
+            }
+            return Ok(Permit {
+                granted: evaluation.granted,
+                reason: evaluation.reason,
+                retry_after_ms,
+                waited_ms,
+                route_template: resolved.template,
+                route_limit: evaluation.route_limit,
+                route_remaining: evaluation.route_remaining,
+            });
+        }
+    }
+
+    /// Feeds a call's outcome to the guardrail. Returns the invalid count
+    /// when this report tripped the group's guard.
+    pub async fn report(&self, report: &Report) -> Result<Option<u64>, B::Error> {
+        if !counts_toward_invalid_limit(report.status_code, report.scope.as_deref()) {
+            return Ok(None);
+        }
+        self.backend
+            .record_invalid(
+                &normalize_key_part(&report.group_id),
+                self.config.invalid_threshold,
+                self.config.guardrail_cooldown_ms,
+                unix_ms(),
+            )
+            .await
+    }
+
+    /// Retry guidance for the `attempt`th try of a denied request.
+    pub fn retry_guidance(&self, attempt: u32, retry_after_ms: u64) -> RetryGuidance {
+        self.config.retry.guidance(attempt, retry_after_ms)
+    }
+}
+
+/// Counts a waiting call for as long as it waits, even if it is cancelled.
+struct Queued<'a>(&'a AtomicU64);
+
+impl<'a> Queued<'a> {
+    fn new(depth: &'a AtomicU64) -> Self {
+        depth.fetch_add(1, Ordering::Relaxed);
+        Self(depth)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
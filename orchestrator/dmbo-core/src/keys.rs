@@ -0,0 +1,103 @@
+//! Key naming shared by every backend, so a bucket means the same thing in
+//! Redis and in memory, and the service's Redis keys.
+
+use crate::Algorithm;
+
+/// Makes client-supplied text safe to embed in a `:`-separated key.
+pub fn normalize_key_part(input: &str) -> String {
+    input.trim().replace([' ', ':', '/', '\\', '\t', '\n'], "_")
+}
+
+/// Identity, method, route and major parameter joined into the key suffix
+/// shared by the route counters and learned Discord bucket state.
+pub fn bucket_id(
+    discord_identity: &str,
+    method: &str,
+    route: &str,
+    major_parameter: &str,
+) -> String {
+    format!(
+        "{}:{}:{}:{}",
+        normalize_key_part(discord_identity),
+        normalize_key_part(method),
+        normalize_key_part(route),
+        normalize_key_part(major_parameter)
+    )
+}
+
+/// Key suffix for a route whose Discord bucket is known from
+/// `X-RateLimit-Bucket`: routes sharing the bucket hash (and major
+/// parameter) share counters. `bucket` never collides with a method name.
+pub fn discord_bucket_id(discord_identity: &str, bucket: &str, major_parameter: &str) -> String {
+    format!(
+        "{}:bucket:{}:{}",
+        normalize_key_part(discord_identity),
+        normalize_key_part(bucket),
+        normalize_key_part(major_parameter)
+    )
+}
+
+/// The group's invalid-request guard; present while it denies permits.
+pub fn guard_key(group_id: &str) -> String {
+    format!("rl:guard:{}", normalize_key_part(group_id))
+}
+
+/// The group's invalid responses in the current guardrail window.
+pub fn invalid_key(group_id: &str) -> String {
+    format!("rl:invalid:{}", normalize_key_part(group_id))
+}
+
+/// Key of the identity's (`scope` `global`) or a bucket's (`route`) limit
+/// under `algorithm`, fixed windows `window_ms` long. Algorithms keep
+/// separate keys.
+pub fn limit_key(
+    algorithm: Algorithm,
+    prefix: &str,
+    scope: &str,
+    suffix: &str,
+    now_ms: u64,
+    window_ms: u64,
+) -> String {
+    match algorithm {
+        Algorithm::FixedWindow => format!("{prefix}:{scope}:{suffix}:{}", now_ms / window_ms),
+        Algorithm::Gcra => format!("{prefix}:gcra:{scope}:{suffix}"),
+        Algorithm::TokenBucket => format!("{prefix}:tb:{scope}:{suffix}"),
+    }
+}
+
+/// Set from a reported global-scope 429's Retry-After.
+pub fn global_cooldown_key(discord_identity: &str) -> String {
+    format!(
+        "rl:cooldown:global:{}",
+        normalize_key_part(discord_identity)
+    )
+}
+
+/// Set from a reported 429's Retry-After for the bucket's route.
+pub fn route_cooldown_key(bucket: &str) -> String {
+    format!("rl:cooldown:route:{bucket}")
+}
+
+/// Set from a reported shared-scope 429's Retry-After. Shared limits belong
+/// to the resource, so the cooldown holds every identity and route acting on
+/// it.
+pub fn shared_cooldown_key(major_parameter: &str) -> String {
+    format!("rl:cooldown:shared:{}", normalize_key_part(major_parameter))
+}
+
+/// Set from the `retry_after` of a reported 429's body. Sub-limits such as
+/// channel renames apply to one route, not its whole Discord bucket, so this
+/// is keyed on the literal request tuple.
+pub fn sublimit_cooldown_key(route_bucket: &str) -> String {
+    format!("rl:cooldown:sublimit:{route_bucket}")
+}
+
+/// Whether a Discord response counts toward Discord's invalid-request limit:
+/// 401s, 403s and 429s other than shared-scope ones.
+pub fn counts_toward_invalid_limit(status_code: u16, scope: Option<&str>) -> bool {
+    match status_code {
+        401 | 403 => true,
+        429 => scope != Some("shared"),
+        _ => false,
+    }
+}
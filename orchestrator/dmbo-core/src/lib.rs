@@ -0,0 +1,47 @@
+//! The policy core of the dmbo orchestrator: limiter algorithms, the
+//! invalid-request guardrail, route normalization, bucket keys, retry
+//! guidance, decision reasons, and the Redis keys and scripts that carry
+//! them. The HTTP service builds on these with Redis; single-process bots
+//! can embed a [`RateLimitBroker`] with the in-memory backend and skip the
+//! service entirely.
+//!
+//! ```no_run
+//! use dmbo_core::{BrokerConfig, PermitRequest, RateLimitBroker, Report};
+//! use std::time::Duration;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let broker = RateLimitBroker::in_memory(BrokerConfig::default());
+//! let request = PermitRequest::new("bot-1", "POST", "/channels/123/messages");
+//! let Ok(permit) = broker.acquire(&request, Duration::from_secs(2)).await;
+//! if permit.granted {
+//!     // ...call Discord, then feed the outcome back...
+//!     let _ = broker
+//!         .report(&Report { group_id: request.group_id.clone(), status_code: 200, scope: None })
+//!         .await;
+//! }
+//! # }
+//! ```
+
+mod algorithm;
+mod broker;
+pub mod keys;
+mod memory;
+mod reason;
+mod retry;
+pub mod routes;
+pub mod scripts;
+
+pub use algorithm::{token_bucket_capacity, Algorithm, Evaluation, LimitInput, MethodWeights};
+pub use broker::{
+    Backend, BrokerConfig, Permit, PermitRequest, RateLimitBroker, Report, DEFAULT_GROUP_ID,
+};
+pub use memory::MemoryBackend;
+pub use reason::Reason;
+pub use retry::{RetryGuidance, RetryPolicy};
+
+/// Lifetime of a fixed-window counter from its first increment. A denial's
+/// retry delay is the counter's remaining lifetime.
+pub const FIXED_WINDOW_TTL_MS: u64 = 1500;
+/// Window over which invalid responses are counted toward the guardrail.
+pub const INVALID_WINDOW_MS: u64 = 600_000;
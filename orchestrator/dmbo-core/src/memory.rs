@@ -0,0 +1,285 @@
+//! In-process backend for single-process bots. It applies the same
+//! arithmetic as the service's Redis scripts, key for key, so an embedded
+//! broker and the service grant and deny the same traffic.
+
+use crate::{
+    keys::normalize_key_part, token_bucket_capacity, Algorithm, Backend, Evaluation, LimitInput,
+    Reason, FIXED_WINDOW_TTL_MS, INVALID_WINDOW_MS,
+};
+use std::{collections::HashMap, convert::Infallible, sync::Mutex};
+
+/// Expired entries are swept at most this often.
+const PRUNE_INTERVAL_MS: u64 = 1000;
+
+#[derive(Default)]
+pub struct MemoryBackend {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    /// Fixed-window and invalid-request counters: `(count, expires_ms)`.
+    counters: HashMap<String, (u64, u64)>,
+    /// GCRA theoretical arrival times: `(tat_ms, expires_ms)`.
+    tats: HashMap<String, (f64, u64)>,
+    /// Token buckets: `(tokens, updated_ms, expires_ms)`.
+    buckets: HashMap<String, (f64, u64, u64)>,
+    /// Guard expiry per normalized group.
+    guards: HashMap<String, u64>,
+    next_prune_ms: u64,
+}
+
+impl MemoryState {
+    fn prune(&mut self, now: u64) {
+        if now < self.next_prune_ms {
+            return;
+        }
+        self.next_prune_ms = now + PRUNE_INTERVAL_MS;
+        self.counters.retain(|_, (_, expires)| *expires > now);
+        self.tats.retain(|_, (_, expires)| *expires > now);
+        self.buckets.retain(|_, (_, _, expires)| *expires > now);
+        self.guards.retain(|_, until| *until > now);
+    }
+
+    /// `INCRBY` with `PEXPIRE` on the first increment; returns the count
+    /// and the remaining TTL.
+    fn incr(&mut self, key: String, by: u64, ttl_ms: u64, now: u64) -> (u64, u64) {
+        let entry = self.counters.entry(key).or_insert((0, 0));
+        if entry.1 <= now {
+            *entry = (0, now + ttl_ms);
+        }
+        entry.0 += by;
+        (entry.0, entry.1 - now)
+    }
+
+    fn fixed_window(&mut self, input: &LimitInput, identity: &str, now: u64) -> Evaluation {
+        let second = input.now_ms / 1000;
+        let window = input.route_window_ms.max(1);
+        if !input.global_exempt {
+            let (global_count, global_ttl) = self.incr(
+                format!("global:{identity}:{second}"),
+                input.cost.min(input.global_limit),
+                FIXED_WINDOW_TTL_MS,
+                now,
+            );
+            if global_count > input.global_limit {
+                return denied(global_ttl, Reason::GlobalBucketExhausted);
+            }
+        }
+        let (route_count, route_ttl) = self.incr(
+            format!("route:{}:{}", input.bucket, input.now_ms / window),
+            input.cost.min(input.route_limit),
+            FIXED_WINDOW_TTL_MS - 1000 + window,
+            now,
+        );
+        if route_count > input.route_limit {
+            return denied(route_ttl, Reason::RouteBucketExhausted);
+        }
+        granted(input.route_limit, input.route_limit - route_count)
+    }
+
+    fn gcra(&mut self, input: &LimitInput, identity: &str, now: u64) -> Evaluation {
+        let now_f = now as f64;
+        let route_window = input.route_window_ms.max(1) as f64;
+        let check = |tats: &HashMap<String, (f64, u64)>, key: &str, limit: u64, window: f64| {
+            let tat = tats
+                .get(key)
+                .filter(|(_, expires)| *expires > now)
+                .map_or(now_f, |(tat, _)| *tat)
+                .max(now_f);
+            let next_tat = tat + input.cost.min(limit) as f64 * window / limit as f64;
+            let wait = next_tat - now_f - window;
+            if wait > 0.0 {
+                Err(wait.ceil() as u64)
+            } else {
+                Ok(next_tat)
+            }
+        };
+        let global_key = format!("gcra:global:{identity}");
+        let route_key = format!("gcra:route:{}", input.bucket);
+        let global_tat = match input.global_exempt {
+            true => None,
+            false => match check(&self.tats, &global_key, input.global_limit, 1000.0) {
+                Ok(tat) => Some(tat),
+                Err(wait) => return denied(wait, Reason::GlobalBucketExhausted),
+            },
+        };
+        let route_tat = match check(&self.tats, &route_key, input.route_limit, route_window) {
+            Ok(tat) => tat,
+            Err(wait) => return denied(wait, Reason::RouteBucketExhausted),
+        };
+        let global = global_tat.map(|tat| (global_key, tat, 1000.0));
+        for (key, tat, window) in global
+            .into_iter()
+            .chain([(route_key, route_tat, route_window)])
+        {
+            let expires = now + (tat - now_f + window).ceil() as u64;
+            self.tats.insert(key, (tat, expires));
+        }
+        let remaining =
+            ((now_f + route_window - route_tat) * input.route_limit as f64 / route_window).floor();
+        granted(input.route_limit, remaining.max(0.0) as u64)
+    }
+
+    fn token_bucket(&mut self, input: &LimitInput, identity: &str, now: u64) -> Evaluation {
+        let route_window = input.route_window_ms.max(1) as f64;
+        let take =
+            |buckets: &HashMap<String, (f64, u64, u64)>, key: &str, limit: u64, window: f64| {
+                let capacity = token_bucket_capacity(limit, input.burst_percent);
+                let need = (input.cost as f64).min(capacity);
+                let (mut tokens, updated) = buckets
+                    .get(key)
+                    .filter(|(_, _, expires)| *expires > now)
+                    .map_or((capacity, now), |(tokens, updated, _)| (*tokens, *updated));
+                if now > updated {
+                    tokens = capacity.min(tokens + (now - updated) as f64 * limit as f64 / window);
+                }
+                if tokens < need {
+                    Err(((need - tokens) * window / limit as f64).ceil() as u64)
+                } else {
+                    Ok((tokens - need, capacity, updated.max(now)))
+                }
+            };
+        let global_key = format!("tb:global:{identity}");
+        let route_key = format!("tb:route:{}", input.bucket);
+        let global = match input.global_exempt {
+            true => None,
+            false => match take(&self.buckets, &global_key, input.global_limit, 1000.0) {
+                Ok(global) => Some((global_key, global, input.global_limit, 1000.0)),
+                Err(wait) => return denied(wait, Reason::GlobalBucketExhausted),
+            },
+        };
+        let route = match take(&self.buckets, &route_key, input.route_limit, route_window) {
+            Ok(route) => route,
+            Err(wait) => return denied(wait, Reason::RouteBucketExhausted),
+        };
+        let route_entry = (route_key, route, input.route_limit, route_window);
+        for (key, (tokens, capacity, updated), limit, window) in
+            global.into_iter().chain([route_entry])
+        {
+            let refill_ms = ((capacity - tokens) * window / limit as f64).ceil() as u64;
+            self.buckets
+                .insert(key, (tokens, updated, now + refill_ms + 1000));
+        }
+        granted(input.route_limit, route.0.floor() as u64)
+    }
+}
+
+fn denied(retry_after_ms: u64, reason: Reason) -> Evaluation {
+    Evaluation {
+        granted: false,
+        retry_after_ms,
+        reason,
+        route_limit: 0,
+        route_remaining: 0,
+    }
+}
+
+fn granted(route_limit: u64, route_remaining: u64) -> Evaluation {
+    Evaluation {
+        granted: true,
+        retry_after_ms: 0,
+        reason: Reason::Ok,
+        route_limit,
+        route_remaining,
+    }
+}
+
+impl MemoryBackend {
+    /// [`Backend::record_invalid`] with the whole outcome: the group's count
+    /// and, once it reached `threshold`, whether its guard was already up.
+    pub fn count_invalid(
+        &self,
+        group_id: &str,
+        threshold: u64,
+        cooldown_ms: u64,
+        now_ms: u64,
+    ) -> (u64, Option<bool>) {
+        let mut state = self.state.lock().expect("memory backend poisoned");
+        state.prune(now_ms);
+        let (count, _) = state.incr(format!("invalid:{group_id}"), 1, INVALID_WINDOW_MS, now_ms);
+        if count < threshold {
+            return (count, None);
+        }
+        let already_active = state
+            .guards
+            .insert(group_id.to_string(), now_ms + cooldown_ms)
+            .is_some_and(|until| until > now_ms);
+        (count, Some(already_active))
+    }
+
+    /// Time left on the normalized group's guard, `None` when it is not up.
+    pub fn guard_remaining_ms(&self, group_id: &str, now_ms: u64) -> Option<u64> {
+        let state = self.state.lock().expect("memory backend poisoned");
+        let until = state.guards.get(group_id).copied()?;
+        (until > now_ms).then(|| until - now_ms)
+    }
+
+    /// Arms the normalized group's guard for `cooldown_ms`, leaving a longer
+    /// guard already in place alone.
+    pub fn extend_guard(&self, group_id: &str, cooldown_ms: u64, now_ms: u64) {
+        let mut state = self.state.lock().expect("memory backend poisoned");
+        let until = state.guards.entry(group_id.to_string()).or_default();
+        *until = (*until).max(now_ms + cooldown_ms);
+    }
+
+    /// Lifts the normalized group's guard and resets its invalid count.
+    /// Returns whether there was either to clear.
+    pub fn clear_guard(&self, group_id: &str, now_ms: u64) -> bool {
+        let mut state = self.state.lock().expect("memory backend poisoned");
+        let guard = state
+            .guards
+            .remove(group_id)
+            .is_some_and(|until| until > now_ms);
+        let count = state
+            .counters
+            .remove(&format!("invalid:{group_id}"))
+            .is_some_and(|(_, expires)| expires > now_ms);
+        guard || count
+    }
+}
+
+impl Backend for MemoryBackend {
+    type Error = Infallible;
+
+    async fn evaluate(
+        &self,
+        algorithm: Algorithm,
+        input: &LimitInput,
+        min_retry_ms: u64,
+    ) -> Result<Evaluation, Infallible> {
+        let now = input.now_ms;
+        let mut state = self.state.lock().expect("memory backend poisoned");
+        state.prune(now);
+        let guard_until = state
+            .guards
+            .get(&normalize_key_part(&input.group_id))
+            .copied()
+            .unwrap_or_default();
+        let mut evaluation = if guard_until > now {
+            denied(guard_until - now, Reason::InvalidGuardrailActive)
+        } else {
+            let identity = normalize_key_part(&input.discord_identity);
+            match algorithm {
+                Algorithm::FixedWindow => state.fixed_window(input, &identity, now),
+                Algorithm::Gcra => state.gcra(input, &identity, now),
+                Algorithm::TokenBucket => state.token_bucket(input, &identity, now),
+            }
+        };
+        if !evaluation.granted {
+            evaluation.retry_after_ms = evaluation.retry_after_ms.max(min_retry_ms);
+        }
+        Ok(evaluation)
+    }
+
+    async fn record_invalid(
+        &self,
+        group_id: &str,
+        threshold: u64,
+        cooldown_ms: u64,
+        now_ms: u64,
+    ) -> Result<Option<u64>, Infallible> {
+        let (count, already_active) = self.count_invalid(group_id, threshold, cooldown_ms, now_ms);
+        Ok((already_active == Some(false)).then_some(count))
+    }
+}
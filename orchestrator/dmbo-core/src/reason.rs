@@ -0,0 +1,243 @@
+//! Decision and error reasons shared by the service and embedded brokers.
+
+use serde::Serialize;
+
+/// Stable machine-readable reasons attached to every decision and error.
+/// `code()` is the wire value (and the literal the limiter scripts return);
+/// `message()` is the human-readable companion sent as `reason_message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Ok,
+    InvalidGuardrailActive,
+    GlobalBucketExhausted,
+    RouteBucketExhausted,
+    DiscordBucketExhausted,
+    GlobalCooldownActive,
+    RouteCooldownActive,
+    SharedCooldownActive,
+    SublimitCooldownActive,
+    RedisUnavailable,
+    RedisError,
+    InvalidRequest,
+    ReportNotPersisted,
+    IdempotencyConflict,
+    JobExpired,
+    UpstreamUnavailable,
+    PluginVeto,
+    RuleDenied,
+    MaintenancePaused,
+    LongWindowExhausted,
+    GroupPaused,
+    GatewayCommandsExhausted,
+    PresenceUpdatesExhausted,
+    IdentifyExhausted,
+    CommandRegistrationExhausted,
+    WebhookExhausted,
+    ChannelSendExhausted,
+    ReactionPaced,
+    DailyQuotaExhausted,
+    UploadBytesExhausted,
+    UploadConcurrencyExhausted,
+    RouteConcurrencyExhausted,
+    YieldedToInteraction,
+    QueuedBehindWaiters,
+    QueueFull,
+    ClientQueueFull,
+    Cancelled,
+    Coalesced,
+}
+
+impl Reason {
+    /// Every reason, in declaration order.
+    pub const ALL: [Reason; 38] = [
+        Reason::Ok,
+        Reason::InvalidGuardrailActive,
+        Reason::GlobalBucketExhausted,
+        Reason::RouteBucketExhausted,
+        Reason::DiscordBucketExhausted,
+        Reason::GlobalCooldownActive,
+        Reason::RouteCooldownActive,
+        Reason::SharedCooldownActive,
+        Reason::SublimitCooldownActive,
+        Reason::RedisUnavailable,
+        Reason::RedisError,
+        Reason::InvalidRequest,
+        Reason::ReportNotPersisted,
+        Reason::IdempotencyConflict,
+        Reason::JobExpired,
+        Reason::UpstreamUnavailable,
+        Reason::PluginVeto,
+        Reason::RuleDenied,
+        Reason::MaintenancePaused,
+        Reason::LongWindowExhausted,
+        Reason::GroupPaused,
+        Reason::GatewayCommandsExhausted,
+        Reason::PresenceUpdatesExhausted,
+        Reason::IdentifyExhausted,
+        Reason::CommandRegistrationExhausted,
+        Reason::WebhookExhausted,
+        Reason::ChannelSendExhausted,
+        Reason::ReactionPaced,
+        Reason::DailyQuotaExhausted,
+        Reason::UploadBytesExhausted,
+        Reason::UploadConcurrencyExhausted,
+        Reason::RouteConcurrencyExhausted,
+        Reason::YieldedToInteraction,
+        Reason::QueuedBehindWaiters,
+        Reason::QueueFull,
+        Reason::ClientQueueFull,
+        Reason::Cancelled,
+        Reason::Coalesced,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Reason::Ok => "ok",
+            Reason::InvalidGuardrailActive => "invalid_guardrail_active",
+            Reason::GlobalBucketExhausted => "global_bucket_exhausted",
+            Reason::RouteBucketExhausted => "route_bucket_exhausted",
+            Reason::DiscordBucketExhausted => "discord_bucket_exhausted",
+            Reason::GlobalCooldownActive => "global_cooldown_active",
+            Reason::RouteCooldownActive => "route_cooldown_active",
+            Reason::SharedCooldownActive => "shared_cooldown_active",
+            Reason::SublimitCooldownActive => "sublimit_cooldown_active",
+            Reason::RedisUnavailable => "redis_unavailable",
+            Reason::RedisError => "redis_error",
+            Reason::InvalidRequest => "invalid_request",
+            Reason::ReportNotPersisted => "report_not_persisted",
+            Reason::IdempotencyConflict => "idempotency_conflict",
+            Reason::JobExpired => "job_expired",
+            Reason::UpstreamUnavailable => "upstream_unavailable",
+            Reason::PluginVeto => "plugin_veto",
+            Reason::RuleDenied => "rule_denied",
+            Reason::MaintenancePaused => "maintenance_paused",
+            Reason::LongWindowExhausted => "long_window_exhausted",
+            Reason::GroupPaused => "group_paused",
+            Reason::GatewayCommandsExhausted => "gateway_commands_exhausted",
+            Reason::PresenceUpdatesExhausted => "presence_updates_exhausted",
+            Reason::IdentifyExhausted => "identify_exhausted",
+            Reason::CommandRegistrationExhausted => "command_registration_exhausted",
+            Reason::WebhookExhausted => "webhook_exhausted",
+            Reason::ChannelSendExhausted => "channel_send_exhausted",
+            Reason::ReactionPaced => "reaction_paced",
+            Reason::DailyQuotaExhausted => "daily_quota_exhausted",
+            Reason::UploadBytesExhausted => "upload_bytes_exhausted",
+            Reason::UploadConcurrencyExhausted => "upload_concurrency_exhausted",
+            Reason::RouteConcurrencyExhausted => "route_concurrency_exhausted",
+            Reason::YieldedToInteraction => "yielded_to_interaction",
+            Reason::QueuedBehindWaiters => "queued_behind_waiters",
+            Reason::QueueFull => "queue_full",
+            Reason::ClientQueueFull => "client_queue_full",
+            Reason::Cancelled => "cancelled",
+            Reason::Coalesced => "coalesced",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            Reason::Ok => "permit granted",
+            Reason::InvalidGuardrailActive => "invalid-request guardrail is active for this group",
+            Reason::GlobalBucketExhausted => "per-identity global limit reached for this window",
+            Reason::RouteBucketExhausted => "per-route limit reached for this window",
+            Reason::DiscordBucketExhausted => {
+                "Discord reported no requests left in this bucket until it resets"
+            }
+            Reason::GlobalCooldownActive => {
+                "waiting out the Retry-After of a reported global 429 for this identity"
+            }
+            Reason::RouteCooldownActive => {
+                "waiting out the Retry-After of a reported 429 for this route"
+            }
+            Reason::SharedCooldownActive => {
+                "waiting out the Retry-After of a reported shared-scope 429 for this resource"
+            }
+            Reason::SublimitCooldownActive => {
+                "waiting out the retry_after of a reported sub-limit 429 for this route"
+            }
+            Reason::RedisUnavailable => "could not connect to redis",
+            Reason::RedisError => "redis rejected or failed the permit script",
+            Reason::InvalidRequest => "request body failed validation",
+            Reason::ReportNotPersisted => "report could not be persisted",
+            Reason::IdempotencyConflict => {
+                "a request with this Idempotency-Key is still being processed"
+            }
+            Reason::JobExpired => "job could not be executed before expires_unix_ms",
+            Reason::UpstreamUnavailable => "Discord could not be reached",
+            Reason::PluginVeto => "denied by an operator policy plugin",
+            Reason::RuleDenied => "denied by an operator policy rule",
+            Reason::MaintenancePaused => "paused for a planned maintenance window",
+            Reason::LongWindowExhausted => "long-window limit reached for this resource",
+            Reason::GroupPaused => "group paused by an operator",
+            Reason::GatewayCommandsExhausted => "gateway command limit reached for this shard",
+            Reason::PresenceUpdatesExhausted => "presence update limit reached for this shard",
+            Reason::IdentifyExhausted => {
+                "identify bucket is booked for longer than this shard will wait"
+            }
+            Reason::CommandRegistrationExhausted => {
+                "application command registration limit reached for this bot and guild"
+            }
+            Reason::WebhookExhausted => "per-webhook limit reached for this window",
+            Reason::ChannelSendExhausted => {
+                "message send limit reached for this bot in this channel"
+            }
+            Reason::ReactionPaced => "reactions on this channel are scheduled too far ahead",
+            Reason::DailyQuotaExhausted => "daily request quota used up for this identity or group",
+            Reason::UploadBytesExhausted => "upload byte budget reached for this identity",
+            Reason::UploadConcurrencyExhausted => {
+                "too many large uploads in flight for this identity"
+            }
+            Reason::RouteConcurrencyExhausted => {
+                "too many calls in flight on this route for its configured concurrency"
+            }
+            Reason::YieldedToInteraction => "capacity held for an interaction response due sooner",
+            Reason::QueuedBehindWaiters => "earlier requests are queued for this bucket",
+            Reason::QueueFull => "too many requests are already waiting on this instance",
+            Reason::ClientQueueFull => "this client already has too many requests waiting",
+            Reason::Cancelled => "the request was cancelled while waiting",
+            Reason::Coalesced => "an identical GET is already being made; share its response",
+        }
+    }
+
+    /// Maps a wire value back to its reason; `None` for codes this version
+    /// does not know.
+    pub fn from_code(code: &str) -> Option<Self> {
+        Reason::ALL.into_iter().find(|reason| reason.code() == code)
+    }
+
+    /// Maps a limiter reason literal, as returned by the Redis scripts.
+    /// Unknown literals are treated as script errors so a drifting script
+    /// never leaks free-form text.
+    pub fn from_limiter(code: &str) -> Self {
+        match code {
+            "ok" => Reason::Ok,
+            "invalid_guardrail_active" => Reason::InvalidGuardrailActive,
+            "global_bucket_exhausted" => Reason::GlobalBucketExhausted,
+            "route_bucket_exhausted" => Reason::RouteBucketExhausted,
+            "discord_bucket_exhausted" => Reason::DiscordBucketExhausted,
+            "global_cooldown_active" => Reason::GlobalCooldownActive,
+            "route_cooldown_active" => Reason::RouteCooldownActive,
+            "shared_cooldown_active" => Reason::SharedCooldownActive,
+            "sublimit_cooldown_active" => Reason::SublimitCooldownActive,
+            _ => Reason::RedisError,
+        }
+    }
+}
+
+impl Serialize for Reason {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+#[cfg(feature = "openapi")]
+impl utoipa::PartialSchema for Reason {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::schema::Type::String)
+            .enum_values(Some(Reason::ALL.iter().map(|reason| reason.code())))
+            .into()
+    }
+}
+
+#[cfg(feature = "openapi")]
+impl utoipa::ToSchema for Reason {}
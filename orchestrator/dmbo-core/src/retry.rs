@@ -0,0 +1,57 @@
+//! Retry guidance sent with denials so workers don't each carry their own
+//! backoff tables.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Floor for every retry delay.
+    pub min_retry_ms: u64,
+    /// Ceiling for the exponential backoff.
+    pub max_delay_ms: u64,
+    /// Attempt from which callers should stop retrying; `0` never gives up.
+    pub give_up_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            min_retry_ms: 50,
+            max_delay_ms: 5000,
+            // Clients cap their own attempts (100 in the JS and Rust
+            // clients); a lower server default would override them.
+            give_up_attempts: 0,
+        }
+    }
+}
+
+/// Delays escalate exponentially with `attempt`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RetryGuidance {
+    pub recommended_delay_ms: u64,
+    pub jitter_min_ms: u64,
+    pub jitter_max_ms: u64,
+    pub give_up_after_attempts: u32,
+    pub give_up: bool,
+}
+
+impl RetryPolicy {
+    /// Guidance for the `attempt`th try (1-based) of a request the limiter
+    /// asked to wait `retry_after_ms`.
+    pub fn guidance(&self, attempt: u32, retry_after_ms: u64) -> RetryGuidance {
+        let attempt = attempt.max(1);
+        let backoff_ms = self
+            .min_retry_ms
+            .saturating_mul(1_u64 << (attempt - 1).min(20))
+            .min(self.max_delay_ms);
+        let recommended_delay_ms = backoff_ms.max(retry_after_ms);
+        RetryGuidance {
+            recommended_delay_ms,
+            jitter_min_ms: recommended_delay_ms,
+            jitter_max_ms: recommended_delay_ms.saturating_add(recommended_delay_ms / 2),
+            give_up_after_attempts: self.give_up_attempts,
+            give_up: self.give_up_attempts > 0 && attempt >= self.give_up_attempts,
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Route normalization: turns raw Discord request URLs or concrete paths into
+//! the canonical route template used for bucket keys.
+
+/// Result of normalizing a client-supplied route.
+pub struct NormalizedRoute {
+    /// Canonical template, e.g. `/channels/:channel_id/messages/:message_id`.
+    pub template: String,
+    /// Value of the first Discord major parameter (channel, guild or webhook id)
+    /// found in the path, if the path was concrete.
+    pub major_parameter: Option<String>,
+}
+
+const MAJOR_PLACEHOLDERS: [&str; 3] = [":channel_id", ":guild_id", ":webhook_id"];
+
+/// Accepts `https://discord.com/api/v10/channels/123/messages?limit=5`,
+/// `/api/v10/channels/123/messages`, `/channels/123/messages` or an
+/// already-templated route and returns the canonical template. Templated
+/// input passes through unchanged, so normalization is idempotent.
+pub fn normalize_route(raw: &str) -> NormalizedRoute {
+    let (segments, _) = split_request(raw);
+
+    let mut template = Vec::with_capacity(segments.len());
+    let mut major_parameter = None;
+    let mut previous: Option<&str> = None;
+    let mut previous_placeholder: Option<&'static str> = None;
+    for segment in segments {
+        let placeholder = if segment.starts_with(':') {
+            None
+        } else if previous == Some("reactions") {
+            Some(":emoji")
+        } else if previous_placeholder == Some(":webhook_id") && !is_snowflake(segment) {
+            Some(":webhook_token")
+        } else if previous_placeholder == Some(":interaction_id") {
+            Some(":interaction_token")
+        } else if is_snowflake(segment) {
+            Some(previous.map_or(":id", placeholder_for))
+        } else {
+            None
+        };
+
+        match placeholder {
+            Some(name) => {
+                if major_parameter.is_none() && MAJOR_PLACEHOLDERS.contains(&name) {
+                    major_parameter = Some(segment.to_string());
+                }
+                template.push(name.to_string());
+            }
+            None => template.push(segment.to_string()),
+        }
+        previous = Some(segment);
+        previous_placeholder = placeholder;
+    }
+
+    NormalizedRoute {
+        template: format!("/{}", template.join("/")),
+        major_parameter,
+    }
+}
+
+/// Concrete request path relative to the API base (host and `/api/vN`
+/// removed), keeping the query string. Used when the orchestrator performs
+/// the call itself.
+pub fn request_path(raw: &str) -> String {
+    let (segments, query) = split_request(raw);
+    match query {
+        Some(query) => format!("/{}?{query}", segments.join("/")),
+        None => format!("/{}", segments.join("/")),
+    }
+}
+
+/// Splits a URL or path into its API-relative segments and query string.
+fn split_request(raw: &str) -> (Vec<&str>, Option<&str>) {
+    let mut path = raw.trim();
+    if let Some((_, rest)) = path.split_once("://") {
+        path = rest.find('/').map_or("", |index| &rest[index..]);
+    }
+    if let Some(index) = path.find('#') {
+        path = &path[..index];
+    }
+    let query = match path.split_once('?') {
+        Some((before, query)) => {
+            path = before;
+            Some(query).filter(|query| !query.is_empty())
+        }
+        None => None,
+    };
+
+    let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.first() == Some(&"api") {
+        segments.remove(0);
+        if segments.first().is_some_and(|s| is_api_version(s)) {
+            segments.remove(0);
+        }
+    }
+    (segments, query)
+}
+
+fn is_api_version(segment: &str) -> bool {
+    segment
+        .strip_prefix('v')
+        .is_some_and(|version| !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn is_snowflake(segment: &str) -> bool {
+    !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn placeholder_for(collection: &str) -> &'static str {
+    match collection {
+        "channels" | "stage-instances" => ":channel_id",
+        "guilds" => ":guild_id",
+        "webhooks" => ":webhook_id",
+        "messages" | "pins" => ":message_id",
+        "users" | "members" | "bans" | "recipients" | "thread-members" => ":user_id",
+        "roles" => ":role_id",
+        "emojis" => ":emoji_id",
+        "stickers" => ":sticker_id",
+        "applications" => ":application_id",
+        "commands" => ":command_id",
+        "interactions" => ":interaction_id",
+        "threads" => ":thread_id",
+        "permissions" => ":overwrite_id",
+        "integrations" => ":integration_id",
+        "scheduled-events" => ":guild_scheduled_event_id",
+        "rules" => ":auto_moderation_rule_id",
+        "entitlements" => ":entitlement_id",
+        "skus" => ":sku_id",
+        "soundboard-sounds" => ":sound_id",
+        _ => ":id",
+    }
+}
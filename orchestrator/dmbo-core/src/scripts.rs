@@ -0,0 +1,203 @@
+//! The Redis side of the limiter: Lua scripts the service runs against the
+//! keys in [`keys`](crate::keys). [`MemoryBackend`](crate::MemoryBackend)
+//! mirrors their arithmetic, so changes to one belong in the other.
+
+/// Every limiter algorithm in one script, so the identity's global limit and
+/// a request's route limit can run different algorithms.
+///
+/// `KEYS`: guard, global, route, tighten percentage, learned Discord bucket
+/// state, then the identity's, the bucket's and the resource's reported 429
+/// cooldowns and the literal route's sub-limit cooldown.
+/// `ARGV`: global limit, route limit, fixed-window counter TTL, minimum
+/// retry, whether to record against the learned state (`1`/`0`), now (unix
+/// ms), method weight, whether an empty learned bucket denies (`1`/`0`),
+/// token bucket capacity percentage, the global and route algorithms by
+/// name, then the route limit's window in ms (the global window is always
+/// one second).
+///
+/// - `fixed_window`: counters per window, up to `limit` units per
+///   wall-clock window. The counter is taken even when a later limit denies.
+/// - `gcra`: each key holds the theoretical arrival time (TAT, unix ms) of
+///   the next request; a request is admitted while the TAT is at most one
+///   window ahead of now, and a weighted request advances it by that many
+///   emission intervals.
+/// - `token_bucket`: each key is a hash of the tokens left and when they
+///   were counted; a bucket holds the capacity percentage of its limit (at
+///   least one token) and refills at `limit` tokens per window.
+///
+/// Replies `{granted, retry_after_ms, reason, route_limit, route_remaining,
+/// observed_limit, observed_remaining, observed_resets_in_ms}`, with `-1` for
+/// fields a denial or missing learned state leaves unknown.
+pub const LIMITER: &str = r#"
+local guard_key = KEYS[1]
+local global_key = KEYS[2]
+local route_key = KEYS[3]
+local tighten_key = KEYS[4]
+local observed_key = KEYS[5]
+local global_cooldown_key = KEYS[6]
+local route_cooldown_key = KEYS[7]
+local shared_cooldown_key = KEYS[8]
+local sublimit_cooldown_key = KEYS[9]
+local global_limit = tonumber(ARGV[1])
+local route_limit = tonumber(ARGV[2])
+local ttl_ms = tonumber(ARGV[3])
+local min_retry_ms = tonumber(ARGV[4])
+local record_observed = ARGV[5] == '1'
+local now = tonumber(ARGV[6])
+local cost = tonumber(ARGV[7]) or 1
+local honor_remaining = ARGV[8] == '1'
+local burst_percent = tonumber(ARGV[9]) or 100
+local route_window = tonumber(ARGV[12]) or 1000
+local global_exempt = ARGV[13] == '1'
+local jitter_ms = tonumber(ARGV[14]) or 0
+
+local tighten_percent = tonumber(redis.call('GET', tighten_key))
+if tighten_percent then
+  global_limit = math.max(1, math.floor(global_limit * tighten_percent / 100))
+  route_limit = math.max(1, math.floor(route_limit * tighten_percent / 100))
+end
+
+-- Denials carry no forecast fields. Their retry hint is spread by the
+-- caller's random jitter so denied callers don't return together.
+local function deny(retry_ms, reason)
+  if retry_ms < min_retry_ms then retry_ms = min_retry_ms end
+  return {0, retry_ms + jitter_ms, reason, -1, -1, -1, -1, -1}
+end
+
+local guard_ttl = redis.call('PTTL', guard_key)
+if guard_ttl and guard_ttl > 0 then
+  return deny(guard_ttl, 'invalid_guardrail_active')
+end
+
+-- Retry-After from a reported 429 holds until it runs out.
+local global_cooldown = redis.call('PTTL', global_cooldown_key)
+if global_cooldown > 0 and not global_exempt then
+  return deny(global_cooldown, 'global_cooldown_active')
+end
+local route_cooldown = redis.call('PTTL', route_cooldown_key)
+if route_cooldown > 0 then
+  return deny(route_cooldown, 'route_cooldown_active')
+end
+local shared_cooldown = redis.call('PTTL', shared_cooldown_key)
+if shared_cooldown > 0 then
+  return deny(shared_cooldown, 'shared_cooldown_active')
+end
+local sublimit_cooldown = redis.call('PTTL', sublimit_cooldown_key)
+if sublimit_cooldown > 0 then
+  return deny(sublimit_cooldown, 'sublimit_cooldown_active')
+end
+
+-- Discord bucket state learned from reports: while the last report, less
+-- the grants since, leaves nothing in the bucket, hold grants until it resets.
+if honor_remaining then
+  local known_remaining = tonumber(redis.call('HGET', observed_key, 'remaining'))
+  if known_remaining and known_remaining <= 0 then
+    return deny(redis.call('PTTL', observed_key), 'discord_bucket_exhausted')
+  end
+end
+
+-- take(key, limit, window) admits `cost` units (capped at the limit, so a
+-- weighted request is slowed rather than never granted) and returns the
+-- state to store, or nil and the wait. store(key, limit, window, state) runs
+-- once every limit has admitted; remaining(limit, window, state) is what is
+-- left after the grant.
+local algorithms = {}
+
+algorithms.fixed_window = {
+  take = function(key, limit, window)
+    local units = math.min(cost, limit)
+    local count = redis.call('INCRBY', key, units)
+    if count == units then redis.call('PEXPIRE', key, ttl_ms - 1000 + window) end
+    if count > limit then return nil, redis.call('PTTL', key) end
+    return count
+  end,
+  store = function(key, limit, window, count) end,
+  remaining = function(limit, window, count) return limit - count end,
+}
+
+algorithms.gcra = {
+  take = function(key, limit, window)
+    local tat = tonumber(redis.call('GET', key)) or now
+    if tat < now then tat = now end
+    local next_tat = tat + math.min(cost, limit) * window / limit
+    local wait = next_tat - now - window
+    if wait > 0 then return nil, math.ceil(wait) end
+    return next_tat
+  end,
+  store = function(key, limit, window, tat)
+    redis.call('SET', key, string.format('%.3f', tat), 'PX', math.ceil(tat - now) + window)
+  end,
+  remaining = function(limit, window, tat)
+    return math.max(0, math.floor((now + window - tat) * limit / window))
+  end,
+}
+
+local function capacity(limit)
+  return math.max(1, limit * burst_percent / 100)
+end
+
+algorithms.token_bucket = {
+  take = function(key, limit, window)
+    local full = capacity(limit)
+    local need = math.min(cost, full)
+    local bucket = redis.call('HMGET', key, 'tokens', 'ts')
+    local tokens = tonumber(bucket[1]) or full
+    local ts = tonumber(bucket[2]) or now
+    if now > ts then
+      tokens = math.min(full, tokens + (now - ts) * limit / window)
+    end
+    if tokens < need then return nil, math.ceil((need - tokens) * window / limit) end
+    return tokens - need
+  end,
+  store = function(key, limit, window, tokens)
+    redis.call('HSET', key, 'tokens', string.format('%.3f', tokens), 'ts', now)
+    redis.call('PEXPIRE', key, math.ceil((capacity(limit) - tokens) * window / limit) + 1000)
+  end,
+  remaining = function(limit, window, tokens) return math.floor(tokens) end,
+}
+
+local global_algorithm = algorithms[ARGV[10]]
+local route_algorithm = algorithms[ARGV[11]]
+
+local global_state, global_wait
+if not global_exempt then
+  global_state, global_wait = global_algorithm.take(global_key, global_limit, 1000)
+  if not global_state then
+    return deny(global_wait, 'global_bucket_exhausted')
+  end
+end
+local route_state, route_wait = route_algorithm.take(route_key, route_limit, route_window)
+if not route_state then
+  return deny(route_wait, 'route_bucket_exhausted')
+end
+if not global_exempt then
+  global_algorithm.store(global_key, global_limit, 1000, global_state)
+end
+route_algorithm.store(route_key, route_limit, route_window, route_state)
+
+-- Count this grant against the last reported remaining so forecasts (and
+-- the check above) reflect sends since that report.
+local observed_remaining, observed_reset_ms, observed_limit = -1, -1, -1
+if record_observed and redis.call('EXISTS', observed_key) == 1 then
+  observed_remaining = redis.call('HINCRBY', observed_key, 'remaining', -1)
+  observed_reset_ms = redis.call('PTTL', observed_key)
+  observed_limit = tonumber(redis.call('HGET', observed_key, 'limit')) or -1
+end
+
+return {1, 0, 'ok', route_limit, route_algorithm.remaining(route_limit, route_window, route_state),
+  observed_limit, observed_remaining, observed_reset_ms}
+"#;
+
+/// Increments `KEYS[1]` and, on its first increment, expires it after
+/// `ARGV[1]` seconds. Counts invalid responses toward the guardrail.
+pub const INCR_WITH_EXPIRE: &str = r#"
+local key = KEYS[1]
+local ttl_seconds = tonumber(ARGV[1])
+
+local count = redis.call('INCR', key)
+if count == 1 then
+  redis.call('EXPIRE', key, ttl_seconds)
+end
+
+return count
+"#;
@@ -0,0 +1,419 @@
+//! Admin listing endpoints. Every listing shares one query contract:
+//! `cursor` (opaque, from `next_cursor`), `limit` (default 100, max 1000),
+//! `sort` (`field` or `-field` for descending), `fields` (comma-separated
+//! projection) and `filter` (comma-separated `field:substring` pairs).
+
+use crate::{
+    anomaly::ALERTS_STREAM_KEY, normalize_key_part, problem_response, unix_ms,
+    validation_failed_response, AppState, FieldError, AUDIT_STREAM_KEY, PROBLEM_TYPE_UNAUTHORIZED,
+};
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use redis::AsyncCommands;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{cmp::Ordering as CmpOrdering, collections::BTreeMap, sync::Arc};
+
+const PROBLEM_TYPE_NOT_FOUND: &str = "urn:dmbo:problem:not-found";
+const DEFAULT_PAGE_LIMIT: usize = 100;
+const MAX_PAGE_LIMIT: usize = 1000;
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ListQuery {
+    #[serde(default)]
+    cursor: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    sort: Option<String>,
+    #[serde(default)]
+    fields: Option<String>,
+    #[serde(default)]
+    filter: Option<String>,
+}
+
+/// Rejects admin requests without the configured bearer token. When
+/// `DMBO_ADMIN_TOKEN` is unset the admin API is open, matching the
+/// loopback-only default bind.
+pub(crate) async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = state.config.admin_token.as_deref() else {
+        return next.run(request).await;
+    };
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided == Some(expected) {
+        return next.run(request).await;
+    }
+    problem_response(
+        StatusCode::UNAUTHORIZED,
+        PROBLEM_TYPE_UNAUTHORIZED,
+        "Admin token required",
+        "send `Authorization: Bearer <DMBO_ADMIN_TOKEN>`".to_string(),
+        json!({}),
+    )
+}
+
+pub(crate) async fn list_buckets(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    let mut items = Vec::new();
+    for pattern in ["rl:global:*", "rl:route:*"] {
+        let entries = match scan_counters(&state, pattern).await {
+            Ok(entries) => entries,
+            Err(response) => return response,
+        };
+        for (key, count, ttl_ms) in entries {
+            let parts: Vec<&str> = key.split(':').collect();
+            let item = match parts.as_slice() {
+                ["rl", "global", identity, window] => json!({
+                    "key": key,
+                    "kind": "global",
+                    "discord_identity": identity,
+                    "window": window,
+                    "count": count,
+                    "ttl_ms": ttl_ms,
+                }),
+                ["rl", "route", identity, "bucket", bucket, major, window] => json!({
+                    "key": key,
+                    "kind": "route",
+                    "discord_identity": identity,
+                    "discord_bucket": bucket,
+                    "major_parameter": major,
+                    "window": window,
+                    "count": count,
+                    "ttl_ms": ttl_ms,
+                }),
+                ["rl", "route", identity, method, route, major, window] => json!({
+                    "key": key,
+                    "kind": "route",
+                    "discord_identity": identity,
+                    "method": method,
+                    "route": route,
+                    "major_parameter": major,
+                    "window": window,
+                    "count": count,
+                    "ttl_ms": ttl_ms,
+                }),
+                _ => json!({ "key": key, "kind": "other", "count": count, "ttl_ms": ttl_ms }),
+            };
+            items.push(item);
+        }
+    }
+    page_response(items, &query, "key")
+}
+
+pub(crate) async fn list_guards(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    let mut groups: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+    for (pattern, kind) in [
+        ("rl:guard:*", "guard"),
+        ("rl:invalid:*", "invalid"),
+        ("rl:cloudflare:*", "cloudflare"),
+    ] {
+        let entries = match scan_counters(&state, pattern).await {
+            Ok(entries) => entries,
+            Err(response) => return response,
+        };
+        for (key, count, ttl_ms) in entries {
+            let group = key.splitn(3, ':').nth(2).unwrap_or_default().to_string();
+            let entry = groups.entry(group.clone()).or_insert_with(|| {
+                let mut entry = Map::new();
+                entry.insert("group_id".to_string(), json!(group));
+                entry.insert("active".to_string(), json!(false));
+                entry.insert("remaining_ms".to_string(), json!(0));
+                entry.insert("invalid_count".to_string(), json!(0));
+                entry
+            });
+            if kind == "guard" {
+                entry.insert("active".to_string(), json!(ttl_ms > 0));
+                entry.insert("remaining_ms".to_string(), json!(ttl_ms.max(0)));
+            } else if kind == "cloudflare" {
+                entry.insert("cloudflare_strikes".to_string(), json!(count));
+            } else {
+                entry.insert("invalid_count".to_string(), json!(count));
+                entry.insert("invalid_window_ttl_ms".to_string(), json!(ttl_ms.max(0)));
+            }
+        }
+    }
+    let items = groups.into_values().map(Value::Object).collect();
+    page_response(items, &query, "group_id")
+}
+
+/// `DELETE /admin/guards/{group_id}`: lifts an active guard and resets the
+/// group's invalid-request count, e.g. once a bad token has been rotated.
+pub(crate) async fn clear_guard(
+    State(state): State<Arc<AppState>>,
+    Path(group_id): Path<String>,
+) -> Response {
+    let group = normalize_key_part(&group_id);
+    match state.store.clear_guard(&group).await {
+        Ok(false) => problem_response(
+            StatusCode::NOT_FOUND,
+            PROBLEM_TYPE_NOT_FOUND,
+            "Guard not found",
+            format!("group {group} has no guard or invalid-request count"),
+            json!({}),
+        ),
+        Ok(true) => {
+            crate::signals::guardrail_released(&state, &group);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => crate::backend_unavailable_response(&state),
+    }
+}
+
+pub(crate) async fn list_audit(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    match read_stream(&state, AUDIT_STREAM_KEY).await {
+        Ok(items) => page_response(items, &query, "-id"),
+        Err(response) => response,
+    }
+}
+
+pub(crate) async fn list_alerts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    match read_stream(&state, ALERTS_STREAM_KEY).await {
+        Ok(items) => page_response(items, &query, "-id"),
+        Err(response) => response,
+    }
+}
+
+/// Reads the newest `DMBO_ADMIN_SCAN_LIMIT` entries of a stream as flat
+/// objects keyed by field name, with the entry id under `id`.
+pub(crate) async fn read_stream(state: &AppState, key: &str) -> Result<Vec<Value>, Response> {
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| crate::backend_unavailable_response(state))?;
+    let entries: Vec<(String, Vec<String>)> = redis::cmd("XREVRANGE")
+        .arg(key)
+        .arg("+")
+        .arg("-")
+        .arg("COUNT")
+        .arg(state.config.admin_scan_limit)
+        .query_async(&mut conn)
+        .await
+        .map_err(|_| crate::backend_unavailable_response(state))?;
+    Ok(entries
+        .into_iter()
+        .map(|(id, fields)| {
+            let mut item = Map::new();
+            item.insert("id".to_string(), json!(id));
+            for pair in fields.chunks(2) {
+                if let [field, value] = pair {
+                    let value = match value.as_str() {
+                        "true" => json!(true),
+                        "false" => json!(false),
+                        _ => value
+                            .parse::<u64>()
+                            .map(|number| json!(number))
+                            .unwrap_or_else(|_| json!(value)),
+                    };
+                    item.insert(field.clone(), value);
+                }
+            }
+            Value::Object(item)
+        })
+        .collect())
+}
+
+pub(crate) async fn list_queues(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    let now = unix_ms();
+    let items = state
+        .waiters
+        .snapshot()
+        .into_iter()
+        .map(|waiter| {
+            let mut item = serde_json::to_value(&waiter).unwrap_or_default();
+            if let Some(item) = item.as_object_mut() {
+                item.insert(
+                    "waited_ms".to_string(),
+                    json!(now.saturating_sub(waiter.enqueued_unix_ms)),
+                );
+                item.insert(
+                    "bucket".to_string(),
+                    json!(format!(
+                        "{}:{}:{}:{}",
+                        normalize_key_part(&waiter.discord_identity),
+                        waiter.method,
+                        normalize_key_part(&waiter.route),
+                        normalize_key_part(&waiter.major_parameter)
+                    )),
+                );
+            }
+            item
+        })
+        .collect();
+    page_response(items, &query, "enqueued_unix_ms")
+}
+
+/// SCANs keys matching `pattern` (bounded by `DMBO_ADMIN_SCAN_LIMIT`) and
+/// fetches each key's integer value and PTTL in one pipeline.
+async fn scan_counters(
+    state: &Arc<AppState>,
+    pattern: &str,
+) -> Result<Vec<(String, i64, i64)>, Response> {
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| crate::backend_unavailable_response(state))?;
+    let mut keys = Vec::new();
+    {
+        let mut iter = conn
+            .scan_match::<_, String>(pattern)
+            .await
+            .map_err(|_| crate::backend_unavailable_response(state))?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+            if keys.len() >= state.config.admin_scan_limit {
+                break;
+            }
+        }
+    }
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut pipe = redis::pipe();
+    for key in &keys {
+        pipe.cmd("GET").arg(key).cmd("PTTL").arg(key);
+    }
+    let values: Vec<(Option<String>, i64)> = pipe
+        .query_async(&mut conn)
+        .await
+        .map_err(|_| crate::backend_unavailable_response(state))?;
+    Ok(keys
+        .into_iter()
+        .zip(values)
+        .map(|(key, (value, ttl_ms))| {
+            let count = value.and_then(|v| v.parse().ok()).unwrap_or_default();
+            (key, count, ttl_ms)
+        })
+        .collect())
+}
+
+pub(crate) fn page_response(items: Vec<Value>, query: &ListQuery, default_sort: &str) -> Response {
+    match paginate(items, query, default_sort) {
+        Ok(page) => Json(page).into_response(),
+        Err(error) => validation_failed_response(vec![error]),
+    }
+}
+
+/// Applies filter, sort, cursor and projection to a listing. The cursor is
+/// the offset into the filtered, sorted result.
+fn paginate(
+    mut items: Vec<Value>,
+    query: &ListQuery,
+    default_sort: &str,
+) -> Result<Value, FieldError> {
+    if let Some(filter) = query.filter.as_deref().filter(|f| !f.is_empty()) {
+        let mut clauses = Vec::new();
+        for clause in filter.split(',') {
+            let Some((field, needle)) = clause.split_once(':') else {
+                return Err(FieldError {
+                    field: "filter",
+                    message: "expected comma-separated field:value pairs".to_string(),
+                });
+            };
+            clauses.push((field.trim(), needle.trim()));
+        }
+        items.retain(|item| {
+            clauses.iter().all(|(field, needle)| {
+                item.get(*field).is_some_and(|value| match value {
+                    Value::String(text) => text.contains(needle),
+                    other => other.to_string().as_str() == *needle,
+                })
+            })
+        });
+    }
+
+    let sort = query.sort.as_deref().unwrap_or(default_sort);
+    let (sort_field, descending) = match sort.strip_prefix('-') {
+        Some(field) => (field, true),
+        None => (sort, false),
+    };
+    items.sort_by(|a, b| {
+        let ordering = compare_values(a.get(sort_field), b.get(sort_field));
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+
+    let total = items.len();
+    let offset = match query.cursor.as_deref().filter(|c| !c.is_empty()) {
+        Some(cursor) => cursor.parse::<usize>().map_err(|_| FieldError {
+            field: "cursor",
+            message: "must be a value previously returned as next_cursor".to_string(),
+        })?,
+        None => 0,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+    let end = offset.saturating_add(limit).min(total);
+    let fields: Option<Vec<&str>> = query
+        .fields
+        .as_deref()
+        .filter(|f| !f.is_empty())
+        .map(|f| f.split(',').map(str::trim).collect());
+
+    let page: Vec<Value> = items
+        .into_iter()
+        .skip(offset)
+        .take(end.saturating_sub(offset))
+        .map(|item| match (&fields, item) {
+            (Some(fields), Value::Object(map)) => Value::Object(
+                map.into_iter()
+                    .filter(|(key, _)| fields.contains(&key.as_str()))
+                    .collect(),
+            ),
+            (_, item) => item,
+        })
+        .collect();
+
+    Ok(json!({
+        "items": page,
+        "total": total,
+        "next_cursor": (end < total).then(|| end.to_string()),
+    }))
+}
+
+fn compare_values(a: Option<&Value>, b: Option<&Value>) -> CmpOrdering {
+    match (a, b) {
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(CmpOrdering::Equal),
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        (Some(a), Some(b)) => a.to_string().cmp(&b.to_string()),
+        (Some(_), None) => CmpOrdering::Less,
+        (None, Some(_)) => CmpOrdering::Greater,
+        (None, None) => CmpOrdering::Equal,
+    }
+}
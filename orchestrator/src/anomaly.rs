@@ -0,0 +1,162 @@
+//! Spike detection on reported 429s and invalid requests. Each interval the
+//! per-identity counts seen by this instance are compared with an EWMA
+//! baseline; a count above `baseline * DMBO_ANOMALY_FACTOR` (and at least
+//! `DMBO_ANOMALY_MIN_EVENTS`) raises an alert on the `rl:alerts` stream and,
+//! when enabled, tightens the identity's limits for a cooldown period.
+
+use crate::{
+    normalize_key_part,
+    notifier::{self, Alert, Severity},
+    unix_ms, AppState,
+};
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::interval;
+
+pub(crate) const ALERTS_STREAM_KEY: &str = "rl:alerts";
+const METRICS: [&str; 2] = ["429", "invalid"];
+
+#[derive(Default)]
+struct Baseline {
+    ewma: [f64; 2],
+    samples: u32,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct AnomalyDetector {
+    counts: Arc<Mutex<HashMap<String, [u64; 2]>>>,
+}
+
+impl AnomalyDetector {
+    pub(crate) fn record(&self, discord_identity: &str, rate_limited: bool, invalid: bool) {
+        if !rate_limited && !invalid {
+            return;
+        }
+        let mut counts = self.counts.lock().expect("anomaly counts poisoned");
+        let entry = counts.entry(discord_identity.to_string()).or_default();
+        entry[0] += u64::from(rate_limited);
+        entry[1] += u64::from(invalid);
+    }
+
+    fn take(&self) -> HashMap<String, [u64; 2]> {
+        std::mem::take(&mut *self.counts.lock().expect("anomaly counts poisoned"))
+    }
+}
+
+/// Evaluates the counts collected during each `DMBO_ANOMALY_INTERVAL_MS`
+/// window. Baselines only alert after `DMBO_ANOMALY_WARMUP_INTERVALS`
+/// samples, and identities whose baseline has decayed to zero are forgotten.
+pub(crate) async fn detect_anomalies(state: Arc<AppState>) {
+    let config = &state.config;
+    let mut baselines: HashMap<String, Baseline> = HashMap::new();
+    let mut ticker = interval(Duration::from_millis(config.anomaly_interval_ms));
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let counts = state.anomalies.take();
+        for identity in counts.keys() {
+            baselines.entry(identity.clone()).or_default();
+        }
+        for (identity, baseline) in &mut baselines {
+            let current = counts.get(identity).copied().unwrap_or_default();
+            for (index, metric) in METRICS.iter().enumerate() {
+                let observed = current[index] as f64;
+                let expected = baseline.ewma[index];
+                if baseline.samples >= config.anomaly_warmup_intervals
+                    && current[index] >= config.anomaly_min_events
+                    && observed > expected * config.anomaly_factor
+                {
+                    let counter = if index == 0 {
+                        &state.metrics.anomalies_429
+                    } else {
+                        &state.metrics.anomalies_invalid
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    notifier::notify(
+                        &state,
+                        Alert {
+                            kind: "anomaly",
+                            subject: format!("{identity}:{metric}"),
+                            severity: Severity::Warning,
+                            title: format!("Spike in {metric} reports"),
+                            description: format!(
+                                "`{identity}` reported {} {metric} responses in {}s against a \
+                                 baseline of {expected:.1}.",
+                                current[index],
+                                config.anomaly_interval_ms / 1000
+                            ),
+                            fields: vec![(
+                                "Auto-tightened",
+                                config.anomaly_auto_tighten.to_string(),
+                            )],
+                        },
+                    );
+                    raise_alert(&state, identity, metric, current[index], expected).await;
+                }
+                baseline.ewma[index] =
+                    config.anomaly_alpha * observed + (1.0 - config.anomaly_alpha) * expected;
+            }
+            baseline.samples = baseline.samples.saturating_add(1);
+        }
+        baselines.retain(|identity, baseline| {
+            counts.contains_key(identity) || baseline.ewma.iter().any(|value| *value >= 0.01)
+        });
+    }
+}
+
+async fn raise_alert(state: &AppState, identity: &str, metric: &str, observed: u64, baseline: f64) {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    let tighten = state.config.anomaly_auto_tighten;
+    let mut pipe = redis::pipe();
+    pipe.cmd("XADD")
+        .arg(ALERTS_STREAM_KEY)
+        .arg("MAXLEN")
+        .arg("~")
+        .arg(state.config.audit_maxlen)
+        .arg("*")
+        .arg("kind")
+        .arg("anomaly")
+        .arg("discord_identity")
+        .arg(identity)
+        .arg("metric")
+        .arg(metric)
+        .arg("observed")
+        .arg(observed)
+        .arg("baseline")
+        .arg(format!("{baseline:.2}"))
+        .arg("interval_ms")
+        .arg(state.config.anomaly_interval_ms)
+        .arg("tightened")
+        .arg(tighten.to_string())
+        .arg("unix_ms")
+        .arg(unix_ms())
+        .ignore();
+    if tighten {
+        pipe.cmd("PSETEX")
+            .arg(tighten_key(identity))
+            .arg(state.config.anomaly_tighten_ms)
+            .arg(state.config.anomaly_tighten_percent)
+            .ignore();
+    }
+    if pipe.query_async::<_, ()>(&mut conn).await.is_err() {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Key holding the percentage of the configured limits an identity may use
+/// while tightened. Read by the permit script.
+pub(crate) fn tighten_key(discord_identity: &str) -> String {
+    format!("rl:tighten:{}", normalize_key_part(discord_identity))
+}
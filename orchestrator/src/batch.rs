@@ -0,0 +1,102 @@
+//! `POST /request_tokens`: many permit requests in one call, for bots that
+//! fan a message out to dozens of channels. Every item goes through the same
+//! validation, admission policy and limiter as `request_token`, concurrently,
+//! but none of them waits: an item that cannot be granted right away is
+//! denied with its retry hint, so one busy bucket cannot hold up the batch.
+
+use crate::{
+    decide_permit, prepare_request, validation_failed_response, AppState, FieldError,
+    RequestTokenRequest, TraceContext,
+};
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use dmbo_core::Reason;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::{atomic::Ordering, Arc};
+use tokio::task::JoinSet;
+
+/// Most items one batch may carry.
+const MAX_BATCH_ITEMS: usize = 100;
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct BatchRequest {
+    #[serde(default)]
+    requests: Vec<RequestTokenRequest>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/request_tokens",
+    request_body = BatchRequest,
+    responses(
+        (status = 200, description = "One decision per item", body = crate::openapi::BatchResponse),
+        (status = 400, description = "Invalid body, or empty or oversized batch", body = crate::openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
+pub(crate) async fn request_tokens(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Result<Json<BatchRequest>, JsonRejection>,
+) -> Response {
+    let trace = TraceContext::from_headers(&headers);
+    let Json(batch) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return crate::json_rejection_response(&state, rejection),
+    };
+    if batch.requests.is_empty() || batch.requests.len() > MAX_BATCH_ITEMS {
+        return validation_failed_response(vec![FieldError {
+            field: "requests",
+            message: format!("must hold 1 to {MAX_BATCH_ITEMS} items"),
+        }]);
+    }
+    state
+        .metrics
+        .batch_items
+        .fetch_add(batch.requests.len() as u64, Ordering::Relaxed);
+
+    let mut results = vec![Value::Null; batch.requests.len()];
+    let mut decisions = JoinSet::new();
+    for (index, mut request) in batch.requests.into_iter().enumerate() {
+        let errors = prepare_request(&state.config, &mut request);
+        if !errors.is_empty() {
+            results[index] = json!({
+                "granted": false,
+                "reason": Reason::InvalidRequest,
+                "reason_message": Reason::InvalidRequest.message(),
+                "errors": errors,
+            });
+            continue;
+        }
+        request.max_wait_ms = 0;
+        let state = state.clone();
+        let trace = trace.clone();
+        decisions.spawn(async move {
+            let (response, _) = decide_permit(&state, &mut request, trace.as_ref()).await;
+            (index, response)
+        });
+    }
+    while let Some(decided) = decisions.join_next().await {
+        if let Ok((index, response)) = decided {
+            results[index] = serde_json::to_value(&response).unwrap_or_default();
+        }
+    }
+
+    let granted = results
+        .iter()
+        .filter(|result| result["granted"] == true)
+        .count();
+    let mut response = (
+        StatusCode::OK,
+        Json(json!({ "granted": granted, "results": results })),
+    )
+        .into_response();
+    if let Some(trace) = &trace {
+        trace.propagate(response.headers_mut());
+    }
+    response
+}
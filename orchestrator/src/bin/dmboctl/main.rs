@@ -0,0 +1,559 @@
+//! `dmboctl`: command-line client for the orchestrator's admin API, for
+//! routine operations without hand-written curl calls. Talks to
+//! `DMBO_URL` (default `http://127.0.0.1:8787`) with `DMBO_ADMIN_TOKEN` as
+//! the bearer token; `--url` and `--token` override both. Listings print a
+//! table of the most useful fields, or the raw response with `--json`.
+
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use std::{env, process::ExitCode, time::Duration};
+
+#[cfg(feature = "tui")]
+mod top;
+
+const USAGE: &str = "\
+usage: dmboctl [--url URL] [--token TOKEN] [--json] <command> [args]
+
+inspect:
+  buckets [--filter F] [--sort S] [--limit N]   live limiter counters
+  guards                                         invalid-request guards
+  groups                                         paused groups and limit overrides
+  queues                                         waiting permit requests
+  loans                                          active budget loans
+  dlq                                            dead-lettered reports and jobs
+  audit [--filter F] [--limit N]                 recent permit decisions
+  alerts                                         recent anomaly alerts
+  config                                         effective configuration
+
+operate:
+  guards clear <group>                           lift a guard and reset its count
+  pause <group> <duration> [--reason TEXT]       deny a group's permits, e.g. 15m
+  resume <group>                                 end a pause early
+  limits set <group> [--global-rps N] [--route-rps N]
+  limits clear <group>                           return to the configured limits
+
+follow:
+  tail [events|audit|alerts]                     stream new entries until interrupted
+  top [--interval-ms N]                          live console (needs the tui feature)
+
+Durations accept ms, s, m, h and d suffixes; a bare number is milliseconds.";
+
+/// How often `tail audit` and `tail alerts` poll their stream.
+const POLL_INTERVAL_MS: u64 = 1000;
+
+#[derive(Clone)]
+struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+/// Failure to report on stderr before exiting with status 1.
+struct CliError(String);
+
+type CliResult<T = ()> = Result<T, CliError>;
+
+impl From<reqwest::Error> for CliError {
+    fn from(error: reqwest::Error) -> Self {
+        CliError(format!("request failed: {error}"))
+    }
+}
+
+impl Client {
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{path}", self.base_url));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Sends the request and returns the JSON body of a successful response
+    /// (`null` for 204), or the problem detail as an error.
+    async fn send(&self, request: reqwest::RequestBuilder) -> CliResult<Value> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status == StatusCode::NO_CONTENT {
+            return Ok(Value::Null);
+        }
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        if status.is_success() {
+            return Ok(body);
+        }
+        let title = body["title"].as_str().unwrap_or("request failed");
+        let detail = body["detail"].as_str().unwrap_or_default();
+        Err(CliError(format!("{status}: {title}. {detail}")))
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> CliResult<Value> {
+        self.send(self.request(Method::GET, path).query(query))
+            .await
+    }
+}
+
+/// Remaining arguments with `--name value` options pulled out on demand.
+struct Args(Vec<String>);
+
+impl Args {
+    fn option(&mut self, name: &str) -> CliResult<Option<String>> {
+        let Some(index) = self.0.iter().position(|arg| arg == name) else {
+            return Ok(None);
+        };
+        if index + 1 >= self.0.len() {
+            return Err(CliError(format!("{name} needs a value")));
+        }
+        let value = self.0.remove(index + 1);
+        self.0.remove(index);
+        Ok(Some(value))
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        let Some(index) = self.0.iter().position(|arg| arg == name) else {
+            return false;
+        };
+        self.0.remove(index);
+        true
+    }
+
+    fn positional(&mut self, what: &str) -> CliResult<String> {
+        if self.0.is_empty() {
+            return Err(CliError(format!("missing {what}\n\n{USAGE}")));
+        }
+        Ok(self.0.remove(0))
+    }
+
+    fn finish(self) -> CliResult {
+        match self.0.first() {
+            Some(extra) => Err(CliError(format!("unexpected argument {extra}"))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Args(env::args().skip(1).collect())).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError(message)) => {
+            eprintln!("dmboctl: {message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(mut args: Args) -> CliResult {
+    if args.flag("--help") || args.flag("-h") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let base_url = args
+        .option("--url")?
+        .or_else(|| env::var("DMBO_URL").ok().filter(|url| !url.is_empty()))
+        .unwrap_or_else(|| "http://127.0.0.1:8787".to_string());
+    let token = args.option("--token")?.or_else(|| {
+        env::var("DMBO_ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+    });
+    let raw = args.flag("--json");
+    let client = Client {
+        http: reqwest::Client::new(),
+        base_url: base_url.trim_end_matches('/').to_string(),
+        token,
+    };
+
+    let command = args.positional("command")?;
+    match command.as_str() {
+        "buckets" => {
+            list(
+                &client,
+                args,
+                "/admin/buckets",
+                &["key", "count", "ttl_ms"],
+                raw,
+            )
+            .await?
+        }
+        "guards" if args.0.first().map(String::as_str) == Some("clear") => {
+            args.positional("clear")?;
+            let group = args.positional("group")?;
+            args.finish()?;
+            client
+                .send(client.request(Method::DELETE, &format!("/admin/guards/{group}")))
+                .await?;
+            println!("guard cleared for {group}");
+        }
+        "guards" => {
+            list(
+                &client,
+                args,
+                "/admin/guards",
+                &["group_id", "active", "remaining_ms", "invalid_count"],
+                raw,
+            )
+            .await?
+        }
+        "groups" => {
+            list(
+                &client,
+                args,
+                "/admin/groups",
+                &[
+                    "group_id",
+                    "paused",
+                    "pause_remaining_ms",
+                    "pause_reason",
+                    "global_rps",
+                    "route_rps",
+                ],
+                raw,
+            )
+            .await?
+        }
+        "queues" => {
+            list(
+                &client,
+                args,
+                "/admin/queues",
+                &["bucket", "group_id", "priority", "waited_ms"],
+                raw,
+            )
+            .await?
+        }
+        "loans" => {
+            list(
+                &client,
+                args,
+                "/admin/loans",
+                &[
+                    "id",
+                    "from_group",
+                    "to_group",
+                    "global_rps",
+                    "remaining_ms",
+                    "note",
+                ],
+                raw,
+            )
+            .await?
+        }
+        "dlq" => {
+            list(
+                &client,
+                args,
+                "/admin/dlq",
+                &["id", "kind", "item_id", "attempts", "error"],
+                raw,
+            )
+            .await?
+        }
+        "audit" => list(&client, args, "/admin/audit", AUDIT_COLUMNS, raw).await?,
+        "alerts" => list(&client, args, "/admin/alerts", ALERT_COLUMNS, raw).await?,
+        "config" => {
+            args.finish()?;
+            let config = client.get("/admin/config", &[]).await?;
+            println!("{}", pretty(&config));
+        }
+        "pause" => {
+            let group = args.positional("group")?;
+            let duration_ms = parse_duration(&args.positional("duration")?)?;
+            let reason = args.option("--reason")?.unwrap_or_default();
+            args.finish()?;
+            let pause = client
+                .send(
+                    client
+                        .request(Method::PUT, &format!("/admin/groups/{group}/pause"))
+                        .json(&json!({ "duration_ms": duration_ms, "reason": reason })),
+                )
+                .await?;
+            if raw {
+                println!("{}", pretty(&pause));
+            } else {
+                println!("{group} paused for {}", format_duration(duration_ms));
+            }
+        }
+        "resume" => {
+            let group = args.positional("group")?;
+            args.finish()?;
+            client
+                .send(client.request(Method::DELETE, &format!("/admin/groups/{group}/pause")))
+                .await?;
+            println!("{group} resumed");
+        }
+        "limits" => {
+            let action = args.positional("limits action (set or clear)")?;
+            let group = args.positional("group")?;
+            let path = format!("/admin/groups/{group}/limits");
+            match action.as_str() {
+                "set" => {
+                    let global_rps = number_option(&mut args, "--global-rps")?;
+                    let route_rps = number_option(&mut args, "--route-rps")?;
+                    args.finish()?;
+                    let limits = client
+                        .send(
+                            client
+                                .request(Method::PUT, &path)
+                                .json(&json!({ "global_rps": global_rps, "route_rps": route_rps })),
+                        )
+                        .await?;
+                    println!("{}", pretty(&limits));
+                }
+                "clear" => {
+                    args.finish()?;
+                    client.send(client.request(Method::DELETE, &path)).await?;
+                    println!("{group} limits returned to configured values");
+                }
+                other => return Err(CliError(format!("unknown limits action {other}"))),
+            }
+        }
+        "tail" => {
+            let source = if args.0.is_empty() {
+                "events".to_string()
+            } else {
+                args.positional("source")?
+            };
+            args.finish()?;
+            match source.as_str() {
+                "events" => {
+                    follow_events(&client, |event, data| println!("{event} {data}")).await?
+                }
+                "audit" => tail_stream(&client, "/admin/audit", AUDIT_COLUMNS, raw).await?,
+                "alerts" => tail_stream(&client, "/admin/alerts", ALERT_COLUMNS, raw).await?,
+                other => return Err(CliError(format!("unknown tail source {other}"))),
+            }
+        }
+        "top" => {
+            let interval_ms = number_option(&mut args, "--interval-ms")?.unwrap_or(1000);
+            args.finish()?;
+            #[cfg(feature = "tui")]
+            top::run(client, interval_ms.max(250)).await?;
+            #[cfg(not(feature = "tui"))]
+            {
+                let _ = interval_ms;
+                return Err(CliError(
+                    "top needs dmboctl built with the tui feature".to_string(),
+                ));
+            }
+        }
+        "help" => println!("{USAGE}"),
+        other => return Err(CliError(format!("unknown command {other}\n\n{USAGE}"))),
+    }
+    Ok(())
+}
+
+const AUDIT_COLUMNS: &[&str] = &[
+    "id",
+    "group_id",
+    "discord_identity",
+    "method",
+    "route",
+    "granted",
+    "reason",
+];
+const ALERT_COLUMNS: &[&str] = &[
+    "id",
+    "kind",
+    "discord_identity",
+    "metric",
+    "observed",
+    "baseline",
+];
+
+/// `--filter`, `--sort`, `--limit` and `--cursor`, passed through as the
+/// admin listing query.
+fn listing_query(args: &mut Args) -> CliResult<Vec<(&'static str, String)>> {
+    let mut query = Vec::new();
+    for name in ["filter", "sort", "limit", "cursor"] {
+        if let Some(value) = args.option(&format!("--{name}"))? {
+            query.push((name, value));
+        }
+    }
+    Ok(query)
+}
+
+async fn list(
+    client: &Client,
+    mut args: Args,
+    path: &str,
+    columns: &[&str],
+    raw: bool,
+) -> CliResult {
+    let query = listing_query(&mut args)?;
+    args.finish()?;
+    let page = client.get(path, &query).await?;
+    print_page(&page, columns, raw);
+    Ok(())
+}
+
+fn print_page(page: &Value, columns: &[&str], raw: bool) {
+    if raw {
+        println!("{}", pretty(page));
+        return;
+    }
+    let items = page["items"].as_array().cloned().unwrap_or_default();
+    print_table(&items, columns);
+    if let Some(cursor) = page["next_cursor"].as_str() {
+        println!("(more: --cursor {cursor})");
+    }
+}
+
+/// Prints `items` as left-aligned columns under a header row.
+fn print_table(items: &[Value], columns: &[&str]) {
+    let rows: Vec<Vec<String>> = items
+        .iter()
+        .map(|item| columns.iter().map(|column| cell(&item[*column])).collect())
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            rows.iter()
+                .map(|row| row[index].len())
+                .chain([column.len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let line = |cells: Vec<String>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(columns.iter().map(|column| column.to_uppercase()).collect());
+    for row in rows {
+        line(row);
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+fn number_option(args: &mut Args, name: &str) -> CliResult<Option<u64>> {
+    args.option(name)?
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| CliError(format!("{name} must be a whole number")))
+        })
+        .transpose()
+}
+
+/// Parses `1500`, `90s`, `15m`, `2h` or `1d` into milliseconds.
+fn parse_duration(text: &str) -> CliResult<u64> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let multiplier = match unit {
+        "" | "ms" => 1,
+        "s" => 1000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if multiplier > 0 => Ok(number * multiplier),
+        _ => Err(CliError(format!(
+            "invalid duration {text}; use e.g. 500ms, 90s, 15m, 2h or 1d"
+        ))),
+    }
+}
+
+fn format_duration(ms: u64) -> String {
+    match ms {
+        ms if ms % 86_400_000 == 0 => format!("{}d", ms / 86_400_000),
+        ms if ms % 3_600_000 == 0 => format!("{}h", ms / 3_600_000),
+        ms if ms % 60_000 == 0 => format!("{}m", ms / 60_000),
+        ms if ms % 1000 == 0 => format!("{}s", ms / 1000),
+        ms => format!("{ms}ms"),
+    }
+}
+
+/// Follows the `/events` server-sent event stream, calling `on_event` with
+/// each event's name and data until the stream ends.
+async fn follow_events(client: &Client, mut on_event: impl FnMut(&str, &str)) -> CliResult {
+    let mut response = client.request(Method::GET, "/events").send().await?;
+    if !response.status().is_success() {
+        return Err(CliError(format!(
+            "{}: cannot open /events",
+            response.status()
+        )));
+    }
+    let mut buffer = Vec::new();
+    let mut event = String::new();
+    while let Some(chunk) = response.chunk().await? {
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if let Some(name) = line.strip_prefix("event:") {
+                event = name.trim().to_string();
+            } else if let Some(data) = line.strip_prefix("data:") {
+                on_event(&event, data.trim());
+            } else if line.is_empty() {
+                event.clear();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Polls an admin stream listing and prints entries newer than the last
+/// one seen, one `field=value` line each. Stream ids (`<ms>-<seq>`) order entries.
+async fn tail_stream(client: &Client, path: &str, columns: &[&str], raw: bool) -> CliResult {
+    let stream_id = |item: &Value| -> (u64, u64) {
+        let id = item["id"].as_str().unwrap_or_default();
+        let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+        (
+            ms.parse().unwrap_or_default(),
+            seq.parse().unwrap_or_default(),
+        )
+    };
+    let query = [("sort", "-id".to_string()), ("limit", "1000".to_string())];
+    let mut last = client.get(path, &query).await?["items"]
+        .as_array()
+        .and_then(|items| items.first().map(stream_id))
+        .unwrap_or_default();
+    loop {
+        tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MS)).await;
+        let page = client.get(path, &query).await?;
+        let mut fresh: Vec<Value> = page["items"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|item| stream_id(item) > last)
+            .collect();
+        fresh.reverse();
+        if let Some(newest) = fresh.last() {
+            last = stream_id(newest);
+        }
+        for item in &fresh {
+            if raw {
+                println!("{item}");
+            } else {
+                let fields: Vec<String> = columns
+                    .iter()
+                    .map(|column| format!("{column}={}", cell(&item[*column])))
+                    .collect();
+                println!("{}", fields.join(" "));
+            }
+        }
+    }
+}
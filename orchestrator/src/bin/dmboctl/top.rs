@@ -0,0 +1,410 @@
+//! `dmboctl top`: a live console for incidents. Polls `/metrics` and the
+//! admin listings every interval and follows `/events`, showing grant and
+//! deny rates, queue depth, the hottest route buckets, guards, paused groups
+//! and recent events. Rates come from one replica's `/metrics`, the one
+//! behind `DMBO_URL`; buckets and guards are shared across replicas.
+
+use crate::{cell, follow_events, CliError, CliResult, Client};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, Paragraph, Row, Sparkline, Table},
+    DefaultTerminal, Frame,
+};
+use reqwest::Method;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Rate samples kept for the sparklines.
+const HISTORY: usize = 120;
+/// Events kept for the events pane.
+const EVENT_LINES: usize = 50;
+const BUCKET_ROWS: usize = 15;
+const GUARD_ROWS: usize = 10;
+
+/// One poll of the orchestrator.
+#[derive(Default)]
+struct Sample {
+    metrics: HashMap<String, f64>,
+    buckets: Vec<Value>,
+    guards: Vec<Value>,
+    groups: Vec<Value>,
+    route_rps: f64,
+    instance_id: String,
+}
+
+#[derive(Default)]
+struct Screen {
+    url: String,
+    current: Sample,
+    previous: Option<(Instant, HashMap<String, f64>)>,
+    granted_per_s: f64,
+    denied_per_s: f64,
+    errors_per_s: f64,
+    observed_429_per_s: f64,
+    granted_history: VecDeque<u64>,
+    denied_history: VecDeque<u64>,
+    error: Option<String>,
+}
+
+pub(crate) async fn run(client: Client, interval_ms: u64) -> CliResult {
+    let mut terminal = ratatui::try_init()
+        .map_err(|error| CliError(format!("top needs an interactive terminal: {error}")))?;
+    let events = Arc::new(Mutex::new(VecDeque::new()));
+    let follower = {
+        let client = client.clone();
+        let events = events.clone();
+        tokio::spawn(async move {
+            let _ = follow_events(&client, |event, data| {
+                let mut events = events.lock().expect("events poisoned");
+                if events.len() == EVENT_LINES {
+                    events.pop_back();
+                }
+                events.push_front(format!("{event} {data}"));
+            })
+            .await;
+        })
+    };
+
+    let result = watch(&mut terminal, &client, interval_ms, &events).await;
+    ratatui::restore();
+    follower.abort();
+    result
+}
+
+async fn watch(
+    terminal: &mut DefaultTerminal,
+    client: &Client,
+    interval_ms: u64,
+    events: &Mutex<VecDeque<String>>,
+) -> CliResult {
+    let mut screen = Screen {
+        url: client.base_url.clone(),
+        ..Screen::default()
+    };
+    loop {
+        match poll(client).await {
+            Ok(sample) => {
+                screen.record(sample);
+                screen.error = None;
+            }
+            Err(CliError(message)) => screen.error = Some(message),
+        }
+        let deadline = Instant::now() + Duration::from_millis(interval_ms);
+        while Instant::now() < deadline {
+            let events: Vec<String> = events
+                .lock()
+                .expect("events poisoned")
+                .iter()
+                .cloned()
+                .collect();
+            terminal
+                .draw(|frame| draw(frame, &screen, &events))
+                .map_err(|error| CliError(format!("terminal error: {error}")))?;
+            let wait = deadline.saturating_duration_since(Instant::now());
+            if quit_requested(wait.min(Duration::from_millis(200)))? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Waits up to `timeout` for a key press; true on `q`, Esc or Ctrl-C.
+fn quit_requested(timeout: Duration) -> CliResult<bool> {
+    let terminal_error = |error: std::io::Error| CliError(format!("terminal error: {error}"));
+    if !event::poll(timeout).map_err(terminal_error)? {
+        return Ok(false);
+    }
+    let Event::Key(key) = event::read().map_err(terminal_error)? else {
+        return Ok(false);
+    };
+    Ok(key.kind == KeyEventKind::Press
+        && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))))
+}
+
+async fn poll(client: &Client) -> CliResult<Sample> {
+    let metrics = async {
+        let response = client.request(Method::GET, "/metrics").send().await?;
+        if !response.status().is_success() {
+            return Err(CliError(format!(
+                "{}: cannot read /metrics",
+                response.status()
+            )));
+        }
+        Ok(parse_metrics(&response.text().await?))
+    };
+    let bucket_query = [
+        ("filter", "kind:route".to_string()),
+        ("sort", "-count".to_string()),
+        ("limit", BUCKET_ROWS.to_string()),
+    ];
+    let guard_query = [
+        ("sort", "-invalid_count".to_string()),
+        ("limit", GUARD_ROWS.to_string()),
+    ];
+    let group_query = [
+        ("filter", "paused:true".to_string()),
+        ("limit", GUARD_ROWS.to_string()),
+    ];
+    let buckets = client.get("/admin/buckets", &bucket_query);
+    let guards = client.get("/admin/guards", &guard_query);
+    let groups = client.get("/admin/groups", &group_query);
+    let config = client.get("/admin/config", &[]);
+    let (metrics, buckets, guards, groups, config) =
+        tokio::join!(metrics, buckets, guards, groups, config);
+    let config = config?;
+    let items = |page: Value| page["items"].as_array().cloned().unwrap_or_default();
+    Ok(Sample {
+        metrics: metrics?,
+        buckets: items(buckets?),
+        guards: items(guards?),
+        groups: items(groups?),
+        route_rps: config["limits"]["effective_route_rps"]
+            .as_f64()
+            .unwrap_or_default(),
+        instance_id: config["instance_id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+/// Parses Prometheus text into values keyed by series, e.g.
+/// `orchestrator_request_token_total{outcome="granted"}`.
+fn parse_metrics(text: &str) -> HashMap<String, f64> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (series, value) = line.rsplit_once(' ')?;
+            Some((series.to_string(), value.parse().ok()?))
+        })
+        .collect()
+}
+
+impl Screen {
+    fn record(&mut self, sample: Sample) {
+        let now = Instant::now();
+        if let Some((then, previous)) = &self.previous {
+            let elapsed = now.duration_since(*then).as_secs_f64().max(0.001);
+            let rate = |series: &[&str]| {
+                series
+                    .iter()
+                    .map(|name| {
+                        let current = sample.metrics.get(*name).copied().unwrap_or_default();
+                        let before = previous.get(*name).copied().unwrap_or_default();
+                        (current - before).max(0.0)
+                    })
+                    .sum::<f64>()
+                    / elapsed
+            };
+            self.granted_per_s = rate(&["orchestrator_request_token_total{outcome=\"granted\"}"]);
+            self.denied_per_s = rate(&["orchestrator_request_token_total{outcome=\"denied\"}"]);
+            self.errors_per_s = rate(&["orchestrator_request_token_total{outcome=\"error\"}"]);
+            self.observed_429_per_s = rate(&[
+                "orchestrator_429_observed_total{scope=\"global\"}",
+                "orchestrator_429_observed_total{scope=\"user\"}",
+                "orchestrator_429_observed_total{scope=\"shared\"}",
+                "orchestrator_429_observed_total{scope=\"unknown\"}",
+            ]);
+            for (history, value) in [
+                (&mut self.granted_history, self.granted_per_s),
+                (&mut self.denied_history, self.denied_per_s),
+            ] {
+                if history.len() == HISTORY {
+                    history.pop_front();
+                }
+                history.push_back(value.round() as u64);
+            }
+        }
+        self.previous = Some((now, sample.metrics.clone()));
+        self.current = sample;
+    }
+
+    fn gauge(&self, series: &str) -> f64 {
+        self.current
+            .metrics
+            .get(series)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+fn draw(frame: &mut Frame, screen: &Screen, events: &[String]) {
+    let [header, sparklines, tables, events_area] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Length(5),
+        Constraint::Min(8),
+        Constraint::Length(8),
+    ])
+    .areas(frame.area());
+
+    draw_header(frame, header, screen);
+
+    let [granted, denied] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(sparklines);
+    for (area, title, history, color) in [
+        (granted, "granted/s", &screen.granted_history, Color::Green),
+        (denied, "denied/s", &screen.denied_history, Color::Red),
+    ] {
+        let data: Vec<u64> = history.iter().copied().collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(&data)
+                .style(Style::default().fg(color)),
+            area,
+        );
+    }
+
+    let [buckets, side] =
+        Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(tables);
+    draw_buckets(frame, buckets, screen);
+    let [guards, paused] =
+        Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(side);
+    draw_guards(frame, guards, screen);
+    draw_paused(frame, paused, screen);
+
+    let items: Vec<ListItem> = events
+        .iter()
+        .map(|line| ListItem::new(line.as_str()))
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("events")),
+        events_area,
+    );
+}
+
+fn draw_header(frame: &mut Frame, area: Rect, screen: &Screen) {
+    let title = format!(
+        " dmbo top · {} · {} · q to quit ",
+        screen.url, screen.current.instance_id
+    );
+    let mut lines = vec![
+        Line::from(format!(
+            "granted {:>8.1}/s   denied {:>8.1}/s   errors {:>6.1}/s   discord 429s {:>6.1}/s",
+            screen.granted_per_s,
+            screen.denied_per_s,
+            screen.errors_per_s,
+            screen.observed_429_per_s,
+        )),
+        Line::from(format!(
+            "queue {:>6}   inflight {:>6}   deferred reports {:>6}   loans {:>4}   paused groups {:>4}",
+            screen.gauge("orchestrator_queue_depth"),
+            screen.gauge("inflight_requests"),
+            screen.gauge("orchestrator_dlq_deferred_reports"),
+            screen.gauge("orchestrator_loans_active"),
+            screen.gauge("orchestrator_groups_paused"),
+        )),
+    ];
+    if let Some(error) = &screen.error {
+        lines.truncate(1);
+        lines.push(Line::from(error.as_str()).red());
+    }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+fn draw_buckets(frame: &mut Frame, area: Rect, screen: &Screen) {
+    let limit = screen.current.route_rps;
+    let rows = screen.current.buckets.iter().map(|bucket| {
+        let count = bucket["count"].as_f64().unwrap_or_default();
+        let used = if limit > 0.0 {
+            format!("{:.0}%", count / limit * 100.0)
+        } else {
+            "-".to_string()
+        };
+        let row = Row::new([
+            cell(&bucket["discord_identity"]),
+            cell(&bucket["method"]),
+            cell(&bucket["route"]),
+            cell(&bucket["major_parameter"]),
+            cell(&bucket["count"]),
+            used,
+        ]);
+        if limit > 0.0 && count >= limit {
+            row.red()
+        } else {
+            row
+        }
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(14),
+            Constraint::Length(7),
+            Constraint::Min(20),
+            Constraint::Length(20),
+            Constraint::Length(6),
+            Constraint::Length(6),
+        ],
+    )
+    .header(header_row(&[
+        "IDENTITY", "METHOD", "ROUTE", "MAJOR", "COUNT", "USED",
+    ]))
+    .block(Block::bordered().title("hottest route buckets (current window)"));
+    frame.render_widget(table, area);
+}
+
+fn draw_guards(frame: &mut Frame, area: Rect, screen: &Screen) {
+    let rows = screen.current.guards.iter().map(|guard| {
+        let active = guard["active"].as_bool().unwrap_or_default();
+        let row = Row::new([
+            cell(&guard["group_id"]),
+            if active { "ACTIVE" } else { "-" }.to_string(),
+            cell(&guard["remaining_ms"]),
+            cell(&guard["invalid_count"]),
+        ]);
+        if active {
+            row.red()
+        } else {
+            row
+        }
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(10),
+            Constraint::Length(6),
+            Constraint::Length(9),
+            Constraint::Length(7),
+        ],
+    )
+    .header(header_row(&["GROUP", "GUARD", "LEFT MS", "INVALID"]))
+    .block(Block::bordered().title("guards"));
+    frame.render_widget(table, area);
+}
+
+fn draw_paused(frame: &mut Frame, area: Rect, screen: &Screen) {
+    let rows = screen.current.groups.iter().map(|group| {
+        Row::new([
+            cell(&group["group_id"]),
+            cell(&group["pause_remaining_ms"]),
+            cell(&group["pause_reason"]),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(10),
+            Constraint::Length(9),
+            Constraint::Min(10),
+        ],
+    )
+    .header(header_row(&["GROUP", "LEFT MS", "REASON"]))
+    .block(Block::bordered().title("paused groups"));
+    frame.render_widget(table, area);
+}
+
+fn header_row(columns: &[&'static str]) -> Row<'static> {
+    Row::new(columns.to_vec()).style(Style::default().add_modifier(Modifier::BOLD))
+}
@@ -0,0 +1,147 @@
+//! Discord bucket discovery. Discord groups routes into rate limit buckets
+//! and names them in `X-RateLimit-Bucket`; reports carrying that header
+//! record a route → bucket mapping per identity in Redis, and permits for a
+//! mapped route are keyed on the bucket hash (plus major parameter) instead
+//! of the literal method and route, so routes sharing a bucket share its
+//! counters and learned state. Lookups are cached briefly in process so most
+//! permits skip the extra Redis round trip.
+
+use crate::{Metrics, ReportResultRequest};
+use dmbo_core::keys::{bucket_id, discord_bucket_id, normalize_key_part};
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use std::{collections::HashMap, sync::atomic::Ordering, sync::Mutex};
+
+/// How long a lookup, found or not, is trusted before Redis is asked again,
+/// so mappings learned by other instances are picked up.
+const CACHE_MS: u64 = 30_000;
+/// The cache is swept of stale entries once it grows past this.
+const CACHE_SWEEP_LEN: usize = 10_000;
+/// Longer header values are ignored rather than stored.
+const MAX_BUCKET_LEN: usize = 64;
+
+pub(crate) struct BucketMap {
+    /// Lifetime of a mapping since its last report; 0 disables discovery.
+    ttl_s: u64,
+    cache: Mutex<HashMap<String, (Option<String>, u64)>>,
+}
+
+fn map_key(discord_identity: &str, method: &str, route: &str) -> String {
+    format!(
+        "rl:bucketmap:{}:{}:{}",
+        normalize_key_part(discord_identity),
+        normalize_key_part(method),
+        normalize_key_part(route)
+    )
+}
+
+impl BucketMap {
+    pub(crate) fn new(ttl_s: u64) -> Self {
+        Self {
+            ttl_s,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn cached(&self, key: &str, now_ms: u64) -> Option<Option<String>> {
+        let cache = self.cache.lock().expect("bucket map cache poisoned");
+        cache
+            .get(key)
+            .filter(|(_, expires)| *expires > now_ms)
+            .map(|(bucket, _)| bucket.clone())
+    }
+
+    fn remember(&self, key: String, bucket: Option<String>, now_ms: u64) {
+        let mut cache = self.cache.lock().expect("bucket map cache poisoned");
+        if cache.len() >= CACHE_SWEEP_LEN {
+            cache.retain(|_, (_, expires)| *expires > now_ms);
+        }
+        cache.insert(key, (bucket, now_ms + CACHE_MS));
+    }
+
+    /// The key suffix a permit's route counters use: the Discord bucket when
+    /// one is mapped for the route, otherwise the literal request tuple.
+    /// Lookup errors fall back to the literal tuple.
+    pub(crate) async fn resolve(
+        &self,
+        conn: &mut MultiplexedConnection,
+        discord_identity: &str,
+        method: &str,
+        route: &str,
+        major_parameter: &str,
+        now_ms: u64,
+    ) -> String {
+        let literal = || bucket_id(discord_identity, method, route, major_parameter);
+        if self.ttl_s == 0 {
+            return literal();
+        }
+        let key = map_key(discord_identity, method, route);
+        let bucket = match self.cached(&key, now_ms) {
+            Some(bucket) => bucket,
+            None => match conn.get::<_, Option<String>>(&key).await {
+                Ok(bucket) => {
+                    self.remember(key, bucket.clone(), now_ms);
+                    bucket
+                }
+                Err(_) => None,
+            },
+        };
+        match bucket {
+            Some(bucket) => discord_bucket_id(discord_identity, &bucket, major_parameter),
+            None => literal(),
+        }
+    }
+
+    /// The bucket a report's call was permitted on when the report names
+    /// it; `None` when discovery is off or the header is missing.
+    pub(crate) fn reported(
+        &self,
+        report: &ReportResultRequest,
+        major_parameter: &str,
+    ) -> Option<String> {
+        let bucket = self.reported_hash(report)?;
+        Some(discord_bucket_id(
+            &report.discord_identity,
+            bucket,
+            major_parameter,
+        ))
+    }
+
+    fn reported_hash<'a>(&self, report: &'a ReportResultRequest) -> Option<&'a str> {
+        if self.ttl_s == 0 {
+            return None;
+        }
+        report
+            .x_ratelimit_bucket
+            .as_deref()
+            .map(str::trim)
+            .filter(|bucket| !bucket.is_empty() && bucket.len() <= MAX_BUCKET_LEN)
+    }
+
+    /// Records the route → bucket mapping a report carries, refreshing its
+    /// lifetime. Skips the write while this instance's cached copy already
+    /// holds the same bucket.
+    pub(crate) async fn learn(
+        &self,
+        conn: &mut MultiplexedConnection,
+        metrics: &Metrics,
+        report: &ReportResultRequest,
+        method: &str,
+        route: &str,
+        now_ms: u64,
+    ) -> redis::RedisResult<()> {
+        let Some(bucket) = self.reported_hash(report) else {
+            return Ok(());
+        };
+        if route.trim().is_empty() || method.trim().is_empty() {
+            return Ok(());
+        }
+        let key = map_key(&report.discord_identity, method, route);
+        if self.cached(&key, now_ms).flatten().as_deref() == Some(bucket) {
+            return Ok(());
+        }
+        conn.set_ex::<_, _, ()>(&key, bucket, self.ttl_s).await?;
+        metrics.bucket_map_writes.fetch_add(1, Ordering::Relaxed);
+        self.remember(key, Some(bucket.to_string()), now_ms);
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! Asynchronous grant delivery. A permit request with a `callback_url` is
+//! answered `202 Accepted` at once; the orchestrator waits for capacity in
+//! the background, as for a long poll, and `POST`s the final decision to the
+//! URL. For clients that cannot hold a connection open through a long wait,
+//! such as serverless functions and cron jobs.
+//!
+//! Pending callbacks live in the accepting process only, each holding a
+//! place under `DMBO_MAX_WAITERS` until delivered. A restart drops them
+//! without a grant, so a client that has heard nothing once `max_wait_ms`
+//! has passed should treat the request as denied.
+//!
+//! The orchestrator makes these requests from inside the deployment, so the
+//! URL may only name the hosts in `DMBO_CALLBACK_ALLOWED_HOSTS` or, with none
+//! listed, a host that resolves to public addresses. Redirects are not
+//! followed.
+
+use crate::{
+    clamp_wait, decide_permit, leases, waiters::CallbackSlot, AppState, Config,
+    RequestTokenRequest, TraceContext,
+};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect,
+};
+use serde_json::{json, Value};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::atomic::Ordering,
+    sync::Arc,
+    time::Duration,
+};
+
+/// Checks a `callback_url` before it is accepted. Host names are checked
+/// again when the callback is delivered, against what they resolve to.
+pub(crate) fn check_url(config: &Config, url: &str) -> Result<(), String> {
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        .ok_or("must be an http or https URL")?;
+    if !config.callback_allowed_hosts.is_empty() {
+        if !config.callback_allowed_hosts.contains(&host) {
+            return Err("host is not in DMBO_CALLBACK_ALLOWED_HOSTS".to_string());
+        }
+        return Ok(());
+    }
+    let private = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => !is_public(ip),
+        Err(_) => host == "localhost" || host.ends_with(".localhost"),
+    };
+    if private {
+        return Err("must not name a private, loopback or link-local address".to_string());
+    }
+    Ok(())
+}
+
+/// The client callbacks are posted with. Without an allowlist it only
+/// connects to public addresses, whatever a host name resolves to.
+pub(crate) fn http_client(config: &Config) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder().redirect(redirect::Policy::none());
+    if config.callback_allowed_hosts.is_empty() {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+    builder
+        .build()
+        .expect("failed to build callback HTTP client")
+}
+
+/// Resolves host names to their public addresses only.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let public: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if public.is_empty() {
+                return Err(format!("{host} has no public address").into());
+            }
+            Ok(Box::new(public.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is reachable on the internet, as opposed to loopback,
+/// private, link-local, shared, multicast or reserved space.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The `202 Accepted` body; also what an `Idempotency-Key` retry replays.
+pub(crate) fn accepted_body(request: &RequestTokenRequest) -> Value {
+    json!({ "request_id": request.request_id, "status": "queued" })
+}
+
+/// Queues a validated request for background delivery.
+pub(crate) fn accept(
+    state: &Arc<AppState>,
+    mut request: RequestTokenRequest,
+    trace: Option<TraceContext>,
+) -> Response {
+    request.long_poll = true;
+    clamp_wait(&state.config, &mut request);
+    let body = accepted_body(&request);
+    let slot = state.waiters.hold_callback();
+    tokio::spawn(deliver(state.clone(), request, trace, slot));
+    (StatusCode::ACCEPTED, Json(body)).into_response()
+}
+
+/// Decides the request and posts the decision, with its `request_id`, to the
+/// callback. A grant that could not be delivered hands its lease back.
+async fn deliver(
+    state: Arc<AppState>,
+    mut request: RequestTokenRequest,
+    trace: Option<TraceContext>,
+    _slot: CallbackSlot,
+) {
+    let Some(callback_url) = request.callback_url.clone() else {
+        return;
+    };
+    let (response, _) = decide_permit(&state, &mut request, trace.as_ref()).await;
+    let mut body = serde_json::to_value(&response).unwrap_or_default();
+    body["request_id"] = json!(request.request_id);
+    let mut post = state
+        .callback_http
+        .post(&callback_url)
+        .timeout(Duration::from_secs(10))
+        .json(&body);
+    if let Some(trace) = &trace {
+        post = post.header("traceparent", &trace.traceparent);
+        if let Some(tracestate) = &trace.tracestate {
+            post = post.header("tracestate", tracestate);
+        }
+    }
+    let delivered = post
+        .send()
+        .await
+        .is_ok_and(|callback| callback.status().is_success());
+    if delivered {
+        state
+            .metrics
+            .permit_callbacks_sent
+            .fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    state
+        .metrics
+        .permit_callbacks_failed
+        .fetch_add(1, Ordering::Relaxed);
+    if let (Some(lease_id), Ok(mut conn)) = (
+        &response.lease_id,
+        state.redis.get_multiplexed_async_connection().await,
+    ) {
+        let _ = leases::release(&state, &mut conn, lease_id).await;
+    }
+}
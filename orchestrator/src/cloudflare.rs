@@ -0,0 +1,57 @@
+//! Cloudflare bans. A 429 answered by Cloudflare rather than Discord (no
+//! JSON body) means the whole IP is blocked, usually for an hour or more,
+//! and more requests only extend it. Reports flagged `cloudflare` trip the
+//! group's guard at once for `DMBO_CLOUDFLARE_COOLDOWN_MS`, doubling with
+//! each further ban within `DMBO_CLOUDFLARE_STRIKE_WINDOW_S` up to
+//! `DMBO_CLOUDFLARE_COOLDOWN_MAX_MS`.
+
+use crate::{
+    normalize_key_part,
+    notifier::{self, Alert, Severity},
+    signals,
+    store::StoreError,
+    unix_ms, AppState, ReportResultRequest,
+};
+use std::sync::atomic::Ordering;
+
+/// Trips the report's group guard for a Cloudflare ban and alerts.
+pub(crate) async fn record_ban(
+    state: &AppState,
+    report: &ReportResultRequest,
+) -> Result<(), StoreError> {
+    let config = &state.config;
+    if !report.cloudflare || report.status_code != 429 || config.cloudflare_cooldown_ms == 0 {
+        return Ok(());
+    }
+    let group = normalize_key_part(&report.group_id);
+    let (strikes, cooldown_ms) = state
+        .store
+        .record_ban(
+            &group,
+            config.cloudflare_cooldown_ms,
+            config.cloudflare_cooldown_max_ms,
+            config.cloudflare_strike_window_seconds * 1000,
+        )
+        .await?;
+    state
+        .metrics
+        .cloudflare_bans
+        .fetch_add(1, Ordering::Relaxed);
+    signals::guardrail_activated(state, &group, unix_ms() + cooldown_ms);
+    notifier::notify(
+        state,
+        Alert {
+            kind: "cloudflare_ban",
+            subject: group.clone(),
+            severity: Severity::Critical,
+            title: "Cloudflare ban reported".to_string(),
+            description: format!(
+                "Group `{group}` got a Cloudflare 429, ban {strikes} in the strike window. Its \
+                 permits are denied for {}s.",
+                cooldown_ms / 1000
+            ),
+            fields: vec![("Route", report.route.clone())],
+        },
+    );
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Coalescing of identical GETs. With `DMBO_COALESCE_GET_MS` set, the first
+//! permit request for a GET route bucket claims it as leader for that long;
+//! other requests for the same bucket meanwhile are not granted but told
+//! which request they can share the response of, saving the permit and the
+//! Discord call. Retries of the leader itself are not coalesced.
+
+use crate::{AppState, RequestTokenRequest, Transport};
+use dmbo_core::keys::bucket_id;
+use redis::Script;
+
+/// Makes `ARGV[1]` the leader of `KEYS[1]` for `ARGV[2]` ms unless another
+/// request leads it; returns that leader's request id and remaining ms, or
+/// `{'', 0}` when the caller leads.
+const CLAIM_LUA: &str = r#"
+local leader = redis.call('GET', KEYS[1])
+if not leader then
+  redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+  return {'', 0}
+end
+if leader == ARGV[1] then return {'', 0} end
+return {leader, redis.call('PTTL', KEYS[1])}
+"#;
+
+/// The leading request `request` should share a response with, and how long
+/// its window still runs. `None` when the request goes ahead itself, which
+/// includes any Redis failure.
+pub(crate) async fn leader(
+    state: &AppState,
+    request: &RequestTokenRequest,
+) -> Option<(String, u64)> {
+    let window_ms = state.config.coalesce_get_ms;
+    if window_ms == 0 || request.transport != Transport::Http || request.method != "GET" {
+        return None;
+    }
+    let route_bucket = bucket_id(
+        &request.discord_identity,
+        &request.method,
+        &request.route,
+        &request.major_parameter,
+    );
+    let mut conn = state.redis.get_multiplexed_async_connection().await.ok()?;
+    let (leader, remaining_ms): (String, i64) = Script::new(CLAIM_LUA)
+        .key(format!("rl:coalesce:{route_bucket}"))
+        .arg(&request.request_id)
+        .arg(window_ms)
+        .invoke_async(&mut conn)
+        .await
+        .ok()?;
+    (!leader.is_empty()).then(|| (leader, remaining_ms.max(0) as u64))
+}
@@ -0,0 +1,210 @@
+//! Binary bodies for the hot permit endpoints. `POST /request_token` and
+//! `POST /report_result` accept MessagePack (`Content-Type:
+//! application/msgpack` or `application/x-msgpack`) and, built with the
+//! `cbor` feature, CBOR (`application/cbor`) in place of JSON. They answer
+//! in a binary format when `Accept` names it, or when the request used it
+//! and `Accept` does not name JSON. Either way the documents are the same as
+//! their JSON forms, with map keys by field name.
+
+use crate::{json_rejection_response, problem_response, AppState, PROBLEM_TYPE_INVALID_REQUEST};
+use axum::{
+    async_trait,
+    body::{to_bytes, Body, Bytes},
+    extract::{
+        rejection::{BytesRejection, JsonRejection},
+        FromRequest, Request,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use dmbo_core::Reason;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+/// Largest response body transcoded; permit responses are far smaller.
+const MAX_RESPONSE_BYTES: usize = 1 << 20;
+
+/// Wire encodings besides JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Msgpack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Format {
+    /// The format a media type (parameters allowed) names, if any.
+    fn of(media: &str) -> Option<Self> {
+        let media = media.split(';').next().unwrap_or_default().trim();
+        if media.eq_ignore_ascii_case("application/msgpack")
+            || media.eq_ignore_ascii_case("application/x-msgpack")
+        {
+            return Some(Format::Msgpack);
+        }
+        #[cfg(feature = "cbor")]
+        if media.eq_ignore_ascii_case("application/cbor") {
+            return Some(Format::Cbor);
+        }
+        None
+    }
+
+    fn of_header(value: Option<&HeaderValue>) -> Option<Self> {
+        Self::of(value?.to_str().ok()?)
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Msgpack => "application/msgpack",
+            #[cfg(feature = "cbor")]
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Msgpack => "MessagePack",
+            #[cfg(feature = "cbor")]
+            Format::Cbor => "CBOR",
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Msgpack => rmp_serde::from_slice(bytes).map_err(|error| error.to_string()),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|error| error.to_string()),
+        }
+    }
+
+    fn encode(self, value: &serde_json::Value) -> Option<Vec<u8>> {
+        match self {
+            Format::Msgpack => rmp_serde::to_vec_named(value).ok(),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => {
+                let mut encoded = Vec::new();
+                ciborium::into_writer(value, &mut encoded).ok()?;
+                Some(encoded)
+            }
+        }
+    }
+}
+
+/// A request body in JSON or a binary format, chosen by `Content-Type`.
+pub(crate) struct Payload<T>(pub(crate) T);
+
+pub(crate) enum PayloadRejection {
+    Json(JsonRejection),
+    Body(BytesRejection),
+    /// The body is not valid in its format: the format's name and the error.
+    Decode(&'static str, String),
+}
+
+impl IntoResponse for PayloadRejection {
+    fn into_response(self) -> Response {
+        match self {
+            PayloadRejection::Json(rejection) => rejection.into_response(),
+            PayloadRejection::Body(rejection) => rejection.into_response(),
+            PayloadRejection::Decode(_, error) => (StatusCode::BAD_REQUEST, error).into_response(),
+        }
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Payload<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = PayloadRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(format) = Format::of_header(request.headers().get(header::CONTENT_TYPE)) else {
+            let Json(value) = Json::<T>::from_request(request, state)
+                .await
+                .map_err(PayloadRejection::Json)?;
+            return Ok(Self(value));
+        };
+        let bytes = Bytes::from_request(request, state)
+            .await
+            .map_err(PayloadRejection::Body)?;
+        format
+            .decode(&bytes)
+            .map(Self)
+            .map_err(|error| PayloadRejection::Decode(format.name(), error))
+    }
+}
+
+/// The error response for a body that could not be read or decoded.
+pub(crate) fn rejection_response(state: &AppState, rejection: PayloadRejection) -> Response {
+    if state.config.legacy_status_codes {
+        return rejection.into_response();
+    }
+    let detail = match rejection {
+        PayloadRejection::Json(rejection) => return json_rejection_response(state, rejection),
+        PayloadRejection::Body(rejection) => rejection.body_text(),
+        PayloadRejection::Decode(format, error) => format!("invalid {format} body: {error}"),
+    };
+    problem_response(
+        StatusCode::BAD_REQUEST,
+        PROBLEM_TYPE_INVALID_REQUEST,
+        "Invalid request body",
+        detail,
+        json!({
+            "reason": Reason::InvalidRequest,
+            "reason_message": Reason::InvalidRequest.message(),
+        }),
+    )
+}
+
+/// Re-encodes a JSON response in the binary format the caller asked for.
+/// Other responses pass through unchanged.
+pub(crate) async fn negotiate(headers: &HeaderMap, response: Response) -> Response {
+    let Some(format) = wanted(headers) else {
+        return response;
+    };
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/") && value.contains("json"));
+    if !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_RESPONSE_BYTES).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let encoded = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|value| format.encode(&value));
+    let Some(encoded) = encoded else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    Response::from_parts(parts, Body::from(encoded))
+}
+
+/// The response format: the first binary one `Accept` names, else the
+/// request's own unless `Accept` names JSON.
+fn wanted(headers: &HeaderMap) -> Option<Format> {
+    let accept: Vec<&str> = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    if let Some(format) = accept.iter().find_map(|media| Format::of(media)) {
+        return Some(format);
+    }
+    let accepts_json = accept.iter().any(|media| {
+        let media = media.split(';').next().unwrap_or_default().trim();
+        media.eq_ignore_ascii_case("application/json")
+            || media.eq_ignore_ascii_case("application/problem+json")
+    });
+    Format::of_header(headers.get(header::CONTENT_TYPE)).filter(|_| !accepts_json)
+}
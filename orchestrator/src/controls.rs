@@ -0,0 +1,370 @@
+//! Runtime group controls set through the admin API: pausing a group's
+//! permits for a while, and overriding its global and route limits without
+//! editing `DMBO_RULES` and restarting. Controls live in Redis so every
+//! replica applies them; each replica caches them and refreshes every
+//! second, like budget loans. A limit override takes precedence over policy
+//! rules, plugins and loans until it is cleared.
+
+use crate::{
+    admin::{page_response, ListQuery},
+    normalize_key_part, problem_response, unix_ms, validation_failed_response, AppState,
+    FieldError,
+};
+use axum::{
+    extract::{rejection::JsonRejection, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use redis::{AsyncCommands, Script};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::time::interval;
+
+const PAUSES_KEY: &str = "rl:controls:pauses";
+const LIMITS_KEY: &str = "rl:controls:limits";
+const PROBLEM_TYPE_NOT_FOUND: &str = "urn:dmbo:problem:not-found";
+const SYNC_INTERVAL_MS: u64 = 1000;
+/// Longest pause one call can set; longer outages should use a maintenance
+/// window or a deny rule.
+const MAX_PAUSE_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Deletes pause `ARGV[1]` if it is still the expired one read as `ARGV[2]`,
+/// so a pause set again meanwhile is kept.
+const PRUNE_PAUSE_LUA: &str = r#"
+if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then
+  redis.call('HDEL', KEYS[1], ARGV[1])
+end
+return 0
+"#;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Pause {
+    until_unix_ms: u64,
+    #[serde(default)]
+    reason: String,
+    created_unix_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LimitOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    global_rps: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    route_rps: Option<u64>,
+    updated_unix_ms: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PauseRequest {
+    duration_ms: u64,
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LimitsRequest {
+    #[serde(default)]
+    global_rps: Option<u64>,
+    #[serde(default)]
+    route_rps: Option<u64>,
+}
+
+/// Controls cached per replica, keyed by normalized group id. Pause expiry
+/// is checked on every read.
+#[derive(Clone, Default)]
+pub(crate) struct Controls {
+    pauses: Arc<RwLock<HashMap<String, Pause>>>,
+    limits: Arc<RwLock<HashMap<String, LimitOverride>>>,
+}
+
+impl Controls {
+    /// Milliseconds until `group_id`'s pause ends, if it is paused.
+    pub(crate) fn paused_for(&self, group_id: &str) -> Option<u64> {
+        let pauses = self.pauses.read().expect("controls poisoned");
+        if pauses.is_empty() {
+            return None;
+        }
+        let now = unix_ms();
+        pauses
+            .get(&normalize_key_part(group_id))
+            .filter(|pause| pause.until_unix_ms > now)
+            .map(|pause| pause.until_unix_ms - now)
+    }
+
+    /// `(global_rps, route_rps)` overrides for `group_id`.
+    pub(crate) fn limits(&self, group_id: &str) -> (Option<u64>, Option<u64>) {
+        let limits = self.limits.read().expect("controls poisoned");
+        if limits.is_empty() {
+            return (None, None);
+        }
+        limits
+            .get(&normalize_key_part(group_id))
+            .map_or((None, None), |limit| (limit.global_rps, limit.route_rps))
+    }
+
+    pub(crate) fn paused_count(&self) -> usize {
+        let now = unix_ms();
+        self.pauses
+            .read()
+            .expect("controls poisoned")
+            .values()
+            .filter(|pause| pause.until_unix_ms > now)
+            .count()
+    }
+}
+
+/// Refreshes the cache every `SYNC_INTERVAL_MS` and prunes expired pauses.
+/// On Redis errors the previous cache is kept.
+pub(crate) async fn sync_controls(state: Arc<AppState>) {
+    let mut ticker = interval(Duration::from_millis(SYNC_INTERVAL_MS));
+    loop {
+        ticker.tick().await;
+        let _ = refresh(&state).await;
+    }
+}
+
+async fn refresh(state: &AppState) -> Result<(), ()> {
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| ())?;
+    let (raw_pauses, raw_limits): (HashMap<String, String>, HashMap<String, String>) =
+        redis::pipe()
+            .hgetall(PAUSES_KEY)
+            .hgetall(LIMITS_KEY)
+            .query_async(&mut conn)
+            .await
+            .map_err(|_| ())?;
+    let now = unix_ms();
+    let mut pauses = HashMap::new();
+    for (group, raw) in raw_pauses {
+        let Ok(pause) = serde_json::from_str::<Pause>(&raw) else {
+            continue;
+        };
+        if pause.until_unix_ms > now {
+            pauses.insert(group, pause);
+        } else {
+            let _ = Script::new(PRUNE_PAUSE_LUA)
+                .key(PAUSES_KEY)
+                .arg(&group)
+                .arg(&raw)
+                .invoke_async::<_, i64>(&mut conn)
+                .await;
+        }
+    }
+    let limits = raw_limits
+        .into_iter()
+        .filter_map(|(group, raw)| Some((group, serde_json::from_str(&raw).ok()?)))
+        .collect();
+    *state.controls.pauses.write().expect("controls poisoned") = pauses;
+    *state.controls.limits.write().expect("controls poisoned") = limits;
+    Ok(())
+}
+
+fn normalized_group(group_id: &str) -> Result<String, FieldError> {
+    let group = normalize_key_part(group_id);
+    if group.is_empty() {
+        return Err(FieldError {
+            field: "group_id",
+            message: "must not be empty".to_string(),
+        });
+    }
+    Ok(group)
+}
+
+/// `GET /admin/groups`: groups with an active pause or limit override.
+pub(crate) async fn list(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    if refresh(&state).await.is_err() {
+        return crate::backend_unavailable_response(&state);
+    }
+    let now = unix_ms();
+    let mut groups: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    let blank = |group: &str| {
+        json!({
+            "group_id": group,
+            "paused": false,
+            "pause_remaining_ms": 0,
+            "pause_reason": "",
+            "global_rps": null,
+            "route_rps": null,
+        })
+    };
+    for (group, pause) in state
+        .controls
+        .pauses
+        .read()
+        .expect("controls poisoned")
+        .iter()
+    {
+        let item = groups.entry(group.clone()).or_insert_with(|| blank(group));
+        item["paused"] = json!(pause.until_unix_ms > now);
+        item["pause_remaining_ms"] = json!(pause.until_unix_ms.saturating_sub(now));
+        item["pause_reason"] = json!(pause.reason);
+    }
+    for (group, limit) in state
+        .controls
+        .limits
+        .read()
+        .expect("controls poisoned")
+        .iter()
+    {
+        let item = groups.entry(group.clone()).or_insert_with(|| blank(group));
+        item["global_rps"] = json!(limit.global_rps);
+        item["route_rps"] = json!(limit.route_rps);
+    }
+    page_response(groups.into_values().collect(), &query, "group_id")
+}
+
+/// `PUT /admin/groups/{group_id}/pause`: denies the group's permits with
+/// `group_paused` for `duration_ms`. Replaces an existing pause.
+pub(crate) async fn pause(
+    State(state): State<Arc<AppState>>,
+    Path(group_id): Path<String>,
+    payload: Result<Json<PauseRequest>, JsonRejection>,
+) -> Response {
+    let Json(request) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return crate::json_rejection_response(&state, rejection),
+    };
+    let group = match normalized_group(&group_id) {
+        Ok(group) => group,
+        Err(error) => return validation_failed_response(vec![error]),
+    };
+    if request.duration_ms == 0 || request.duration_ms > MAX_PAUSE_MS {
+        return validation_failed_response(vec![FieldError {
+            field: "duration_ms",
+            message: format!("must be 1-{MAX_PAUSE_MS}"),
+        }]);
+    }
+    let now = unix_ms();
+    let pause = Pause {
+        until_unix_ms: now + request.duration_ms,
+        reason: request.reason,
+        created_unix_ms: now,
+    };
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let stored: redis::RedisResult<()> = conn
+        .hset(
+            PAUSES_KEY,
+            &group,
+            serde_json::to_string(&pause).unwrap_or_default(),
+        )
+        .await;
+    if stored.is_err() {
+        return crate::backend_unavailable_response(&state);
+    }
+    let _ = refresh(&state).await;
+    let mut body = json!(pause);
+    body["group_id"] = json!(group);
+    Json(body).into_response()
+}
+
+/// `DELETE /admin/groups/{group_id}/pause`: resumes a paused group.
+pub(crate) async fn resume(
+    State(state): State<Arc<AppState>>,
+    Path(group_id): Path<String>,
+) -> Response {
+    clear(&state, PAUSES_KEY, &group_id, "Group not paused").await
+}
+
+/// `PUT /admin/groups/{group_id}/limits`: overrides the group's global
+/// and/or route rps. Replaces an existing override.
+pub(crate) async fn set_limits(
+    State(state): State<Arc<AppState>>,
+    Path(group_id): Path<String>,
+    payload: Result<Json<LimitsRequest>, JsonRejection>,
+) -> Response {
+    let Json(request) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return crate::json_rejection_response(&state, rejection),
+    };
+    let group = match normalized_group(&group_id) {
+        Ok(group) => group,
+        Err(error) => return validation_failed_response(vec![error]),
+    };
+    let mut errors = Vec::new();
+    for (field, value) in [
+        ("global_rps", request.global_rps),
+        ("route_rps", request.route_rps),
+    ] {
+        if value == Some(0) {
+            errors.push(FieldError {
+                field,
+                message: "must be at least 1".to_string(),
+            });
+        }
+    }
+    if request.global_rps.is_none() && request.route_rps.is_none() {
+        errors.push(FieldError {
+            field: "global_rps",
+            message: "set global_rps, route_rps or both".to_string(),
+        });
+    }
+    if !errors.is_empty() {
+        return validation_failed_response(errors);
+    }
+    let limit = LimitOverride {
+        global_rps: request.global_rps,
+        route_rps: request.route_rps,
+        updated_unix_ms: unix_ms(),
+    };
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let stored: redis::RedisResult<()> = conn
+        .hset(
+            LIMITS_KEY,
+            &group,
+            serde_json::to_string(&limit).unwrap_or_default(),
+        )
+        .await;
+    if stored.is_err() {
+        return crate::backend_unavailable_response(&state);
+    }
+    let _ = refresh(&state).await;
+    let mut body = json!(limit);
+    body["group_id"] = json!(group);
+    Json(body).into_response()
+}
+
+/// `DELETE /admin/groups/{group_id}/limits`: returns the group to its
+/// configured limits.
+pub(crate) async fn clear_limits(
+    State(state): State<Arc<AppState>>,
+    Path(group_id): Path<String>,
+) -> Response {
+    clear(&state, LIMITS_KEY, &group_id, "No limit override").await
+}
+
+async fn clear(state: &AppState, key: &str, group_id: &str, title: &str) -> Response {
+    let group = normalize_key_part(group_id);
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(state);
+    };
+    match conn.hdel::<_, _, u64>(key, &group).await {
+        Ok(0) => problem_response(
+            StatusCode::NOT_FOUND,
+            PROBLEM_TYPE_NOT_FOUND,
+            title,
+            format!("no control set for group {group}"),
+            json!({}),
+        ),
+        Ok(_) => {
+            let _ = refresh(state).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => crate::backend_unavailable_response(state),
+    }
+}
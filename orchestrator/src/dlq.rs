@@ -0,0 +1,297 @@
+//! Dead-letter handling. Reports whose Redis writes fail are kept in memory and
+//! retried in the background; reports that keep failing and jobs that end in
+//! failure are appended to the `rl:dlq` stream, where operators can list,
+//! retry or purge them.
+
+use crate::{
+    admin::{page_response, read_stream, ListQuery},
+    jobs::{self, JobSpec},
+    persist_report, problem_response, unix_ms, AppState, ReportResultRequest,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use redis::AsyncCommands;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::sleep;
+
+pub(crate) const DLQ_STREAM_KEY: &str = "rl:dlq";
+const PROBLEM_TYPE_NOT_FOUND: &str = "urn:dmbo:problem:not-found";
+const RETRY_INTERVAL_MS: u64 = 1000;
+
+struct DeferredReport {
+    report: ReportResultRequest,
+    attempts: u32,
+}
+
+/// Bounded in-memory buffer of reports that could not be persisted. The
+/// oldest entry is dropped (and counted) when the buffer is full.
+#[derive(Clone, Default)]
+pub(crate) struct DeferredReports {
+    entries: Arc<Mutex<VecDeque<DeferredReport>>>,
+}
+
+impl DeferredReports {
+    pub(crate) fn defer(&self, state: &AppState, report: ReportResultRequest) {
+        self.push(
+            state,
+            DeferredReport {
+                report,
+                attempts: 1,
+            },
+        );
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .expect("deferred reports poisoned")
+            .len()
+    }
+
+    fn push(&self, state: &AppState, entry: DeferredReport) {
+        let mut entries = self.entries.lock().expect("deferred reports poisoned");
+        while entries.len() >= state.config.dlq_buffer_capacity.max(1) {
+            entries.pop_front();
+            state
+                .metrics
+                .deferred_reports_dropped
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        entries.push_back(entry);
+    }
+
+    fn take_all(&self) -> Vec<DeferredReport> {
+        self.entries
+            .lock()
+            .expect("deferred reports poisoned")
+            .drain(..)
+            .collect()
+    }
+}
+
+/// Retries deferred reports once a second. A report that still fails after
+/// `DMBO_DLQ_REPORT_ATTEMPTS` writes is moved to the dead-letter stream; if
+/// that write fails too it stays buffered until Redis is reachable again.
+pub(crate) async fn retry_deferred_reports(state: Arc<AppState>) {
+    loop {
+        sleep(Duration::from_millis(RETRY_INTERVAL_MS)).await;
+        for mut entry in state.deferred_reports.take_all() {
+            if persist_report(&state, &entry.report).await.is_ok() {
+                continue;
+            }
+            entry.attempts += 1;
+            if entry.attempts >= state.config.dlq_report_attempts {
+                let payload = serde_json::to_string(&entry.report).unwrap_or_default();
+                let written = append(
+                    &state,
+                    "report",
+                    &entry.report.request_id,
+                    &payload,
+                    crate::Reason::ReportNotPersisted.code(),
+                    entry.attempts,
+                )
+                .await;
+                if written {
+                    state
+                        .metrics
+                        .dead_lettered_reports
+                        .fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            }
+            state.deferred_reports.push(&state, entry);
+        }
+    }
+}
+
+pub(crate) async fn dead_letter_job(
+    state: &AppState,
+    job_id: &str,
+    spec: &JobSpec,
+    error: &str,
+    attempts: u32,
+) {
+    let payload = serde_json::to_string(spec).unwrap_or_default();
+    if append(state, "job", job_id, &payload, error, attempts).await {
+        state
+            .metrics
+            .dead_lettered_jobs
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+async fn append(
+    state: &AppState,
+    kind: &str,
+    item_id: &str,
+    payload: &str,
+    error: &str,
+    attempts: u32,
+) -> bool {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+        return false;
+    };
+    let result: redis::RedisResult<String> = redis::cmd("XADD")
+        .arg(DLQ_STREAM_KEY)
+        .arg("MAXLEN")
+        .arg("~")
+        .arg(state.config.dlq_maxlen)
+        .arg("*")
+        .arg("kind")
+        .arg(kind)
+        .arg("item_id")
+        .arg(item_id)
+        .arg("error")
+        .arg(error)
+        .arg("attempts")
+        .arg(attempts)
+        .arg("failed_unix_ms")
+        .arg(unix_ms())
+        .arg("payload")
+        .arg(payload)
+        .query_async(&mut conn)
+        .await;
+    if result.is_err() {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    true
+}
+
+pub(crate) async fn list(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    let mut items = match read_stream(&state, DLQ_STREAM_KEY).await {
+        Ok(items) => items,
+        Err(response) => return response,
+    };
+    for item in &mut items {
+        if let Some(payload) = item.get_mut("payload") {
+            if let Some(parsed) = payload
+                .as_str()
+                .and_then(|text| serde_json::from_str::<Value>(text).ok())
+            {
+                *payload = parsed;
+            }
+        }
+    }
+    page_response(items, &query, "-id")
+}
+
+/// Replays one dead-lettered item: reports are written again, jobs are
+/// resubmitted under a new job id. The entry is removed on success.
+pub(crate) async fn retry(
+    State(state): State<Arc<AppState>>,
+    Path(entry_id): Path<String>,
+) -> Response {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let entries: redis::RedisResult<Vec<(String, HashMap<String, String>)>> = redis::cmd("XRANGE")
+        .arg(DLQ_STREAM_KEY)
+        .arg(&entry_id)
+        .arg(&entry_id)
+        .query_async(&mut conn)
+        .await;
+    let Ok(entries) = entries else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let Some((_, fields)) = entries.into_iter().next() else {
+        return not_found(&entry_id);
+    };
+    let payload = fields.get("payload").map(String::as_str).unwrap_or("");
+
+    let response = match fields.get("kind").map(String::as_str) {
+        Some("report") => {
+            let Ok(report) = serde_json::from_str::<ReportResultRequest>(payload) else {
+                return unreadable(&entry_id);
+            };
+            if persist_report(&state, &report).await.is_err() {
+                return crate::backend_unavailable_response(&state);
+            }
+            Json(json!({ "ok": true, "kind": "report" })).into_response()
+        }
+        Some("job") => {
+            let Ok(spec) = serde_json::from_str::<JobSpec>(payload) else {
+                return unreadable(&entry_id);
+            };
+            match jobs::enqueue_job(&state, spec).await {
+                Some(job_id) => jobs::accepted_response(&job_id),
+                None => return crate::backend_unavailable_response(&state),
+            }
+        }
+        _ => return unreadable(&entry_id),
+    };
+    let _: redis::RedisResult<i64> = conn.xdel(DLQ_STREAM_KEY, &[&entry_id]).await;
+    response
+}
+
+pub(crate) async fn remove(
+    State(state): State<Arc<AppState>>,
+    Path(entry_id): Path<String>,
+) -> Response {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let removed: redis::RedisResult<i64> = conn.xdel(DLQ_STREAM_KEY, &[&entry_id]).await;
+    match removed {
+        Ok(0) => not_found(&entry_id),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => crate::backend_unavailable_response(&state),
+    }
+}
+
+pub(crate) async fn purge(State(state): State<Arc<AppState>>) -> Response {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let purged: redis::RedisResult<(u64, ())> = redis::pipe()
+        .atomic()
+        .cmd("XLEN")
+        .arg(DLQ_STREAM_KEY)
+        .del(DLQ_STREAM_KEY)
+        .ignore()
+        .query_async(&mut conn)
+        .await;
+    match purged {
+        Ok((count, ())) => Json(json!({ "purged": count })).into_response(),
+        Err(_) => crate::backend_unavailable_response(&state),
+    }
+}
+
+fn not_found(entry_id: &str) -> Response {
+    problem_response(
+        StatusCode::NOT_FOUND,
+        PROBLEM_TYPE_NOT_FOUND,
+        "Dead-letter entry not found",
+        format!("no dead-letter entry {entry_id}"),
+        json!({}),
+    )
+}
+
+fn unreadable(entry_id: &str) -> Response {
+    problem_response(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        crate::PROBLEM_TYPE_INVALID_REQUEST,
+        "Dead-letter entry cannot be retried",
+        format!("entry {entry_id} has an unknown kind or unreadable payload"),
+        json!({}),
+    )
+}
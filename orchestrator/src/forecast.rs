@@ -0,0 +1,141 @@
+//! Bucket exhaustion forecasting. Every grant updates an EWMA of the grant
+//! rate for its bucket; combined with the bucket's remaining capacity (the
+//! orchestrator's per-second route window and, when reports have taught us
+//! one, the Discord bucket) this predicts whether and when the bucket runs
+//! dry before it resets. The most constraining forecast is attached to grant
+//! responses so clients can smooth submissions before hitting the wall.
+
+use crate::unix_ms;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Weight of the newest inter-grant interval in the rate estimate.
+const RATE_ALPHA: f64 = 0.2;
+/// Forecasts are kept for metrics only while their bucket is this fresh.
+const FORECAST_RETENTION_MS: u64 = 10_000;
+
+/// Capacity figures returned by the permit script for a granted request.
+pub(crate) struct BucketState {
+    pub(crate) route_limit: u64,
+    pub(crate) route_remaining: u64,
+    pub(crate) window_resets_in_ms: u64,
+    pub(crate) observed_limit: Option<u64>,
+    pub(crate) observed_remaining: Option<u64>,
+    pub(crate) observed_resets_in_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub(crate) struct Forecast {
+    /// `window` (orchestrator per-second limit) or `discord` (bucket learned
+    /// from reported `X-RateLimit-*` headers).
+    pub(crate) source: &'static str,
+    pub(crate) limit: u64,
+    pub(crate) remaining: u64,
+    pub(crate) resets_in_ms: u64,
+    pub(crate) grant_rate_per_s: f64,
+    /// Predicted time until `remaining` reaches zero at the current rate;
+    /// absent when the bucket is expected to reset first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) exhausts_in_ms: Option<u64>,
+}
+
+struct Rate {
+    per_s: f64,
+    last_unix_ms: u64,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Forecaster {
+    rates: Arc<Mutex<HashMap<String, Rate>>>,
+    latest: Arc<Mutex<HashMap<String, (Forecast, u64)>>>,
+}
+
+impl Forecaster {
+    /// Records a grant on `bucket` and returns its most constraining forecast.
+    pub(crate) fn on_grant(&self, bucket: &str, state: &BucketState) -> Forecast {
+        let now = unix_ms();
+        let per_s = {
+            let mut rates = self.rates.lock().expect("forecast rates poisoned");
+            rates.retain(|_, rate| now.saturating_sub(rate.last_unix_ms) < FORECAST_RETENTION_MS);
+            let rate = rates.entry(bucket.to_string()).or_insert(Rate {
+                per_s: 0.0,
+                last_unix_ms: now,
+            });
+            let interval_ms = now.saturating_sub(rate.last_unix_ms).max(1) as f64;
+            if rate.per_s == 0.0 {
+                rate.per_s = 1.0;
+            } else {
+                rate.per_s = RATE_ALPHA * (1000.0 / interval_ms) + (1.0 - RATE_ALPHA) * rate.per_s;
+            }
+            rate.last_unix_ms = now;
+            rate.per_s
+        };
+
+        let mut forecast = project(
+            "window",
+            state.route_limit,
+            state.route_remaining,
+            state.window_resets_in_ms,
+            per_s,
+        );
+        if let (Some(limit), Some(remaining), Some(resets_in_ms)) = (
+            state.observed_limit,
+            state.observed_remaining,
+            state.observed_resets_in_ms,
+        ) {
+            let discord = project("discord", limit, remaining, resets_in_ms, per_s);
+            if sooner(&discord, &forecast) {
+                forecast = discord;
+            }
+        }
+
+        let mut latest = self.latest.lock().expect("forecast latest poisoned");
+        latest.insert(bucket.to_string(), (forecast.clone(), now));
+        forecast
+    }
+
+    /// Buckets forecast to exhaust before they reset, and the soonest
+    /// predicted exhaustion, over recently active buckets.
+    pub(crate) fn summary(&self) -> (u64, Option<u64>) {
+        let now = unix_ms();
+        let mut latest = self.latest.lock().expect("forecast latest poisoned");
+        latest.retain(|_, (_, at)| now.saturating_sub(*at) < FORECAST_RETENTION_MS);
+        let exhausting: Vec<u64> = latest
+            .values()
+            .filter_map(|(forecast, _)| forecast.exhausts_in_ms)
+            .collect();
+        (exhausting.len() as u64, exhausting.into_iter().min())
+    }
+}
+
+fn project(
+    source: &'static str,
+    limit: u64,
+    remaining: u64,
+    resets_in_ms: u64,
+    per_s: f64,
+) -> Forecast {
+    let exhausts_in_ms = (per_s > 0.0)
+        .then(|| (remaining as f64 / per_s * 1000.0) as u64)
+        .filter(|ms| *ms < resets_in_ms);
+    Forecast {
+        source,
+        limit,
+        remaining,
+        resets_in_ms,
+        grant_rate_per_s: (per_s * 100.0).round() / 100.0,
+        exhausts_in_ms,
+    }
+}
+
+fn sooner(a: &Forecast, b: &Forecast) -> bool {
+    match (a.exhausts_in_ms, b.exhausts_in_ms) {
+        (Some(a), Some(b)) => a < b,
+        (Some(_), None) => true,
+        (None, Some(_)) => false,
+        (None, None) => a.remaining < b.remaining,
+    }
+}
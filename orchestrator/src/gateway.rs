@@ -0,0 +1,120 @@
+//! Gateway command pacing. Discord limits each gateway connection (shard) to
+//! 120 sent events per 60 seconds, and presence updates to 5 per 60 seconds
+//! within that. Permits requested with `transport: "gateway"` name the
+//! command as `route` and the shard as `shard_id`, and are paced by sliding
+//! logs per identity and shard instead of the per-second REST limiter.
+
+use crate::{
+    long_limits, normalize_key_part, AppState, Config, FieldError, PermitDecision, Reason,
+    RequestTokenRequest,
+};
+use redis::aio::MultiplexedConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::atomic::Ordering;
+
+/// Gateway limits reset on a rolling minute.
+const WINDOW_MS: u64 = 60_000;
+
+/// Commands a bot sends over the gateway (send opcodes).
+const COMMANDS: [&str; 7] = [
+    "heartbeat",
+    "identify",
+    "presence_update",
+    "voice_state_update",
+    "resume",
+    "request_guild_members",
+    "request_soundboard_sounds",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Transport {
+    #[default]
+    Http,
+    Gateway,
+}
+
+/// Normalizes a gateway request before validation: commands are
+/// case-insensitive, the method is a fixed `GATEWAY`, and the shard stands in
+/// for the major parameter so usage and audit records are per shard.
+pub(crate) fn normalize(request: &mut RequestTokenRequest) {
+    request.method = "GATEWAY".to_string();
+    request.route = request.route.trim().to_ascii_lowercase();
+    request.major_parameter = request.shard_id.to_string();
+}
+
+pub(crate) fn validate(request: &RequestTokenRequest) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if !COMMANDS.contains(&request.route.as_str()) {
+        errors.push(FieldError {
+            field: "route",
+            message: format!("must be a gateway command: {}", COMMANDS.join(", ")),
+        });
+    }
+    if request.discord_identity.trim().is_empty() {
+        errors.push(FieldError {
+            field: "discord_identity",
+            message: "must not be empty".to_string(),
+        });
+    }
+    errors
+}
+
+/// Gateway limits for `/policy`.
+pub(crate) fn describe(config: &Config) -> Value {
+    json!({
+        "window_ms": WINDOW_MS,
+        "command_limit": config.gateway_command_limit,
+        "presence_limit": config.gateway_presence_limit,
+        "identify_interval_ms": config.identify_interval_ms,
+    })
+}
+
+/// Reserves the command's slots on its shard: one in the command log and,
+/// for presence updates, one in the presence log.
+pub(crate) async fn acquire(
+    state: &AppState,
+    conn: &mut MultiplexedConnection,
+    request: &RequestTokenRequest,
+    now_ms: u64,
+) -> redis::RedisResult<PermitDecision> {
+    let shard = format!(
+        "{}:{}",
+        normalize_key_part(&request.discord_identity),
+        request.shard_id
+    );
+    let config = &state.config;
+    let mut keys = vec![format!("rl:gateway:commands:{shard}")];
+    let mut windows = vec![(config.gateway_command_limit, WINDOW_MS)];
+    if request.route == "presence_update" {
+        keys.push(format!("rl:gateway:presence:{shard}"));
+        windows.push((config.gateway_presence_limit, WINDOW_MS));
+    }
+    let metrics = &state.metrics;
+    let (granted, retry_after_ms, reason) =
+        match long_limits::reserve(conn, keys, &windows, now_ms).await? {
+            Ok(_) => (true, 0, Reason::Ok),
+            Err((0, retry_after_ms)) => {
+                metrics
+                    .gateway_command_denials
+                    .fetch_add(1, Ordering::Relaxed);
+                (false, retry_after_ms, Reason::GatewayCommandsExhausted)
+            }
+            Err((_, retry_after_ms)) => {
+                metrics
+                    .gateway_presence_denials
+                    .fetch_add(1, Ordering::Relaxed);
+                (false, retry_after_ms, Reason::PresenceUpdatesExhausted)
+            }
+        };
+    Ok(PermitDecision {
+        granted,
+        retry_after_ms,
+        reason,
+        errored: false,
+        forecast: None,
+        long_limit: None,
+        not_before_unix_ms: None,
+    })
+}
@@ -0,0 +1,159 @@
+//! Gateway IDENTIFY coordination. Discord lets a bot start one gateway
+//! session per 5 seconds in each of its `max_concurrency` buckets, shard
+//! `shard_id % max_concurrency` using its bucket; identifying sooner gets the
+//! session invalidated. Shards of one bot usually run in several processes,
+//! so `POST /request_identify` queues them on a shared schedule per bot and
+//! bucket: each call takes the next slot, `DMBO_IDENTIFY_INTERVAL_MS` after
+//! the last, and is told to identify at `not_before_unix_ms`. A slot further
+//! away than the caller's `max_wait_ms` is not taken.
+
+use crate::{
+    normalize_key_part, problem_response, reactions, unix_ms, validation_failed_response, AppState,
+    FieldError, Reason, PROBLEM_TYPE_RATE_LIMITED,
+};
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{atomic::Ordering, Arc};
+
+/// Largest `max_concurrency` accepted; Discord hands out powers of two well
+/// below this.
+const MAX_CONCURRENCY: u64 = 1024;
+
+const PROBLEM_TYPE_NOT_FOUND: &str = "urn:dmbo:problem:not-found";
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct IdentifyRequest {
+    #[serde(default)]
+    discord_identity: String,
+    #[serde(default)]
+    shard_id: u64,
+    /// `session_start_limit.max_concurrency` from `GET /gateway/bot`.
+    #[serde(default = "default_max_concurrency")]
+    max_concurrency: u64,
+    #[serde(default)]
+    max_wait_ms: u64,
+}
+
+fn default_max_concurrency() -> u64 {
+    1
+}
+
+fn validate(request: &IdentifyRequest) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if request.discord_identity.trim().is_empty() {
+        errors.push(FieldError {
+            field: "discord_identity",
+            message: "must not be empty".to_string(),
+        });
+    }
+    if !(1..=MAX_CONCURRENCY).contains(&request.max_concurrency) {
+        errors.push(FieldError {
+            field: "max_concurrency",
+            message: format!("must be 1-{MAX_CONCURRENCY}"),
+        });
+    }
+    errors
+}
+
+/// `POST /request_identify`: schedules one shard's IDENTIFY.
+#[utoipa::path(
+    post,
+    path = "/request_identify",
+    request_body = IdentifyRequest,
+    responses(
+        (status = 200, description = "Start booked", body = crate::openapi::IdentifyResponse),
+        (status = 400, description = "Invalid body", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Not available with DMBO_STORE=memory", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 429, description = "No start within max_wait_ms", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Backend unavailable", body = crate::openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
+pub(crate) async fn request_identify(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<IdentifyRequest>, JsonRejection>,
+) -> Response {
+    // The schedule is shared through Redis.
+    if state.config.memory_store {
+        return problem_response(
+            StatusCode::NOT_FOUND,
+            PROBLEM_TYPE_NOT_FOUND,
+            "Identify scheduling disabled",
+            "/request_identify needs DMBO_STORE=redis".to_string(),
+            json!({}),
+        );
+    }
+    let Json(request) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return crate::json_rejection_response(&state, rejection),
+    };
+    let errors = validate(&request);
+    if !errors.is_empty() {
+        return validation_failed_response(errors);
+    }
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let bucket = request.shard_id % request.max_concurrency;
+    let key = format!(
+        "rl:identify:{}:{bucket}",
+        normalize_key_part(&request.discord_identity)
+    );
+    let now = unix_ms();
+    let max_wait_ms = request.max_wait_ms.min(state.config.max_wait_cap_ms);
+    let scheduled = reactions::schedule(
+        &mut conn,
+        &key,
+        now,
+        state.config.identify_interval_ms,
+        max_wait_ms,
+    )
+    .await;
+    match scheduled {
+        Ok(Ok(not_before_unix_ms)) => {
+            state
+                .metrics
+                .identify_grants
+                .fetch_add(1, Ordering::Relaxed);
+            Json(json!({
+                "granted": true,
+                "bucket": bucket,
+                "not_before_unix_ms": not_before_unix_ms,
+                "server_unix_ms": now,
+            }))
+            .into_response()
+        }
+        Ok(Err(retry_after_ms)) => {
+            state
+                .metrics
+                .identify_denials
+                .fetch_add(1, Ordering::Relaxed);
+            let reason = Reason::IdentifyExhausted;
+            let mut response = problem_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                PROBLEM_TYPE_RATE_LIMITED,
+                "Permit denied",
+                format!("{}; retry after {retry_after_ms}ms", reason.message()),
+                json!({
+                    "granted": false,
+                    "bucket": bucket,
+                    "retry_after_ms": retry_after_ms,
+                    "reason": reason,
+                    "reason_message": reason.message(),
+                    "server_unix_ms": now,
+                }),
+            );
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after_ms.div_ceil(1000).max(1)),
+            );
+            response
+        }
+        Err(_) => crate::backend_unavailable_response(&state),
+    }
+}
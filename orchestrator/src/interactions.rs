@@ -0,0 +1,49 @@
+//! Deadline-aware scheduling for interaction responses. Discord drops an
+//! interaction that is not answered within 3 seconds, so callbacks (and any
+//! request carrying `interaction_deadline_unix_ms`) are raised to `high`
+//! priority, never wait past their deadline, and take precedence over other
+//! traffic on the same identity: while one is waiting for capacity, requests
+//! without an earlier deadline yield to it, and interactions are served in
+//! deadline order.
+
+use crate::{rules::route_matches, waiters::Waiters, Config, RequestTokenRequest};
+
+/// Initial responses, the calls the 3-second deadline applies to.
+const CALLBACK_ROUTE: &str = "/interactions/:interaction_id/:interaction_token/callback";
+
+/// Priority given to interaction requests left at the default.
+const BOOST_PRIORITY: &str = "high";
+
+/// Marks interaction traffic: a callback without an explicit deadline gets
+/// one `DMBO_INTERACTION_DEADLINE_MS` from now, and requests left at the
+/// default priority are raised to `high`. Runs before the admission policy,
+/// so rules and plugins can still change the priority.
+pub(crate) fn boost(config: &Config, request: &mut RequestTokenRequest, now_ms: u64) {
+    if request.interaction_deadline_unix_ms.is_none()
+        && route_matches(CALLBACK_ROUTE, &request.route)
+    {
+        request.interaction_deadline_unix_ms =
+            Some(now_ms.saturating_add(config.interaction_deadline_ms));
+    }
+    if request.interaction_deadline_unix_ms.is_some() && request.priority == "normal" {
+        request.priority = BOOST_PRIORITY.to_string();
+    }
+}
+
+/// Whether `request` should hold back because an interaction on the same
+/// identity, due before its own deadline (if any), is waiting for capacity.
+/// `own_waiter` excludes the request's own registry entry.
+pub(crate) fn must_yield(
+    waiters: &Waiters,
+    request: &RequestTokenRequest,
+    own_waiter: Option<u64>,
+) -> bool {
+    let due = request.interaction_deadline_unix_ms.unwrap_or(u64::MAX);
+    waiters.any(|waiter| {
+        Some(waiter.id) != own_waiter
+            && waiter.discord_identity == request.discord_identity
+            && waiter
+                .interaction_deadline_unix_ms
+                .is_some_and(|other| other < due)
+    })
+}
@@ -0,0 +1,765 @@
+//! Built-in send scheduler. Clients submit a complete Discord request; the
+//! orchestrator waits for the earliest execute time, acquires the permit
+//! itself, performs the call and stores the outcome for polling or delivers
+//! it to a callback URL.
+//!
+//! Jobs are queued durably in Redis: future jobs wait in the
+//! `rl:jobs:delayed` sorted set until they are due, then move to the
+//! `rl:jobs:queue` stream, which every replica reads through one consumer
+//! group. Entries left pending by a crashed consumer are reclaimed after
+//! `DMBO_JOB_CLAIM_IDLE_MS`; running jobs refresh their claim so they are not
+//! taken over while still waiting for a permit.
+
+use crate::{
+    admission_policy, callbacks, default_group_id, default_priority, dlq, interactions,
+    issue_permit, normalize_key_part, problem_response, record_report, routes, unix_ms, upstream,
+    upstream::RetryBudget, validate_request, validation_failed_response, AppState, FieldError,
+    Reason, ReportResultRequest, RequestTokenRequest,
+};
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use redis::{AsyncCommands, Script};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::atomic::Ordering, sync::Arc, time::Duration};
+use tokio::time::{interval, sleep};
+
+const PROBLEM_TYPE_NOT_FOUND: &str = "urn:dmbo:problem:not-found";
+const JOB_QUEUE_KEY: &str = "rl:jobs:queue";
+const JOB_DELAYED_KEY: &str = "rl:jobs:delayed";
+const JOB_CONSUMER_GROUP: &str = "dmbo-jobs";
+const QUEUE_BLOCK_MS: u64 = 5000;
+const PROMOTE_INTERVAL_MS: u64 = 250;
+const PROMOTE_BATCH: u64 = 100;
+
+/// Moves due job ids from the delayed set to the queue stream. Runs as one
+/// script so concurrent replicas never promote the same job twice.
+const PROMOTE_DUE_LUA: &str = r#"
+local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[2])
+for _, job_id in ipairs(due) do
+  redis.call('ZREM', KEYS[1], job_id)
+  redis.call('XADD', KEYS[2], '*', 'job_id', job_id)
+end
+return #due
+"#;
+/// Upstream response bodies larger than this are truncated in job results.
+const MAX_STORED_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub(crate) struct JobSpec {
+    #[serde(default)]
+    pub(crate) client_id: String,
+    #[serde(default = "default_group_id")]
+    pub(crate) group_id: String,
+    pub(crate) discord_identity: String,
+    pub(crate) method: String,
+    /// Concrete path or full Discord URL to call.
+    pub(crate) route: String,
+    #[serde(default)]
+    pub(crate) major_parameter: String,
+    /// JSON body sent to Discord, if any.
+    #[serde(default)]
+    pub(crate) body: Option<Value>,
+    /// Name of a bot token configured via `DMBO_TOKEN_<NAME>`.
+    pub(crate) token_ref: String,
+    #[serde(default = "default_priority")]
+    pub(crate) priority: String,
+    /// Do not execute before this time.
+    #[serde(default)]
+    pub(crate) not_before_unix_ms: Option<u64>,
+    /// Give up if the call could not be made by this time.
+    #[serde(default)]
+    pub(crate) expires_unix_ms: Option<u64>,
+    /// Receives the finished job as a JSON `POST`.
+    #[serde(default)]
+    pub(crate) callback_url: Option<String>,
+    /// Bot feature the call is made for; one of `DMBO_FEATURES`.
+    #[serde(default)]
+    pub(crate) feature: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub(crate) struct JobResult {
+    pub(crate) status_code: u16,
+    pub(crate) headers: HashMap<String, String>,
+    pub(crate) body: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs",
+    request_body = JobSpec,
+    responses(
+        (status = 202, description = "Job queued", body = crate::openapi::JobAccepted),
+        (status = 400, description = "Invalid body", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Backend unavailable", body = crate::openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
+pub(crate) async fn submit_job(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<JobSpec>, JsonRejection>,
+) -> Response {
+    let Json(mut spec) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return crate::json_rejection_response(&state, rejection),
+    };
+    spec.method = spec.method.trim().to_ascii_uppercase();
+    let permit_request = permit_request_for(&spec, String::new());
+    let mut errors = validate_request(&state.config, &permit_request);
+    if !state
+        .config
+        .discord_tokens
+        .contains_key(&spec.token_ref.to_ascii_uppercase())
+    {
+        errors.push(FieldError {
+            field: "token_ref",
+            message: "no token configured under this name (DMBO_TOKEN_<NAME>)".to_string(),
+        });
+    }
+    if spec.route.contains("/:") {
+        errors.push(FieldError {
+            field: "route",
+            message: "must be a concrete path or URL, not a template".to_string(),
+        });
+    }
+    if let Some(Err(message)) = spec
+        .callback_url
+        .as_deref()
+        .map(|url| callbacks::check_url(&state.config, url))
+    {
+        errors.push(FieldError {
+            field: "callback_url",
+            message,
+        });
+    }
+    if !errors.is_empty() {
+        return validation_failed_response(errors);
+    }
+
+    match enqueue_job(&state, spec).await {
+        Some(job_id) => accepted_response(&job_id),
+        None => crate::backend_unavailable_response(&state),
+    }
+}
+
+/// Stores a validated job and queues it, in the delayed set when
+/// `not_before_unix_ms` is in the future. Returns `None` when the job could
+/// not be written to Redis.
+pub(crate) async fn enqueue_job(state: &Arc<AppState>, spec: JobSpec) -> Option<String> {
+    let job_id = uuid::Uuid::new_v4().to_string();
+    let now = unix_ms();
+    let delay_ms = spec
+        .not_before_unix_ms
+        .map_or(0, |not_before| not_before.saturating_sub(now));
+    let key = job_key(&job_id);
+    let fields = [
+        ("spec", serde_json::to_string(&spec).unwrap_or_default()),
+        ("status", "queued".to_string()),
+        ("attempts", "0".to_string()),
+        ("created_unix_ms", now.to_string()),
+        ("updated_unix_ms", now.to_string()),
+    ];
+
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .hset_multiple(&key, &fields)
+        .ignore()
+        .expire(
+            &key,
+            (state.config.job_ttl_seconds + delay_ms / 1000) as i64,
+        )
+        .ignore();
+    if delay_ms > 0 {
+        pipe.zadd(JOB_DELAYED_KEY, &job_id, now + delay_ms).ignore();
+    } else {
+        pipe.cmd("XADD")
+            .arg(JOB_QUEUE_KEY)
+            .arg("*")
+            .arg("job_id")
+            .arg(&job_id)
+            .ignore();
+    }
+    let stored: redis::RedisResult<()> = match state.redis.get_multiplexed_async_connection().await
+    {
+        Ok(mut conn) => pipe.query_async(&mut conn).await,
+        Err(error) => Err(error),
+    };
+    if stored.is_err() {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+        return None;
+    }
+    state.metrics.jobs_submitted.fetch_add(1, Ordering::Relaxed);
+    Some(job_id)
+}
+
+/// Long-running job consumer for this replica. Takes a concurrency slot,
+/// reclaims one stale pending entry or reads one new entry, and runs it on
+/// its own task. Redis errors back off for a second and retry.
+pub(crate) async fn consume_jobs(state: Arc<AppState>) {
+    tokio::spawn(promote_due_jobs(state.clone()));
+    let mut group_ready = false;
+    loop {
+        let Ok(slot) = state.job_slots.clone().acquire_owned().await else {
+            return;
+        };
+        let next = async {
+            let mut conn = state.redis.get_multiplexed_async_connection().await?;
+            if !group_ready {
+                create_group(&mut conn).await?;
+                group_ready = true;
+            }
+            if let Some(entry) = claim_stale(&state, &mut conn).await? {
+                state.metrics.jobs_reclaimed.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(entry));
+            }
+            read_new(&state, &mut conn).await
+        }
+        .await;
+        match next {
+            Ok(Some((entry_id, job_id))) => {
+                let task_state = state.clone();
+                tokio::spawn(async move {
+                    process_entry(&task_state, &entry_id, &job_id).await;
+                    drop(slot);
+                });
+            }
+            Ok(None) => {}
+            Err(_) => {
+                state
+                    .metrics
+                    .redis_errors_total
+                    .fetch_add(1, Ordering::Relaxed);
+                // The stream may have been deleted; recreate the group.
+                group_ready = false;
+                sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+async fn promote_due_jobs(state: Arc<AppState>) {
+    let script = Script::new(PROMOTE_DUE_LUA);
+    let mut ticker = interval(Duration::from_millis(PROMOTE_INTERVAL_MS));
+    loop {
+        ticker.tick().await;
+        let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+            continue;
+        };
+        let _: redis::RedisResult<u64> = script
+            .key(JOB_DELAYED_KEY)
+            .key(JOB_QUEUE_KEY)
+            .arg(unix_ms())
+            .arg(PROMOTE_BATCH)
+            .invoke_async(&mut conn)
+            .await;
+    }
+}
+
+async fn create_group(conn: &mut redis::aio::MultiplexedConnection) -> redis::RedisResult<()> {
+    let created: redis::RedisResult<()> = redis::cmd("XGROUP")
+        .arg("CREATE")
+        .arg(JOB_QUEUE_KEY)
+        .arg(JOB_CONSUMER_GROUP)
+        .arg("0")
+        .arg("MKSTREAM")
+        .query_async(conn)
+        .await;
+    match created {
+        Err(error) if error.code() == Some("BUSYGROUP") => Ok(()),
+        other => other,
+    }
+}
+
+type QueueEntry = (String, String);
+type StreamEntry = (String, HashMap<String, String>);
+
+/// Takes over one entry another consumer has held longer than
+/// `DMBO_JOB_CLAIM_IDLE_MS`. Claimed entries whose stream data was deleted
+/// (Redis 6.2 returns them with nil fields) are acknowledged and skipped.
+async fn claim_stale(
+    state: &AppState,
+    conn: &mut redis::aio::MultiplexedConnection,
+) -> redis::RedisResult<Option<QueueEntry>> {
+    let reply: Vec<redis::Value> = redis::cmd("XAUTOCLAIM")
+        .arg(JOB_QUEUE_KEY)
+        .arg(JOB_CONSUMER_GROUP)
+        .arg(&state.config.instance_id)
+        .arg(state.config.job_claim_idle_ms)
+        .arg("0-0")
+        .arg("COUNT")
+        .arg(1)
+        .query_async(conn)
+        .await?;
+    let Some(entries) = reply.get(1) else {
+        return Ok(None);
+    };
+    let entries: Vec<(String, Option<HashMap<String, String>>)> = redis::from_redis_value(entries)?;
+    for (entry_id, fields) in entries {
+        match fields.and_then(|mut fields| fields.remove("job_id")) {
+            Some(job_id) => return Ok(Some((entry_id, job_id))),
+            None => {
+                redis::cmd("XACK")
+                    .arg(JOB_QUEUE_KEY)
+                    .arg(JOB_CONSUMER_GROUP)
+                    .arg(&entry_id)
+                    .query_async::<_, ()>(conn)
+                    .await?;
+            }
+        }
+    }
+    Ok(None)
+}
+
+async fn read_new(
+    state: &AppState,
+    conn: &mut redis::aio::MultiplexedConnection,
+) -> redis::RedisResult<Option<QueueEntry>> {
+    let reply: Option<Vec<(String, Vec<StreamEntry>)>> = redis::cmd("XREADGROUP")
+        .arg("GROUP")
+        .arg(JOB_CONSUMER_GROUP)
+        .arg(&state.config.instance_id)
+        .arg("COUNT")
+        .arg(1)
+        .arg("BLOCK")
+        .arg(QUEUE_BLOCK_MS)
+        .arg("STREAMS")
+        .arg(JOB_QUEUE_KEY)
+        .arg(">")
+        .query_async(conn)
+        .await?;
+    Ok(reply
+        .into_iter()
+        .flatten()
+        .flat_map(|(_, entries)| entries)
+        .find_map(|(entry_id, mut fields)| Some((entry_id, fields.remove("job_id")?))))
+}
+
+/// Runs one queue entry, refreshing its claim while the job is in progress,
+/// then acknowledges it. Jobs that already finished (a consumer crashed
+/// between completing and acknowledging) or expired are only acknowledged.
+async fn process_entry(state: &Arc<AppState>, entry_id: &str, job_id: &str) {
+    let pending = match load_pending(state, job_id).await {
+        Ok(pending) => pending,
+        Err(()) => return,
+    };
+    if let Some((spec, attempts)) = pending {
+        let job = run_job(state.clone(), job_id.to_string(), spec, attempts);
+        tokio::pin!(job);
+        let mut heartbeat = interval(Duration::from_millis(
+            (state.config.job_claim_idle_ms / 3).max(1),
+        ));
+        heartbeat.tick().await;
+        loop {
+            tokio::select! {
+                () = &mut job => break,
+                _ = heartbeat.tick() => refresh_claim(state, entry_id).await,
+            }
+        }
+    }
+    if let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await {
+        let _: redis::RedisResult<()> = redis::pipe()
+            .atomic()
+            .cmd("XACK")
+            .arg(JOB_QUEUE_KEY)
+            .arg(JOB_CONSUMER_GROUP)
+            .arg(entry_id)
+            .ignore()
+            .cmd("XDEL")
+            .arg(JOB_QUEUE_KEY)
+            .arg(entry_id)
+            .ignore()
+            .query_async(&mut conn)
+            .await;
+    }
+}
+
+async fn refresh_claim(state: &AppState, entry_id: &str) {
+    if let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await {
+        let _: redis::RedisResult<Vec<String>> = redis::cmd("XCLAIM")
+            .arg(JOB_QUEUE_KEY)
+            .arg(JOB_CONSUMER_GROUP)
+            .arg(&state.config.instance_id)
+            .arg(0)
+            .arg(entry_id)
+            .arg("JUSTID")
+            .query_async(&mut conn)
+            .await;
+    }
+}
+
+/// Loads the spec and attempt count of a job that still needs to run.
+/// `Ok(None)` means the job is finished or gone and its queue entry can be
+/// acknowledged.
+async fn load_pending(state: &AppState, job_id: &str) -> Result<Option<(JobSpec, u32)>, ()> {
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| ())?;
+    let (status, spec, attempts): (Option<String>, Option<String>, Option<u32>) =
+        redis::cmd("HMGET")
+            .arg(job_key(job_id))
+            .arg("status")
+            .arg("spec")
+            .arg("attempts")
+            .query_async(&mut conn)
+            .await
+            .map_err(|_| ())?;
+    if matches!(status.as_deref(), None | Some("succeeded") | Some("failed")) {
+        return Ok(None);
+    }
+    Ok(spec
+        .and_then(|spec| serde_json::from_str(&spec).ok())
+        .map(|spec| (spec, attempts.unwrap_or_default())))
+}
+
+pub(crate) fn accepted_response(job_id: &str) -> Response {
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "job_id": job_id,
+            "status": "queued",
+            "status_url": format!("/jobs/{job_id}"),
+        })),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{job_id}",
+    params(("job_id" = String, Path)),
+    responses(
+        (status = 200, description = "Job status", body = crate::openapi::Job),
+        (status = 404, description = "Unknown or expired job", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Backend unavailable", body = crate::openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
+pub(crate) async fn get_job(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Response {
+    match load_job(&state, &job_id).await {
+        Ok(Some(job)) => Json(job).into_response(),
+        Ok(None) => problem_response(
+            StatusCode::NOT_FOUND,
+            PROBLEM_TYPE_NOT_FOUND,
+            "Job not found",
+            format!("no job {job_id} (it may have expired)"),
+            json!({}),
+        ),
+        Err(()) => crate::backend_unavailable_response(&state),
+    }
+}
+
+/// Drives one due job to completion: take a permit, call Discord (retrying
+/// 429s, transient 5xx and network failures up to `DMBO_JOB_MAX_ATTEMPTS`,
+/// counting attempts made before a reclaim, and within
+/// `DMBO_UPSTREAM_RETRY_BUDGET_MS`), record the outcome and fire the callback.
+async fn run_job(state: Arc<AppState>, job_id: String, spec: JobSpec, mut attempts: u32) {
+    let budget = RetryBudget::new(
+        state.config.job_max_attempts,
+        state.config.upstream_retry_budget_ms,
+    );
+    let mut permit_request = permit_request_for(&spec, job_id.clone());
+    let mut veto = admission_policy(&state, &mut permit_request).await;
+    let outcome = loop {
+        if let Some(denial) = &veto {
+            if denial.reason != Reason::MaintenancePaused {
+                break Err(denial.reason);
+            }
+            // Paused jobs wait out the window instead of failing.
+            sleep(Duration::from_millis(
+                denial.retry_after_ms.max(state.config.min_retry_ms),
+            ))
+            .await;
+            permit_request = permit_request_for(&spec, job_id.clone());
+            veto = admission_policy(&state, &mut permit_request).await;
+            continue;
+        }
+        if spec
+            .expires_unix_ms
+            .is_some_and(|expires| unix_ms() >= expires)
+        {
+            break Err(Reason::JobExpired);
+        }
+        // Background sends step aside while an interaction response waits.
+        if interactions::must_yield(&state.waiters, &permit_request, None) {
+            state
+                .metrics
+                .interaction_yields
+                .fetch_add(1, Ordering::Relaxed);
+            sleep(Duration::from_millis(state.config.min_retry_ms)).await;
+            continue;
+        }
+        let decision = issue_permit(&state, &permit_request).await;
+        if !decision.granted {
+            sleep(Duration::from_millis(
+                decision.retry_after_ms.max(state.config.min_retry_ms),
+            ))
+            .await;
+            continue;
+        }
+        // Paced grants (reactions) are scheduled for a slot just ahead.
+        if let Some(not_before) = decision.not_before_unix_ms {
+            sleep(Duration::from_millis(not_before.saturating_sub(unix_ms()))).await;
+        }
+
+        attempts += 1;
+        save_job(
+            &state,
+            &job_id,
+            &[
+                ("status", "running".to_string()),
+                ("attempts", attempts.to_string()),
+                ("updated_unix_ms", unix_ms().to_string()),
+            ],
+        )
+        .await;
+        let result = execute(&state, &spec).await;
+        if let Ok(result) = &result {
+            record_report(&state, &report_for(&permit_request, result)).await;
+        }
+        let retry = match &result {
+            Ok(result) => upstream::retry_after(
+                Some(result.status_code),
+                &result.headers,
+                state.config.min_retry_ms,
+            ),
+            Err(()) => upstream::retry_after(None, &HashMap::new(), state.config.min_retry_ms),
+        };
+        match retry {
+            Some((cause, delay)) if budget.spend(&state.metrics, attempts, cause, delay) => {
+                sleep(Duration::from_millis(delay)).await;
+            }
+            _ => break result.map_err(|()| Reason::UpstreamUnavailable),
+        }
+    };
+
+    let mut fields = vec![("updated_unix_ms", unix_ms().to_string())];
+    let failure = match &outcome {
+        Ok(result) if result.status_code < 400 => {
+            state.metrics.jobs_succeeded.fetch_add(1, Ordering::Relaxed);
+            fields.push(("status", "succeeded".to_string()));
+            fields.push(("result", serde_json::to_string(result).unwrap_or_default()));
+            None
+        }
+        Ok(result) => {
+            state.metrics.jobs_failed.fetch_add(1, Ordering::Relaxed);
+            fields.push(("status", "failed".to_string()));
+            fields.push(("result", serde_json::to_string(result).unwrap_or_default()));
+            Some(format!("http_{}", result.status_code))
+        }
+        Err(reason) => {
+            state.metrics.jobs_failed.fetch_add(1, Ordering::Relaxed);
+            fields.push(("status", "failed".to_string()));
+            fields.push(("error", reason.code().to_string()));
+            Some(reason.code().to_string())
+        }
+    };
+    save_job(&state, &job_id, &fields).await;
+    if let Some(error) = failure {
+        dlq::dead_letter_job(&state, &job_id, &spec, &error, attempts).await;
+    }
+
+    // Checked again for jobs replayed from the dead-letter queue.
+    let callback_url = spec
+        .callback_url
+        .as_deref()
+        .filter(|url| callbacks::check_url(&state.config, url).is_ok());
+    if let Some(callback_url) = callback_url {
+        if let Ok(Some(job)) = load_job(&state, &job_id).await {
+            let _ = state
+                .callback_http
+                .post(callback_url)
+                .timeout(Duration::from_secs(10))
+                .json(&job)
+                .send()
+                .await;
+        }
+    }
+}
+
+async fn execute(state: &AppState, spec: &JobSpec) -> Result<JobResult, ()> {
+    let token = state
+        .config
+        .discord_tokens
+        .get(&spec.token_ref.to_ascii_uppercase())
+        .ok_or(())?;
+    let method = reqwest::Method::from_bytes(spec.method.as_bytes()).map_err(|_| ())?;
+    let url = format!(
+        "{}{}",
+        state.config.discord_api_base.trim_end_matches('/'),
+        routes::request_path(&spec.route)
+    );
+    let mut request = state
+        .http
+        .request(method, url)
+        .header("authorization", format!("Bot {token}"))
+        .timeout(Duration::from_secs(30));
+    if let Some(body) = &spec.body {
+        request = request.json(body);
+    }
+    let response = request.send().await.map_err(|_| ())?;
+    let status_code = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.as_str();
+            name.starts_with("x-ratelimit-") || name == "retry-after"
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let mut body = response.text().await.unwrap_or_default();
+    if body.len() > MAX_STORED_BODY_BYTES {
+        let mut cut = MAX_STORED_BODY_BYTES;
+        while !body.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        body.truncate(cut);
+    }
+    Ok(JobResult {
+        status_code,
+        headers,
+        body,
+    })
+}
+
+fn permit_request_for(spec: &JobSpec, request_id: String) -> RequestTokenRequest {
+    let resolved = routes::normalize_route(&spec.route);
+    let major_parameter = if spec.major_parameter.trim().is_empty() {
+        resolved.major_parameter.unwrap_or_default()
+    } else {
+        spec.major_parameter.clone()
+    };
+    RequestTokenRequest {
+        client_id: spec.client_id.clone(),
+        group_id: spec.group_id.clone(),
+        discord_identity: spec.discord_identity.clone(),
+        method: spec.method.clone(),
+        route: resolved.template,
+        major_parameter,
+        priority: spec.priority.clone(),
+        request_id,
+        feature: spec.feature.clone(),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn report_for(request: &RequestTokenRequest, result: &JobResult) -> ReportResultRequest {
+    ReportResultRequest {
+        request_id: request.request_id.clone(),
+        client_id: request.client_id.clone(),
+        discord_identity: request.discord_identity.clone(),
+        group_id: request.group_id.clone(),
+        method: request.method.clone(),
+        route: request.route.clone(),
+        major_parameter: request.major_parameter.clone(),
+        status_code: result.status_code,
+        x_ratelimit_bucket: result.headers.get("x-ratelimit-bucket").cloned(),
+        x_ratelimit_limit: header_number(result, "x-ratelimit-limit"),
+        x_ratelimit_remaining: header_number(result, "x-ratelimit-remaining"),
+        x_ratelimit_reset_after_s: header_number(result, "x-ratelimit-reset-after"),
+        x_ratelimit_scope: result.headers.get("x-ratelimit-scope").cloned(),
+        x_ratelimit_global: result
+            .headers
+            .get("x-ratelimit-global")
+            .is_some_and(|value| value.eq_ignore_ascii_case("true")),
+        retry_after_ms: header_number::<f64>(result, "retry-after")
+            .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+            .map(|seconds| (seconds * 1000.0).ceil() as u64),
+        body_retry_after_s: (result.status_code == 429)
+            .then(|| serde_json::from_str::<Value>(&result.body).ok())
+            .flatten()
+            .and_then(|body| body.get("retry_after")?.as_f64()),
+        // Discord answers its own 429s with JSON and rate-limit headers;
+        // Cloudflare with an HTML page and neither.
+        cloudflare: result.status_code == 429
+            && result
+                .headers
+                .get("content-type")
+                .is_some_and(|content_type| {
+                    !content_type.is_empty() && !content_type.contains("json")
+                })
+            && !result
+                .headers
+                .keys()
+                .any(|name| name.starts_with("x-ratelimit-")),
+        feature: request.feature.clone(),
+        ..Default::default()
+    }
+}
+
+fn header_number<T: std::str::FromStr>(result: &JobResult, name: &str) -> Option<T> {
+    result
+        .headers
+        .get(name)
+        .and_then(|value| value.parse().ok())
+}
+
+fn job_key(job_id: &str) -> String {
+    format!("rl:job:{}", normalize_key_part(job_id))
+}
+
+async fn save_job(state: &AppState, job_id: &str, fields: &[(&str, String)]) -> bool {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+        return false;
+    };
+    let key = job_key(job_id);
+    let result: redis::RedisResult<()> = redis::pipe()
+        .atomic()
+        .hset_multiple(&key, fields)
+        .ignore()
+        .expire(&key, state.config.job_ttl_seconds as i64)
+        .ignore()
+        .query_async(&mut conn)
+        .await;
+    if result.is_err() {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    true
+}
+
+async fn load_job(state: &AppState, job_id: &str) -> Result<Option<Value>, ()> {
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| ())?;
+    let fields: HashMap<String, String> = conn.hgetall(job_key(job_id)).await.map_err(|_| ())?;
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let parse = |name: &str| {
+        fields
+            .get(name)
+            .and_then(|value| serde_json::from_str::<Value>(value).ok())
+    };
+    let number = |name: &str| fields.get(name).and_then(|value| value.parse::<u64>().ok());
+    Ok(Some(json!({
+        "job_id": job_id,
+        "status": fields.get("status"),
+        "attempts": number("attempts"),
+        "created_unix_ms": number("created_unix_ms"),
+        "updated_unix_ms": number("updated_unix_ms"),
+        "spec": parse("spec"),
+        "result": parse("result"),
+        "error": fields.get("error"),
+    })))
+}
@@ -0,0 +1,424 @@
+//! Permit leases. Every granted REST permit carries a `lease_id` naming a
+//! lease kept in Redis for `DMBO_LEASE_TTL_MS`. The lease ends when the call
+//! is reported with that `lease_id`, when the client hands it back with
+//! `POST /release_lease` (a call it will not make), or when it expires.
+//! Ending a lease frees the route and large-upload concurrency slots its
+//! call held, so an abandoned permit gives its capacity back at the lease's
+//! end rather than the slot's. Each replica sweeps expired leases every
+//! second; the sweep runs as one script so a lease is only ended once.
+//! A call that outlasts the TTL, such as a large upload, keeps its lease
+//! and slots with `POST /extend_lease`. With `DMBO_STORE=memory`, which runs
+//! none of those slots, permits carry no lease.
+
+use crate::{
+    admin::{page_response, ListQuery},
+    problem_response, route_limits, routes, unix_ms, uploads, validation_failed_response, AppState,
+    FieldError, ReportResultRequest, RequestTokenRequest,
+};
+use axum::{
+    extract::{rejection::JsonRejection, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use dmbo_core::keys::bucket_id;
+use redis::{aio::MultiplexedConnection, Script};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::time::interval;
+
+const LEASES_KEY: &str = "rl:leases";
+const LEASE_EXPIRY_KEY: &str = "rl:leases:expiry";
+const PROBLEM_TYPE_NOT_FOUND: &str = "urn:dmbo:problem:not-found";
+const SWEEP_INTERVAL_MS: u64 = 1000;
+
+/// Longest single extension; callers needing more extend again.
+const MAX_EXTEND_MS: u64 = 600_000;
+
+/// Removes the leases named in `ARGV[2..]` (or, with none named, up to 1000
+/// leases due by `ARGV[1]`) and returns the ones that were still held.
+const END_LEASES_LUA: &str = r#"
+local ids = {}
+for i = 2, #ARGV do ids[#ids + 1] = ARGV[i] end
+if #ids == 0 then
+  ids = redis.call('ZRANGEBYSCORE', KEYS[2], '-inf', ARGV[1], 'LIMIT', 0, 1000)
+end
+local ended = {}
+for _, id in ipairs(ids) do
+  local raw = redis.call('HGET', KEYS[1], id)
+  redis.call('HDEL', KEYS[1], id)
+  redis.call('ZREM', KEYS[2], id)
+  if raw then ended[#ended + 1] = raw end
+end
+return ended
+"#;
+
+/// Moves lease `ARGV[1]` to expire at `ARGV[3]` (stored as `ARGV[4]`) if it
+/// is still held at `ARGV[2]`, and moves its slots in `KEYS[3..]` (member
+/// `ARGV[5]`) along with it. Returns 1, or 0 when the lease has ended.
+const EXTEND_LUA: &str = r#"
+local expires = redis.call('ZSCORE', KEYS[2], ARGV[1])
+if not expires or tonumber(expires) <= tonumber(ARGV[2]) then
+  return 0
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[4])
+redis.call('ZADD', KEYS[2], ARGV[3], ARGV[1])
+local ttl = tonumber(ARGV[3]) - tonumber(ARGV[2])
+for i = 3, #KEYS do
+  if redis.call('ZADD', KEYS[i], 'XX', 'CH', ARGV[3], ARGV[5]) == 1
+    and redis.call('PTTL', KEYS[i]) < ttl then
+    redis.call('PEXPIRE', KEYS[i], ttl)
+  end
+end
+return 1
+"#;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Lease {
+    lease_id: String,
+    request_id: String,
+    group_id: String,
+    discord_identity: String,
+    method: String,
+    route: String,
+    major_parameter: String,
+    granted_unix_ms: u64,
+    expires_unix_ms: u64,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct ReleaseLeaseRequest {
+    #[serde(default)]
+    lease_id: String,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct ExtendLeaseRequest {
+    #[serde(default)]
+    lease_id: String,
+    /// From now; `DMBO_LEASE_TTL_MS` when omitted.
+    #[serde(default)]
+    extend_ms: Option<u64>,
+}
+
+/// Stores a lease for a granted permit and returns its id. Returns `None`
+/// when Redis cannot take it; the grant stands either way.
+pub(crate) async fn issue(state: &AppState, request: &RequestTokenRequest) -> Option<String> {
+    if state.config.memory_store {
+        return None;
+    }
+    let now = unix_ms();
+    let lease = Lease {
+        lease_id: uuid::Uuid::new_v4().to_string(),
+        request_id: request.request_id.clone(),
+        group_id: request.group_id.clone(),
+        discord_identity: request.discord_identity.clone(),
+        method: request.method.clone(),
+        route: request.route.clone(),
+        major_parameter: request.major_parameter.clone(),
+        granted_unix_ms: now,
+        expires_unix_ms: now + state.config.lease_ttl_ms,
+    };
+    let raw = serde_json::to_string(&lease).ok()?;
+    let mut conn = state.redis.get_multiplexed_async_connection().await.ok()?;
+    redis::pipe()
+        .atomic()
+        .hset(LEASES_KEY, &lease.lease_id, raw)
+        .ignore()
+        .zadd(LEASE_EXPIRY_KEY, &lease.lease_id, lease.expires_unix_ms)
+        .ignore()
+        .query_async::<_, ()>(&mut conn)
+        .await
+        .ok()?;
+    state.metrics.leases_issued.fetch_add(1, Ordering::Relaxed);
+    Some(lease.lease_id)
+}
+
+async fn end_leases(
+    conn: &mut MultiplexedConnection,
+    ids: &[&str],
+) -> redis::RedisResult<Vec<Lease>> {
+    let raw: Vec<String> = Script::new(END_LEASES_LUA)
+        .key(LEASES_KEY)
+        .key(LEASE_EXPIRY_KEY)
+        .arg(unix_ms())
+        .arg(ids)
+        .invoke_async(conn)
+        .await?;
+    Ok(raw
+        .iter()
+        .filter_map(|raw| serde_json::from_str(raw).ok())
+        .collect())
+}
+
+/// Why a report's `lease_id` names no outstanding lease of its identity and
+/// route: `unknown` when no such lease is held (never issued, or already
+/// reported, released or expired), `mismatch` when it belongs to another
+/// call. `None` when it matches, when the report names no lease, or when
+/// Redis cannot tell; a mismatch is counted here.
+pub(crate) async fn check_report(
+    state: &AppState,
+    report: &ReportResultRequest,
+) -> Option<&'static str> {
+    let lease_id = report.lease_id.as_deref().filter(|id| !id.is_empty())?;
+    if state.config.memory_store {
+        return None;
+    }
+    let mut conn = state.redis.get_multiplexed_async_connection().await.ok()?;
+    let raw: Option<String> = redis::cmd("HGET")
+        .arg(LEASES_KEY)
+        .arg(lease_id)
+        .query_async(&mut conn)
+        .await
+        .ok()?;
+    let lease = raw.and_then(|raw| serde_json::from_str::<Lease>(&raw).ok());
+    let (kind, counter) = match lease {
+        None => ("unknown", &state.metrics.lease_mismatches_unknown),
+        Some(lease)
+            if lease.discord_identity != report.discord_identity
+                || lease.route != routes::normalize_route(&report.route).template =>
+        {
+            ("mismatch", &state.metrics.lease_mismatches_foreign)
+        }
+        Some(_) => return None,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    Some(kind)
+}
+
+/// Frees the concurrency slots the lease's call held.
+async fn free(
+    state: &AppState,
+    conn: &mut MultiplexedConnection,
+    lease: &Lease,
+) -> redis::RedisResult<()> {
+    let route_bucket = bucket_id(
+        &lease.discord_identity,
+        &lease.method,
+        &lease.route,
+        &lease.major_parameter,
+    );
+    state
+        .route_limits
+        .finish(
+            conn,
+            &lease.request_id,
+            &lease.method,
+            &lease.route,
+            &route_bucket,
+        )
+        .await?;
+    uploads::finish(state, conn, &lease.discord_identity, &lease.request_id).await?;
+    crate::wakeups::announce(state, &route_bucket);
+    Ok(())
+}
+
+/// Ends `lease_id` and frees its capacity. Returns whether it was still held.
+pub(crate) async fn release(
+    state: &AppState,
+    conn: &mut MultiplexedConnection,
+    lease_id: &str,
+) -> redis::RedisResult<bool> {
+    let ended = end_leases(conn, &[lease_id]).await?;
+    for lease in &ended {
+        free(state, conn, lease).await?;
+    }
+    Ok(!ended.is_empty())
+}
+
+/// Ends expired leases every second, freeing what they held.
+pub(crate) async fn expire_leases(state: Arc<AppState>) {
+    let mut ticker = interval(Duration::from_millis(SWEEP_INTERVAL_MS));
+    loop {
+        ticker.tick().await;
+        let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+            continue;
+        };
+        let Ok(expired) = end_leases(&mut conn, &[]).await else {
+            continue;
+        };
+        for lease in &expired {
+            let _ = free(&state, &mut conn, lease).await;
+        }
+        state
+            .metrics
+            .leases_expired
+            .fetch_add(expired.len() as u64, Ordering::Relaxed);
+    }
+}
+
+/// `POST /release_lease`: hands back a permit whose call will not be made.
+#[utoipa::path(
+    post,
+    path = "/release_lease",
+    request_body = ReleaseLeaseRequest,
+    responses(
+        (status = 204, description = "Lease released"),
+        (status = 400, description = "Invalid body", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Lease already reported, released or expired", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Backend unavailable", body = crate::openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
+pub(crate) async fn release_lease(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<ReleaseLeaseRequest>, JsonRejection>,
+) -> Response {
+    let Json(request) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return crate::json_rejection_response(&state, rejection),
+    };
+    if request.lease_id.trim().is_empty() {
+        return validation_failed_response(vec![FieldError {
+            field: "lease_id",
+            message: "must not be empty".to_string(),
+        }]);
+    }
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    match release(&state, &mut conn, &request.lease_id).await {
+        Ok(true) => {
+            state
+                .metrics
+                .leases_released
+                .fetch_add(1, Ordering::Relaxed);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => lease_not_found(&request.lease_id),
+        Err(_) => crate::backend_unavailable_response(&state),
+    }
+}
+
+/// `POST /extend_lease`: keeps a lease, and the slots its call holds, for
+/// `extend_ms` more from now.
+#[utoipa::path(
+    post,
+    path = "/extend_lease",
+    request_body = ExtendLeaseRequest,
+    responses(
+        (status = 200, description = "Lease extended", body = crate::openapi::ExtendLeaseResponse),
+        (status = 400, description = "Invalid body", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Lease already reported, released or expired", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Backend unavailable", body = crate::openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
+pub(crate) async fn extend_lease(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<ExtendLeaseRequest>, JsonRejection>,
+) -> Response {
+    let Json(request) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return crate::json_rejection_response(&state, rejection),
+    };
+    let mut errors = Vec::new();
+    if request.lease_id.trim().is_empty() {
+        errors.push(FieldError {
+            field: "lease_id",
+            message: "must not be empty".to_string(),
+        });
+    }
+    let extend_ms = request.extend_ms.unwrap_or(state.config.lease_ttl_ms);
+    if !(1..=MAX_EXTEND_MS).contains(&extend_ms) {
+        errors.push(FieldError {
+            field: "extend_ms",
+            message: format!("must be 1-{MAX_EXTEND_MS}"),
+        });
+    }
+    if !errors.is_empty() {
+        return validation_failed_response(errors);
+    }
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let raw: Option<String> = match redis::cmd("HGET")
+        .arg(LEASES_KEY)
+        .arg(&request.lease_id)
+        .query_async(&mut conn)
+        .await
+    {
+        Ok(raw) => raw,
+        Err(_) => return crate::backend_unavailable_response(&state),
+    };
+    let Some(mut lease) = raw.and_then(|raw| serde_json::from_str::<Lease>(&raw).ok()) else {
+        return lease_not_found(&request.lease_id);
+    };
+    let now = unix_ms();
+    lease.expires_unix_ms = now + extend_ms;
+    let route_bucket = bucket_id(
+        &lease.discord_identity,
+        &lease.method,
+        &lease.route,
+        &lease.major_parameter,
+    );
+    let extended: redis::RedisResult<i64> = Script::new(EXTEND_LUA)
+        .key(LEASES_KEY)
+        .key(LEASE_EXPIRY_KEY)
+        .key(route_limits::slot_key(&route_bucket))
+        .key(uploads::slots_key(&lease.discord_identity))
+        .arg(&lease.lease_id)
+        .arg(now)
+        .arg(lease.expires_unix_ms)
+        .arg(serde_json::to_string(&lease).unwrap_or_default())
+        .arg(&lease.request_id)
+        .invoke_async(&mut conn)
+        .await;
+    match extended {
+        Ok(1) => {
+            state
+                .metrics
+                .lease_extensions
+                .fetch_add(1, Ordering::Relaxed);
+            Json(json!({
+                "lease_id": lease.lease_id,
+                "expires_unix_ms": lease.expires_unix_ms,
+                "server_unix_ms": now,
+            }))
+            .into_response()
+        }
+        Ok(_) => lease_not_found(&request.lease_id),
+        Err(_) => crate::backend_unavailable_response(&state),
+    }
+}
+
+fn lease_not_found(lease_id: &str) -> Response {
+    problem_response(
+        StatusCode::NOT_FOUND,
+        PROBLEM_TYPE_NOT_FOUND,
+        "Lease not found",
+        format!("no active lease {lease_id}"),
+        json!({}),
+    )
+}
+
+/// `GET /admin/leases`: leases still held, with their remaining time.
+pub(crate) async fn list(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let Ok(raw) = redis::cmd("HVALS")
+        .arg(LEASES_KEY)
+        .query_async::<_, Vec<String>>(&mut conn)
+        .await
+    else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let now = unix_ms();
+    let items = raw
+        .iter()
+        .filter_map(|raw| serde_json::from_str::<Lease>(raw).ok())
+        .filter(|lease| lease.expires_unix_ms > now)
+        .map(|lease| {
+            let mut item = json!(lease);
+            item["remaining_ms"] = json!(lease.expires_unix_ms - now);
+            item
+        })
+        .collect();
+    page_response(items, &query, "expires_unix_ms")
+}
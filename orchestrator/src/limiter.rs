@@ -0,0 +1,185 @@
+//! Limiter algorithms. `DMBO_LIMITER_ALGORITHM` picks the one that enforces;
+//! `DMBO_SHADOW_ALGORITHM` optionally runs a second one on the same traffic
+//! without enforcing it, keeping its own state under `rl:shadow:` keys. Every
+//! enforcing evaluation is mirrored in the background, and the shadow's
+//! would-be decisions are compared with the enforced ones and with the 429s
+//! reported afterwards, so a migration can be judged on real traffic before
+//! cutover.
+
+use crate::{rules::route_matches, AppState};
+use axum::{extract::State, Json};
+use dmbo_core::{Algorithm, LimitInput};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// A 429 reported this soon after a grant the shadow would have denied is
+/// attributed to that grant.
+const DISPUTE_WINDOW_MS: u64 = 5_000;
+/// Disputed buckets kept for 429 attribution before old entries are pruned.
+const MAX_DISPUTED_BUCKETS: usize = 10_000;
+
+/// Algorithms chosen per route by `DMBO_ROUTE_ALGORITHMS`, a comma-separated
+/// list of `pattern=algorithm` with patterns as in policy rules, e.g.
+/// `/channels/:channel_id/messages=gcra`. The first matching pattern wins;
+/// other routes use `DMBO_LIMITER_ALGORITHM`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RouteAlgorithms(Vec<(String, Algorithm)>);
+
+impl RouteAlgorithms {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for pair in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (pattern, name) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("{pair:?} is not pattern=algorithm"))?;
+            let algorithm = Algorithm::parse(name.trim())
+                .ok_or_else(|| format!("unknown algorithm {:?} for {pattern}", name.trim()))?;
+            entries.push((pattern.trim().to_string(), algorithm));
+        }
+        Ok(Self(entries))
+    }
+
+    pub(crate) fn for_route(&self, route: &str) -> Option<Algorithm> {
+        self.0
+            .iter()
+            .find(|(pattern, _)| route_matches(pattern, route))
+            .map(|(_, algorithm)| *algorithm)
+    }
+
+    pub(crate) fn describe(&self) -> Value {
+        Value::Array(
+            self.0
+                .iter()
+                .map(|(route, algorithm)| json!({ "route": route, "algorithm": algorithm }))
+                .collect(),
+        )
+    }
+}
+
+/// Comparison of the shadow algorithm against the enforcing one since
+/// startup, per replica.
+#[derive(Default)]
+pub(crate) struct ShadowStats {
+    /// Decisions indexed by `[enforced granted][shadow granted]`.
+    decisions: [[AtomicU64; 2]; 2],
+    errors: AtomicU64,
+    observed_429_after_shadow_deny: AtomicU64,
+    observed_429_after_shadow_grant: AtomicU64,
+    /// Buckets whose latest enforced grant the shadow would have denied, with
+    /// the grant time.
+    disputed: Mutex<HashMap<String, u64>>,
+}
+
+impl ShadowStats {
+    fn record(&self, enforced: bool, shadow: bool, bucket: &str, now_ms: u64) {
+        self.decisions[usize::from(enforced)][usize::from(shadow)].fetch_add(1, Ordering::Relaxed);
+        if enforced && !shadow {
+            let mut disputed = self.disputed.lock().expect("shadow stats poisoned");
+            if disputed.len() >= MAX_DISPUTED_BUCKETS {
+                disputed
+                    .retain(|_, granted_ms| now_ms.saturating_sub(*granted_ms) < DISPUTE_WINDOW_MS);
+            }
+            disputed.insert(bucket.to_string(), now_ms);
+        }
+    }
+
+    /// Attributes a reported 429 to whether the shadow would have denied the
+    /// bucket's latest grant.
+    pub(crate) fn record_429(&self, bucket: &str, now_ms: u64) {
+        let disputed = self
+            .disputed
+            .lock()
+            .expect("shadow stats poisoned")
+            .get(bucket)
+            .is_some_and(|granted_ms| now_ms.saturating_sub(*granted_ms) < DISPUTE_WINDOW_MS);
+        let counter = if disputed {
+            &self.observed_429_after_shadow_deny
+        } else {
+            &self.observed_429_after_shadow_grant
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn decisions(&self, enforced: bool, shadow: bool) -> u64 {
+        self.decisions[usize::from(enforced)][usize::from(shadow)].load(Ordering::Relaxed)
+    }
+
+    /// 429s reported after a grant the shadow would have denied (`true`) or
+    /// granted (`false`).
+    pub(crate) fn observed_429(&self, shadow_denied: bool) -> u64 {
+        if shadow_denied {
+            self.observed_429_after_shadow_deny.load(Ordering::Relaxed)
+        } else {
+            self.observed_429_after_shadow_grant.load(Ordering::Relaxed)
+        }
+    }
+
+    pub(crate) fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+}
+
+/// Mirrors an enforced decision with the shadow algorithm in the background
+/// so the shadow never adds latency to the permit path.
+pub(crate) fn spawn_shadow(state: &Arc<AppState>, input: LimitInput, enforced: bool) {
+    let Some(algorithm) = state.config.shadow_algorithm else {
+        return;
+    };
+    let state = state.clone();
+    tokio::spawn(async move {
+        let shadow = state
+            .store
+            .evaluate(&state.config, algorithm, algorithm, &input, true)
+            .await;
+        match shadow {
+            Ok((granted, ..)) => {
+                state
+                    .shadow
+                    .record(enforced, granted == 1, &input.bucket, input.now_ms)
+            }
+            Err(_) => {
+                state.shadow.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+/// `GET /admin/limiter/compare`: the shadow comparison report.
+pub(crate) async fn compare(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let stats = &state.shadow;
+    let both_granted = stats.decisions(true, true);
+    let both_denied = stats.decisions(false, false);
+    let shadow_stricter = stats.decisions(true, false);
+    let shadow_looser = stats.decisions(false, true);
+    let total = both_granted + both_denied + shadow_stricter + shadow_looser;
+    let enforced_grants = both_granted + shadow_stricter;
+    Json(json!({
+        "enforcing": state.config.limiter_algorithm,
+        "shadow": state.config.shadow_algorithm,
+        "decisions": {
+            "total": total,
+            "both_granted": both_granted,
+            "both_denied": both_denied,
+            "shadow_stricter": shadow_stricter,
+            "shadow_looser": shadow_looser,
+        },
+        "agreement_rate": (total > 0).then(|| (both_granted + both_denied) as f64 / total as f64),
+        "shadow_grant_rate": (total > 0).then(|| (both_granted + shadow_looser) as f64 / total as f64),
+        "enforced_grant_rate": (total > 0).then(|| enforced_grants as f64 / total as f64),
+        "observed_429": {
+            "after_shadow_deny": stats.observed_429(true),
+            "after_shadow_grant": stats.observed_429(false),
+        },
+        "shadow_errors": stats.errors(),
+    }))
+}
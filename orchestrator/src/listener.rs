@@ -0,0 +1,140 @@
+//! Listeners axum cannot serve itself: a Unix domain socket
+//! (`DMBO_BIND=unix:/path.sock`) for bots on the same host, and TCP with TLS
+//! terminated here (`DMBO_TLS_CERT` / `DMBO_TLS_KEY`) for bots elsewhere on a
+//! LAN or VPN. Connections are accepted here and handed to hyper directly
+//! (HTTP/1.1 with upgrades, for `GET /permits/ws`). On shutdown the listener
+//! closes and open connections finish their in-flight requests.
+
+use axum::Router;
+use hyper::server::conn::http1;
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use std::{
+    fs, future::Future, io, os::unix::fs::FileTypeExt, path::Path, sync::Arc, time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+    sync::watch,
+    time::sleep,
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+/// TLS handshakes slower than this are dropped so they cannot pile up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) enum Listener {
+    Unix(UnixListener),
+    Tls(TcpListener, TlsAcceptor),
+}
+
+/// Binds `path`, replacing a socket left behind by an earlier run.
+pub(crate) fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Loads the PEM certificate chain and private key for the TLS listener.
+pub(crate) fn tls_acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, String> {
+    let certs = fs::read(cert_path)
+        .map_err(|error| format!("cannot read {cert_path}: {error}"))
+        .and_then(|pem| {
+            rustls_pemfile::certs(&mut pem.as_slice())
+                .collect::<Result<Vec<CertificateDer<'static>>, _>>()
+                .map_err(|error| format!("invalid certificate in {cert_path}: {error}"))
+        })?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {cert_path}"));
+    }
+    let key: PrivateKeyDer<'static> = fs::read(key_path)
+        .map_err(|error| format!("cannot read {key_path}: {error}"))
+        .and_then(|pem| {
+            rustls_pemfile::private_key(&mut pem.as_slice())
+                .map_err(|error| format!("invalid private key in {key_path}: {error}"))
+        })?
+        .ok_or_else(|| format!("no private key in {key_path}"))?;
+    let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|error| error.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|error| format!("certificate and key do not match: {error}"))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Serves `app` on `listener` until `shutdown` completes.
+pub(crate) async fn serve(listener: Listener, app: Router, shutdown: impl Future<Output = ()>) {
+    // Every connection holds a receiver; the sender closes once all are done.
+    let (stop, stopped) = watch::channel(false);
+    tokio::pin!(shutdown);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => break,
+        };
+        let app = app.clone();
+        let stopped = stopped.clone();
+        match accepted {
+            Ok(Accepted::Unix(stream)) => {
+                tokio::spawn(serve_connection(stream, app, stopped));
+            }
+            Ok(Accepted::Tls(stream, acceptor)) => {
+                tokio::spawn(async move {
+                    let handshake =
+                        tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+                    if let Ok(Ok(stream)) = handshake.await {
+                        serve_connection(stream, app, stopped).await;
+                    }
+                });
+            }
+            // Out of file descriptors and the like; back off and retry.
+            Err(_) => sleep(Duration::from_millis(100)).await,
+        }
+    }
+    drop(listener);
+    drop(stopped);
+    let _ = stop.send(true);
+    stop.closed().await;
+}
+
+enum Accepted {
+    Unix(tokio::net::UnixStream),
+    Tls(tokio::net::TcpStream, TlsAcceptor),
+}
+
+impl Listener {
+    async fn accept(&self) -> io::Result<Accepted> {
+        match self {
+            Listener::Unix(listener) => Ok(Accepted::Unix(listener.accept().await?.0)),
+            Listener::Tls(listener, acceptor) => {
+                let (stream, _) = listener.accept().await?;
+                let _ = stream.set_nodelay(true);
+                Ok(Accepted::Tls(stream, acceptor.clone()))
+            }
+        }
+    }
+}
+
+async fn serve_connection<I>(io: I, app: Router, mut stopped: watch::Receiver<bool>)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let connection = http1::Builder::new()
+        .serve_connection(TokioIo::new(io), TowerToHyperService::new(app))
+        .with_upgrades();
+    tokio::pin!(connection);
+    tokio::select! {
+        _ = connection.as_mut() => return,
+        _ = stopped.changed() => connection.as_mut().graceful_shutdown(),
+    }
+    let _ = connection.await;
+}
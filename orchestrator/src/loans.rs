@@ -0,0 +1,339 @@
+//! Temporary budget loans between groups. A loan moves part of one group's
+//! global rate (`DMBO_GLOBAL_RPS`, or the active calendar window's) to
+//! another group until it expires, e.g. to give a team a burst for a
+//! migration while another group is idle. Loans live in Redis so every
+//! replica applies them; each replica caches the per-group adjustments and
+//! refreshes them every second, returning expired loans as it goes. Lending,
+//! early recall and return are appended to the `rl:loans:audit` stream.
+
+use crate::{
+    admin::{page_response, read_stream, ListQuery},
+    normalize_key_part, problem_response, unix_ms, validation_failed_response, AppState,
+    FieldError,
+};
+use axum::{
+    extract::{rejection::JsonRejection, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use redis::Script;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::time::interval;
+
+const LOANS_KEY: &str = "rl:loans";
+const LOAN_EXPIRY_KEY: &str = "rl:loans:expiry";
+const LOAN_AUDIT_STREAM_KEY: &str = "rl:loans:audit";
+const PROBLEM_TYPE_NOT_FOUND: &str = "urn:dmbo:problem:not-found";
+const SYNC_INTERVAL_MS: u64 = 1000;
+
+/// Stores a loan unless the lender's outstanding loans plus this one would
+/// leave it less than 1 rps of `ARGV[4]`. Returns `{1}` on success or
+/// `{0, available}`.
+const LEND_LUA: &str = r#"
+local now = tonumber(ARGV[5])
+local lent = 0
+for _, raw in ipairs(redis.call('HVALS', KEYS[1])) do
+  local loan = cjson.decode(raw)
+  if loan.from_group == ARGV[3] and loan.expires_unix_ms > now then
+    lent = lent + loan.global_rps
+  end
+end
+local amount = tonumber(ARGV[7])
+local available = tonumber(ARGV[4]) - 1 - lent
+if amount > available then
+  return {0, math.max(0, available)}
+end
+local loan = cjson.decode(ARGV[2])
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+redis.call('ZADD', KEYS[2], ARGV[6], ARGV[1])
+redis.call('XADD', KEYS[3], 'MAXLEN', '~', ARGV[8], '*', 'event', 'lent', 'loan_id', ARGV[1],
+  'from_group', ARGV[3], 'to_group', loan.to_group, 'global_rps', ARGV[7],
+  'expires_unix_ms', ARGV[6], 'note', loan.note)
+return {1}
+"#;
+
+/// Removes the loans named in `ARGV[4..]` (or, with none named, every loan
+/// due by `ARGV[1]`) and records `ARGV[2]` as the audit event for each. Runs
+/// as one script so concurrent replicas never return the same loan twice.
+const END_LOANS_LUA: &str = r#"
+local ids = {}
+for i = 4, #ARGV do ids[#ids + 1] = ARGV[i] end
+if #ids == 0 then
+  ids = redis.call('ZRANGEBYSCORE', KEYS[2], '-inf', ARGV[1])
+end
+local ended = 0
+for _, id in ipairs(ids) do
+  local raw = redis.call('HGET', KEYS[1], id)
+  redis.call('HDEL', KEYS[1], id)
+  redis.call('ZREM', KEYS[2], id)
+  if raw then
+    local loan = cjson.decode(raw)
+    redis.call('XADD', KEYS[3], 'MAXLEN', '~', ARGV[3], '*', 'event', ARGV[2], 'loan_id', id,
+      'from_group', loan.from_group, 'to_group', loan.to_group,
+      'global_rps', loan.global_rps, 'expires_unix_ms', loan.expires_unix_ms, 'note', loan.note)
+    ended = ended + 1
+  end
+end
+return ended
+"#;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Loan {
+    id: String,
+    /// Normalized group ids.
+    from_group: String,
+    to_group: String,
+    global_rps: u64,
+    created_unix_ms: u64,
+    expires_unix_ms: u64,
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LendRequest {
+    from_group: String,
+    to_group: String,
+    global_rps: u64,
+    duration_ms: u64,
+    #[serde(default)]
+    note: String,
+}
+
+/// Loans cached per replica. Expiry is checked on every read, so a loan stops
+/// applying on time even while Redis is unreachable.
+#[derive(Clone, Default)]
+pub(crate) struct Loans {
+    loans: Arc<RwLock<Vec<Loan>>>,
+}
+
+impl Loans {
+    /// `global_rps` for `group_id` after its active loans, never below 1.
+    pub(crate) fn adjust(&self, group_id: &str, global_rps: u64) -> u64 {
+        let loans = self.loans.read().expect("loans poisoned");
+        if loans.is_empty() {
+            return global_rps;
+        }
+        let group = normalize_key_part(group_id);
+        let now = unix_ms();
+        let delta: i64 = loans
+            .iter()
+            .filter(|loan| loan.expires_unix_ms > now)
+            .map(|loan| {
+                if loan.to_group == group {
+                    loan.global_rps as i64
+                } else if loan.from_group == group {
+                    -(loan.global_rps as i64)
+                } else {
+                    0
+                }
+            })
+            .sum();
+        (global_rps as i64).saturating_add(delta).max(1) as u64
+    }
+
+    pub(crate) fn active_count(&self) -> usize {
+        let now = unix_ms();
+        self.loans
+            .read()
+            .expect("loans poisoned")
+            .iter()
+            .filter(|loan| loan.expires_unix_ms > now)
+            .count()
+    }
+}
+
+/// Returns due loans and refreshes the cache every `SYNC_INTERVAL_MS`. On
+/// Redis errors the previous cache is kept.
+pub(crate) async fn sync_loans(state: Arc<AppState>) {
+    let mut ticker = interval(Duration::from_millis(SYNC_INTERVAL_MS));
+    loop {
+        ticker.tick().await;
+        let _ = end_loans(&state, "returned", &[]).await;
+        let _ = refresh(&state).await;
+    }
+}
+
+async fn refresh(state: &AppState) -> Result<Vec<Loan>, ()> {
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| ())?;
+    let raw: Vec<String> = redis::cmd("HVALS")
+        .arg(LOANS_KEY)
+        .query_async(&mut conn)
+        .await
+        .map_err(|_| ())?;
+    let loans: Vec<Loan> = raw
+        .iter()
+        .filter_map(|raw| serde_json::from_str(raw).ok())
+        .collect();
+    *state.loans.loans.write().expect("loans poisoned") = loans.clone();
+    Ok(loans)
+}
+
+async fn end_loans(state: &AppState, event: &str, ids: &[&str]) -> Result<u64, ()> {
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| ())?;
+    Script::new(END_LOANS_LUA)
+        .key(LOANS_KEY)
+        .key(LOAN_EXPIRY_KEY)
+        .key(LOAN_AUDIT_STREAM_KEY)
+        .arg(unix_ms())
+        .arg(event)
+        .arg(state.config.audit_maxlen)
+        .arg(ids)
+        .invoke_async(&mut conn)
+        .await
+        .map_err(|_| ())
+}
+
+/// `POST /admin/loans`: lends `global_rps` of `from_group`'s budget to
+/// `to_group` for `duration_ms`.
+pub(crate) async fn lend(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<LendRequest>, JsonRejection>,
+) -> Response {
+    let Json(request) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return crate::json_rejection_response(&state, rejection),
+    };
+    let from_group = normalize_key_part(&request.from_group);
+    let to_group = normalize_key_part(&request.to_group);
+    let mut errors = Vec::new();
+    for (field, group) in [("from_group", &from_group), ("to_group", &to_group)] {
+        if group.is_empty() {
+            errors.push(FieldError {
+                field,
+                message: "must not be empty".to_string(),
+            });
+        }
+    }
+    if from_group == to_group {
+        errors.push(FieldError {
+            field: "to_group",
+            message: "must differ from from_group".to_string(),
+        });
+    }
+    if request.global_rps == 0 {
+        errors.push(FieldError {
+            field: "global_rps",
+            message: "must be at least 1".to_string(),
+        });
+    }
+    let max_duration_ms = state.config.loan_max_duration_ms;
+    if request.duration_ms == 0 || request.duration_ms > max_duration_ms {
+        errors.push(FieldError {
+            field: "duration_ms",
+            message: format!("must be 1-{max_duration_ms} (DMBO_LOAN_MAX_MS)"),
+        });
+    }
+    if !errors.is_empty() {
+        return validation_failed_response(errors);
+    }
+
+    let now = unix_ms();
+    let loan = Loan {
+        id: uuid::Uuid::new_v4().to_string(),
+        from_group,
+        to_group,
+        global_rps: request.global_rps,
+        created_unix_ms: now,
+        expires_unix_ms: now + request.duration_ms,
+        note: request.note,
+    };
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let stored: redis::RedisResult<Vec<u64>> = Script::new(LEND_LUA)
+        .key(LOANS_KEY)
+        .key(LOAN_EXPIRY_KEY)
+        .key(LOAN_AUDIT_STREAM_KEY)
+        .arg(&loan.id)
+        .arg(serde_json::to_string(&loan).unwrap_or_default())
+        .arg(&loan.from_group)
+        .arg(state.config.global_rps)
+        .arg(now)
+        .arg(loan.expires_unix_ms)
+        .arg(loan.global_rps)
+        .arg(state.config.audit_maxlen)
+        .invoke_async(&mut conn)
+        .await;
+    match stored.as_deref() {
+        Ok([1]) => {
+            let _ = refresh(&state).await;
+            (StatusCode::CREATED, Json(json!(loan))).into_response()
+        }
+        Ok([0, available]) => validation_failed_response(vec![FieldError {
+            field: "global_rps",
+            message: format!(
+                "{} can lend at most {available} more rps of its {} rps budget",
+                loan.from_group, state.config.global_rps
+            ),
+        }]),
+        _ => crate::backend_unavailable_response(&state),
+    }
+}
+
+/// `GET /admin/loans`: active loans with their remaining time.
+pub(crate) async fn list(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    let Ok(loans) = refresh(&state).await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let now = unix_ms();
+    let items = loans
+        .into_iter()
+        .filter(|loan| loan.expires_unix_ms > now)
+        .map(|loan| {
+            let mut item = json!(loan);
+            item["remaining_ms"] = json!(loan.expires_unix_ms - now);
+            item
+        })
+        .collect();
+    page_response(items, &query, "expires_unix_ms")
+}
+
+/// `DELETE /admin/loans/{loan_id}`: returns a loan early.
+pub(crate) async fn recall(
+    State(state): State<Arc<AppState>>,
+    Path(loan_id): Path<String>,
+) -> Response {
+    match end_loans(&state, "recalled", &[&loan_id]).await {
+        Ok(0) => problem_response(
+            StatusCode::NOT_FOUND,
+            PROBLEM_TYPE_NOT_FOUND,
+            "Loan not found",
+            format!("no active loan {loan_id}"),
+            json!({}),
+        ),
+        Ok(_) => {
+            let _ = refresh(&state).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(()) => crate::backend_unavailable_response(&state),
+    }
+}
+
+/// `GET /admin/loans/audit`: lent, recalled and returned events.
+pub(crate) async fn audit(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    match read_stream(&state, LOAN_AUDIT_STREAM_KEY).await {
+        Ok(items) => page_response(items, &query, "-id"),
+        Err(response) => response,
+    }
+}
@@ -0,0 +1,459 @@
+//! Long-window limits loaded from the JSON file named by `DMBO_LONG_LIMITS`,
+//! for Discord limits that span minutes or days (e.g. 2 channel renames per
+//! 10 minutes) and that the per-second limiter cannot see. Each limit is a
+//! sliding log in Redis: a sorted set of grant times per resource or
+//! identity, trimmed to its longest window on every check. A limit may stack
+//! several windows on its log (e.g. 5 per second and 100 per minute); a call
+//! needs room in all of them. A permit reserves a slot in every matching
+//! limit before the per-second limiter runs and gives the slots back if that
+//! limiter denies, so only granted calls count.
+//!
+//! Application command registration has a built-in class on top of the
+//! configured limits, since deploy scripts that re-register commands on every
+//! start are the usual way bots get temporarily blocked. Webhook executions
+//! have another: Discord limits each webhook on its own, whoever calls it, so
+//! that class is keyed on the webhook id alone rather than the identity. A
+//! third holds message sends to Discord's per-channel sub-limit (5 per 5
+//! seconds), which its headers do not announce.
+
+use crate::{
+    normalize_key_part, routes, rules::route_matches, Config, Reason, RequestTokenRequest,
+};
+use redis::{aio::MultiplexedConnection, Script};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Reserves `ARGV[2]` (a unique member) at time `ARGV[1]` in every log in
+/// `KEYS` when all have room; `ARGV[3..]` are `limit, window_ms` pairs in key
+/// order. A log listed more than once has stacked windows and is trimmed to
+/// the longest. Returns `{1}`, or `{0, retry_after_ms, index}` naming the
+/// full window (1-based) that frees up last.
+const ACQUIRE_LUA: &str = r#"
+local now = tonumber(ARGV[1])
+local longest = {}
+for i, key in ipairs(KEYS) do
+  local window = tonumber(ARGV[2 + 2 * i])
+  if not longest[key] or window > longest[key] then longest[key] = window end
+end
+for key, window in pairs(longest) do
+  redis.call('ZREMRANGEBYSCORE', key, '-inf', now - window)
+end
+local retry, position = 0, 0
+for i, key in ipairs(KEYS) do
+  local limit = tonumber(ARGV[1 + 2 * i])
+  local window = tonumber(ARGV[2 + 2 * i])
+  local since = '(' .. (now - window)
+  local count = redis.call('ZCOUNT', key, since, '+inf')
+  if count >= limit then
+    local freeing = redis.call('ZRANGEBYSCORE', key, since, '+inf', 'WITHSCORES', 'LIMIT', count - limit, 1)
+    local wait = math.max(1, tonumber(freeing[2]) + window - now)
+    if wait > retry then retry, position = wait, i end
+  end
+end
+if position > 0 then
+  return {0, retry, position}
+end
+for key, window in pairs(longest) do
+  redis.call('ZADD', key, now, ARGV[2])
+  redis.call('PEXPIRE', key, window)
+end
+return {1}
+"#;
+
+/// Global and per-guild command endpoints, including bulk overwrites.
+const REGISTRATION_ROUTES: [&str; 2] = [
+    "/applications/:application_id/commands/**",
+    "/applications/:application_id/guilds/:guild_id/commands/**",
+];
+/// Reads are not limited; every write is counted.
+const REGISTRATION_METHODS: [&str; 4] = ["POST", "PUT", "PATCH", "DELETE"];
+/// Calls authorized by the webhook token rather than a bot token: executions
+/// and edits or deletes of the messages they sent.
+const WEBHOOK_ROUTE: &str = "/webhooks/:webhook_id/:webhook_token/**";
+const WEBHOOK_METHODS: [&str; 3] = ["POST", "PATCH", "DELETE"];
+const MESSAGE_SEND_ROUTE: &str = "/channels/:channel_id/messages";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Scope {
+    /// One log per identity, method, route and major parameter, like the
+    /// per-second route counters.
+    #[default]
+    Resource,
+    /// One log per identity across every matching route.
+    Identity,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Window {
+    limit: u64,
+    window_seconds: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct LongLimit {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    /// Route pattern as in policy rules.
+    route: String,
+    /// A single window; moved into `windows` on load.
+    #[serde(default, skip_serializing)]
+    limit: Option<u64>,
+    #[serde(default, skip_serializing)]
+    window_seconds: Option<u64>,
+    /// Windows stacked on the limit's log.
+    #[serde(default)]
+    windows: Vec<Window>,
+    #[serde(default)]
+    per: Scope,
+}
+
+/// Slots reserved for one permit attempt, released if the attempt is denied.
+pub(crate) struct Reservation {
+    keys: Vec<String>,
+    member: String,
+}
+
+pub(crate) enum Acquired {
+    Reserved(Reservation),
+    /// A limit is full until a slot frees up. `limit` names a configured
+    /// limit; the built-in classes have none.
+    Exhausted {
+        reason: Reason,
+        limit: Option<String>,
+        retry_after_ms: u64,
+    },
+}
+
+/// Calls a built-in class allows in a window: command registration writes
+/// per bot and guild (or globally), webhook calls per webhook, or message
+/// sends per bot and channel.
+#[derive(Debug, Serialize)]
+struct BuiltinClass {
+    limit: u64,
+    window_seconds: u64,
+}
+
+impl BuiltinClass {
+    fn new(limit: u64, window_seconds: u64) -> Option<Self> {
+        (limit > 0).then_some(Self {
+            limit,
+            window_seconds: window_seconds.max(1),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Builtin {
+    Registration,
+    Webhook,
+    MessageSend,
+}
+
+#[derive(Default)]
+pub(crate) struct LongLimits {
+    limits: Vec<LongLimit>,
+    denials: Vec<AtomicU64>,
+    registration: Option<BuiltinClass>,
+    registration_denials: AtomicU64,
+    webhook: Option<BuiltinClass>,
+    webhook_denials: AtomicU64,
+    message_send: Option<BuiltinClass>,
+    message_send_denials: AtomicU64,
+}
+
+impl LongLimits {
+    /// Loads `DMBO_LONG_LIMITS` and the built-in classes, which a limit of 0
+    /// disables. A missing or invalid file aborts startup.
+    pub(crate) fn load(config: &Config) -> Self {
+        let registration = BuiltinClass::new(
+            config.command_registration_limit,
+            config.command_registration_window_seconds,
+        );
+        let webhook = BuiltinClass::new(config.webhook_limit, config.webhook_window_seconds);
+        let message_send = BuiltinClass::new(
+            config.message_send_limit,
+            config.message_send_window_seconds,
+        );
+        let Some(path) = config.long_limits_path.as_deref() else {
+            return Self {
+                registration,
+                webhook,
+                message_send,
+                ..Self::default()
+            };
+        };
+        let raw = std::fs::read_to_string(path)
+            .unwrap_or_else(|error| panic!("failed to read DMBO_LONG_LIMITS {path}: {error}"));
+        let mut limits: Vec<LongLimit> = serde_json::from_str(&raw)
+            .unwrap_or_else(|error| panic!("invalid DMBO_LONG_LIMITS {path}: {error}"));
+        let mut names = HashSet::new();
+        for limit in &mut limits {
+            match (limit.limit.take(), limit.window_seconds.take()) {
+                (Some(calls), Some(window_seconds)) => limit.windows.insert(
+                    0,
+                    Window {
+                        limit: calls,
+                        window_seconds,
+                    },
+                ),
+                (None, None) => {}
+                _ => panic!(
+                    "invalid DMBO_LONG_LIMITS {path}: limit {} needs both limit and window_seconds",
+                    limit.name
+                ),
+            }
+            assert!(
+                !limit.windows.is_empty()
+                    && limit
+                        .windows
+                        .iter()
+                        .all(|window| window.limit > 0 && window.window_seconds > 0),
+                "invalid DMBO_LONG_LIMITS {path}: limit {} needs windows with limit and window_seconds of at least 1",
+                limit.name
+            );
+            limit.route = routes::normalize_route(&limit.route).template;
+            if let Some(method) = &mut limit.method {
+                *method = method.to_ascii_uppercase();
+            }
+            assert!(
+                names.insert(limit.name.clone()),
+                "invalid DMBO_LONG_LIMITS {path}: duplicate limit name {}",
+                limit.name
+            );
+        }
+        let denials = limits.iter().map(|_| AtomicU64::new(0)).collect();
+        Self {
+            limits,
+            denials,
+            registration,
+            registration_denials: AtomicU64::new(0),
+            webhook,
+            webhook_denials: AtomicU64::new(0),
+            message_send,
+            message_send_denials: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether no limits are configured; the registration class aside.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    /// Configured limits, for `/admin/config`.
+    pub(crate) fn describe(&self) -> serde_json::Value {
+        serde_json::json!(self.limits)
+    }
+
+    /// The command registration class, for `/admin/config`.
+    pub(crate) fn describe_registration(&self) -> serde_json::Value {
+        serde_json::json!(self.registration)
+    }
+
+    /// Permits denied by the registration class since startup.
+    pub(crate) fn registration_denials(&self) -> u64 {
+        self.registration_denials.load(Ordering::Relaxed)
+    }
+
+    /// The webhook class, for `/admin/config`.
+    pub(crate) fn describe_webhook(&self) -> serde_json::Value {
+        serde_json::json!(self.webhook)
+    }
+
+    /// Permits denied by the webhook class since startup.
+    pub(crate) fn webhook_denials(&self) -> u64 {
+        self.webhook_denials.load(Ordering::Relaxed)
+    }
+
+    /// The message send class, for `/admin/config`.
+    pub(crate) fn describe_message_send(&self) -> serde_json::Value {
+        serde_json::json!(self.message_send)
+    }
+
+    /// Permits denied by the message send class since startup.
+    pub(crate) fn message_send_denials(&self) -> u64 {
+        self.message_send_denials.load(Ordering::Relaxed)
+    }
+
+    /// `(name, denials)` per limit since startup.
+    pub(crate) fn denials(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.limits
+            .iter()
+            .zip(&self.denials)
+            .map(|(limit, denials)| (limit.name.as_str(), denials.load(Ordering::Relaxed)))
+    }
+
+    /// Reserves a slot in every limit matching `request`, the built-in
+    /// classes last. Returns `None` when no limit matches.
+    pub(crate) async fn acquire(
+        &self,
+        conn: &mut MultiplexedConnection,
+        request: &RequestTokenRequest,
+        bucket: &str,
+        now_ms: u64,
+    ) -> redis::RedisResult<Option<Acquired>> {
+        let matching: Vec<usize> = self
+            .limits
+            .iter()
+            .enumerate()
+            .filter(|(_, limit)| {
+                limit
+                    .method
+                    .as_deref()
+                    .is_none_or(|method| method == request.method)
+                    && route_matches(&limit.route, &request.route)
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let registration = self.registration.as_ref().filter(|_| {
+            REGISTRATION_METHODS.contains(&request.method.as_str())
+                && REGISTRATION_ROUTES
+                    .iter()
+                    .any(|pattern| route_matches(pattern, &request.route))
+        });
+        let webhook = self.webhook.as_ref().filter(|_| {
+            WEBHOOK_METHODS.contains(&request.method.as_str())
+                && !request.major_parameter.trim().is_empty()
+                && route_matches(WEBHOOK_ROUTE, &request.route)
+        });
+        let message_send = self.message_send.as_ref().filter(|_| {
+            request.method == "POST"
+                && !request.major_parameter.trim().is_empty()
+                && route_matches(MESSAGE_SEND_ROUTE, &request.route)
+        });
+        if matching.is_empty()
+            && registration.is_none()
+            && webhook.is_none()
+            && message_send.is_none()
+        {
+            return Ok(None);
+        }
+        // One entry per window; `owners` maps each back to its limit.
+        let mut keys = Vec::new();
+        let mut windows = Vec::new();
+        let mut owners = Vec::new();
+        for index in &matching {
+            let limit = &self.limits[*index];
+            let subject = match limit.per {
+                Scope::Resource => bucket.to_string(),
+                Scope::Identity => normalize_key_part(&request.discord_identity),
+            };
+            let key = format!("rl:long:{}:{subject}", normalize_key_part(&limit.name));
+            for window in &limit.windows {
+                keys.push(key.clone());
+                windows.push((window.limit, window.window_seconds * 1000));
+                owners.push(*index);
+            }
+        }
+        let mut builtins = Vec::new();
+        if let Some(class) = registration {
+            // Per bot and guild across every command endpoint and method;
+            // global commands have no major parameter.
+            let target = match request.major_parameter.trim() {
+                "" => "global".to_string(),
+                guild => normalize_key_part(guild),
+            };
+            keys.push(format!(
+                "rl:commands:{}:{target}",
+                normalize_key_part(&request.discord_identity)
+            ));
+            windows.push((class.limit, class.window_seconds * 1000));
+            builtins.push(Builtin::Registration);
+        }
+        if let Some(class) = webhook {
+            // The major parameter of a webhook route is its id; the token is
+            // kept out of Redis.
+            keys.push(format!(
+                "rl:webhook:{}",
+                normalize_key_part(&request.major_parameter)
+            ));
+            windows.push((class.limit, class.window_seconds * 1000));
+            builtins.push(Builtin::Webhook);
+        }
+        if let Some(class) = message_send {
+            keys.push(format!(
+                "rl:messages:{}:{}",
+                normalize_key_part(&request.discord_identity),
+                normalize_key_part(&request.major_parameter)
+            ));
+            windows.push((class.limit, class.window_seconds * 1000));
+            builtins.push(Builtin::MessageSend);
+        }
+        Ok(Some(match reserve(conn, keys, &windows, now_ms).await? {
+            Err((position, retry_after_ms)) if position >= owners.len() => {
+                let (counter, reason) = match builtins[position - owners.len()] {
+                    Builtin::Registration => (
+                        &self.registration_denials,
+                        Reason::CommandRegistrationExhausted,
+                    ),
+                    Builtin::Webhook => (&self.webhook_denials, Reason::WebhookExhausted),
+                    Builtin::MessageSend => {
+                        (&self.message_send_denials, Reason::ChannelSendExhausted)
+                    }
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                Acquired::Exhausted {
+                    reason,
+                    limit: None,
+                    retry_after_ms,
+                }
+            }
+            Err((position, retry_after_ms)) => {
+                let index = owners[position];
+                self.denials[index].fetch_add(1, Ordering::Relaxed);
+                Acquired::Exhausted {
+                    reason: Reason::LongWindowExhausted,
+                    limit: Some(self.limits[index].name.clone()),
+                    retry_after_ms,
+                }
+            }
+            Ok(reservation) => Acquired::Reserved(reservation),
+        }))
+    }
+}
+
+/// Reserves a slot at `now_ms` in every sliding log in `keys`, with the
+/// `(limit, window_ms)` in `windows` at the same index, when all have room.
+/// Otherwise returns the index of the full window that frees up last, and
+/// when it does.
+pub(crate) async fn reserve(
+    conn: &mut MultiplexedConnection,
+    keys: Vec<String>,
+    windows: &[(u64, u64)],
+    now_ms: u64,
+) -> redis::RedisResult<Result<Reservation, (usize, u64)>> {
+    let member = uuid::Uuid::new_v4().to_string();
+    let script = Script::new(ACQUIRE_LUA);
+    let mut invocation = script.prepare_invoke();
+    for key in &keys {
+        invocation.key(key);
+    }
+    invocation.arg(now_ms).arg(&member);
+    for (limit, window_ms) in windows {
+        invocation.arg(limit).arg(window_ms);
+    }
+    let reply: Vec<u64> = invocation.invoke_async(conn).await?;
+    Ok(match reply.as_slice() {
+        [0, retry_after_ms, position] => {
+            Err(((*position as usize).saturating_sub(1), *retry_after_ms))
+        }
+        _ => Ok(Reservation { keys, member }),
+    })
+}
+
+impl Reservation {
+    /// Gives the reserved slots back after the per-second limiter denied.
+    pub(crate) async fn release(self, conn: &mut MultiplexedConnection) {
+        let mut pipe = redis::pipe();
+        for key in &self.keys {
+            pipe.zrem(key, &self.member).ignore();
+        }
+        let _ = pipe.query_async::<_, ()>(conn).await;
+    }
+}
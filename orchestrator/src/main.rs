@@ -1,15 +1,49 @@
+use anomaly::AnomalyDetector;
 use axum::{
-    extract::State,
-    http::{header, StatusCode},
-    response::IntoResponse,
-    routing::{get, post},
+    extract::{rejection::JsonRejection, DefaultBodyLimit, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{any, delete, get, post, put},
     Json, Router,
 };
-use redis::{AsyncCommands, Script};
+use bucket_map::BucketMap;
+use codec::{Payload, PayloadRejection};
+use controls::Controls;
+use dlq::DeferredReports;
+use dmbo_core::{
+    keys::{self, bucket_id, counts_toward_invalid_limit, normalize_key_part},
+    routes, Algorithm, LimitInput, MethodWeights, Reason, RetryGuidance, RetryPolicy,
+    DEFAULT_GROUP_ID,
+};
+use forecast::{BucketState, Forecast, Forecaster};
+use gateway::Transport;
+use limiter::{RouteAlgorithms, ShadowStats};
+use loans::Loans;
+use long_limits::{Acquired, LongLimits};
+use maintenance::{Effect, Maintenance};
+use notifier::{Alert, Notifier, Severity};
+use plugins::Plugins;
+use priority::PriorityShares;
+use publisher::Publisher;
+use queue::QueueTicket;
+use quotas::QuotaAcquired;
+use reactions::ReactionAcquired;
+use redis::AsyncCommands;
+use region::RegionState;
+use route_limits::{RouteLimits, SlotAcquired};
+use rules::{route_matches, Rules};
+use schedule::Schedule;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use signals::Signals;
+use sql::SqlSink;
+use stats::{Decision, UsageStats};
+use statsd::Statsd;
 use std::{
+    collections::{BTreeMap, HashMap},
     env,
+    fmt::Write as _,
     net::SocketAddr,
     sync::{
         atomic::{AtomicU64, Ordering},
@@ -17,85 +51,509 @@ use std::{
     },
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::{net::TcpListener, time::sleep};
-
-const INVALID_COUNTER_TTL_SECONDS: i64 = 600;
-
-const REQUEST_TOKEN_LUA: &str = r#"
-local guard_key = KEYS[1]
-local global_key = KEYS[2]
-local route_key = KEYS[3]
-local global_limit = tonumber(ARGV[1])
-local route_limit = tonumber(ARGV[2])
-local ttl_ms = tonumber(ARGV[3])
-local min_retry_ms = tonumber(ARGV[4])
-
-local guard_ttl = redis.call('PTTL', guard_key)
-if guard_ttl and guard_ttl > 0 then
-  if guard_ttl < min_retry_ms then guard_ttl = min_retry_ms end
-  return {0, guard_ttl, 'invalid_guardrail_active'}
-end
-
-local global_count = redis.call('INCR', global_key)
-if global_count == 1 then redis.call('PEXPIRE', global_key, ttl_ms) end
-if global_count > global_limit then
-  local retry_ms = redis.call('PTTL', global_key)
-  if retry_ms < min_retry_ms then retry_ms = min_retry_ms end
-  return {0, retry_ms, 'global_bucket_exhausted'}
-end
-
-local route_count = redis.call('INCR', route_key)
-if route_count == 1 then redis.call('PEXPIRE', route_key, ttl_ms) end
-if route_count > route_limit then
-  local retry_ms = redis.call('PTTL', route_key)
-  if retry_ms < min_retry_ms then retry_ms = min_retry_ms end
-  return {0, retry_ms, 'route_bucket_exhausted'}
-end
-
-return {1, 0, 'ok'}
-"#;
-
-// Lua script to atomically increment a counter and set its expiration.
-// If redis.call fails, the error will be propagated to the caller.
-const INCR_WITH_EXPIRE_LUA: &str = r#"
-local key = KEYS[1]
-local ttl_seconds = tonumber(ARGV[1])
-
-local count = redis.call('INCR', key)
-if count == 1 then
-  redis.call('EXPIRE', key, ttl_seconds)
-end
-
-return count
-"#;
+use store::{GuardTrip, MemoryStore, PermitStore, RedisStore, StoreError};
+use tokio::{net::TcpListener, sync::Semaphore, time::sleep};
+use tower_http::compression::CompressionLayer;
+use uploads::UploadAcquired;
+use waiters::{WaiterGuard, WaiterInfo, Waiters};
+
+mod admin;
+mod anomaly;
+mod batch;
+mod bucket_map;
+mod callbacks;
+mod cloudflare;
+mod coalesce;
+mod codec;
+mod controls;
+mod dlq;
+mod forecast;
+mod gateway;
+mod identify;
+mod interactions;
+mod jobs;
+mod leases;
+mod limiter;
+mod listener;
+mod loans;
+mod long_limits;
+mod maintenance;
+#[cfg(feature = "nats")]
+mod nats;
+mod notifier;
+mod openapi;
+mod plugins;
+mod priority;
+mod proxy;
+mod proxy_cache;
+mod publisher;
+mod pubsub;
+mod queue;
+mod quotas;
+mod reactions;
+mod region;
+mod route_limits;
+mod rules;
+mod schedule;
+mod signals;
+mod sql;
+mod stats;
+mod statsd;
+mod store;
+mod uploads;
+mod upstream;
+mod waiters;
+mod wakeups;
+mod ws;
+
+const PROTOCOL_VERSION: u32 = 1;
+/// API versions this build serves; the last is the current one.
+const SUPPORTED_API_VERSIONS: [u32; 1] = [PROTOCOL_VERSION];
+const API_VERSION_HEADER: &str = "dmbo-api-version";
+const SUPPORTED_TRANSPORTS: [&str; 2] = ["http", "gateway"];
+const AUDIT_STREAM_KEY: &str = "rl:audit";
+
+const MAX_ROUTE_LEN: usize = 512;
+const MAX_MAJOR_PARAMETER_LEN: usize = 128;
+/// Major parameter of calls whose route has none, as the JS client sends.
+const UNKNOWN_MAJOR_PARAMETER: &str = "unknown";
+const MAX_IDENTITY_LEN: usize = 256;
+const MAX_FEATURE_LEN: usize = 64;
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+/// Largest `cost` a single permit may ask for.
+const MAX_REQUEST_COST: u64 = 100;
+const KNOWN_METHODS: [&str; 5] = ["GET", "POST", "PUT", "PATCH", "DELETE"];
+/// Interaction callbacks and followups (webhook routes keyed on the
+/// interaction token) do not count toward a bot's global limit.
+const DEFAULT_GLOBAL_EXEMPT_ROUTES: &str = "/interactions/**,/webhooks/**";
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+const PROBLEM_TYPE_RATE_LIMITED: &str = "urn:dmbo:problem:rate-limited";
+const PROBLEM_TYPE_BACKEND_UNAVAILABLE: &str = "urn:dmbo:problem:backend-unavailable";
+const PROBLEM_TYPE_INVALID_REQUEST: &str = "urn:dmbo:problem:invalid-request";
+const PROBLEM_TYPE_UNAUTHORIZED: &str = "urn:dmbo:problem:unauthorized";
+const PROBLEM_TYPE_IDEMPOTENCY_CONFLICT: &str = "urn:dmbo:problem:idempotency-conflict";
+const PROBLEM_TYPE_LEASE_MISMATCH: &str = "urn:dmbo:problem:lease-mismatch";
+const PROBLEM_TYPE_QUEUE_FULL: &str = "urn:dmbo:problem:queue-full";
+const PROBLEM_TYPE_UNSUPPORTED_API_VERSION: &str = "urn:dmbo:problem:unsupported-api-version";
+const PROBLEM_TYPE_UPSTREAM_UNAVAILABLE: &str = "urn:dmbo:problem:upstream-unavailable";
+const IDEMPOTENCY_PENDING: &str = "pending";
 
 #[derive(Clone)]
 struct Config {
-    bind_addr: SocketAddr,
+    bind: Bind,
     redis_url: String,
+    /// `DMBO_STORE=memory`: limiter and guardrail state in this process.
+    memory_store: bool,
     global_rps: u64,
+    /// Global limits of identities Discord has raised above the default.
+    identity_global_rps: BTreeMap<String, u64>,
     route_rps: u64,
     min_retry_ms: u64,
     invalid_threshold: u64,
     guardrail_cooldown_ms: u64,
     redis_required_for_health: bool,
+    legacy_status_codes: bool,
+    retry_max_delay_ms: u64,
+    retry_jitter_ms: u64,
+    retry_give_up_attempts: u32,
+    idempotency_ttl_seconds: u64,
+    /// How long a `request_token` is replayed for repeats of its body
+    /// `request_id` when no `Idempotency-Key` is sent; 0 disables.
+    request_id_dedup_seconds: u64,
+    /// Window in which identical GETs share one permit; 0 disables.
+    coalesce_get_ms: u64,
+    audit_maxlen: u64,
+    max_wait_cap_ms: u64,
+    queue_poll_ms: u64,
+    /// How often a long-poll waiter checks for its turn unwoken.
+    long_poll_recheck_ms: u64,
+    /// Waiting handlers this instance holds before shedding; 0 is unlimited.
+    max_waiters: u64,
+    /// Waiting handlers one `client_id` may hold here; 0 is unlimited.
+    max_waiters_per_client: u64,
+    /// Hosts a `callback_url` may name; empty allows any public address.
+    callback_allowed_hosts: Vec<String>,
+    strict_queue_full: bool,
+    admin_token: Option<String>,
+    admin_scan_limit: usize,
+    clock_skew_threshold_ms: u64,
+    discord_api_base: String,
+    discord_tokens: HashMap<String, String>,
+    job_ttl_seconds: u64,
+    job_concurrency: usize,
+    job_max_attempts: u32,
+    /// Time allowed for retrying one Discord call after its first attempt.
+    upstream_retry_budget_ms: u64,
+    /// Serve `/api/*` as a reverse proxy to `discord_api_base`.
+    proxy: bool,
+    proxy_max_attempts: u32,
+    proxy_max_body_bytes: usize,
+    /// How long proxied GET responses are cached; 0 disables the cache.
+    proxy_cache_ttl_ms: u64,
+    /// Redis channel permit requests may be published on.
+    pubsub_channel: Option<String>,
+    job_claim_idle_ms: u64,
+    instance_id: String,
+    stats_minute_retention: u64,
+    stats_hour_retention: u64,
+    region: Option<String>,
+    coordinator_redis_url: Option<String>,
+    region_sync_ms: u64,
+    anomaly_interval_ms: u64,
+    anomaly_alpha: f64,
+    anomaly_factor: f64,
+    anomaly_min_events: u64,
+    anomaly_warmup_intervals: u32,
+    anomaly_auto_tighten: bool,
+    anomaly_tighten_percent: u64,
+    anomaly_tighten_ms: u64,
+    dlq_maxlen: u64,
+    dlq_report_attempts: u32,
+    dlq_buffer_capacity: usize,
+    rules_path: Option<String>,
+    schedule_path: Option<String>,
+    maintenance_path: Option<String>,
+    /// PEM certificate chain and private key; TCP is served over TLS when
+    /// both are set.
+    tls_cert_path: Option<String>,
+    tls_key_path: Option<String>,
+    long_limits_path: Option<String>,
+    route_limits_path: Option<String>,
+    /// Lease of a per-route concurrency slot whose report never arrives.
+    route_concurrency_lease_ms: u64,
+    /// Calls in flight per route bucket where no route limit entry sets
+    /// `concurrency`; 0 disables.
+    route_max_inflight: u64,
+    /// How long a granted permit's lease is held without a report.
+    lease_ttl_ms: u64,
+    /// Refuse reports whose `lease_id` names no outstanding lease of theirs,
+    /// instead of only counting them.
+    reject_lease_mismatch: bool,
+    /// Command registration writes per bot and guild per window; 0 disables.
+    command_registration_limit: u64,
+    command_registration_window_seconds: u64,
+    /// Webhook calls per webhook per window; 0 disables.
+    webhook_limit: u64,
+    webhook_window_seconds: u64,
+    /// Message sends per bot and channel per window; 0 disables.
+    message_send_limit: u64,
+    message_send_window_seconds: u64,
+    /// Granted permits per identity / per group per UTC day; 0 disables.
+    identity_daily_quota: u64,
+    group_daily_quota: u64,
+    /// Spacing between reactions on one channel; 0 disables pacing.
+    reaction_interval_ms: u64,
+    /// Furthest ahead a reaction may be scheduled before it is denied.
+    reaction_max_ahead_ms: u64,
+    /// Upload bytes allowed per identity per interval; 0 disables.
+    upload_bytes_per_interval: u64,
+    upload_interval_ms: u64,
+    /// Uploads this large take a concurrency slot; a cap of 0 disables.
+    large_upload_bytes: u64,
+    large_upload_concurrency: u64,
+    large_upload_lease_ms: u64,
+    /// Lifetime of a learned route → Discord bucket mapping; 0 disables.
+    bucket_map_ttl_seconds: u64,
+    /// Deadline given to interaction callbacks that do not carry one.
+    interaction_deadline_ms: u64,
+    /// Gateway commands and presence updates allowed per shard per minute.
+    gateway_command_limit: u64,
+    gateway_presence_limit: u64,
+    /// Spacing between IDENTIFYs in one `max_concurrency` bucket.
+    identify_interval_ms: u64,
+    plugin_lua_path: Option<String>,
+    plugin_wasm_path: Option<String>,
+    #[cfg_attr(not(feature = "wasm-plugins"), allow(dead_code))]
+    plugin_wasm_fuel: u64,
+    /// Feature tags requests may carry; empty disables feature accounting.
+    features: Vec<String>,
+    limiter_algorithm: Algorithm,
+    shadow_algorithm: Option<Algorithm>,
+    /// Algorithms for matching routes' limits in place of `limiter_algorithm`.
+    route_algorithms: RouteAlgorithms,
+    /// Route patterns Discord does not count against the global limit.
+    global_exempt_routes: Vec<String>,
+    /// Token bucket capacity as a percentage of each limit.
+    token_bucket_burst_percent: u64,
+    /// Cost of a request against the per-second limits, by method.
+    method_weights: MethodWeights,
+    /// Percent of each limit a priority class may fill.
+    priority_shares: PriorityShares,
+    /// Waiting time that raises a request's priority class one step.
+    priority_aging_ms: u64,
+    /// Deny grants while a reported Discord bucket has nothing remaining.
+    honor_discord_remaining: bool,
+    /// Longest cooldown a reported 429's Retry-After can set; 0 disables.
+    reported_cooldown_max_ms: u64,
+    /// Guard cooldown for a first Cloudflare ban, doubling per repeat within
+    /// the strike window up to the maximum; 0 disables.
+    cloudflare_cooldown_ms: u64,
+    cloudflare_cooldown_max_ms: u64,
+    cloudflare_strike_window_seconds: u64,
+    loan_max_duration_ms: u64,
+    /// StatsD `host:port`; unset disables the exporter.
+    statsd_addr: Option<String>,
+    statsd_prefix: String,
+    statsd_tags: Vec<String>,
+    statsd_dogstatsd: bool,
+    statsd_interval_ms: u64,
+    /// Discord webhook for ops alerts; unset disables alerting.
+    alert_webhook_url: Option<String>,
+    alert_cooldown_ms: u64,
+    alert_max_per_minute: u64,
+    alert_redis_outage_ms: u64,
+    alert_429_per_minute: u64,
+    alert_429_minutes: u64,
+    events_nats_url: Option<String>,
+    events_kafka_brokers: Option<String>,
+    events_prefix: String,
+    /// NATS server permit requests and reports are accepted from.
+    nats_url: Option<String>,
+    nats_subject: String,
+    /// Postgres or SQLite URL for long-term history; unset disables it.
+    sql_url: Option<String>,
+    #[cfg_attr(not(feature = "sql"), allow(dead_code))]
+    sql_audit_retention_days: u64,
+    #[cfg_attr(not(feature = "sql"), allow(dead_code))]
+    sql_usage_retention_days: u64,
 }
 
 impl Config {
     fn from_env() -> Self {
-        let bind_addr = env::var("DMBO_BIND")
+        let bind = env::var("DMBO_BIND")
             .ok()
-            .and_then(|value| value.parse::<SocketAddr>().ok())
-            .unwrap_or_else(|| "127.0.0.1:8787".parse().expect("default bind should parse"));
+            .and_then(|value| Bind::parse(&value))
+            .unwrap_or_else(|| {
+                Bind::Tcp("127.0.0.1:8787".parse().expect("default bind should parse"))
+            });
+        let memory_store = match env::var("DMBO_STORE").as_deref() {
+            Err(_) | Ok("redis") => false,
+            Ok("memory") => true,
+            Ok(other) => panic!("invalid DMBO_STORE: {other:?} is not redis or memory"),
+        };
+        if memory_store {
+            let conflicts = memory_store_conflicts();
+            if !conflicts.is_empty() {
+                panic!(
+                    "DMBO_STORE=memory cannot be combined with {}: their state is kept in Redis",
+                    conflicts.join(", ")
+                );
+            }
+        }
+        // Built-in features that keep their state in Redis are off in memory.
+        let redis_default = |value: u64| if memory_store { 0 } else { value };
         Self {
-            bind_addr,
+            bind,
             redis_url: env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379/".to_string()),
+            memory_store,
             global_rps: env_u64("DMBO_GLOBAL_RPS", 50),
+            identity_global_rps: env_identity_global_rps(),
             route_rps: env_u64("DMBO_ROUTE_RPS", 5),
             min_retry_ms: env_u64("DMBO_MIN_RETRY_MS", 50),
             invalid_threshold: env_u64("DMBO_INVALID_THRESHOLD", 8000),
             guardrail_cooldown_ms: env_u64("DMBO_GUARDRAIL_COOLDOWN_MS", 30000),
             redis_required_for_health: env_bool("DMBO_REDIS_REQUIRED_FOR_HEALTH", true),
+            legacy_status_codes: env_bool("DMBO_LEGACY_STATUS_CODES", false),
+            retry_max_delay_ms: env_u64("DMBO_RETRY_MAX_DELAY_MS", 5000),
+            retry_jitter_ms: env_u64("DMBO_RETRY_JITTER_MS", 25),
+            retry_give_up_attempts: env_u64("DMBO_RETRY_GIVE_UP_ATTEMPTS", 0) as u32,
+            idempotency_ttl_seconds: env_u64("DMBO_IDEMPOTENCY_TTL_SECONDS", 300),
+            request_id_dedup_seconds: env_u64("DMBO_REQUEST_ID_DEDUP_SECONDS", 30),
+            coalesce_get_ms: env_u64("DMBO_COALESCE_GET_MS", 0),
+            audit_maxlen: env_u64("DMBO_AUDIT_MAXLEN", 10_000),
+            max_wait_cap_ms: env_u64("DMBO_MAX_WAIT_CAP_MS", 30_000),
+            queue_poll_ms: env_u64("DMBO_QUEUE_POLL_MS", 250),
+            long_poll_recheck_ms: env_u64("DMBO_LONG_POLL_RECHECK_MS", 2_000),
+            max_waiters: env_u64("DMBO_MAX_WAITERS", 10_000),
+            max_waiters_per_client: env_u64("DMBO_MAX_WAITERS_PER_CLIENT", 1_000),
+            callback_allowed_hosts: env::var("DMBO_CALLBACK_ALLOWED_HOSTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|host| host.trim().to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            strict_queue_full: env_bool("DMBO_STRICT_QUEUE_FULL", false),
+            admin_token: env::var("DMBO_ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            admin_scan_limit: env_u64("DMBO_ADMIN_SCAN_LIMIT", 10_000) as usize,
+            clock_skew_threshold_ms: env_u64("DMBO_CLOCK_SKEW_THRESHOLD_MS", 1000),
+            discord_api_base: env::var("DMBO_DISCORD_API_BASE")
+                .unwrap_or_else(|_| "https://discord.com/api/v10".to_string()),
+            discord_tokens: env::vars()
+                .filter_map(|(key, value)| {
+                    let name = key.strip_prefix("DMBO_TOKEN_")?;
+                    (!name.is_empty() && !value.is_empty()).then(|| (name.to_string(), value))
+                })
+                .collect(),
+            job_ttl_seconds: env_u64("DMBO_JOB_TTL_SECONDS", 86_400),
+            job_concurrency: env_u64("DMBO_JOB_CONCURRENCY", 16).max(1) as usize,
+            job_max_attempts: env_u64("DMBO_JOB_MAX_ATTEMPTS", 3).max(1) as u32,
+            upstream_retry_budget_ms: env_u64("DMBO_UPSTREAM_RETRY_BUDGET_MS", 60_000),
+            proxy: env_bool("DMBO_PROXY", false),
+            proxy_max_attempts: env_u64("DMBO_PROXY_MAX_ATTEMPTS", 3).max(1) as u32,
+            proxy_max_body_bytes: env_u64("DMBO_PROXY_MAX_BODY_BYTES", 26_214_400) as usize,
+            proxy_cache_ttl_ms: env_u64("DMBO_PROXY_CACHE_TTL_MS", 0),
+            pubsub_channel: env::var("DMBO_PUBSUB_CHANNEL")
+                .ok()
+                .filter(|channel| !channel.is_empty()),
+            job_claim_idle_ms: env_u64("DMBO_JOB_CLAIM_IDLE_MS", 60_000).max(1),
+            instance_id: env::var("DMBO_INSTANCE_ID")
+                .or_else(|_| env::var("HOSTNAME"))
+                .ok()
+                .filter(|id| !id.is_empty())
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            stats_minute_retention: env_u64("DMBO_STATS_MINUTE_RETENTION", 1440),
+            stats_hour_retention: env_u64("DMBO_STATS_HOUR_RETENTION", 168),
+            region: env::var("DMBO_REGION")
+                .ok()
+                .filter(|region| !region.is_empty()),
+            coordinator_redis_url: env::var("DMBO_COORDINATOR_REDIS_URL")
+                .ok()
+                .filter(|url| !url.is_empty()),
+            region_sync_ms: env_u64("DMBO_REGION_SYNC_MS", 1000).max(100),
+            anomaly_interval_ms: env_u64("DMBO_ANOMALY_INTERVAL_MS", 10_000).max(100),
+            anomaly_alpha: env_f64("DMBO_ANOMALY_EWMA_ALPHA", 0.1).clamp(0.001, 1.0),
+            anomaly_factor: env_f64("DMBO_ANOMALY_FACTOR", 3.0),
+            anomaly_min_events: env_u64("DMBO_ANOMALY_MIN_EVENTS", 5),
+            anomaly_warmup_intervals: env_u64("DMBO_ANOMALY_WARMUP_INTERVALS", 6) as u32,
+            anomaly_auto_tighten: env_bool("DMBO_ANOMALY_AUTO_TIGHTEN", false),
+            anomaly_tighten_percent: env_u64("DMBO_ANOMALY_TIGHTEN_PERCENT", 50).clamp(1, 100),
+            anomaly_tighten_ms: env_u64("DMBO_ANOMALY_TIGHTEN_MS", 300_000).max(1),
+            dlq_maxlen: env_u64("DMBO_DLQ_MAXLEN", 10_000),
+            dlq_report_attempts: env_u64("DMBO_DLQ_REPORT_ATTEMPTS", 5).max(1) as u32,
+            dlq_buffer_capacity: env_u64("DMBO_DLQ_BUFFER_CAPACITY", 10_000) as usize,
+            rules_path: env::var("DMBO_RULES").ok().filter(|path| !path.is_empty()),
+            tls_cert_path: env::var("DMBO_TLS_CERT")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            tls_key_path: env::var("DMBO_TLS_KEY")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            schedule_path: env::var("DMBO_SCHEDULE")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            maintenance_path: env::var("DMBO_MAINTENANCE")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            long_limits_path: env::var("DMBO_LONG_LIMITS")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            route_limits_path: env::var("DMBO_ROUTE_LIMITS")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            route_concurrency_lease_ms: env_u64("DMBO_ROUTE_CONCURRENCY_LEASE_MS", 30_000).max(1),
+            route_max_inflight: env_u64("DMBO_ROUTE_MAX_INFLIGHT", 0),
+            lease_ttl_ms: env_u64("DMBO_LEASE_TTL_MS", 30_000).max(1),
+            reject_lease_mismatch: env_bool("DMBO_REJECT_LEASE_MISMATCH", false),
+            command_registration_limit: env_u64(
+                "DMBO_COMMAND_REGISTRATION_LIMIT",
+                redis_default(200),
+            ),
+            command_registration_window_seconds: env_u64(
+                "DMBO_COMMAND_REGISTRATION_WINDOW_S",
+                86_400,
+            )
+            .max(1),
+            webhook_limit: env_u64("DMBO_WEBHOOK_LIMIT", redis_default(30)),
+            webhook_window_seconds: env_u64("DMBO_WEBHOOK_WINDOW_S", 60).max(1),
+            message_send_limit: env_u64("DMBO_MESSAGE_SEND_LIMIT", redis_default(5)),
+            message_send_window_seconds: env_u64("DMBO_MESSAGE_SEND_WINDOW_S", 5).max(1),
+            identity_daily_quota: env_u64("DMBO_IDENTITY_DAILY_QUOTA", 0),
+            group_daily_quota: env_u64("DMBO_GROUP_DAILY_QUOTA", 0),
+            reaction_interval_ms: env_u64("DMBO_REACTION_INTERVAL_MS", redis_default(250)),
+            reaction_max_ahead_ms: env_u64("DMBO_REACTION_MAX_AHEAD_MS", 2000),
+            upload_bytes_per_interval: env_u64(
+                "DMBO_UPLOAD_BYTES_PER_INTERVAL",
+                redis_default(104_857_600),
+            ),
+            upload_interval_ms: env_u64("DMBO_UPLOAD_INTERVAL_MS", 10_000).max(1),
+            large_upload_bytes: env_u64("DMBO_LARGE_UPLOAD_BYTES", 26_214_400).max(1),
+            large_upload_concurrency: env_u64("DMBO_LARGE_UPLOAD_CONCURRENCY", redis_default(2)),
+            large_upload_lease_ms: env_u64("DMBO_LARGE_UPLOAD_LEASE_MS", 120_000).max(1),
+            bucket_map_ttl_seconds: env_u64("DMBO_BUCKET_MAP_TTL_S", 2_592_000),
+            interaction_deadline_ms: env_u64("DMBO_INTERACTION_DEADLINE_MS", 3000),
+            gateway_command_limit: env_u64("DMBO_GATEWAY_COMMAND_LIMIT", 120).max(1),
+            gateway_presence_limit: env_u64("DMBO_GATEWAY_PRESENCE_LIMIT", 5).max(1),
+            identify_interval_ms: env_u64("DMBO_IDENTIFY_INTERVAL_MS", 5000).max(1),
+            plugin_lua_path: env::var("DMBO_PLUGIN_LUA")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            plugin_wasm_path: env::var("DMBO_PLUGIN_WASM")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            plugin_wasm_fuel: env_u64("DMBO_PLUGIN_WASM_FUEL", 1_000_000).max(1),
+            features: env_features(),
+            limiter_algorithm: env_algorithm("DMBO_LIMITER_ALGORITHM")
+                .unwrap_or(Algorithm::FixedWindow),
+            shadow_algorithm: env_algorithm("DMBO_SHADOW_ALGORITHM"),
+            route_algorithms: RouteAlgorithms::parse(
+                &env::var("DMBO_ROUTE_ALGORITHMS").unwrap_or_default(),
+            )
+            .unwrap_or_else(|error| panic!("invalid DMBO_ROUTE_ALGORITHMS: {error}")),
+            global_exempt_routes: env::var("DMBO_GLOBAL_EXEMPT_ROUTES")
+                .unwrap_or_else(|_| DEFAULT_GLOBAL_EXEMPT_ROUTES.to_string())
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(str::to_string)
+                .collect(),
+            token_bucket_burst_percent: env_u64("DMBO_TOKEN_BUCKET_BURST_PERCENT", 100)
+                .clamp(1, 1000),
+            method_weights: MethodWeights::parse(
+                &env::var("DMBO_METHOD_WEIGHTS").unwrap_or_default(),
+            )
+            .unwrap_or_else(|error| panic!("invalid DMBO_METHOD_WEIGHTS: {error}")),
+            priority_shares: PriorityShares::parse(
+                &env::var("DMBO_PRIORITY_SHARES").unwrap_or_default(),
+            )
+            .unwrap_or_else(|error| panic!("invalid DMBO_PRIORITY_SHARES: {error}")),
+            priority_aging_ms: env_u64("DMBO_PRIORITY_AGING_MS", 5_000),
+            honor_discord_remaining: env_bool("DMBO_HONOR_DISCORD_REMAINING", !memory_store),
+            reported_cooldown_max_ms: env_u64("DMBO_REPORTED_COOLDOWN_MAX_MS", 300_000),
+            cloudflare_cooldown_ms: env_u64("DMBO_CLOUDFLARE_COOLDOWN_MS", 900_000),
+            cloudflare_cooldown_max_ms: env_u64("DMBO_CLOUDFLARE_COOLDOWN_MAX_MS", 14_400_000)
+                .max(1),
+            cloudflare_strike_window_seconds: env_u64("DMBO_CLOUDFLARE_STRIKE_WINDOW_S", 86_400)
+                .max(1),
+            loan_max_duration_ms: env_u64("DMBO_LOAN_MAX_MS", 86_400_000).max(1),
+            statsd_addr: env::var("DMBO_STATSD_ADDR")
+                .ok()
+                .filter(|addr| !addr.is_empty()),
+            statsd_prefix: env::var("DMBO_STATSD_PREFIX").unwrap_or_else(|_| "dmbo.".to_string()),
+            statsd_tags: env::var("DMBO_STATSD_TAGS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect(),
+            statsd_dogstatsd: env_bool("DMBO_STATSD_DOGSTATSD", true),
+            statsd_interval_ms: env_u64("DMBO_STATSD_INTERVAL_MS", 10_000).max(100),
+            alert_webhook_url: env::var("DMBO_ALERT_WEBHOOK_URL")
+                .ok()
+                .filter(|url| !url.is_empty()),
+            alert_cooldown_ms: env_u64("DMBO_ALERT_COOLDOWN_MS", 300_000),
+            alert_max_per_minute: env_u64("DMBO_ALERT_MAX_PER_MINUTE", 10).max(1),
+            alert_redis_outage_ms: env_u64("DMBO_ALERT_REDIS_OUTAGE_MS", 15_000),
+            alert_429_per_minute: env_u64("DMBO_ALERT_429_PER_MINUTE", 30).max(1),
+            alert_429_minutes: env_u64("DMBO_ALERT_429_MINUTES", 3).max(1),
+            events_nats_url: env::var("DMBO_EVENTS_NATS_URL")
+                .ok()
+                .filter(|url| !url.is_empty()),
+            events_kafka_brokers: env::var("DMBO_EVENTS_KAFKA_BROKERS")
+                .ok()
+                .filter(|brokers| !brokers.is_empty()),
+            events_prefix: env::var("DMBO_EVENTS_PREFIX").unwrap_or_else(|_| "dmbo".to_string()),
+            nats_url: env::var("DMBO_NATS_URL").ok().filter(|url| !url.is_empty()),
+            nats_subject: env::var("DMBO_NATS_SUBJECT")
+                .ok()
+                .filter(|subject| !subject.is_empty())
+                .unwrap_or_else(|| "dmbo".to_string()),
+            sql_url: env::var("DMBO_SQL_URL").ok().filter(|url| !url.is_empty()),
+            sql_audit_retention_days: env_u64("DMBO_SQL_AUDIT_RETENTION_DAYS", 30).max(1),
+            sql_usage_retention_days: env_u64("DMBO_SQL_USAGE_RETENTION_DAYS", 365).max(1),
         }
     }
 }
@@ -108,6 +566,24 @@ struct Metrics {
     tokens_granted_total: Arc<AtomicU64>,
     tokens_denied_total: Arc<AtomicU64>,
     queue_depth: Arc<AtomicU64>,
+    queue_holds: Arc<AtomicU64>,
+    queue_shed: Arc<AtomicU64>,
+    client_queue_shed: Arc<AtomicU64>,
+    requests_cancelled: Arc<AtomicU64>,
+    aged_grants: Arc<AtomicU64>,
+    queue_preemptions: Arc<AtomicU64>,
+    coalesced: Arc<AtomicU64>,
+    batch_items: Arc<AtomicU64>,
+    ws_connections: Arc<AtomicU64>,
+    relayed_wakeups: Arc<AtomicU64>,
+    signals_sent: Arc<AtomicU64>,
+    proxied_requests: Arc<AtomicU64>,
+    permit_callbacks_sent: Arc<AtomicU64>,
+    permit_callbacks_failed: Arc<AtomicU64>,
+    pubsub_commands: Arc<AtomicU64>,
+    pubsub_invalid: Arc<AtomicU64>,
+    nats_permit_requests: Arc<AtomicU64>,
+    nats_reports: Arc<AtomicU64>,
     inflight_requests: Arc<AtomicU64>,
     redis_errors_total: Arc<AtomicU64>,
     observed_429_global: Arc<AtomicU64>,
@@ -121,10 +597,72 @@ struct Metrics {
     request_wait_ms_count: Arc<AtomicU64>,
     redis_latency_ms_sum: Arc<AtomicU64>,
     redis_latency_ms_count: Arc<AtomicU64>,
+    clock_skew_exceeded_total: Arc<AtomicU64>,
+    clock_skew_last_abs_ms: Arc<AtomicU64>,
+    jobs_submitted: Arc<AtomicU64>,
+    jobs_succeeded: Arc<AtomicU64>,
+    jobs_failed: Arc<AtomicU64>,
+    jobs_reclaimed: Arc<AtomicU64>,
+    upstream_retries_429: Arc<AtomicU64>,
+    upstream_retries_5xx: Arc<AtomicU64>,
+    upstream_retries_network: Arc<AtomicU64>,
+    upstream_retry_budget_exhausted: Arc<AtomicU64>,
+    proxy_cache_hits: Arc<AtomicU64>,
+    proxy_cache_misses: Arc<AtomicU64>,
+    proxy_cache_stores: Arc<AtomicU64>,
+    dead_lettered_reports: Arc<AtomicU64>,
+    anomalies_429: Arc<AtomicU64>,
+    region_sync_failures: Arc<AtomicU64>,
+    anomalies_invalid: Arc<AtomicU64>,
+    dead_lettered_jobs: Arc<AtomicU64>,
+    deferred_reports_dropped: Arc<AtomicU64>,
+    rule_denials: Arc<AtomicU64>,
+    maintenance_denials: Arc<AtomicU64>,
+    group_pause_denials: Arc<AtomicU64>,
+    gateway_command_denials: Arc<AtomicU64>,
+    upload_bytes_granted: Arc<AtomicU64>,
+    upload_bytes_denials: Arc<AtomicU64>,
+    upload_concurrency_denials: Arc<AtomicU64>,
+    interaction_yields: Arc<AtomicU64>,
+    bucket_map_writes: Arc<AtomicU64>,
+    reported_cooldowns_global: Arc<AtomicU64>,
+    reported_cooldowns_route: Arc<AtomicU64>,
+    reported_cooldowns_shared: Arc<AtomicU64>,
+    reported_cooldowns_sublimit: Arc<AtomicU64>,
+    cloudflare_bans: Arc<AtomicU64>,
+    reaction_pacing_delays: Arc<AtomicU64>,
+    reaction_pacing_denials: Arc<AtomicU64>,
+    identify_grants: Arc<AtomicU64>,
+    identify_denials: Arc<AtomicU64>,
+    leases_issued: Arc<AtomicU64>,
+    leases_released: Arc<AtomicU64>,
+    leases_reported: Arc<AtomicU64>,
+    leases_expired: Arc<AtomicU64>,
+    lease_extensions: Arc<AtomicU64>,
+    lease_mismatches_unknown: Arc<AtomicU64>,
+    lease_mismatches_foreign: Arc<AtomicU64>,
+    daily_quota_denials_identity: Arc<AtomicU64>,
+    daily_quota_denials_group: Arc<AtomicU64>,
+    interaction_deadline_misses: Arc<AtomicU64>,
+    gateway_presence_denials: Arc<AtomicU64>,
+    maintenance_windows_started: Arc<AtomicU64>,
+    plugin_permit_vetoes: Arc<AtomicU64>,
+    plugin_report_vetoes: Arc<AtomicU64>,
+    plugin_errors: Arc<AtomicU64>,
+    alerts_sent: Arc<AtomicU64>,
+    alerts_suppressed: Arc<AtomicU64>,
+    alerts_failed: Arc<AtomicU64>,
+    events_published: Arc<AtomicU64>,
+    events_dropped: Arc<AtomicU64>,
+    events_failed: Arc<AtomicU64>,
+    sql_records_written: Arc<AtomicU64>,
+    sql_records_dropped: Arc<AtomicU64>,
+    sql_records_failed: Arc<AtomicU64>,
+    statsd: Statsd,
 }
 
 impl Metrics {
-    fn new() -> Self {
+    fn new(statsd: Statsd) -> Self {
         Self {
             request_granted: Arc::new(AtomicU64::new(0)),
             request_denied: Arc::new(AtomicU64::new(0)),
@@ -132,6 +670,24 @@ impl Metrics {
             tokens_granted_total: Arc::new(AtomicU64::new(0)),
             tokens_denied_total: Arc::new(AtomicU64::new(0)),
             queue_depth: Arc::new(AtomicU64::new(0)),
+            queue_holds: Arc::new(AtomicU64::new(0)),
+            queue_shed: Arc::new(AtomicU64::new(0)),
+            client_queue_shed: Arc::new(AtomicU64::new(0)),
+            requests_cancelled: Arc::new(AtomicU64::new(0)),
+            aged_grants: Arc::new(AtomicU64::new(0)),
+            queue_preemptions: Arc::new(AtomicU64::new(0)),
+            coalesced: Arc::new(AtomicU64::new(0)),
+            batch_items: Arc::new(AtomicU64::new(0)),
+            ws_connections: Arc::new(AtomicU64::new(0)),
+            relayed_wakeups: Arc::new(AtomicU64::new(0)),
+            signals_sent: Arc::new(AtomicU64::new(0)),
+            proxied_requests: Arc::new(AtomicU64::new(0)),
+            permit_callbacks_sent: Arc::new(AtomicU64::new(0)),
+            permit_callbacks_failed: Arc::new(AtomicU64::new(0)),
+            pubsub_commands: Arc::new(AtomicU64::new(0)),
+            pubsub_invalid: Arc::new(AtomicU64::new(0)),
+            nats_permit_requests: Arc::new(AtomicU64::new(0)),
+            nats_reports: Arc::new(AtomicU64::new(0)),
             inflight_requests: Arc::new(AtomicU64::new(0)),
             redis_errors_total: Arc::new(AtomicU64::new(0)),
             observed_429_global: Arc::new(AtomicU64::new(0)),
@@ -145,12 +701,75 @@ impl Metrics {
             request_wait_ms_count: Arc::new(AtomicU64::new(0)),
             redis_latency_ms_sum: Arc::new(AtomicU64::new(0)),
             redis_latency_ms_count: Arc::new(AtomicU64::new(0)),
+            clock_skew_exceeded_total: Arc::new(AtomicU64::new(0)),
+            clock_skew_last_abs_ms: Arc::new(AtomicU64::new(0)),
+            jobs_submitted: Arc::new(AtomicU64::new(0)),
+            jobs_succeeded: Arc::new(AtomicU64::new(0)),
+            jobs_failed: Arc::new(AtomicU64::new(0)),
+            jobs_reclaimed: Arc::new(AtomicU64::new(0)),
+            upstream_retries_429: Arc::new(AtomicU64::new(0)),
+            upstream_retries_5xx: Arc::new(AtomicU64::new(0)),
+            upstream_retries_network: Arc::new(AtomicU64::new(0)),
+            upstream_retry_budget_exhausted: Arc::new(AtomicU64::new(0)),
+            proxy_cache_hits: Arc::new(AtomicU64::new(0)),
+            proxy_cache_misses: Arc::new(AtomicU64::new(0)),
+            proxy_cache_stores: Arc::new(AtomicU64::new(0)),
+            dead_lettered_reports: Arc::new(AtomicU64::new(0)),
+            anomalies_429: Arc::new(AtomicU64::new(0)),
+            region_sync_failures: Arc::new(AtomicU64::new(0)),
+            anomalies_invalid: Arc::new(AtomicU64::new(0)),
+            dead_lettered_jobs: Arc::new(AtomicU64::new(0)),
+            deferred_reports_dropped: Arc::new(AtomicU64::new(0)),
+            rule_denials: Arc::new(AtomicU64::new(0)),
+            maintenance_denials: Arc::new(AtomicU64::new(0)),
+            group_pause_denials: Arc::new(AtomicU64::new(0)),
+            gateway_command_denials: Arc::new(AtomicU64::new(0)),
+            upload_bytes_granted: Arc::new(AtomicU64::new(0)),
+            upload_bytes_denials: Arc::new(AtomicU64::new(0)),
+            upload_concurrency_denials: Arc::new(AtomicU64::new(0)),
+            interaction_yields: Arc::new(AtomicU64::new(0)),
+            bucket_map_writes: Arc::new(AtomicU64::new(0)),
+            reported_cooldowns_global: Arc::new(AtomicU64::new(0)),
+            reported_cooldowns_route: Arc::new(AtomicU64::new(0)),
+            reported_cooldowns_shared: Arc::new(AtomicU64::new(0)),
+            reported_cooldowns_sublimit: Arc::new(AtomicU64::new(0)),
+            cloudflare_bans: Arc::new(AtomicU64::new(0)),
+            reaction_pacing_delays: Arc::new(AtomicU64::new(0)),
+            reaction_pacing_denials: Arc::new(AtomicU64::new(0)),
+            identify_grants: Arc::new(AtomicU64::new(0)),
+            identify_denials: Arc::new(AtomicU64::new(0)),
+            leases_issued: Arc::new(AtomicU64::new(0)),
+            leases_released: Arc::new(AtomicU64::new(0)),
+            leases_reported: Arc::new(AtomicU64::new(0)),
+            leases_expired: Arc::new(AtomicU64::new(0)),
+            lease_extensions: Arc::new(AtomicU64::new(0)),
+            lease_mismatches_unknown: Arc::new(AtomicU64::new(0)),
+            lease_mismatches_foreign: Arc::new(AtomicU64::new(0)),
+            daily_quota_denials_identity: Arc::new(AtomicU64::new(0)),
+            daily_quota_denials_group: Arc::new(AtomicU64::new(0)),
+            interaction_deadline_misses: Arc::new(AtomicU64::new(0)),
+            gateway_presence_denials: Arc::new(AtomicU64::new(0)),
+            maintenance_windows_started: Arc::new(AtomicU64::new(0)),
+            plugin_permit_vetoes: Arc::new(AtomicU64::new(0)),
+            plugin_report_vetoes: Arc::new(AtomicU64::new(0)),
+            plugin_errors: Arc::new(AtomicU64::new(0)),
+            alerts_sent: Arc::new(AtomicU64::new(0)),
+            alerts_suppressed: Arc::new(AtomicU64::new(0)),
+            alerts_failed: Arc::new(AtomicU64::new(0)),
+            events_published: Arc::new(AtomicU64::new(0)),
+            events_dropped: Arc::new(AtomicU64::new(0)),
+            events_failed: Arc::new(AtomicU64::new(0)),
+            sql_records_written: Arc::new(AtomicU64::new(0)),
+            sql_records_dropped: Arc::new(AtomicU64::new(0)),
+            sql_records_failed: Arc::new(AtomicU64::new(0)),
+            statsd,
         }
     }
 
     fn observe_request_wait_ms(&self, value: u64) {
         self.request_wait_ms_sum.fetch_add(value, Ordering::Relaxed);
         self.request_wait_ms_count.fetch_add(1, Ordering::Relaxed);
+        self.statsd.timing("request_wait_ms", value);
     }
 
     fn observe_redis_latency_ms(&self, value: u64) {
@@ -158,6 +777,7 @@ impl Metrics {
             .fetch_add(value, Ordering::Relaxed);
         self.redis_latency_ms_count
             .fetch_add(1, Ordering::Relaxed);
+        self.statsd.timing("redis_latency_ms", value);
     }
 }
 
@@ -166,50 +786,164 @@ struct AppState {
     redis: redis::Client,
     config: Config,
     metrics: Metrics,
-    request_token_script: Script,
-    incr_with_expire_script: Script,
+    waiters: Waiters,
+    deferred_reports: DeferredReports,
+    usage: UsageStats,
+    anomalies: AnomalyDetector,
+    forecasts: Forecaster,
+    region: RegionState,
+    rules: Arc<Rules>,
+    schedule: Arc<Schedule>,
+    maintenance: Arc<Maintenance>,
+    signals: Arc<Signals>,
+    plugins: Plugins,
+    http: reqwest::Client,
+    /// Posts permit callbacks; see `callbacks::http_client`.
+    callback_http: reqwest::Client,
+    job_slots: Arc<Semaphore>,
+    store: Arc<dyn PermitStore>,
+    shadow: Arc<ShadowStats>,
+    loans: Loans,
+    controls: Controls,
+    long_limits: Arc<LongLimits>,
+    route_limits: Arc<RouteLimits>,
+    bucket_map: Arc<BucketMap>,
+    notifier: Notifier,
+    publisher: Publisher,
+    sql: SqlSink,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, utoipa::ToSchema)]
 struct RequestTokenRequest {
     #[serde(default)]
-    #[allow(dead_code)]
     client_id: String,
     #[serde(default = "default_group_id")]
-    #[allow(dead_code)]
     group_id: String,
     discord_identity: String,
+    /// Not used by gateway requests.
+    #[serde(default)]
     method: String,
+    /// Route template, concrete path, or full Discord request URL; for
+    /// gateway requests, the gateway command.
+    #[serde(default)]
     route: String,
+    /// Raw request path or URL as sent to Discord. When set, the route and
+    /// major parameter are derived from it here, in place of `route`.
+    #[serde(default)]
+    path: Option<String>,
+    /// May be omitted when `route` is a concrete path; it is then derived
+    /// from the path.
+    #[serde(default)]
     major_parameter: String,
+    /// `gateway` paces a gateway command on `shard_id` instead of a REST call.
+    #[serde(default)]
+    transport: Transport,
+    #[serde(default)]
+    shard_id: u32,
+    /// Size of the files an upload route call sends, for byte pacing.
+    #[serde(default)]
+    payload_bytes: Option<u64>,
+    /// Slots the permit reserves, for a call the client knows fans out;
+    /// multiplies the method weight. Defaults to 1.
+    #[serde(default)]
+    cost: Option<u64>,
     #[serde(default = "default_priority")]
-    #[allow(dead_code)]
     priority: String,
+    /// When Discord stops accepting the interaction response this call
+    /// belongs to; set automatically for interaction callbacks.
+    #[serde(default)]
+    interaction_deadline_unix_ms: Option<u64>,
     #[serde(default)]
     max_wait_ms: u64,
+    /// Holds the call until it is granted or its wait runs out, woken by
+    /// the queue instead of rechecking every `DMBO_QUEUE_POLL_MS`. Waits up
+    /// to `DMBO_MAX_WAIT_CAP_MS` when `max_wait_ms` is not set.
+    #[serde(default)]
+    long_poll: bool,
+    /// Answer `202 Accepted` at once and `POST` the decision here once it is
+    /// made, instead of holding the connection. Implies `long_poll`.
+    #[serde(default)]
+    callback_url: Option<String>,
     #[serde(default)]
-    #[allow(dead_code)]
     request_id: String,
+    /// 1-based attempt number for this logical request; 0 when not tracked.
+    #[serde(default)]
+    attempt: u32,
+    /// Client wall clock when the request was sent, for skew detection.
+    #[serde(default)]
+    client_unix_ms: Option<u64>,
+    /// Bot feature the call is made for; one of `DMBO_FEATURES`.
+    #[serde(default)]
+    feature: Option<String>,
+    /// Limits set by a policy rule or plugin in place of the scheduled ones.
+    #[serde(skip)]
+    global_rps_override: Option<u64>,
+    #[serde(skip)]
+    route_rps_override: Option<u64>,
+    /// Tags attached by matching policy rules.
+    #[serde(skip)]
+    tags: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, utoipa::ToSchema)]
 struct RequestTokenResponse {
     granted: bool,
     not_before_unix_ms: u64,
+    /// Canonical route template the request was keyed on.
+    route_template: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     lease_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     retry_after_ms: Option<u64>,
-    reason: String,
+    reason: Reason,
+    reason_message: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry: Option<RetryGuidance>,
+    server_unix_ms: u64,
+    /// Server clock minus client clock at arrival; positive means the client
+    /// is behind. Only present when the request carried `client_unix_ms`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock_skew_ms: Option<i64>,
+    /// Exhaustion forecast for the granted bucket.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    forecast: Option<Forecast>,
+    /// Operator-defined reason when a policy plugin vetoed the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plugin_reason: Option<String>,
+    /// Name of the policy rule that denied the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy_rule: Option<String>,
+    /// Name of the maintenance window pausing the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maintenance_window: Option<String>,
+    /// Name of the long-window limit that is full.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    long_limit: Option<String>,
+    /// Tags attached by matching policy rules.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Calls queued ahead of a denied one on its route bucket.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue_position: Option<u64>,
+    /// Rough time until a denied call would be granted: its retry hint plus
+    /// one refill of the route limit per call queued ahead of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_wait_ms: Option<u64>,
+    /// `request_id` of the identical GET whose response a coalesced request
+    /// should share.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coalesced_with: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, utoipa::ToSchema)]
 struct ReportResultRequest {
     #[serde(default)]
     #[allow(dead_code)]
     request_id: String,
     #[serde(default)]
-    #[allow(dead_code)]
+    client_id: String,
+    /// Ends the permit's lease when set.
+    #[serde(default)]
     lease_id: Option<String>,
     #[serde(default)]
     #[allow(dead_code)]
@@ -222,48 +956,347 @@ struct ReportResultRequest {
     #[serde(default)]
     #[allow(dead_code)]
     route: String,
+    /// Raw request path, as on permit requests.
+    #[serde(default)]
+    path: Option<String>,
     #[serde(default)]
     #[allow(dead_code)]
     major_parameter: String,
     #[serde(default)]
     status_code: u16,
     #[serde(default)]
+    x_ratelimit_bucket: Option<String>,
+    #[serde(default)]
+    x_ratelimit_limit: Option<u64>,
+    #[serde(default)]
+    x_ratelimit_remaining: Option<u64>,
+    #[serde(default)]
+    x_ratelimit_reset_after_s: Option<f64>,
+    #[serde(default)]
     x_ratelimit_scope: Option<String>,
+    /// Discord's `X-RateLimit-Global` header, set on 429s of the global limit.
+    #[serde(default)]
+    x_ratelimit_global: bool,
+    /// Discord's `Retry-After` on a 429, in milliseconds.
+    #[serde(default)]
+    retry_after_ms: Option<u64>,
+    /// `retry_after` from the 429's JSON body, in seconds. Exact where the
+    /// headers are not, e.g. for undocumented sub-limits.
+    #[serde(default)]
+    body_retry_after_s: Option<f64>,
+    /// The 429 came from Cloudflare (no JSON body), not Discord.
+    #[serde(default)]
+    cloudflare: bool,
+    /// Bot feature the call was made for; one of `DMBO_FEATURES`.
+    #[serde(default)]
+    feature: Option<String>,
+}
+
+impl ReportResultRequest {
+    /// The 429's scope, `global` whenever `X-RateLimit-Global` was set even
+    /// if the scope header was missing.
+    fn scope(&self) -> Option<&str> {
+        if self.x_ratelimit_global && self.status_code == 429 {
+            Some("global")
+        } else {
+            self.x_ratelimit_scope.as_deref()
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let config = Config::from_env();
     let redis = redis::Client::open(config.redis_url.clone()).expect("invalid REDIS_URL");
+    let (statsd, statsd_timings) = Statsd::start(&config);
+    let (publisher, publisher_task) = Publisher::start(&config);
+    let (sql, sql_task) = SqlSink::start(&config);
     let state = Arc::new(AppState {
+        store: if config.memory_store {
+            Arc::new(MemoryStore::default())
+        } else {
+            Arc::new(RedisStore::new(redis.clone()))
+        },
         redis,
         config: config.clone(),
-        metrics: Metrics::new(),
-        request_token_script: Script::new(REQUEST_TOKEN_LUA),
-        incr_with_expire_script: Script::new(INCR_WITH_EXPIRE_LUA),
+        metrics: Metrics::new(statsd),
+        waiters: Waiters::default(),
+        deferred_reports: DeferredReports::default(),
+        usage: UsageStats::new(!config.features.is_empty()),
+        anomalies: AnomalyDetector::default(),
+        forecasts: Forecaster::default(),
+        region: RegionState::default(),
+        rules: Arc::new(Rules::load(config.rules_path.as_deref())),
+        schedule: Arc::new(Schedule::load(config.schedule_path.as_deref())),
+        maintenance: Arc::new(Maintenance::load(config.maintenance_path.as_deref())),
+        signals: Arc::default(),
+        plugins: Plugins::load(&config),
+        http: reqwest::Client::builder()
+            .user_agent(concat!(
+                "DiscordBot (https://github.com/da1g/dmbo, ",
+                env!("CARGO_PKG_VERSION"),
+                ")"
+            ))
+            .build()
+            .expect("failed to build HTTP client"),
+        callback_http: callbacks::http_client(&config),
+        job_slots: Arc::new(Semaphore::new(config.job_concurrency)),
+        shadow: Arc::new(ShadowStats::default()),
+        loans: Loans::default(),
+        controls: Controls::default(),
+        long_limits: Arc::new(LongLimits::load(&config)),
+        route_limits: Arc::new(RouteLimits::load(
+            config.route_limits_path.as_deref(),
+            config.route_concurrency_lease_ms,
+            config.route_max_inflight,
+        )),
+        bucket_map: Arc::new(BucketMap::new(config.bucket_map_ttl_seconds)),
+        notifier: Notifier::default(),
+        publisher,
+        sql,
     });
+    tokio::spawn(dlq::retry_deferred_reports(state.clone()));
+    tokio::spawn(jobs::consume_jobs(state.clone()));
+    tokio::spawn(stats::flush_rollups(state.clone()));
+    tokio::spawn(loans::sync_loans(state.clone()));
+    tokio::spawn(controls::sync_controls(state.clone()));
+    tokio::spawn(signals::release_due(state.clone()));
+    // Leases, anomaly alerts and the replicas' wakeups and signals go
+    // through Redis.
+    if !config.memory_store {
+        tokio::spawn(anomaly::detect_anomalies(state.clone()));
+        tokio::spawn(leases::expire_leases(state.clone()));
+        tokio::spawn(wakeups::relay(state.clone()));
+        tokio::spawn(signals::relay(state.clone()));
+    }
+    if let Some(channel) = config.pubsub_channel.clone() {
+        tokio::spawn(pubsub::serve(state.clone(), channel));
+    }
+    #[cfg(not(feature = "nats"))]
+    if config.nats_url.is_some() {
+        panic!("DMBO_NATS_URL is set but the orchestrator was built without the nats feature");
+    }
+    #[cfg(feature = "nats")]
+    if let Some(url) = config.nats_url.clone() {
+        tokio::spawn(nats::serve(state.clone(), url));
+    }
+    if config.alert_webhook_url.is_some() {
+        tokio::spawn(notifier::watch(state.clone()));
+    }
+    if let Some(task) = sql_task {
+        tokio::spawn(sql::run(state.clone(), task));
+    }
+    if let Some(task) = publisher_task {
+        tokio::spawn(publisher::run(state.clone(), task));
+    }
+    if let Some(timings) = statsd_timings {
+        tokio::spawn(statsd::export(state.clone(), timings));
+    }
+    if !state.maintenance.is_empty() {
+        tokio::spawn(maintenance::track_windows(state.clone()));
+    }
+    if let (Some(_), Some(url)) = (&config.region, &config.coordinator_redis_url) {
+        let coordinator =
+            redis::Client::open(url.as_str()).expect("invalid DMBO_COORDINATOR_REDIS_URL");
+        tokio::spawn(region::reconcile(state.clone(), coordinator));
+    }
 
-    let app = Router::new()
-        .route("/healthz", get(healthz))
+    let admin = Router::new()
+        .route("/admin/buckets", get(admin::list_buckets))
+        .route("/admin/guards", get(admin::list_guards))
+        .route("/admin/guards/:group_id", delete(admin::clear_guard))
+        .route("/admin/groups", get(controls::list))
+        .route(
+            "/admin/groups/:group_id/pause",
+            put(controls::pause).delete(controls::resume),
+        )
+        .route(
+            "/admin/groups/:group_id/limits",
+            put(controls::set_limits).delete(controls::clear_limits),
+        )
+        .route("/admin/audit", get(admin::list_audit))
+        .route("/admin/alerts", get(admin::list_alerts))
+        .route("/admin/alerts/test", post(notifier::test))
+        .route("/admin/queues", get(admin::list_queues))
+        .route("/admin/config", get(admin_config))
+        .route("/admin/limiter/compare", get(limiter::compare))
+        .route("/admin/loans", get(loans::list).post(loans::lend))
+        .route("/admin/loans/audit", get(loans::audit))
+        .route("/admin/loans/:loan_id", delete(loans::recall))
+        .route("/admin/leases", get(leases::list))
+        .route("/admin/export/usage", get(stats::export_usage))
+        .route("/admin/dlq", get(dlq::list).delete(dlq::purge))
+        .route("/admin/dlq/:entry_id", delete(dlq::remove))
+        .route("/admin/dlq/:entry_id/retry", post(dlq::retry))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            admin::require_admin,
+        ));
+
+    // Large, infrequent responses are compressed when the caller sends
+    // Accept-Encoding; the permit hot path stays uncompressed.
+    let compressed = Router::new()
         .route("/metrics", get(metrics))
+        .route("/stats/history", get(stats::history))
+        .route("/stats/features", get(stats::features))
+        .merge(admin)
+        .layer(CompressionLayer::new());
+
+    // Proxy mode forwards Discord calls of any method and size.
+    let proxy = if config.proxy {
+        Router::new()
+            .route("/api/*path", any(proxy::forward))
+            .layer(DefaultBodyLimit::max(config.proxy_max_body_bytes))
+    } else {
+        Router::new()
+    };
+
+    // Every endpoint is served under `/v1` and, for clients written before
+    // versioning, at its original path.
+    let api = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/policy", get(policy))
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/events", get(signals::events))
         .route("/request_token", post(request_token))
+        .route("/request_tokens", post(batch::request_tokens))
+        .route("/permits/ws", get(ws::permits_ws))
+        .route("/request_identify", post(identify::request_identify))
         .route("/report_result", post(report_result))
+        .route("/release_lease", post(leases::release_lease))
+        .route("/extend_lease", post(leases::extend_lease))
+        .route("/cancel_request", post(queue::cancel_request))
+        .route("/jobs", post(jobs::submit_job))
+        .route("/jobs/:job_id", get(jobs::get_job))
+        .merge(compressed);
+
+    let app = Router::new()
+        .nest("/v1", api.clone())
+        .merge(api)
+        .merge(proxy)
+        .layer(middleware::from_fn(negotiate_api_version))
         .with_state(state);
 
-    let listener = TcpListener::bind(config.bind_addr)
-        .await
-        .expect("failed to bind orchestrator");
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .expect("orchestrator server failed");
+    let tls = match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert), Some(key)) => Some(
+            listener::tls_acceptor(cert, key)
+                .unwrap_or_else(|error| panic!("invalid DMBO_TLS_CERT / DMBO_TLS_KEY: {error}")),
+        ),
+        (None, None) => None,
+        _ => panic!("DMBO_TLS_CERT and DMBO_TLS_KEY must be set together"),
+    };
+    match (&config.bind, tls) {
+        (Bind::Tcp(addr), None) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .expect("failed to bind orchestrator");
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .expect("orchestrator server failed");
+        }
+        (Bind::Tcp(addr), Some(acceptor)) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .expect("failed to bind orchestrator");
+            listener::serve(
+                listener::Listener::Tls(listener, acceptor),
+                app,
+                shutdown_signal(),
+            )
+            .await;
+        }
+        // The socket's file permissions guard it; TLS adds nothing there.
+        (Bind::Unix(path), _) => {
+            let listener = listener::bind_unix(path).expect("failed to bind orchestrator socket");
+            listener::serve(listener::Listener::Unix(listener), app, shutdown_signal()).await;
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Where the orchestrator listens: a TCP address, or a Unix domain socket
+/// for `unix:/path.sock`.
+#[derive(Debug, Clone)]
+enum Bind {
+    Tcp(SocketAddr),
+    Unix(std::path::PathBuf),
+}
+
+impl Bind {
+    fn parse(value: &str) -> Option<Self> {
+        match value.strip_prefix("unix:") {
+            Some(path) if !path.is_empty() => Some(Bind::Unix(path.into())),
+            Some(_) => None,
+            None => value.parse().ok().map(Bind::Tcp),
+        }
+    }
+}
+
+impl std::fmt::Display for Bind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bind::Tcp(addr) => write!(f, "{addr}"),
+            Bind::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Rejects requests whose `Dmbo-Api-Version` header asks for a version this
+/// build does not serve, and stamps every response with the version it
+/// speaks, so clients can detect a server older or newer than themselves.
+async fn negotiate_api_version(
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let requested = request.headers().get(API_VERSION_HEADER).map(|value| {
+        value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse::<u32>().ok())
+    });
+    let mut response = match requested {
+        Some(Some(version)) if SUPPORTED_API_VERSIONS.contains(&version) => next.run(request).await,
+        None => next.run(request).await,
+        Some(_) => problem_response(
+            StatusCode::BAD_REQUEST,
+            PROBLEM_TYPE_UNSUPPORTED_API_VERSION,
+            "Unsupported API version",
+            format!(
+                "Dmbo-Api-Version must be one of {}",
+                SUPPORTED_API_VERSIONS
+                    .map(|version| version.to_string())
+                    .join(", ")
+            ),
+            json!({ "supported_versions": SUPPORTED_API_VERSIONS }),
+        ),
+    };
+    response.headers_mut().insert(
+        API_VERSION_HEADER,
+        header::HeaderValue::from(PROTOCOL_VERSION),
+    );
+    response
 }
 
 async fn shutdown_signal() {
     let _ = tokio::signal::ctrl_c().await;
 }
 
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses(
+        (status = 200, description = "Serving", body = openapi::Health),
+        (status = 503, description = "Redis is down and required for health", body = openapi::Health),
+    )
+)]
 async fn healthz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    if state.config.memory_store {
+        return (
+            StatusCode::OK,
+            Json(json!({ "ok": true, "redis": "unused" })),
+        );
+    }
     let redis_ok = match state.redis.get_multiplexed_async_connection().await {
         Ok(mut conn) => redis::cmd("PING")
             .query_async::<_, String>(&mut conn)
@@ -285,8 +1318,143 @@ async fn healthz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     )
 }
 
+#[derive(Debug, Deserialize)]
+struct PolicyQuery {
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    discord_identity: Option<String>,
+}
+
+/// Effective server policy for the calling client, so SDKs can configure their
+/// retry and wait behavior at startup instead of hardcoding it.
+#[utoipa::path(
+    get,
+    path = "/policy",
+    params(
+        ("client_id" = Option<String>, Query),
+        ("discord_identity" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "Effective limits, retry and wait policy", body = Object))
+)]
+async fn policy(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PolicyQuery>,
+) -> impl IntoResponse {
+    let config = &state.config;
+    let (global_rps, route_rps) = base_limits(&state, unix_ms(), query.discord_identity.as_deref());
+    Json(json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "api_versions": SUPPORTED_API_VERSIONS,
+        "client_id": query.client_id,
+        "discord_identity": query.discord_identity,
+        "supported_transports": SUPPORTED_TRANSPORTS,
+        "gateway": gateway::describe(config),
+        "min_retry_ms": config.min_retry_ms,
+        "max_wait_cap_ms": config.max_wait_cap_ms,
+        "global_rps": global_rps,
+        "route_rps": route_rps,
+        "method_weights": config.method_weights,
+        "priority_shares": config.priority_shares,
+        "priority_aging_ms": config.priority_aging_ms,
+        "schedule_window": state.schedule.active(unix_ms()).map(|window| &window.name),
+        "maintenance": state.maintenance.snapshot(),
+        "retry": {
+            "max_delay_ms": config.retry_max_delay_ms,
+            "jitter_ms": config.retry_jitter_ms,
+            "give_up_after_attempts": config.retry_give_up_attempts,
+        },
+        "legacy_status_codes": config.legacy_status_codes,
+        "idempotency_ttl_seconds": config.idempotency_ttl_seconds,
+        "request_id_dedup_seconds": config.request_id_dedup_seconds,
+        "coalesce_get_ms": config.coalesce_get_ms,
+        "region": config.region.as_ref().map(|name| json!({
+            "name": name,
+            "share": state.region.share(),
+            "live_regions": state.region.live_regions(),
+            "regional_global_rps": state.region.scale(global_rps),
+            "regional_route_rps": state.region.scale(route_rps),
+        })),
+    }))
+}
+
+/// Effective runtime configuration for operators, with secrets reduced to
+/// whether they are set, plus the quota calendar and its active window.
+async fn admin_config(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let config = &state.config;
+    let now = unix_ms();
+    let (global_rps, route_rps) = base_limits(&state, now, None);
+    let mut token_refs: Vec<&String> = config.discord_tokens.keys().collect();
+    token_refs.sort();
+    Json(json!({
+        "instance_id": config.instance_id,
+        "bind_addr": config.bind.to_string(),
+        "store": if config.memory_store { "memory" } else { "redis" },
+        "tls": config.tls_cert_path.is_some() && matches!(config.bind, Bind::Tcp(_)),
+        "limits": {
+            "configured_global_rps": config.global_rps,
+            "identity_global_rps": config.identity_global_rps,
+            "configured_route_rps": config.route_rps,
+            "effective_global_rps": global_rps,
+            "effective_route_rps": route_rps,
+            "min_retry_ms": config.min_retry_ms,
+            "max_wait_cap_ms": config.max_wait_cap_ms,
+            "queue_poll_ms": config.queue_poll_ms,
+            "long_poll_recheck_ms": config.long_poll_recheck_ms,
+            "max_waiters": config.max_waiters,
+            "max_waiters_per_client": config.max_waiters_per_client,
+            "strict_queue_full": config.strict_queue_full,
+            "invalid_threshold": config.invalid_threshold,
+            "guardrail_cooldown_ms": config.guardrail_cooldown_ms,
+            "identity_daily_quota": config.identity_daily_quota,
+            "group_daily_quota": config.group_daily_quota,
+            "route_max_inflight": config.route_max_inflight,
+            "lease_ttl_ms": config.lease_ttl_ms,
+            "reject_lease_mismatch": config.reject_lease_mismatch,
+        },
+        "schedule": {
+            "utc_offset_minutes": state.schedule.utc_offset_minutes(),
+            "active_window": state.schedule.active(now).map(|window| &window.name),
+            "windows": state.schedule.windows(),
+        },
+        "region": config.region,
+        "rules": state.rules.len(),
+        "long_limits": state.long_limits.describe(),
+        "command_registration": state.long_limits.describe_registration(),
+        "webhook": state.long_limits.describe_webhook(),
+        "message_send": state.long_limits.describe_message_send(),
+        "route_limits": state.route_limits.describe(),
+        "limiter": {
+            "algorithm": config.limiter_algorithm,
+            "shadow_algorithm": config.shadow_algorithm,
+            "route_algorithms": config.route_algorithms.describe(),
+            "global_exempt_routes": config.global_exempt_routes,
+            "token_bucket_burst_percent": config.token_bucket_burst_percent,
+            "method_weights": config.method_weights,
+            "priority_shares": config.priority_shares,
+            "priority_aging_ms": config.priority_aging_ms,
+            "honor_discord_remaining": config.honor_discord_remaining,
+            "reported_cooldown_max_ms": config.reported_cooldown_max_ms,
+        },
+        "plugins_enabled": state.plugins.enabled(),
+        "features": config.features,
+        "admin_token_set": config.admin_token.is_some(),
+        "discord_token_refs": token_refs,
+        "legacy_status_codes": config.legacy_status_codes,
+        "proxy": {
+            "enabled": config.proxy,
+            "discord_api_base": config.discord_api_base,
+            "max_attempts": config.proxy_max_attempts,
+            "max_body_bytes": config.proxy_max_body_bytes,
+            "cache_ttl_ms": config.proxy_cache_ttl_ms,
+        },
+        "pubsub_channel": config.pubsub_channel,
+        "nats": config.nats_url.as_ref().map(|_| &config.nats_subject),
+    }))
+}
+
 async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let body = format!(
+    let mut body = format!(
         "# HELP orchestrator_request_token_total request_token outcomes\n\
 # TYPE orchestrator_request_token_total counter\n\
 orchestrator_request_token_total{{outcome=\"granted\"}} {}\n\
@@ -301,6 +1469,55 @@ tokens_denied_total {}\n\
 # HELP orchestrator_queue_depth Current server-side queue depth\n\
 # TYPE orchestrator_queue_depth gauge\n\
 orchestrator_queue_depth {}\n\
+# HELP orchestrator_queue_holds_total Permit attempts held back behind earlier waiters on the same bucket\n\
+# TYPE orchestrator_queue_holds_total counter\n\
+orchestrator_queue_holds_total {}\n\
+# HELP orchestrator_waiter_wakeups_total Waiters woken early because capacity on their bucket may have freed\n\
+# TYPE orchestrator_waiter_wakeups_total counter\n\
+orchestrator_waiter_wakeups_total {}\n\
+# HELP orchestrator_queue_shed_total Requests denied instead of waiting because a waiter cap was reached\n\
+# TYPE orchestrator_queue_shed_total counter\n\
+orchestrator_queue_shed_total{{scope=\"instance\"}} {}\n\
+orchestrator_queue_shed_total{{scope=\"client\"}} {}\n\
+# HELP orchestrator_requests_cancelled_total Waits ended by POST /cancel_request\n\
+# TYPE orchestrator_requests_cancelled_total counter\n\
+orchestrator_requests_cancelled_total {}\n\
+# HELP orchestrator_priority_aged_grants_total Grants made after waiting raised the request's priority class\n\
+# TYPE orchestrator_priority_aged_grants_total counter\n\
+orchestrator_priority_aged_grants_total {}\n\
+# HELP orchestrator_queue_preemptions_total Critical requests that went ahead of queued normal or background requests\n\
+# TYPE orchestrator_queue_preemptions_total counter\n\
+orchestrator_queue_preemptions_total {}\n\
+# HELP orchestrator_coalesced_total GET permit requests pointed at an identical request's response instead of granted\n\
+# TYPE orchestrator_coalesced_total counter\n\
+orchestrator_coalesced_total {}\n\
+# HELP orchestrator_batch_items_total Permit requests received through POST /request_tokens\n\
+# TYPE orchestrator_batch_items_total counter\n\
+orchestrator_batch_items_total {}\n\
+# HELP orchestrator_ws_connections Open permit WebSocket connections\n\
+# TYPE orchestrator_ws_connections gauge\n\
+orchestrator_ws_connections {}\n\
+# HELP orchestrator_relayed_wakeups_total Wakeups received from other replicas\n\
+# TYPE orchestrator_relayed_wakeups_total counter\n\
+orchestrator_relayed_wakeups_total {}\n\
+# HELP orchestrator_signals_sent_total Guardrail and bucket state changes announced by this instance\n\
+# TYPE orchestrator_signals_sent_total counter\n\
+orchestrator_signals_sent_total {}\n\
+# HELP orchestrator_proxied_requests_total Discord calls forwarded in proxy mode\n\
+# TYPE orchestrator_proxied_requests_total counter\n\
+orchestrator_proxied_requests_total {}\n\
+# HELP orchestrator_permit_callbacks_total Decisions posted to a permit request's callback_url, by outcome\n\
+# TYPE orchestrator_permit_callbacks_total counter\n\
+orchestrator_permit_callbacks_total{{outcome=\"sent\"}} {}\n\
+orchestrator_permit_callbacks_total{{outcome=\"failed\"}} {}\n\
+# HELP orchestrator_pubsub_commands_total Permit requests received over Redis pub/sub, by outcome\n\
+# TYPE orchestrator_pubsub_commands_total counter\n\
+orchestrator_pubsub_commands_total{{outcome=\"decided\"}} {}\n\
+orchestrator_pubsub_commands_total{{outcome=\"invalid\"}} {}\n\
+# HELP orchestrator_nats_messages_total Messages received over the NATS transport, by subject\n\
+# TYPE orchestrator_nats_messages_total counter\n\
+orchestrator_nats_messages_total{{subject=\"request_token\"}} {}\n\
+orchestrator_nats_messages_total{{subject=\"report_result\"}} {}\n\
 # HELP inflight_requests Inflight request_token handlers\n\
 # TYPE inflight_requests gauge\n\
 inflight_requests {}\n\
@@ -336,6 +1553,25 @@ redis_roundtrip_ms_count {}\n",
         state.metrics.tokens_granted_total.load(Ordering::Relaxed),
         state.metrics.tokens_denied_total.load(Ordering::Relaxed),
         state.metrics.queue_depth.load(Ordering::Relaxed),
+        state.metrics.queue_holds.load(Ordering::Relaxed),
+        state.waiters.wakeups(),
+        state.metrics.queue_shed.load(Ordering::Relaxed),
+        state.metrics.client_queue_shed.load(Ordering::Relaxed),
+        state.metrics.requests_cancelled.load(Ordering::Relaxed),
+        state.metrics.aged_grants.load(Ordering::Relaxed),
+        state.metrics.queue_preemptions.load(Ordering::Relaxed),
+        state.metrics.coalesced.load(Ordering::Relaxed),
+        state.metrics.batch_items.load(Ordering::Relaxed),
+        state.metrics.ws_connections.load(Ordering::Relaxed),
+        state.metrics.relayed_wakeups.load(Ordering::Relaxed),
+        state.metrics.signals_sent.load(Ordering::Relaxed),
+        state.metrics.proxied_requests.load(Ordering::Relaxed),
+        state.metrics.permit_callbacks_sent.load(Ordering::Relaxed),
+        state.metrics.permit_callbacks_failed.load(Ordering::Relaxed),
+        state.metrics.pubsub_commands.load(Ordering::Relaxed),
+        state.metrics.pubsub_invalid.load(Ordering::Relaxed),
+        state.metrics.nats_permit_requests.load(Ordering::Relaxed),
+        state.metrics.nats_reports.load(Ordering::Relaxed),
         state.metrics.inflight_requests.load(Ordering::Relaxed),
         state.metrics.observed_429_global.load(Ordering::Relaxed),
         state.metrics.observed_429_user.load(Ordering::Relaxed),
@@ -352,6 +1588,441 @@ redis_roundtrip_ms_count {}\n",
         state.metrics.redis_latency_ms_sum.load(Ordering::Relaxed),
         state.metrics.redis_latency_ms_count.load(Ordering::Relaxed),
     );
+    let _ = write!(
+        body,
+        "# HELP orchestrator_clock_skew_exceeded_total Requests whose client clock differed from the server by more than DMBO_CLOCK_SKEW_THRESHOLD_MS\n\
+# TYPE orchestrator_clock_skew_exceeded_total counter\n\
+orchestrator_clock_skew_exceeded_total {}\n\
+# HELP orchestrator_clock_skew_last_abs_ms Absolute clock skew of the most recent client that sent client_unix_ms\n\
+# TYPE orchestrator_clock_skew_last_abs_ms gauge\n\
+orchestrator_clock_skew_last_abs_ms {}\n",
+        state.metrics.clock_skew_exceeded_total.load(Ordering::Relaxed),
+        state.metrics.clock_skew_last_abs_ms.load(Ordering::Relaxed),
+    );
+    let _ = write!(
+        body,
+        "# HELP orchestrator_jobs_total Scheduled Discord calls by outcome\n\
+# TYPE orchestrator_jobs_total counter\n\
+orchestrator_jobs_total{{outcome=\"submitted\"}} {}\n\
+orchestrator_jobs_total{{outcome=\"succeeded\"}} {}\n\
+orchestrator_jobs_total{{outcome=\"failed\"}} {}\n\
+orchestrator_jobs_total{{outcome=\"reclaimed\"}} {}\n\
+# HELP orchestrator_upstream_retries_total Discord calls retried by the orchestrator, by cause\n\
+# TYPE orchestrator_upstream_retries_total counter\n\
+orchestrator_upstream_retries_total{{cause=\"429\"}} {}\n\
+orchestrator_upstream_retries_total{{cause=\"5xx\"}} {}\n\
+orchestrator_upstream_retries_total{{cause=\"network\"}} {}\n\
+# HELP orchestrator_upstream_retry_budget_exhausted_total Retryable Discord outcomes returned because the attempt or time budget ran out\n\
+# TYPE orchestrator_upstream_retry_budget_exhausted_total counter\n\
+orchestrator_upstream_retry_budget_exhausted_total {}\n\
+# HELP orchestrator_proxy_cache_total Proxied GETs answered from, missing or added to the response cache\n\
+# TYPE orchestrator_proxy_cache_total counter\n\
+orchestrator_proxy_cache_total{{outcome=\"hit\"}} {}\n\
+orchestrator_proxy_cache_total{{outcome=\"miss\"}} {}\n\
+orchestrator_proxy_cache_total{{outcome=\"stored\"}} {}\n",
+        state.metrics.jobs_submitted.load(Ordering::Relaxed),
+        state.metrics.jobs_succeeded.load(Ordering::Relaxed),
+        state.metrics.jobs_failed.load(Ordering::Relaxed),
+        state.metrics.jobs_reclaimed.load(Ordering::Relaxed),
+        state.metrics.upstream_retries_429.load(Ordering::Relaxed),
+        state.metrics.upstream_retries_5xx.load(Ordering::Relaxed),
+        state.metrics.upstream_retries_network.load(Ordering::Relaxed),
+        state.metrics.upstream_retry_budget_exhausted.load(Ordering::Relaxed),
+        state.metrics.proxy_cache_hits.load(Ordering::Relaxed),
+        state.metrics.proxy_cache_misses.load(Ordering::Relaxed),
+        state.metrics.proxy_cache_stores.load(Ordering::Relaxed),
+    );
+    let _ = write!(
+        body,
+        "# HELP orchestrator_dlq_deferred_reports Reports waiting in memory for a Redis write retry\n\
+# TYPE orchestrator_dlq_deferred_reports gauge\n\
+orchestrator_dlq_deferred_reports {}\n\
+# HELP orchestrator_dlq_dead_lettered_total Items moved to the dead-letter stream\n\
+# TYPE orchestrator_dlq_dead_lettered_total counter\n\
+orchestrator_dlq_dead_lettered_total{{kind=\"report\"}} {}\n\
+orchestrator_dlq_dead_lettered_total{{kind=\"job\"}} {}\n\
+# HELP orchestrator_dlq_dropped_total Deferred reports dropped because the retry buffer was full\n\
+# TYPE orchestrator_dlq_dropped_total counter\n\
+orchestrator_dlq_dropped_total {}\n",
+        state.deferred_reports.len(),
+        state.metrics.dead_lettered_reports.load(Ordering::Relaxed),
+        state.metrics.dead_lettered_jobs.load(Ordering::Relaxed),
+        state.metrics.deferred_reports_dropped.load(Ordering::Relaxed),
+    );
+    let _ = write!(
+        body,
+        "# HELP orchestrator_anomalies_total Report spikes above the EWMA baseline\n\
+# TYPE orchestrator_anomalies_total counter\n\
+orchestrator_anomalies_total{{metric=\"429\"}} {}\n\
+orchestrator_anomalies_total{{metric=\"invalid\"}} {}\n",
+        state.metrics.anomalies_429.load(Ordering::Relaxed),
+        state.metrics.anomalies_invalid.load(Ordering::Relaxed),
+    );
+    if !state.maintenance.is_empty() {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_maintenance_active Maintenance windows currently in force\n\
+# TYPE orchestrator_maintenance_active gauge\n\
+orchestrator_maintenance_active {}\n\
+# HELP orchestrator_maintenance_windows_started_total Maintenance windows that have started\n\
+# TYPE orchestrator_maintenance_windows_started_total counter\n\
+orchestrator_maintenance_windows_started_total {}\n\
+# HELP orchestrator_maintenance_denials_total Requests denied because a maintenance window paused them\n\
+# TYPE orchestrator_maintenance_denials_total counter\n\
+orchestrator_maintenance_denials_total {}\n",
+            state.maintenance.active_count(),
+            state
+                .metrics
+                .maintenance_windows_started
+                .load(Ordering::Relaxed),
+            state.metrics.maintenance_denials.load(Ordering::Relaxed),
+        );
+    }
+    if state.rules.len() > 0 {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_rule_denials_total Requests denied by a policy rule\n\
+# TYPE orchestrator_rule_denials_total counter\n\
+orchestrator_rule_denials_total {}\n",
+            state.metrics.rule_denials.load(Ordering::Relaxed),
+        );
+    }
+    if !state.long_limits.is_empty() {
+        let _ = writeln!(
+            body,
+            "# HELP orchestrator_long_limit_denials_total Permits denied by a long-window limit\n\
+# TYPE orchestrator_long_limit_denials_total counter"
+        );
+        for (limit, denials) in state.long_limits.denials() {
+            let _ = writeln!(
+                body,
+                "orchestrator_long_limit_denials_total{{limit=\"{limit}\"}} {denials}"
+            );
+        }
+    }
+    if state.route_limits.caps_concurrency() {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_route_concurrency_denials_total Permits denied because a route bucket's concurrency slots were taken\n\
+# TYPE orchestrator_route_concurrency_denials_total counter\n\
+orchestrator_route_concurrency_denials_total {}\n",
+            state.route_limits.concurrency_denials(),
+        );
+    }
+    if state.config.command_registration_limit > 0 {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_command_registration_denials_total Permits denied by the command registration limit\n\
+# TYPE orchestrator_command_registration_denials_total counter\n\
+orchestrator_command_registration_denials_total {}\n",
+            state.long_limits.registration_denials(),
+        );
+    }
+    if state.config.webhook_limit > 0 {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_webhook_denials_total Permits denied by the per-webhook limit\n\
+# TYPE orchestrator_webhook_denials_total counter\n\
+orchestrator_webhook_denials_total {}\n",
+            state.long_limits.webhook_denials(),
+        );
+    }
+    if state.config.message_send_limit > 0 {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_message_send_denials_total Permits denied by the per-channel message send limit\n\
+# TYPE orchestrator_message_send_denials_total counter\n\
+orchestrator_message_send_denials_total {}\n",
+            state.long_limits.message_send_denials(),
+        );
+    }
+    if state.config.identity_daily_quota > 0 || state.config.group_daily_quota > 0 {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_daily_quota_denials_total Permits denied by a used-up daily quota, by scope\n\
+# TYPE orchestrator_daily_quota_denials_total counter\n\
+orchestrator_daily_quota_denials_total{{scope=\"identity\"}} {}\n\
+orchestrator_daily_quota_denials_total{{scope=\"group\"}} {}\n",
+            state
+                .metrics
+                .daily_quota_denials_identity
+                .load(Ordering::Relaxed),
+            state
+                .metrics
+                .daily_quota_denials_group
+                .load(Ordering::Relaxed),
+        );
+    }
+    if state.config.reaction_interval_ms > 0 {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_reaction_pacing_total Reaction permits scheduled behind earlier reactions or denied, by outcome\n\
+# TYPE orchestrator_reaction_pacing_total counter\n\
+orchestrator_reaction_pacing_total{{outcome=\"delayed\"}} {}\n\
+orchestrator_reaction_pacing_total{{outcome=\"denied\"}} {}\n",
+            state.metrics.reaction_pacing_delays.load(Ordering::Relaxed),
+            state.metrics.reaction_pacing_denials.load(Ordering::Relaxed),
+        );
+    }
+    if !state.config.features.is_empty() {
+        let _ = writeln!(
+            body,
+            "# HELP orchestrator_feature_permits_total Permit decisions by feature tag\n\
+# TYPE orchestrator_feature_permits_total counter"
+        );
+        let features = state
+            .config
+            .features
+            .iter()
+            .map(String::as_str)
+            .chain([stats::UNTAGGED_FEATURE]);
+        for feature in features.clone() {
+            for (outcome, counter) in [("granted", "grants"), ("denied", "denials")] {
+                let _ = writeln!(
+                    body,
+                    "orchestrator_feature_permits_total{{feature=\"{feature}\",outcome=\"{outcome}\"}} {}",
+                    state.usage.feature_total(feature, counter),
+                );
+            }
+        }
+        let _ = writeln!(
+            body,
+            "# HELP orchestrator_feature_429_total Reported 429 responses by feature tag\n\
+# TYPE orchestrator_feature_429_total counter"
+        );
+        for feature in features {
+            let _ = writeln!(
+                body,
+                "orchestrator_feature_429_total{{feature=\"{feature}\"}} {}",
+                state.usage.feature_total(feature, "observed_429"),
+            );
+        }
+    }
+    if state.config.alert_webhook_url.is_some() {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_alerts_total Ops alerts by delivery outcome\n\
+# TYPE orchestrator_alerts_total counter\n\
+orchestrator_alerts_total{{outcome=\"sent\"}} {}\n\
+orchestrator_alerts_total{{outcome=\"suppressed\"}} {}\n\
+orchestrator_alerts_total{{outcome=\"failed\"}} {}\n",
+            state.metrics.alerts_sent.load(Ordering::Relaxed),
+            state.metrics.alerts_suppressed.load(Ordering::Relaxed),
+            state.metrics.alerts_failed.load(Ordering::Relaxed),
+        );
+    }
+    if state.sql.enabled() {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_sql_records_total Audit and guard trip records by SQL write outcome\n\
+# TYPE orchestrator_sql_records_total counter\n\
+orchestrator_sql_records_total{{outcome=\"written\"}} {}\n\
+orchestrator_sql_records_total{{outcome=\"dropped\"}} {}\n\
+orchestrator_sql_records_total{{outcome=\"failed\"}} {}\n",
+            state.metrics.sql_records_written.load(Ordering::Relaxed),
+            state.metrics.sql_records_dropped.load(Ordering::Relaxed),
+            state.metrics.sql_records_failed.load(Ordering::Relaxed),
+        );
+    }
+    if state.publisher.enabled() {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_events_total Decision and rate-limit events by publish outcome\n\
+# TYPE orchestrator_events_total counter\n\
+orchestrator_events_total{{outcome=\"published\"}} {}\n\
+orchestrator_events_total{{outcome=\"dropped\"}} {}\n\
+orchestrator_events_total{{outcome=\"failed\"}} {}\n",
+            state.metrics.events_published.load(Ordering::Relaxed),
+            state.metrics.events_dropped.load(Ordering::Relaxed),
+            state.metrics.events_failed.load(Ordering::Relaxed),
+        );
+    }
+    let _ = write!(
+        body,
+        "# HELP orchestrator_loans_active Budget loans between groups currently in force\n\
+# TYPE orchestrator_loans_active gauge\n\
+orchestrator_loans_active {}\n\
+# HELP orchestrator_groups_paused Groups currently paused through the admin API\n\
+# TYPE orchestrator_groups_paused gauge\n\
+orchestrator_groups_paused {}\n\
+# HELP orchestrator_group_pause_denials_total Requests denied because their group was paused\n\
+# TYPE orchestrator_group_pause_denials_total counter\n\
+orchestrator_group_pause_denials_total {}\n\
+# HELP orchestrator_gateway_denials_total Gateway command evaluations denied, by limit\n\
+# TYPE orchestrator_gateway_denials_total counter\n\
+orchestrator_gateway_denials_total{{limit=\"commands\"}} {}\n\
+orchestrator_gateway_denials_total{{limit=\"presence\"}} {}\n\
+# HELP orchestrator_upload_bytes_total Upload bytes granted against the byte budget\n\
+# TYPE orchestrator_upload_bytes_total counter\n\
+orchestrator_upload_bytes_total {}\n\
+# HELP orchestrator_upload_denials_total Upload permit evaluations denied, by limit\n\
+# TYPE orchestrator_upload_denials_total counter\n\
+orchestrator_upload_denials_total{{limit=\"bytes\"}} {}\n\
+orchestrator_upload_denials_total{{limit=\"concurrency\"}} {}\n\
+# HELP orchestrator_interaction_yields_total Permit evaluations held back for a waiting interaction response\n\
+# TYPE orchestrator_interaction_yields_total counter\n\
+orchestrator_interaction_yields_total {}\n\
+# HELP orchestrator_interaction_deadline_misses_total Interaction requests denied too late to retry before their deadline\n\
+# TYPE orchestrator_interaction_deadline_misses_total counter\n\
+orchestrator_interaction_deadline_misses_total {}\n\
+# HELP orchestrator_bucket_map_writes_total Route to Discord bucket mappings written from reports\n\
+# TYPE orchestrator_bucket_map_writes_total counter\n\
+orchestrator_bucket_map_writes_total {}\n\
+# HELP orchestrator_reported_cooldowns_total Cooldowns set from reported 429 Retry-After values, by scope\n\
+# TYPE orchestrator_reported_cooldowns_total counter\n\
+orchestrator_reported_cooldowns_total{{scope=\"global\"}} {}\n\
+orchestrator_reported_cooldowns_total{{scope=\"route\"}} {}\n\
+orchestrator_reported_cooldowns_total{{scope=\"shared\"}} {}\n\
+orchestrator_reported_cooldowns_total{{scope=\"sublimit\"}} {}\n\
+# HELP orchestrator_cloudflare_bans_total Reported Cloudflare 429s that tripped a group guard\n\
+# TYPE orchestrator_cloudflare_bans_total counter\n\
+orchestrator_cloudflare_bans_total {}\n\
+# HELP orchestrator_identify_total Gateway IDENTIFY requests, by outcome\n\
+# TYPE orchestrator_identify_total counter\n\
+orchestrator_identify_total{{outcome=\"granted\"}} {}\n\
+orchestrator_identify_total{{outcome=\"denied\"}} {}\n\
+# HELP orchestrator_leases_total Permit leases issued, and how they ended\n\
+# TYPE orchestrator_leases_total counter\n\
+orchestrator_leases_total{{outcome=\"issued\"}} {}\n\
+orchestrator_leases_total{{outcome=\"released\"}} {}\n\
+orchestrator_leases_total{{outcome=\"reported\"}} {}\n\
+orchestrator_leases_total{{outcome=\"expired\"}} {}\n\
+# HELP orchestrator_lease_extensions_total Leases kept alive with /extend_lease\n\
+# TYPE orchestrator_lease_extensions_total counter\n\
+orchestrator_lease_extensions_total {}\n\
+# HELP orchestrator_report_lease_mismatches_total Reports whose lease_id named no outstanding lease of their identity and route\n\
+# TYPE orchestrator_report_lease_mismatches_total counter\n\
+orchestrator_report_lease_mismatches_total{{kind=\"unknown\"}} {}\n\
+orchestrator_report_lease_mismatches_total{{kind=\"mismatch\"}} {}\n",
+        state.loans.active_count(),
+        state.controls.paused_count(),
+        state.metrics.group_pause_denials.load(Ordering::Relaxed),
+        state
+            .metrics
+            .gateway_command_denials
+            .load(Ordering::Relaxed),
+        state
+            .metrics
+            .gateway_presence_denials
+            .load(Ordering::Relaxed),
+        state.metrics.upload_bytes_granted.load(Ordering::Relaxed),
+        state.metrics.upload_bytes_denials.load(Ordering::Relaxed),
+        state
+            .metrics
+            .upload_concurrency_denials
+            .load(Ordering::Relaxed),
+        state.metrics.interaction_yields.load(Ordering::Relaxed),
+        state
+            .metrics
+            .interaction_deadline_misses
+            .load(Ordering::Relaxed),
+        state.metrics.bucket_map_writes.load(Ordering::Relaxed),
+        state
+            .metrics
+            .reported_cooldowns_global
+            .load(Ordering::Relaxed),
+        state
+            .metrics
+            .reported_cooldowns_route
+            .load(Ordering::Relaxed),
+        state
+            .metrics
+            .reported_cooldowns_shared
+            .load(Ordering::Relaxed),
+        state
+            .metrics
+            .reported_cooldowns_sublimit
+            .load(Ordering::Relaxed),
+        state.metrics.cloudflare_bans.load(Ordering::Relaxed),
+        state.metrics.identify_grants.load(Ordering::Relaxed),
+        state.metrics.identify_denials.load(Ordering::Relaxed),
+        state.metrics.leases_issued.load(Ordering::Relaxed),
+        state.metrics.leases_released.load(Ordering::Relaxed),
+        state.metrics.leases_reported.load(Ordering::Relaxed),
+        state.metrics.leases_expired.load(Ordering::Relaxed),
+        state.metrics.lease_extensions.load(Ordering::Relaxed),
+        state
+            .metrics
+            .lease_mismatches_unknown
+            .load(Ordering::Relaxed),
+        state
+            .metrics
+            .lease_mismatches_foreign
+            .load(Ordering::Relaxed),
+    );
+    if state.config.shadow_algorithm.is_some() {
+        let shadow = &state.shadow;
+        let _ = write!(
+            body,
+            "# HELP orchestrator_shadow_decisions_total Enforced decisions paired with the shadow algorithm's\n\
+# TYPE orchestrator_shadow_decisions_total counter\n\
+orchestrator_shadow_decisions_total{{enforced=\"granted\",shadow=\"granted\"}} {}\n\
+orchestrator_shadow_decisions_total{{enforced=\"granted\",shadow=\"denied\"}} {}\n\
+orchestrator_shadow_decisions_total{{enforced=\"denied\",shadow=\"granted\"}} {}\n\
+orchestrator_shadow_decisions_total{{enforced=\"denied\",shadow=\"denied\"}} {}\n\
+# HELP orchestrator_shadow_429_total Reported 429s by the shadow's decision on the bucket's latest grant\n\
+# TYPE orchestrator_shadow_429_total counter\n\
+orchestrator_shadow_429_total{{shadow=\"denied\"}} {}\n\
+orchestrator_shadow_429_total{{shadow=\"granted\"}} {}\n\
+# HELP orchestrator_shadow_errors_total Shadow evaluations that failed\n\
+# TYPE orchestrator_shadow_errors_total counter\n\
+orchestrator_shadow_errors_total {}\n",
+            shadow.decisions(true, true),
+            shadow.decisions(true, false),
+            shadow.decisions(false, true),
+            shadow.decisions(false, false),
+            shadow.observed_429(true),
+            shadow.observed_429(false),
+            shadow.errors(),
+        );
+    }
+    if state.plugins.enabled() {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_plugin_vetoes_total Requests and reports vetoed by a policy plugin\n\
+# TYPE orchestrator_plugin_vetoes_total counter\n\
+orchestrator_plugin_vetoes_total{{hook=\"permit\"}} {}\n\
+orchestrator_plugin_vetoes_total{{hook=\"report\"}} {}\n\
+# HELP orchestrator_plugin_errors_total Plugin invocations that failed and were skipped\n\
+# TYPE orchestrator_plugin_errors_total counter\n\
+orchestrator_plugin_errors_total {}\n",
+            state.metrics.plugin_permit_vetoes.load(Ordering::Relaxed),
+            state.metrics.plugin_report_vetoes.load(Ordering::Relaxed),
+            state.metrics.plugin_errors.load(Ordering::Relaxed),
+        );
+    }
+    if state.config.region.is_some() {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_region_share Fraction of the budgets this region admits against\n\
+# TYPE orchestrator_region_share gauge\n\
+orchestrator_region_share {}\n\
+# HELP orchestrator_region_live Regions that reported to the coordinator recently\n\
+# TYPE orchestrator_region_live gauge\n\
+orchestrator_region_live {}\n\
+# HELP orchestrator_region_sync_failures_total Failed coordinator reconciliations\n\
+# TYPE orchestrator_region_sync_failures_total counter\n\
+orchestrator_region_sync_failures_total {}\n",
+            state.region.share(),
+            state.region.live_regions(),
+            state.metrics.region_sync_failures.load(Ordering::Relaxed),
+        );
+    }
+    let (exhausting, soonest_ms) = state.forecasts.summary();
+    let _ = write!(
+        body,
+        "# HELP orchestrator_forecast_exhausting_buckets Recently active buckets forecast to exhaust before they reset\n\
+# TYPE orchestrator_forecast_exhausting_buckets gauge\n\
+orchestrator_forecast_exhausting_buckets {exhausting}\n",
+    );
+    if let Some(soonest_ms) = soonest_ms {
+        let _ = write!(
+            body,
+            "# HELP orchestrator_forecast_soonest_exhaustion_ms Shortest predicted time until a bucket exhausts\n\
+# TYPE orchestrator_forecast_soonest_exhaustion_ms gauge\n\
+orchestrator_forecast_soonest_exhaustion_ms {soonest_ms}\n",
+        );
+    }
     (
         StatusCode::OK,
         [(
@@ -362,17 +2033,342 @@ redis_roundtrip_ms_count {}\n",
     )
 }
 
+#[utoipa::path(
+    post,
+    path = "/request_token",
+    request_body(content(
+        (RequestTokenRequest = "application/json"),
+        (RequestTokenRequest = "application/msgpack"),
+        (RequestTokenRequest = "application/cbor"),
+    )),
+    params(
+        ("Idempotency-Key" = Option<String>, Header),
+        ("traceparent" = Option<String>, Header),
+    ),
+    responses(
+        (status = 200, description = "Permit granted", body = RequestTokenResponse),
+        (status = 202, description = "Queued; the decision will be posted to `callback_url`", body = openapi::CallbackAccepted),
+        (status = 400, description = "Invalid body", body = openapi::Problem, content_type = "application/problem+json"),
+        (status = 429, description = "Permit denied; the problem carries the decision's members", body = openapi::Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Backend unavailable, or queue full in strict mode", body = openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn request_token(
     State(state): State<Arc<AppState>>,
-    Json(request): Json<RequestTokenRequest>,
-) -> impl IntoResponse {
+    headers: HeaderMap,
+    payload: Result<Payload<RequestTokenRequest>, PayloadRejection>,
+) -> Response {
+    let trace = TraceContext::from_headers(&headers);
+    let mut response = process_request_token(&state, &headers, payload, trace.as_ref()).await;
+    if let Some(trace) = &trace {
+        trace.propagate(response.headers_mut());
+    }
+    codec::negotiate(&headers, response).await
+}
+
+async fn process_request_token(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    payload: Result<Payload<RequestTokenRequest>, PayloadRejection>,
+    trace: Option<&TraceContext>,
+) -> Response {
+    let Payload(mut request) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return codec::rejection_response(state, rejection),
+    };
+    normalize_request(&mut request);
+    let idempotency_key = match idempotency_key(headers) {
+        Ok(key) => key,
+        Err(error) => return validation_failed_response(vec![error]),
+    };
+    if let Some(key) = &idempotency_key {
+        request.request_id = key.clone();
+    }
+    let field_errors = validate_request(&state.config, &request);
+    if !field_errors.is_empty() {
+        return validation_failed_response(field_errors);
+    }
+    // Without an Idempotency-Key, the client's own request_id still dedups
+    // retries after a lost response, for a shorter window.
+    let dedup = match idempotency_key {
+        Some(key) => Some((key, state.config.idempotency_ttl_seconds)),
+        None if !request.request_id.is_empty() && state.config.request_id_dedup_seconds > 0 => {
+            Some((
+                request.request_id.clone(),
+                state.config.request_id_dedup_seconds,
+            ))
+        }
+        None => None,
+    };
+    // Concurrency slots and leases are tracked by request id.
+    if request.request_id.is_empty() {
+        request.request_id = uuid::Uuid::new_v4().to_string();
+    }
+    clamp_wait(&state.config, &mut request);
+    interactions::boost(&state.config, &mut request, unix_ms());
+
+    let idempotency_redis_key = dedup.as_ref().map(|(key, _)| {
+        format!(
+            "rl:idem:token:{}:{}",
+            normalize_key_part(&request.discord_identity),
+            normalize_key_part(key)
+        )
+    });
+    if let Some(redis_key) = &idempotency_redis_key {
+        let pending_ttl_ms = request.max_wait_ms.saturating_add(5_000);
+        match begin_idempotent(state, redis_key, pending_ttl_ms).await {
+            IdempotencyState::Fresh => {}
+            IdempotencyState::Replay(body) => return replayed_response(body),
+            IdempotencyState::InProgress => return idempotency_conflict_response(),
+        }
+    }
+    if request.callback_url.is_some() {
+        // A pending callback holds a waiter's place from now on.
+        if let Some(reason) = shed_reason(state, &request) {
+            if let Some(redis_key) = &idempotency_redis_key {
+                finish_idempotent(state, redis_key, None, 0).await;
+            }
+            let decision = PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms,
+                reason,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+            return denied_response(state, deny_permit(state, &request, decision, 0), false);
+        }
+        if let Some(redis_key) = &idempotency_redis_key {
+            let body = callbacks::accepted_body(&request).to_string();
+            let ttl_seconds = dedup.as_ref().map_or(0, |(_, ttl)| *ttl);
+            finish_idempotent(state, redis_key, Some(body), ttl_seconds).await;
+        }
+        return callbacks::accept(state, request, trace.cloned());
+    }
+
+    let (response, errored) = decide_permit(state, &mut request, trace).await;
+    if response.granted {
+        if let Some(redis_key) = &idempotency_redis_key {
+            let body = serde_json::to_string(&response).unwrap_or_default();
+            let ttl_seconds = dedup.as_ref().map_or(0, |(_, ttl)| *ttl);
+            finish_idempotent(state, redis_key, Some(body), ttl_seconds).await;
+        }
+        return (StatusCode::OK, Json(response)).into_response();
+    }
+    // Denials are not replayed: a retry with the same key should be
+    // re-evaluated once capacity frees up.
+    if let Some(redis_key) = &idempotency_redis_key {
+        finish_idempotent(state, redis_key, None, 0).await;
+    }
+    denied_response(state, response, errored)
+}
+
+/// Puts a parsed request's method, route and major parameter in the form
+/// buckets are keyed on.
+fn normalize_request(request: &mut RequestTokenRequest) {
+    if request.transport == Transport::Gateway {
+        gateway::normalize(request);
+    } else {
+        request.method = request.method.trim().to_ascii_uppercase();
+    }
+    if request.transport == Transport::Http {
+        if let Some(path) = request.path.take().filter(|path| !path.trim().is_empty()) {
+            apply_path(&path, &mut request.route, &mut request.major_parameter);
+        } else if !request.route.trim().is_empty() {
+            let resolved = routes::normalize_route(&request.route);
+            request.route = resolved.template;
+            if request.major_parameter.trim().is_empty() {
+                request.major_parameter = resolved.major_parameter.unwrap_or_default();
+            }
+        }
+        if request.major_parameter.trim().is_empty() {
+            request.major_parameter = UNKNOWN_MAJOR_PARAMETER.to_string();
+        }
+    }
+}
+
+/// Readies a permit request that arrived without HTTP headers, as a batch
+/// item or a WebSocket frame: normalizes and validates it, assigns a request
+/// id and clamps its wait. Returns the validation errors, if any.
+fn prepare_request(config: &Config, request: &mut RequestTokenRequest) -> Vec<FieldError> {
+    normalize_request(request);
+    let mut errors = validate_request(config, request);
+    if request.callback_url.is_some() {
+        errors.push(FieldError {
+            field: "callback_url",
+            message: "only supported by POST /request_token".to_string(),
+        });
+    }
+    if !errors.is_empty() {
+        return errors;
+    }
+    if request.request_id.is_empty() {
+        request.request_id = uuid::Uuid::new_v4().to_string();
+    }
+    clamp_wait(config, request);
+    interactions::boost(config, request, unix_ms());
+    Vec::new()
+}
+
+/// Caps `max_wait_ms`; long-poll requests without one wait the full cap.
+fn clamp_wait(config: &Config, request: &mut RequestTokenRequest) {
+    if request.long_poll && request.max_wait_ms == 0 {
+        request.max_wait_ms = config.max_wait_cap_ms;
+    }
+    request.max_wait_ms = request.max_wait_ms.min(config.max_wait_cap_ms);
+}
+
+/// Decides a validated request: admission policy, coalescing, then the
+/// grant/wait loop, with the decision audited and published. Returns the
+/// response and whether a denial came from a backend error.
+async fn decide_permit(
+    state: &Arc<AppState>,
+    request: &mut RequestTokenRequest,
+    trace: Option<&TraceContext>,
+) -> (RequestTokenResponse, bool) {
+    let clock_skew_ms = request
+        .client_unix_ms
+        .map(|client_unix_ms| observe_clock_skew(state, client_unix_ms));
+    let (mut response, errored) = match admission_policy(state, request).await {
+        Some(veto) => {
+            let decision = PermitDecision {
+                granted: false,
+                retry_after_ms: veto.retry_after_ms,
+                reason: veto.reason,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+            let mut response = deny_permit(state, request, decision, 0);
+            response.plugin_reason = veto.plugin_reason;
+            response.policy_rule = veto.policy_rule;
+            response.maintenance_window = veto.maintenance_window;
+            (response, false)
+        }
+        None => match coalesce::leader(state, request).await {
+            Some((leader, remaining_ms)) => {
+                state.metrics.coalesced.fetch_add(1, Ordering::Relaxed);
+                let decision = PermitDecision {
+                    granted: false,
+                    retry_after_ms: remaining_ms,
+                    reason: Reason::Coalesced,
+                    errored: false,
+                    forecast: None,
+                    long_limit: None,
+                    not_before_unix_ms: None,
+                };
+                let mut response = deny_permit(state, request, decision, 0);
+                response.coalesced_with = Some(leader);
+                (response, false)
+            }
+            None => await_permit(state, request).await,
+        },
+    };
+    response.tags = request.tags.clone();
+    response.clock_skew_ms = clock_skew_ms;
+    record_decision_audit(state, request, &response, trace);
+    state.publisher.decision(
+        state,
+        request,
+        response.granted,
+        response.reason.code(),
+        response.retry_after_ms,
+        trace.map(|trace| trace.trace_id.as_str()),
+    );
+    (response, errored)
+}
+
+/// Runs the grant/wait loop for one request. Returns the decision and whether
+/// the final denial came from a backend error rather than a limit.
+async fn await_permit(
+    state: &Arc<AppState>,
+    request: &RequestTokenRequest,
+) -> (RequestTokenResponse, bool) {
     let _inflight = InflightGuard::new(state.metrics.clone());
     let started = unix_ms();
-    let deadline = started.saturating_add(request.max_wait_ms);
+    // Waiting past an interaction's deadline only produces a late response.
+    let deadline = started
+        .saturating_add(request.max_wait_ms)
+        .min(request.interaction_deadline_unix_ms.unwrap_or(u64::MAX));
     let mut waited_ms = 0_u64;
+    let mut waiter: Option<WaiterGuard> = None;
+    // Interactions are ordered by deadline instead, and gateway commands
+    // are paced per shard. Tickets live in Redis; a memory store is a
+    // single instance, whose waiters are woken in order anyway.
+    let mut ticket = (request.transport == Transport::Http
+        && request.interaction_deadline_unix_ms.is_none()
+        && !state.config.memory_store)
+        .then(|| QueueTicket::new(state, request, started, deadline));
 
     loop {
-        let decision = issue_permit(&state, &request).await;
+        let own_waiter = waiter.as_ref().map(WaiterGuard::id);
+        let turn = match &mut ticket {
+            Some(ticket) => Some(ticket.ahead(unix_ms()).await),
+            None => None,
+        };
+        let cancelled = waiter.as_ref().is_some_and(WaiterGuard::cancelled) || turn == Some(None);
+        let ahead = turn.flatten();
+        let queued_behind = ahead.is_some_and(|ahead| ahead > 0);
+        let decision = if cancelled {
+            state
+                .metrics
+                .requests_cancelled
+                .fetch_add(1, Ordering::Relaxed);
+            PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms,
+                reason: Reason::Cancelled,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            }
+        } else if interactions::must_yield(&state.waiters, request, own_waiter) {
+            state
+                .metrics
+                .interaction_yields
+                .fetch_add(1, Ordering::Relaxed);
+            PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms + retry_jitter_ms(&state.config),
+                reason: Reason::YieldedToInteraction,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            }
+        } else if queued_behind {
+            state.metrics.queue_holds.fetch_add(1, Ordering::Relaxed);
+            PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms + retry_jitter_ms(&state.config),
+                reason: Reason::QueuedBehindWaiters,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            }
+        } else {
+            match priority::aged(&request.priority, waited_ms, state.config.priority_aging_ms) {
+                Some(priority) => {
+                    let aged = RequestTokenRequest {
+                        priority: priority.to_string(),
+                        ..request.clone()
+                    };
+                    let decision = issue_permit(state, &aged).await;
+                    if decision.granted {
+                        state.metrics.aged_grants.fetch_add(1, Ordering::Relaxed);
+                    }
+                    decision
+                }
+                None => issue_permit(state, request).await,
+            }
+        };
+        if !decision.granted {
+            signals::observe_denial(state, request, decision.reason, decision.retry_after_ms);
+        }
         if decision.granted {
             state
                 .metrics
@@ -383,62 +2379,420 @@ async fn request_token(
                 .tokens_granted_total
                 .fetch_add(1, Ordering::Relaxed);
             state.metrics.observe_request_wait_ms(waited_ms);
+            state
+                .usage
+                .record_decision(request, Decision::Granted, waited_ms);
+            if let Some(ticket) = ticket.take() {
+                ticket.leave().await;
+            }
             let response = RequestTokenResponse {
                 granted: true,
-                not_before_unix_ms: unix_ms(),
-                lease_id: Some(format!("lease-{}-{}", request.request_id, unix_ms())),
+                not_before_unix_ms: decision.not_before_unix_ms.unwrap_or_else(unix_ms),
+                route_template: request.route.clone(),
+                lease_id: match request.transport {
+                    Transport::Http => leases::issue(state, request).await,
+                    Transport::Gateway => None,
+                },
                 retry_after_ms: None,
                 reason: decision.reason,
+                reason_message: decision.reason.message(),
+                retry: None,
+                server_unix_ms: unix_ms(),
+                clock_skew_ms: None,
+                forecast: decision.forecast,
+                plugin_reason: None,
+                policy_rule: None,
+                maintenance_window: None,
+                long_limit: None,
+                tags: Vec::new(),
+                queue_position: None,
+                estimated_wait_ms: None,
+                coalesced_with: None,
             };
-            return (StatusCode::OK, Json(response));
+            return (response, false);
         }
 
         let now = unix_ms();
         let retry_after_ms = decision.retry_after_ms.max(state.config.min_retry_ms);
-        let can_wait = request.max_wait_ms > 0
+        let can_wait = !cancelled
+            && request.max_wait_ms > 0
             && now < deadline
             && now.saturating_add(retry_after_ms) <= deadline
             && waited_ms.saturating_add(retry_after_ms) <= request.max_wait_ms;
+        // Every waiter holds a handler, a connection and a timer; past the
+        // caps new waiters are turned away instead of queueing.
+        let shed = if can_wait && waiter.is_none() {
+            shed_reason(state, request)
+        } else {
+            None
+        };
 
-        if can_wait {
+        if can_wait && shed.is_none() {
+            if let Some(ticket) = &mut ticket {
+                ticket.join(state.config.max_wait_cap_ms).await;
+            }
+            let waiter = waiter.get_or_insert_with(|| {
+                state.waiters.register(WaiterInfo {
+                    id: 0,
+                    request_id: request.request_id.clone(),
+                    client_id: request.client_id.clone(),
+                    group_id: request.group_id.clone(),
+                    discord_identity: request.discord_identity.clone(),
+                    method: request.method.clone(),
+                    route: request.route.clone(),
+                    major_parameter: request.major_parameter.clone(),
+                    priority: request.priority.clone(),
+                    callback: request.callback_url.is_some(),
+                    interaction_deadline_unix_ms: request.interaction_deadline_unix_ms,
+                    enqueued_unix_ms: started,
+                    deadline_unix_ms: deadline,
+                    route_bucket: bucket_id(
+                        &request.discord_identity,
+                        &request.method,
+                        &request.route,
+                        &request.major_parameter,
+                    ),
+                    wakeup: Arc::default(),
+                    cancelled: Arc::default(),
+                })
+            });
+            // A waiter queued behind others is woken when the one ahead of
+            // it leaves; the poll only catches up with missed wakeups, rarely
+            // for long-poll waiters.
+            let poll_ms = if request.long_poll {
+                state.config.long_poll_recheck_ms
+            } else {
+                state.config.queue_poll_ms
+            };
+            let nap_ms = if queued_behind {
+                poll_ms.min(deadline - now).max(retry_after_ms)
+            } else {
+                retry_after_ms
+            };
             state.metrics.queue_depth.fetch_add(1, Ordering::Relaxed);
-            sleep(Duration::from_millis(retry_after_ms)).await;
+            tokio::select! {
+                _ = sleep(Duration::from_millis(nap_ms)) => {}
+                _ = waiter.woken() => {}
+            }
             state.metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
-            waited_ms = waited_ms.saturating_add(retry_after_ms);
+            waited_ms = unix_ms().saturating_sub(started);
             continue;
         }
 
-        if decision.errored {
-            state.metrics.request_error.fetch_add(1, Ordering::Relaxed);
-        } else {
-            state
-                .metrics
-                .request_denied
-                .fetch_add(1, Ordering::Relaxed);
+        if let Some(ticket) = ticket.take() {
+            ticket.leave().await;
+        }
+        let decision = match shed {
+            Some(reason) => PermitDecision { reason, ..decision },
+            None => decision,
+        };
+        let errored = decision.errored;
+        let mut response = deny_permit(state, request, decision, waited_ms);
+        if let Some(ahead) = ahead {
+            response.queue_position = Some(ahead);
+            response.estimated_wait_ms = response
+                .retry_after_ms
+                .map(|retry_after_ms| retry_after_ms + ahead * route_refill_ms(state, request));
         }
+        return (response, errored);
+    }
+}
+
+/// Why a call that would start waiting is turned away instead: this
+/// instance, or the call's client, already holds as many waiters as allowed.
+fn shed_reason(state: &AppState, request: &RequestTokenRequest) -> Option<Reason> {
+    let config = &state.config;
+    if config.max_waiters > 0 && state.waiters.queued() as u64 >= config.max_waiters {
+        state.metrics.queue_shed.fetch_add(1, Ordering::Relaxed);
+        return Some(Reason::QueueFull);
+    }
+    // Callers that send no `client_id` are not one client.
+    if config.max_waiters_per_client > 0
+        && !request.client_id.is_empty()
+        && state
+            .waiters
+            .count(|waiter| waiter.client_id == request.client_id) as u64
+            >= config.max_waiters_per_client
+    {
         state
             .metrics
-            .tokens_denied_total
+            .client_queue_shed
             .fetch_add(1, Ordering::Relaxed);
-        state.metrics.observe_request_wait_ms(waited_ms);
-
-        let response = RequestTokenResponse {
-            granted: false,
-            not_before_unix_ms: now.saturating_add(retry_after_ms),
-            lease_id: None,
-            retry_after_ms: Some(retry_after_ms),
-            reason: decision.reason,
-        };
-        return (StatusCode::OK, Json(response));
+        return Some(Reason::ClientQueueFull);
+    }
+    None
+}
+
+/// Records a final denial and builds its response.
+fn deny_permit(
+    state: &AppState,
+    request: &RequestTokenRequest,
+    decision: PermitDecision,
+    waited_ms: u64,
+) -> RequestTokenResponse {
+    let now = unix_ms();
+    let retry_after_ms = decision.retry_after_ms.max(state.config.min_retry_ms);
+    if decision.errored {
+        state.metrics.request_error.fetch_add(1, Ordering::Relaxed);
+        state
+            .usage
+            .record_decision(request, Decision::Errored, waited_ms);
+    } else {
+        state.metrics.request_denied.fetch_add(1, Ordering::Relaxed);
+        state
+            .usage
+            .record_decision(request, Decision::Denied, waited_ms);
+    }
+    state
+        .metrics
+        .tokens_denied_total
+        .fetch_add(1, Ordering::Relaxed);
+    state.metrics.observe_request_wait_ms(waited_ms);
+    let mut retry = retry_guidance(&state.config, request.attempt, retry_after_ms);
+    // A rule denial holds for every retry of the same request.
+    retry.give_up |= decision.reason == Reason::RuleDenied;
+    // Registration windows run for hours; retrying in a deploy loop only
+    // delays the deploy further.
+    retry.give_up |= decision.reason == Reason::CommandRegistrationExhausted;
+    // A daily quota only comes back at midnight UTC.
+    retry.give_up |= decision.reason == Reason::DailyQuotaExhausted;
+    // The client asked for this one to stop.
+    retry.give_up |= decision.reason == Reason::Cancelled;
+    // Discord rejects an interaction response sent after its deadline.
+    if request
+        .interaction_deadline_unix_ms
+        .is_some_and(|due| now.saturating_add(retry_after_ms) > due)
+    {
+        state
+            .metrics
+            .interaction_deadline_misses
+            .fetch_add(1, Ordering::Relaxed);
+        retry.give_up = true;
     }
+
+    RequestTokenResponse {
+        granted: false,
+        not_before_unix_ms: now.saturating_add(retry_after_ms),
+        route_template: request.route.clone(),
+        lease_id: None,
+        retry_after_ms: Some(retry_after_ms),
+        reason: decision.reason,
+        reason_message: decision.reason.message(),
+        retry: Some(retry),
+        server_unix_ms: now,
+        clock_skew_ms: None,
+        forecast: None,
+        plugin_reason: None,
+        policy_rule: None,
+        maintenance_window: None,
+        long_limit: decision.long_limit,
+        tags: Vec::new(),
+        queue_position: None,
+        estimated_wait_ms: None,
+        coalesced_with: None,
+    }
+}
+
+/// Why the admission policy refused a request before it reached the limiter.
+struct PolicyVeto {
+    reason: Reason,
+    retry_after_ms: u64,
+    plugin_reason: Option<String>,
+    policy_rule: Option<String>,
+    maintenance_window: Option<String>,
 }
 
+impl PolicyVeto {
+    fn new(reason: Reason, retry_after_ms: u64) -> Self {
+        Self {
+            reason,
+            retry_after_ms,
+            plugin_reason: None,
+            policy_rule: None,
+            maintenance_window: None,
+        }
+    }
+}
+
+/// Checks maintenance windows and group pauses, then runs the declarative
+/// rules and the policy plugins, applying their priority, limit and tag
+/// adjustments to `request`. Runtime limit overrides replace the limits
+/// those set, and a maintenance reduction caps whatever limits result. Shared by
+/// `request_token` and the job runner.
+async fn admission_policy(
+    state: &AppState,
+    request: &mut RequestTokenRequest,
+) -> Option<PolicyVeto> {
+    let now = unix_ms();
+    let maintenance = state.maintenance.evaluate(request);
+    if let Some(Effect::Paused {
+        window,
+        ends_unix_ms,
+    }) = maintenance
+    {
+        state
+            .metrics
+            .maintenance_denials
+            .fetch_add(1, Ordering::Relaxed);
+        return Some(PolicyVeto {
+            maintenance_window: Some(window),
+            ..PolicyVeto::new(Reason::MaintenancePaused, ends_unix_ms.saturating_sub(now))
+        });
+    }
+    if let Some(remaining_ms) = state.controls.paused_for(&request.group_id) {
+        state
+            .metrics
+            .group_pause_denials
+            .fetch_add(1, Ordering::Relaxed);
+        return Some(PolicyVeto::new(Reason::GroupPaused, remaining_ms));
+    }
+
+    let outcome = state.rules.evaluate(request, now);
+    outcome.apply(request);
+    if let Some(rule) = outcome.denied_by {
+        state.metrics.rule_denials.fetch_add(1, Ordering::Relaxed);
+        return Some(PolicyVeto {
+            policy_rule: Some(rule),
+            ..PolicyVeto::new(Reason::RuleDenied, 0)
+        });
+    }
+
+    let verdict = state.plugins.on_permit(state, request).await;
+    verdict.apply(request);
+    if let Some(veto) = verdict.veto {
+        state
+            .metrics
+            .plugin_permit_vetoes
+            .fetch_add(1, Ordering::Relaxed);
+        return Some(PolicyVeto {
+            plugin_reason: Some(veto),
+            ..PolicyVeto::new(
+                Reason::PluginVeto,
+                verdict.retry_after_ms.unwrap_or_default(),
+            )
+        });
+    }
+
+    let (global_override, route_override) = state.controls.limits(&request.group_id);
+    if global_override.is_some() {
+        request.global_rps_override = global_override;
+    }
+    if route_override.is_some() {
+        request.route_rps_override = route_override;
+    }
+
+    if let Some(Effect::Reduced { percent }) = maintenance {
+        let (global_rps, route_rps) = base_limits(state, now, Some(&request.discord_identity));
+        let reduce = |rps: u64| (rps * percent / 100).max(1);
+        request.global_rps_override =
+            Some(reduce(request.global_rps_override.unwrap_or(global_rps)));
+        request.route_rps_override = Some(reduce(request.route_rps_override.unwrap_or(route_rps)));
+    }
+    None
+}
+
+#[utoipa::path(
+    post,
+    path = "/report_result",
+    request_body(content(
+        (ReportResultRequest = "application/json"),
+        (ReportResultRequest = "application/msgpack"),
+        (ReportResultRequest = "application/cbor"),
+    )),
+    params(("Idempotency-Key" = Option<String>, Header)),
+    responses(
+        (status = 200, description = "Report applied", body = openapi::ReportResponse),
+        (status = 400, description = "Invalid body", body = openapi::Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Lease mismatch with DMBO_REJECT_LEASE_MISMATCH", body = openapi::Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Report could not be persisted", body = openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn report_result(
     State(state): State<Arc<AppState>>,
-    Json(report): Json<ReportResultRequest>,
-) -> impl IntoResponse {
+    headers: HeaderMap,
+    payload: Result<Payload<ReportResultRequest>, PayloadRejection>,
+) -> Response {
+    let trace = TraceContext::from_headers(&headers);
+    let mut response = process_report_result(&state, &headers, payload).await;
+    if let Some(trace) = &trace {
+        trace.propagate(response.headers_mut());
+    }
+    codec::negotiate(&headers, response).await
+}
+
+async fn process_report_result(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    payload: Result<Payload<ReportResultRequest>, PayloadRejection>,
+) -> Response {
+    let Payload(mut report) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return codec::rejection_response(state, rejection),
+    };
+    if let Some(error) = validate_feature(&state.config, report.feature.as_deref()) {
+        return validation_failed_response(vec![error]);
+    }
+    if let Some(path) = report.path.take().filter(|path| !path.trim().is_empty()) {
+        apply_path(&path, &mut report.route, &mut report.major_parameter);
+    }
+    let idempotency_redis_key = match idempotency_key(headers) {
+        Ok(Some(key)) => {
+            let redis_key = format!(
+                "rl:idem:report:{}:{}",
+                normalize_key_part(&report.discord_identity),
+                normalize_key_part(&key)
+            );
+            report.request_id = key;
+            Some(redis_key)
+        }
+        Ok(None) => None,
+        Err(error) => return validation_failed_response(vec![error]),
+    };
+    if let Some(redis_key) = &idempotency_redis_key {
+        match begin_idempotent(state, redis_key, 5_000).await {
+            IdempotencyState::Fresh => {}
+            IdempotencyState::Replay(body) => return replayed_response(body),
+            IdempotencyState::InProgress => return idempotency_conflict_response(),
+        }
+    }
+
+    let response = record_report(state, &report).await;
+    if let Some(redis_key) = &idempotency_redis_key {
+        let body = response
+            .status()
+            .is_success()
+            .then(|| json!({ "ok": true }).to_string());
+        finish_idempotent(state, redis_key, body, state.config.idempotency_ttl_seconds).await;
+    }
+    response
+}
+
+async fn record_report(state: &Arc<AppState>, report: &ReportResultRequest) -> Response {
+    if let Some(veto) = state.plugins.on_report(state, report).await.veto {
+        state
+            .metrics
+            .plugin_report_vetoes
+            .fetch_add(1, Ordering::Relaxed);
+        return (
+            StatusCode::OK,
+            Json(json!({ "ok": true, "plugin_vetoed": veto })),
+        )
+            .into_response();
+    }
+    // Reports on a lease that is not theirs would feed the guardrail with
+    // calls that were never permitted, or count one call twice.
+    let lease_mismatch = leases::check_report(state, report).await;
+    if let Some(kind) = lease_mismatch.filter(|_| state.config.reject_lease_mismatch) {
+        return problem_response(
+            StatusCode::CONFLICT,
+            PROBLEM_TYPE_LEASE_MISMATCH,
+            "Lease mismatch",
+            format!("lease_id is not an outstanding lease of this identity and route ({kind})"),
+            json!({ "ok": false, "lease_mismatch": kind }),
+        );
+    }
     if report.status_code == 429 {
-        match report.x_ratelimit_scope.as_deref() {
+        match report.scope() {
             Some("global") => state
                 .metrics
                 .observed_429_global
@@ -465,78 +2819,671 @@ async fn report_result(
         403 => {
             state.metrics.invalid_403.fetch_add(1, Ordering::Relaxed);
         }
-        429 if report.x_ratelimit_scope.as_deref() != Some("shared") => {
+        429 if report.scope() != Some("shared") => {
             state.metrics.invalid_429.fetch_add(1, Ordering::Relaxed);
         }
         _ => {}
     }
+    if report.status_code == 429 {
+        state.publisher.rate_limited(state, report);
+    }
+    if report.status_code == 429 && state.config.shadow_algorithm.is_some() {
+        state
+            .shadow
+            .record_429(&report_bucket(state, report), unix_ms());
+    }
+    state.anomalies.record(
+        &report.discord_identity,
+        report.status_code == 429 && report.scope() != Some("shared"),
+        counts_toward_invalid_limit(report.status_code, report.scope()),
+    );
+    state.usage.record_report(
+        report,
+        counts_toward_invalid_limit(report.status_code, report.scope()),
+    );
 
-    let mut conn = match state.redis.get_multiplexed_async_connection().await {
-        Ok(conn) => conn,
-        Err(_) => {
+    if persist_report(state, report).await.is_err() {
+        state.deferred_reports.defer(state, report.clone());
+        return report_failed_response(state);
+    }
+    // The call's concurrency slot is free now; let the next waiter on its
+    // bucket try instead of sleeping out its retry hint.
+    wakeups::announce(state, &report_route_bucket(report));
+    let body = match lease_mismatch {
+        Some(kind) => json!({ "ok": true, "lease_mismatch": kind }),
+        None => json!({ "ok": true }),
+    };
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+/// Writes a report's Redis side effects: the report marker and, for invalid
+/// responses, the group's invalid counter and guardrail. Shared by
+/// `report_result` and the dead-letter retry loop.
+async fn persist_report(state: &AppState, report: &ReportResultRequest) -> Result<(), ()> {
+    let result = write_report(state, report).await;
+    if result.is_err() {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    result.map_err(|_| ())
+}
+
+async fn write_report(state: &AppState, report: &ReportResultRequest) -> redis::RedisResult<()> {
+    if !state.config.memory_store {
+        write_service_report(state, report).await?;
+    }
+    cloudflare::record_ban(state, report).await?;
+    if let Some(cooldown_ms) = reported_cooldown_ms(&state.config, report) {
+        let major_parameter = report_major_parameter(report);
+        let (key, counter) = match report.scope() {
+            Some("global") => (
+                keys::global_cooldown_key(&report.discord_identity),
+                &state.metrics.reported_cooldowns_global,
+            ),
+            Some("shared") if major_parameter != UNKNOWN_MAJOR_PARAMETER => (
+                keys::shared_cooldown_key(&major_parameter),
+                &state.metrics.reported_cooldowns_shared,
+            ),
+            _ => (
+                keys::route_cooldown_key(&report_bucket(state, report)),
+                &state.metrics.reported_cooldowns_route,
+            ),
+        };
+        state
+            .store
+            .put(&key, Some("1".to_string()), cooldown_ms)
+            .await?;
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    if let Some(cooldown_ms) = sublimit_cooldown_ms(&state.config, report) {
+        state
+            .store
+            .put(
+                &keys::sublimit_cooldown_key(&report_route_bucket(report)),
+                Some("1".to_string()),
+                cooldown_ms,
+            )
+            .await?;
+        state
+            .metrics
+            .reported_cooldowns_sublimit
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    count_invalid_response(state, report).await?;
+    Ok(())
+}
+
+/// The report's share of service-only state, all of it in Redis: the report
+/// marker, upload, lease and route-limit bookkeeping and learned Discord
+/// buckets. `DMBO_STORE=memory` runs with none of these features.
+async fn write_service_report(
+    state: &AppState,
+    report: &ReportResultRequest,
+) -> redis::RedisResult<()> {
+    let mut conn = state.redis.get_multiplexed_async_connection().await?;
+    let key = format!("rl:report:{}:{}", report.status_code, report.request_id);
+    conn.set_ex::<_, _, ()>(key, 1_u8, 300).await?;
+    uploads::finish(
+        state,
+        &mut conn,
+        &report.discord_identity,
+        &report.request_id,
+    )
+    .await?;
+    if let Some(lease_id) = report.lease_id.as_deref().filter(|id| !id.is_empty()) {
+        if leases::release(state, &mut conn, lease_id).await? {
             state
                 .metrics
-                .redis_errors_total
+                .leases_reported
                 .fetch_add(1, Ordering::Relaxed);
-            return (StatusCode::OK, Json(json!({ "ok": false })));
         }
+    }
+    let method = report.method.trim().to_ascii_uppercase();
+    let route = routes::normalize_route(&report.route).template;
+    state
+        .route_limits
+        .finish(
+            &mut conn,
+            &report.request_id,
+            &method,
+            &route,
+            &report_route_bucket(report),
+        )
+        .await?;
+    state
+        .bucket_map
+        .learn(
+            &mut conn,
+            &state.metrics,
+            report,
+            &method,
+            &route,
+            unix_ms(),
+        )
+        .await?;
+
+    if let (Some(limit), Some(remaining), Some(reset_after_s)) = (
+        report.x_ratelimit_limit,
+        report.x_ratelimit_remaining,
+        report.x_ratelimit_reset_after_s.filter(|s| *s > 0.0),
+    ) {
+        let key = observed_key(&report_bucket(state, report));
+        redis::pipe()
+            .atomic()
+            .hset_multiple(
+                &key,
+                &[
+                    ("limit", limit.to_string()),
+                    ("remaining", remaining.to_string()),
+                    (
+                        "bucket",
+                        report.x_ratelimit_bucket.clone().unwrap_or_default(),
+                    ),
+                ],
+            )
+            .ignore()
+            .pexpire(&key, (reset_after_s * 1000.0).ceil() as i64)
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Counts an invalid response toward its group's guardrail and alerts when
+/// the report trips it.
+async fn count_invalid_response(
+    state: &AppState,
+    report: &ReportResultRequest,
+) -> Result<(), StoreError> {
+    if !counts_toward_invalid_limit(report.status_code, report.scope()) {
+        return Ok(());
+    }
+    let group = normalize_key_part(&report.group_id);
+    let trip = state
+        .store
+        .record_invalid(
+            &group,
+            state.config.invalid_threshold,
+            state.config.guardrail_cooldown_ms,
+        )
+        .await?;
+    if let Some(GuardTrip {
+        invalid_count,
+        already_active,
+    }) = trip
+    {
+        // Only the report that trips the guard alerts, not every report
+        // made while it is already active.
+        signals::guardrail_activated(
+            state,
+            &group,
+            unix_ms() + state.config.guardrail_cooldown_ms,
+        );
+        if !already_active {
+            state
+                .sql
+                .guard_trip(state, unix_ms(), &group, invalid_count as i64);
+            notifier::notify(
+                state,
+                Alert {
+                    kind: "guard_trip",
+                    subject: group.clone(),
+                    severity: Severity::Critical,
+                    title: "Invalid-request guardrail tripped".to_string(),
+                    description: format!(
+                        "Group `{group}` reached {invalid_count} invalid responses. Its permits \
+                     are denied for {}s.",
+                        state.config.guardrail_cooldown_ms / 1000
+                    ),
+                    fields: vec![("Last status", report.status_code.to_string())],
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Builds an RFC 7807 `application/problem+json` response. Extension members
+/// are merged into the top-level object next to the standard fields.
+fn problem_response(
+    status: StatusCode,
+    problem_type: &str,
+    title: &str,
+    detail: String,
+    extensions: serde_json::Value,
+) -> Response {
+    let mut body = json!({
+        "type": problem_type,
+        "title": title,
+        "status": status.as_u16(),
+        "detail": detail,
+    });
+    if let (Some(body), serde_json::Value::Object(extensions)) = (body.as_object_mut(), extensions)
+    {
+        body.extend(extensions);
+    }
+    (
+        status,
+        [(header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE)],
+        body.to_string(),
+    )
+        .into_response()
+}
+
+/// Denials map to 429 with `Retry-After`, backend failures to 503. The permit
+/// fields stay in the body as extension members so clients can keep reading
+/// `granted`/`retry_after_ms` regardless of the status code.
+fn denied_response(state: &AppState, response: RequestTokenResponse, errored: bool) -> Response {
+    let retry_after_ms = response.retry_after_ms.unwrap_or(state.config.min_retry_ms);
+    if response.reason == Reason::QueueFull && state.config.strict_queue_full {
+        let detail = format!(
+            "{}; retry after {retry_after_ms}ms",
+            response.reason_message
+        );
+        let extensions = serde_json::to_value(&response).unwrap_or_default();
+        let mut http_response = problem_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            PROBLEM_TYPE_QUEUE_FULL,
+            "Waiter queue full",
+            detail,
+            extensions,
+        );
+        http_response.headers_mut().insert(
+            header::RETRY_AFTER,
+            header::HeaderValue::from(retry_after_ms.div_ceil(1000).max(1)),
+        );
+        return http_response;
+    }
+    if state.config.legacy_status_codes {
+        return (StatusCode::OK, Json(response)).into_response();
+    }
+    let (status, problem_type, title) = if errored {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            PROBLEM_TYPE_BACKEND_UNAVAILABLE,
+            "Rate limit backend unavailable",
+        )
+    } else {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            PROBLEM_TYPE_RATE_LIMITED,
+            "Permit denied",
+        )
     };
-    let key = format!("rl:report:{}:{}", report.status_code, report.request_id);
-    let persisted: redis::RedisResult<()> = conn.set_ex(key, 1_u8, 300).await;
-    if persisted.is_err() {
+    let detail = format!("{}; retry after {retry_after_ms}ms", response.reason_message);
+    let extensions = serde_json::to_value(&response).unwrap_or_default();
+    let mut http_response = problem_response(status, problem_type, title, detail, extensions);
+    http_response.headers_mut().insert(
+        header::RETRY_AFTER,
+        header::HeaderValue::from(retry_after_ms.div_ceil(1000).max(1)),
+    );
+    http_response
+}
+
+fn backend_unavailable_response(state: &AppState) -> Response {
+    state
+        .metrics
+        .redis_errors_total
+        .fetch_add(1, Ordering::Relaxed);
+    problem_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        PROBLEM_TYPE_BACKEND_UNAVAILABLE,
+        "Rate limit backend unavailable",
+        Reason::RedisError.message().to_string(),
+        json!({
+            "reason": Reason::RedisError,
+            "reason_message": Reason::RedisError.message(),
+        }),
+    )
+}
+
+fn report_failed_response(state: &AppState) -> Response {
+    if state.config.legacy_status_codes {
+        return (StatusCode::OK, Json(json!({ "ok": false }))).into_response();
+    }
+    problem_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        PROBLEM_TYPE_BACKEND_UNAVAILABLE,
+        "Rate limit backend unavailable",
+        Reason::ReportNotPersisted.message().to_string(),
+        json!({
+            "ok": false,
+            "reason": Reason::ReportNotPersisted,
+            "reason_message": Reason::ReportNotPersisted.message(),
+        }),
+    )
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct FieldError {
+    field: &'static str,
+    message: String,
+}
+
+fn validate_request(config: &Config, request: &RequestTokenRequest) -> Vec<FieldError> {
+    if request.transport == Transport::Gateway {
+        let mut errors = gateway::validate(request);
+        errors.extend(validate_feature(config, request.feature.as_deref()));
+        if config.memory_store {
+            errors.push(FieldError {
+                field: "transport",
+                message: "gateway pacing needs DMBO_STORE=redis".to_string(),
+            });
+        }
+        return errors;
+    }
+    let mut errors = Vec::new();
+    if !KNOWN_METHODS.contains(&request.method.as_str()) {
+        errors.push(FieldError {
+            field: "method",
+            message: format!("must be one of {}", KNOWN_METHODS.join(", ")),
+        });
+    }
+    let bounded = [
+        ("discord_identity", &request.discord_identity, MAX_IDENTITY_LEN),
+        ("route", &request.route, MAX_ROUTE_LEN),
+        ("major_parameter", &request.major_parameter, MAX_MAJOR_PARAMETER_LEN),
+    ];
+    for (field, value, max_len) in bounded {
+        if value.trim().is_empty() {
+            errors.push(FieldError {
+                field,
+                message: "must not be empty".to_string(),
+            });
+        } else if value.len() > max_len {
+            errors.push(FieldError {
+                field,
+                message: format!("must be at most {max_len} bytes"),
+            });
+        }
+    }
+    if request
+        .cost
+        .is_some_and(|cost| !(1..=MAX_REQUEST_COST).contains(&cost))
+    {
+        errors.push(FieldError {
+            field: "cost",
+            message: format!("must be 1-{MAX_REQUEST_COST}"),
+        });
+    }
+    if let Some(Err(message)) = request
+        .callback_url
+        .as_deref()
+        .map(|url| callbacks::check_url(config, url))
+    {
+        errors.push(FieldError {
+            field: "callback_url",
+            message,
+        });
+    }
+    errors.extend(uploads::validate(request));
+    errors.extend(validate_feature(config, request.feature.as_deref()));
+    errors
+}
+
+/// A `feature` must name one of `DMBO_FEATURES` so per-feature rollups and
+/// metrics stay bounded.
+fn validate_feature(config: &Config, feature: Option<&str>) -> Option<FieldError> {
+    let feature = feature?;
+    if config.features.iter().any(|name| name == feature) {
+        return None;
+    }
+    let message = if config.features.is_empty() {
+        "no features are configured (DMBO_FEATURES)".to_string()
+    } else {
+        format!("must be one of {}", config.features.join(", "))
+    };
+    Some(FieldError {
+        field: "feature",
+        message,
+    })
+}
+
+fn validation_failed_response(errors: Vec<FieldError>) -> Response {
+    let detail = errors
+        .iter()
+        .map(|error| format!("{}: {}", error.field, error.message))
+        .collect::<Vec<_>>()
+        .join("; ");
+    problem_response(
+        StatusCode::BAD_REQUEST,
+        PROBLEM_TYPE_INVALID_REQUEST,
+        "Request validation failed",
+        detail,
+        json!({
+            "reason": Reason::InvalidRequest,
+            "reason_message": Reason::InvalidRequest.message(),
+            "errors": errors,
+        }),
+    )
+}
+
+enum IdempotencyState {
+    Fresh,
+    Replay(String),
+    InProgress,
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, FieldError> {
+    let Some(value) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+    let key = value.to_str().unwrap_or_default().trim();
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err(FieldError {
+            field: "Idempotency-Key",
+            message: format!("must be 1-{MAX_IDEMPOTENCY_KEY_LEN} visible ASCII characters"),
+        });
+    }
+    Ok(Some(key.to_string()))
+}
+
+/// Claims an idempotency key with a short-lived pending marker, or returns the
+/// stored response when the key already completed. Store failures fall back
+/// to processing the request normally.
+async fn begin_idempotent(
+    state: &AppState,
+    redis_key: &str,
+    pending_ttl_ms: u64,
+) -> IdempotencyState {
+    match state
+        .store
+        .claim(redis_key, IDEMPOTENCY_PENDING, pending_ttl_ms)
+        .await
+    {
+        Ok(Some(body)) if body != IDEMPOTENCY_PENDING => IdempotencyState::Replay(body),
+        Ok(Some(_)) => IdempotencyState::InProgress,
+        _ => IdempotencyState::Fresh,
+    }
+}
+
+/// Stores the response for replay for `ttl_seconds`, or releases the claim
+/// when `body` is `None`.
+async fn finish_idempotent(
+    state: &AppState,
+    redis_key: &str,
+    body: Option<String>,
+    ttl_seconds: u64,
+) {
+    let result = state
+        .store
+        .put(redis_key, body, ttl_seconds.saturating_mul(1000))
+        .await;
+    if result.is_err() {
         state
             .metrics
             .redis_errors_total
             .fetch_add(1, Ordering::Relaxed);
-        return (StatusCode::OK, Json(json!({ "ok": false })));
     }
+}
 
-    if counts_toward_invalid_limit(report.status_code, report.x_ratelimit_scope.as_deref()) {
-        let group = normalize_key_part(&report.group_id);
-        let invalid_key = format!("rl:invalid:{group}");
-        let guard_key = format!("rl:guard:{group}");
+fn replayed_response(body: String) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::HeaderName::from_static("idempotent-replayed"),
+                "true",
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
 
-        let invalid_count: redis::RedisResult<i64> = state
-            .incr_with_expire_script
-            .key(&invalid_key)
-            .arg(INVALID_COUNTER_TTL_SECONDS)
-            .invoke_async(&mut conn)
-            .await;
-        let invalid_count = match invalid_count {
-            Ok(count) => count,
-            Err(_) => {
-                state
-                    .metrics
-                    .redis_errors_total
-                    .fetch_add(1, Ordering::Relaxed);
-                return (StatusCode::OK, Json(json!({ "ok": false })));
-            }
+fn idempotency_conflict_response() -> Response {
+    problem_response(
+        StatusCode::CONFLICT,
+        PROBLEM_TYPE_IDEMPOTENCY_CONFLICT,
+        "Request already in progress",
+        Reason::IdempotencyConflict.message().to_string(),
+        json!({
+            "reason": Reason::IdempotencyConflict,
+            "reason_message": Reason::IdempotencyConflict.message(),
+        }),
+    )
+}
+
+fn json_rejection_response(state: &AppState, rejection: JsonRejection) -> Response {
+    if state.config.legacy_status_codes {
+        return rejection.into_response();
+    }
+    problem_response(
+        StatusCode::BAD_REQUEST,
+        PROBLEM_TYPE_INVALID_REQUEST,
+        "Invalid request body",
+        rejection.body_text(),
+        json!({
+            "reason": Reason::InvalidRequest,
+            "reason_message": Reason::InvalidRequest.message(),
+        }),
+    )
+}
+
+/// W3C trace context received from the caller. The orchestrator does not
+/// start spans of its own; it records the trace id and hands the headers back
+/// so downstream hops stay correlated.
+#[derive(Clone)]
+struct TraceContext {
+    traceparent: String,
+    tracestate: Option<String>,
+    trace_id: String,
+}
+
+impl TraceContext {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let traceparent = headers.get("traceparent")?.to_str().ok()?.trim();
+        let mut parts = traceparent.split('-');
+        let (version, trace_id, parent_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        let is_hex = |value: &str, len: usize| {
+            value.len() == len
+                && value
+                    .bytes()
+                    .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
         };
+        if !is_hex(version, 2)
+            || version == "ff"
+            || !is_hex(trace_id, 32)
+            || !is_hex(parent_id, 16)
+            || !is_hex(flags, 2)
+            || trace_id.bytes().all(|b| b == b'0')
+            || parent_id.bytes().all(|b| b == b'0')
+        {
+            return None;
+        }
+        Some(Self {
+            traceparent: traceparent.to_string(),
+            tracestate: headers
+                .get("tracestate")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            trace_id: trace_id.to_string(),
+        })
+    }
 
-        if invalid_count as u64 >= state.config.invalid_threshold {
-            let guard_result = redis::cmd("PSETEX")
-                .arg(&guard_key)
-                .arg(state.config.guardrail_cooldown_ms as i64)
-                .arg(invalid_count)
-                .query_async::<_, ()>(&mut conn)
-                .await;
-            if guard_result.is_err() {
-                state
-                    .metrics
-                    .redis_errors_total
-                    .fetch_add(1, Ordering::Relaxed);
-                return (StatusCode::OK, Json(json!({ "ok": false })));
-            }
+    fn propagate(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = header::HeaderValue::from_str(&self.traceparent) {
+            headers.insert("traceparent", value);
+        }
+        if let Some(value) = self
+            .tracestate
+            .as_deref()
+            .and_then(|value| header::HeaderValue::from_str(value).ok())
+        {
+            headers.insert("tracestate", value);
         }
     }
-    (StatusCode::OK, Json(json!({ "ok": true })))
+}
+
+/// Appends the final decision to the capped `rl:audit` stream. Runs in the
+/// background so auditing never adds latency to the permit path.
+fn record_decision_audit(
+    state: &Arc<AppState>,
+    request: &RequestTokenRequest,
+    response: &RequestTokenResponse,
+    trace: Option<&TraceContext>,
+) {
+    let fields = [
+        ("unix_ms", unix_ms().to_string()),
+        ("request_id", request.request_id.clone()),
+        ("client_id", request.client_id.clone()),
+        ("group_id", request.group_id.clone()),
+        ("discord_identity", request.discord_identity.clone()),
+        ("method", request.method.clone()),
+        ("route", request.route.clone()),
+        ("major_parameter", request.major_parameter.clone()),
+        ("granted", response.granted.to_string()),
+        ("reason", response.reason.code().to_string()),
+        ("tags", request.tags.join(",")),
+        ("feature", request.feature.clone().unwrap_or_default()),
+        (
+            "trace_id",
+            trace
+                .map(|trace| trace.trace_id.clone())
+                .unwrap_or_default(),
+        ),
+    ];
+    state.sql.audit(state, &fields);
+    if state.config.audit_maxlen == 0 {
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+            return;
+        };
+        let result = redis::cmd("XADD")
+            .arg(AUDIT_STREAM_KEY)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(state.config.audit_maxlen)
+            .arg("*")
+            .arg(&fields[..])
+            .query_async::<_, String>(&mut conn)
+            .await;
+        if result.is_err() {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    });
 }
 
 struct PermitDecision {
     granted: bool,
     retry_after_ms: u64,
-    reason: String,
+    reason: Reason,
     errored: bool,
+    forecast: Option<Forecast>,
+    long_limit: Option<String>,
+    /// Scheduled start for paced grants; now when `None`.
+    not_before_unix_ms: Option<u64>,
 }
 
 struct InflightGuard {
@@ -561,21 +3508,10 @@ impl Drop for InflightGuard {
 }
 
 async fn issue_permit(state: &Arc<AppState>, request: &RequestTokenRequest) -> PermitDecision {
+    if state.config.memory_store {
+        return issue_local_permit(state, request).await;
+    }
     let now_ms = unix_ms();
-    let second = now_ms / 1000;
-    let guard_key = format!("rl:guard:{}", normalize_key_part(&request.group_id));
-    let global_key = format!(
-        "rl:global:{}:{second}",
-        normalize_key_part(&request.discord_identity)
-    );
-    let route_key = format!(
-        "rl:route:{}:{}:{}:{}:{second}",
-        normalize_key_part(&request.discord_identity),
-        normalize_key_part(&request.method),
-        normalize_key_part(&request.route),
-        normalize_key_part(&request.major_parameter)
-    );
-
     let mut conn = match state.redis.get_multiplexed_async_connection().await {
         Ok(conn) => conn,
         Err(_) => {
@@ -586,36 +3522,406 @@ async fn issue_permit(state: &Arc<AppState>, request: &RequestTokenRequest) -> P
             return PermitDecision {
                 granted: false,
                 retry_after_ms: state.config.min_retry_ms,
-                reason: "redis_unavailable".to_string(),
+                reason: Reason::RedisUnavailable,
                 errored: true,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
             };
         }
     };
 
     let started = Instant::now();
-    let result: redis::RedisResult<(i32, i64, String)> = state
-        .request_token_script
-        .key(guard_key)
-        .key(global_key)
-        .key(route_key)
-        .arg(state.config.global_rps as i64)
-        .arg(state.config.route_rps as i64)
-        .arg(1_500_i64)
-        .arg(state.config.min_retry_ms as i64)
-        .invoke_async(&mut conn)
+    if request.transport == Transport::Gateway {
+        let result = gateway::acquire(state, &mut conn, request, now_ms).await;
+        state
+            .metrics
+            .observe_redis_latency_ms(started.elapsed().as_millis() as u64);
+        return result.unwrap_or_else(|_| {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+            PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms,
+                reason: Reason::RedisError,
+                errored: true,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            }
+        });
+    }
+    // Routes sharing a Discord bucket share its counters once the bucket is
+    // known; long-window limits stay on the literal route they name.
+    let bucket = state
+        .bucket_map
+        .resolve(
+            &mut conn,
+            &request.discord_identity,
+            &request.method,
+            &request.route,
+            &request.major_parameter,
+            now_ms,
+        )
+        .await;
+    let input = limit_input(state, request, bucket.clone(), now_ms);
+    let route_window_ms = input.route_window_ms;
+
+    let upload = match uploads::acquire(state, &mut conn, request, now_ms).await {
+        Ok(Some(UploadAcquired::Exhausted {
+            reason,
+            retry_after_ms,
+        })) => {
+            state
+                .metrics
+                .observe_redis_latency_ms(started.elapsed().as_millis() as u64);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms,
+                reason,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+        Ok(Some(UploadAcquired::Reserved(reservation))) => Some(reservation),
+        Ok(None) => None,
+        Err(_) => {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms,
+                reason: Reason::RedisError,
+                errored: true,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+    };
+    let reservation = match state
+        .long_limits
+        .acquire(&mut conn, request, &input.route_bucket, now_ms)
+        .await
+    {
+        Ok(Some(Acquired::Exhausted {
+            reason,
+            limit,
+            retry_after_ms,
+        })) => {
+            if let Some(upload) = upload {
+                upload.release(&mut conn).await;
+            }
+            state
+                .metrics
+                .observe_redis_latency_ms(started.elapsed().as_millis() as u64);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms,
+                reason,
+                errored: false,
+                forecast: None,
+                long_limit: limit,
+                not_before_unix_ms: None,
+            };
+        }
+        Ok(Some(Acquired::Reserved(reservation))) => Some(reservation),
+        Ok(None) => None,
+        Err(_) => {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms,
+                reason: Reason::RedisError,
+                errored: true,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+    };
+    let slot = match state
+        .route_limits
+        .acquire(&mut conn, request, &input.route_bucket, now_ms)
+        .await
+    {
+        Ok(Some(SlotAcquired::Exhausted { retry_after_ms })) => {
+            if let Some(reservation) = reservation {
+                reservation.release(&mut conn).await;
+            }
+            if let Some(upload) = upload {
+                upload.release(&mut conn).await;
+            }
+            state
+                .metrics
+                .observe_redis_latency_ms(started.elapsed().as_millis() as u64);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms,
+                reason: Reason::RouteConcurrencyExhausted,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+        Ok(Some(SlotAcquired::Reserved(slot))) => Some(slot),
+        Ok(None) => None,
+        Err(_) => {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms,
+                reason: Reason::RedisError,
+                errored: true,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+    };
+    let paced = match reactions::acquire(state, &mut conn, request, now_ms).await {
+        Ok(Some(ReactionAcquired::Exhausted { retry_after_ms })) => {
+            if let Some(reservation) = reservation {
+                reservation.release(&mut conn).await;
+            }
+            if let Some(upload) = upload {
+                upload.release(&mut conn).await;
+            }
+            if let Some(slot) = slot {
+                slot.release(&mut conn).await;
+            }
+            state
+                .metrics
+                .observe_redis_latency_ms(started.elapsed().as_millis() as u64);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms,
+                reason: Reason::ReactionPaced,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+        Ok(Some(ReactionAcquired::Scheduled(paced))) => Some(paced),
+        Ok(None) => None,
+        Err(_) => {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms,
+                reason: Reason::RedisError,
+                errored: true,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+    };
+    let quota = match quotas::acquire(state, &mut conn, request, now_ms).await {
+        Ok(Some(QuotaAcquired::Exhausted { retry_after_ms })) => {
+            if let Some(reservation) = reservation {
+                reservation.release(&mut conn).await;
+            }
+            if let Some(upload) = upload {
+                upload.release(&mut conn).await;
+            }
+            if let Some(slot) = slot {
+                slot.release(&mut conn).await;
+            }
+            if let Some(paced) = paced {
+                paced.release(&mut conn).await;
+            }
+            state
+                .metrics
+                .observe_redis_latency_ms(started.elapsed().as_millis() as u64);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms,
+                reason: Reason::DailyQuotaExhausted,
+                errored: false,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+        Ok(Some(QuotaAcquired::Reserved(quota))) => Some(quota),
+        Ok(None) => None,
+        Err(_) => {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+            return PermitDecision {
+                granted: false,
+                retry_after_ms: state.config.min_retry_ms,
+                reason: Reason::RedisError,
+                errored: true,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
+            };
+        }
+    };
+    let not_before_unix_ms = paced
+        .as_ref()
+        .map(|paced| paced.slot_unix_ms)
+        .filter(|slot| *slot > now_ms);
+    let algorithm = state.config.limiter_algorithm;
+    let route_algorithm = state
+        .config
+        .route_algorithms
+        .for_route(&request.route)
+        .unwrap_or(algorithm);
+    let result = state
+        .store
+        .evaluate(&state.config, algorithm, route_algorithm, &input, false)
         .await;
     state
         .metrics
         .observe_redis_latency_ms(started.elapsed().as_millis() as u64);
+    if !matches!(&result, Ok((1, ..))) {
+        if let Some(reservation) = reservation {
+            reservation.release(&mut conn).await;
+        }
+        if let Some(upload) = upload {
+            upload.release(&mut conn).await;
+        }
+        if let Some(slot) = slot {
+            slot.release(&mut conn).await;
+        }
+        if let Some(paced) = paced {
+            paced.release(&mut conn).await;
+        }
+        if let Some(quota) = quota {
+            quota.release(&mut conn).await;
+        }
+    }
+    if let Ok((granted, ..)) = &result {
+        limiter::spawn_shadow(state, input, *granted == 1);
+    }
+
+    limiter_decision(
+        state,
+        result,
+        &bucket,
+        route_algorithm,
+        route_window_ms,
+        now_ms,
+        not_before_unix_ms,
+    )
+}
 
+/// `issue_permit` with `DMBO_STORE=memory`: the limiter alone, on the
+/// literal route's bucket. The reservations kept in Redis (uploads,
+/// long-window and concurrency limits, reaction pacing, quotas) are switched
+/// off in that mode (`REDIS_ONLY_SETTINGS`), and Discord buckets are not
+/// learned.
+async fn issue_local_permit(
+    state: &Arc<AppState>,
+    request: &RequestTokenRequest,
+) -> PermitDecision {
+    let now_ms = unix_ms();
+    let bucket = bucket_id(
+        &request.discord_identity,
+        &request.method,
+        &request.route,
+        &request.major_parameter,
+    );
+    let input = limit_input(state, request, bucket.clone(), now_ms);
+    let route_window_ms = input.route_window_ms;
+    let algorithm = state.config.limiter_algorithm;
+    let result = state
+        .store
+        .evaluate(&state.config, algorithm, algorithm, &input, false)
+        .await;
+    if let Ok((granted, ..)) = &result {
+        limiter::spawn_shadow(state, input, *granted == 1);
+    }
+    limiter_decision(
+        state,
+        result,
+        &bucket,
+        algorithm,
+        route_window_ms,
+        now_ms,
+        None,
+    )
+}
+
+/// The decision a limiter evaluation amounts to. Grants update the bucket's
+/// forecast.
+fn limiter_decision(
+    state: &AppState,
+    result: Result<PermitScriptReply, StoreError>,
+    bucket: &str,
+    route_algorithm: Algorithm,
+    route_window_ms: u64,
+    now_ms: u64,
+    not_before_unix_ms: Option<u64>,
+) -> PermitDecision {
     match result {
-        Ok((granted, retry_after_ms, reason)) => PermitDecision {
-            granted: granted == 1,
-            retry_after_ms: retry_after_ms.max(0) as u64,
+        Ok((
+            granted,
+            retry_after_ms,
             reason,
-            errored: false,
-        },
-        Err(_) => {
+            route_limit,
+            route_remaining,
+            observed_limit,
+            observed_remaining,
+            observed_resets_in_ms,
+        )) if Reason::from_limiter(&reason) != Reason::RedisError => {
+            if granted == 1 {
+                state.region.record_grant();
+            }
+            let known = |value: i64| u64::try_from(value).ok();
+            let forecast = (granted == 1).then(|| {
+                state.forecasts.on_grant(
+                    bucket,
+                    &BucketState {
+                        route_limit: known(route_limit).unwrap_or_default(),
+                        route_remaining: known(route_remaining).unwrap_or_default(),
+                        window_resets_in_ms: route_algorithm.resets_in_ms(
+                            now_ms,
+                            known(route_limit).unwrap_or_default(),
+                            known(route_remaining).unwrap_or_default(),
+                            route_window_ms,
+                        ),
+                        observed_limit: known(observed_limit),
+                        observed_remaining: Some(known(observed_remaining).unwrap_or(0))
+                            .filter(|_| observed_limit >= 0),
+                        observed_resets_in_ms: known(observed_resets_in_ms),
+                    },
+                )
+            });
+            PermitDecision {
+                granted: granted == 1,
+                retry_after_ms: retry_after_ms.max(0) as u64,
+                reason: Reason::from_limiter(&reason),
+                errored: false,
+                forecast,
+                long_limit: None,
+                not_before_unix_ms: not_before_unix_ms.filter(|_| granted == 1),
+            }
+        }
+        // A reason code this build does not know means the script has drifted.
+        _ => {
             state
                 .metrics
                 .redis_errors_total
@@ -623,17 +3929,179 @@ async fn issue_permit(state: &Arc<AppState>, request: &RequestTokenRequest) -> P
             PermitDecision {
                 granted: false,
                 retry_after_ms: state.config.min_retry_ms,
-                reason: "redis_error".to_string(),
+                reason: Reason::RedisError,
                 errored: true,
+                forecast: None,
+                long_limit: None,
+                not_before_unix_ms: None,
             }
         }
     }
 }
 
-fn normalize_key_part(input: &str) -> String {
-    input
-        .trim()
-        .replace([' ', ':', '/', '\\', '\t', '\n'], "_")
+/// The limiter's view of `request` on `bucket`, with every limit in force.
+fn limit_input(
+    state: &AppState,
+    request: &RequestTokenRequest,
+    bucket: String,
+    now_ms: u64,
+) -> LimitInput {
+    let (global_rps, route_rps) = base_limits(state, now_ms, Some(&request.discord_identity));
+    let (route_rps, route_window_ms) = state.route_limits.route_limit(request, route_rps);
+    LimitInput {
+        group_id: request.group_id.clone(),
+        discord_identity: request.discord_identity.clone(),
+        bucket,
+        route_bucket: bucket_id(
+            &request.discord_identity,
+            &request.method,
+            &request.route,
+            &request.major_parameter,
+        ),
+        major_parameter: request.major_parameter.clone(),
+        global_limit: state.config.priority_shares.scale(
+            &request.priority,
+            state.region.scale(
+                request
+                    .global_rps_override
+                    .unwrap_or_else(|| state.loans.adjust(&request.group_id, global_rps)),
+            ),
+        ),
+        global_exempt: state
+            .config
+            .global_exempt_routes
+            .iter()
+            .any(|pattern| route_matches(pattern, &request.route)),
+        route_limit: effective_route_limit(state, request, route_rps),
+        route_window_ms,
+        cost: state.config.method_weights.cost(&request.method) * request.cost.unwrap_or(1),
+        burst_percent: state.config.token_bucket_burst_percent,
+        now_ms,
+    }
+}
+
+/// `(granted, retry_after_ms, reason, route_limit, route_remaining,
+/// observed_limit, observed_remaining, observed_reset_ms)`; the last five are
+/// `-1` on denials or when unknown.
+type PermitScriptReply = (i32, i64, String, i64, i64, i64, i64, i64);
+
+/// Global and route limits in force at `now_ms`: the active quota calendar
+/// window's, falling back to `DMBO_GLOBAL_RPS` / `DMBO_ROUTE_RPS`. An
+/// identity listed in `DMBO_IDENTITY_GLOBAL_RPS` gets its own global limit
+/// in place of either, since that is what Discord allows it.
+fn base_limits(state: &AppState, now_ms: u64, discord_identity: Option<&str>) -> (u64, u64) {
+    let window = state.schedule.active(now_ms);
+    let identity_rps = discord_identity
+        .and_then(|identity| state.config.identity_global_rps.get(identity.trim()))
+        .copied();
+    (
+        identity_rps
+            .or_else(|| window.and_then(|window| window.global_rps))
+            .unwrap_or(state.config.global_rps),
+        window
+            .and_then(|window| window.route_rps)
+            .unwrap_or(state.config.route_rps),
+    )
+}
+
+/// The route limit `request` may fill: its override or `route_rps`, scaled
+/// to this region's share and the request's priority class.
+fn effective_route_limit(state: &AppState, request: &RequestTokenRequest, route_rps: u64) -> u64 {
+    state.config.priority_shares.scale(
+        &request.priority,
+        state
+            .region
+            .scale(request.route_rps_override.unwrap_or(route_rps)),
+    )
+}
+
+/// Milliseconds the request's route bucket takes to free room for one more
+/// call at its effective limit.
+fn route_refill_ms(state: &AppState, request: &RequestTokenRequest) -> u64 {
+    let (_, route_rps) = base_limits(state, unix_ms(), Some(&request.discord_identity));
+    let (route_rps, route_window_ms) = state.route_limits.route_limit(request, route_rps);
+    route_window_ms / effective_route_limit(state, request, route_rps).max(1)
+}
+
+/// How long a reported 429 holds its route (or, for global scope, its
+/// identity): Discord's Retry-After, else the bucket's reset, capped at
+/// `DMBO_REPORTED_COOLDOWN_MAX_MS`.
+fn reported_cooldown_ms(config: &Config, report: &ReportResultRequest) -> Option<u64> {
+    if report.status_code != 429 || config.reported_cooldown_max_ms == 0 {
+        return None;
+    }
+    report
+        .retry_after_ms
+        .or_else(|| {
+            report
+                .x_ratelimit_reset_after_s
+                .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+                .map(|seconds| (seconds * 1000.0).ceil() as u64)
+        })
+        .map(|ms| ms.min(config.reported_cooldown_max_ms))
+        .filter(|ms| *ms > 0)
+}
+
+/// How long a reported 429's body `retry_after` holds its literal route,
+/// capped like the header cooldown. Global 429s are held by the header
+/// cooldown alone.
+fn sublimit_cooldown_ms(config: &Config, report: &ReportResultRequest) -> Option<u64> {
+    if report.status_code != 429
+        || config.reported_cooldown_max_ms == 0
+        || report.scope() == Some("global")
+    {
+        return None;
+    }
+    report
+        .body_retry_after_s
+        .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+        .map(|seconds| ((seconds * 1000.0).ceil() as u64).min(config.reported_cooldown_max_ms))
+}
+
+/// The bucket a report's call was permitted on, resolved like the permit's:
+/// the Discord bucket the report names, or the literal request tuple.
+fn report_bucket(state: &AppState, report: &ReportResultRequest) -> String {
+    state
+        .bucket_map
+        .reported(report, &report_major_parameter(report))
+        .unwrap_or_else(|| report_route_bucket(report))
+}
+
+/// The literal request tuple of a report's call, as a bucket id.
+fn report_route_bucket(report: &ReportResultRequest) -> String {
+    bucket_id(
+        &report.discord_identity,
+        &report.method.to_ascii_uppercase(),
+        &routes::normalize_route(&report.route).template,
+        &report_major_parameter(report),
+    )
+}
+
+/// Replaces a client's route with the template of the raw `path`, and its
+/// major parameter with the path's when the path names one, so every client
+/// lands on the same bucket however it normalizes.
+fn apply_path(path: &str, route: &mut String, major_parameter: &mut String) {
+    let resolved = routes::normalize_route(path);
+    *route = resolved.template;
+    if let Some(derived) = resolved.major_parameter {
+        *major_parameter = derived;
+    }
+}
+
+/// The report's major parameter, or the one its route carries, as
+/// `normalize_request` fills it in for the permit.
+fn report_major_parameter(report: &ReportResultRequest) -> String {
+    if report.major_parameter.trim().is_empty() {
+        routes::normalize_route(&report.route)
+            .major_parameter
+            .unwrap_or_else(|| UNKNOWN_MAJOR_PARAMETER.to_string())
+    } else {
+        report.major_parameter.clone()
+    }
+}
+
+fn observed_key(bucket: &str) -> String {
+    format!("rl:observed:{bucket}")
 }
 
 fn unix_ms() -> u64 {
@@ -643,6 +4111,48 @@ fn unix_ms() -> u64 {
         .as_millis() as u64
 }
 
+/// Settings for features that keep their state in Redis, so `DMBO_STORE=memory`
+/// has nothing to run them on. `DMBO_ROUTE_ALGORITHMS` is here because one
+/// in-memory limiter keeps one algorithm's state per identity.
+const REDIS_ONLY_SETTINGS: [&str; 21] = [
+    "DMBO_ROUTE_ALGORITHMS",
+    "DMBO_HONOR_DISCORD_REMAINING",
+    "DMBO_PUBSUB_CHANNEL",
+    "DMBO_PROXY_CACHE_TTL_MS",
+    "DMBO_PLUGIN_LUA",
+    "DMBO_COALESCE_GET_MS",
+    "DMBO_IDENTITY_DAILY_QUOTA",
+    "DMBO_GROUP_DAILY_QUOTA",
+    "DMBO_LONG_LIMITS",
+    "DMBO_COMMAND_REGISTRATION_LIMIT",
+    "DMBO_WEBHOOK_LIMIT",
+    "DMBO_MESSAGE_SEND_LIMIT",
+    "DMBO_ROUTE_LIMITS",
+    "DMBO_ROUTE_MAX_INFLIGHT",
+    "DMBO_REACTION_INTERVAL_MS",
+    "DMBO_UPLOAD_BYTES_PER_INTERVAL",
+    "DMBO_LARGE_UPLOAD_CONCURRENCY",
+    "DMBO_REJECT_LEASE_MISMATCH",
+    "DMBO_ANOMALY_AUTO_TIGHTEN",
+    "DMBO_REGION",
+    "DMBO_COORDINATOR_REDIS_URL",
+];
+
+/// The `REDIS_ONLY_SETTINGS` set to something other than empty, 0 or false.
+fn memory_store_conflicts() -> Vec<&'static str> {
+    REDIS_ONLY_SETTINGS
+        .into_iter()
+        .filter(|key| {
+            env::var(key).is_ok_and(|value| {
+                !matches!(
+                    value.trim().to_ascii_lowercase().as_str(),
+                    "" | "0" | "false" | "no"
+                )
+            })
+        })
+        .collect()
+}
+
 fn env_u64(key: &str, default: u64) -> u64 {
     env::var(key)
         .ok()
@@ -650,6 +4160,76 @@ fn env_u64(key: &str, default: u64) -> u64 {
         .unwrap_or(default)
 }
 
+fn env_f64(key: &str, default: f64) -> f64 {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|value| value.is_finite())
+        .unwrap_or(default)
+}
+
+/// Parses `DMBO_FEATURES`, a comma-separated list of feature tags. Names are
+/// stored in rollup keys, so anything but letters, digits, `-` and `_` aborts
+/// startup, as does the reserved `untagged`.
+fn env_features() -> Vec<String> {
+    let mut features: Vec<String> = env::var("DMBO_FEATURES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    for name in &features {
+        assert!(
+            name.len() <= MAX_FEATURE_LEN
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                && name != stats::UNTAGGED_FEATURE,
+            "invalid DMBO_FEATURES entry {name:?}: use up to {MAX_FEATURE_LEN} letters, digits, - or _ (\"{}\" is reserved)",
+            stats::UNTAGGED_FEATURE
+        );
+    }
+    features.sort();
+    features.dedup();
+    features
+}
+
+/// Parses `DMBO_IDENTITY_GLOBAL_RPS`, comma-separated `identity=rps` pairs
+/// for bots whose global limit Discord has raised. A malformed pair or a
+/// zero limit aborts startup.
+fn env_identity_global_rps() -> BTreeMap<String, u64> {
+    env::var("DMBO_IDENTITY_GLOBAL_RPS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (identity, rps) = pair.rsplit_once('=').unwrap_or_else(|| {
+                panic!("invalid DMBO_IDENTITY_GLOBAL_RPS: {pair:?} is not identity=rps")
+            });
+            let rps = rps
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|rps| *rps > 0)
+                .unwrap_or_else(|| {
+                    panic!("invalid DMBO_IDENTITY_GLOBAL_RPS: {pair:?} needs a limit of at least 1")
+                });
+            (identity.trim().to_string(), rps)
+        })
+        .collect()
+}
+
+/// Parses a limiter algorithm name; an unknown name aborts startup rather
+/// than silently enforcing a different algorithm.
+fn env_algorithm(key: &str) -> Option<Algorithm> {
+    let value = env::var(key).ok().filter(|value| !value.is_empty())?;
+    Some(Algorithm::parse(&value).unwrap_or_else(|| {
+        panic!("invalid {key} {value:?}: expected fixed_window, gcra or token_bucket")
+    }))
+}
+
 fn env_bool(key: &str, default: bool) -> bool {
     env::var(key)
         .ok()
@@ -661,16 +4241,44 @@ fn env_bool(key: &str, default: bool) -> bool {
         .unwrap_or(default)
 }
 
-fn counts_toward_invalid_limit(status_code: u16, scope: Option<&str>) -> bool {
-    match status_code {
-        401 | 403 => true,
-        429 => scope != Some("shared"),
-        _ => false,
+fn observe_clock_skew(state: &AppState, client_unix_ms: u64) -> i64 {
+    // Any u64 is accepted, so a client far in the future saturates.
+    let skew_ms =
+        i64::try_from(i128::from(unix_ms()) - i128::from(client_unix_ms)).unwrap_or(i64::MIN);
+    let abs_ms = skew_ms.unsigned_abs();
+    state
+        .metrics
+        .clock_skew_last_abs_ms
+        .store(abs_ms, Ordering::Relaxed);
+    if abs_ms > state.config.clock_skew_threshold_ms {
+        state
+            .metrics
+            .clock_skew_exceeded_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    skew_ms
+}
+
+/// A random spread of up to `DMBO_RETRY_JITTER_MS` added to retry hints, so
+/// callers denied in the same instant don't all come back in the same one.
+fn retry_jitter_ms(config: &Config) -> u64 {
+    if config.retry_jitter_ms == 0 {
+        return 0;
+    }
+    uuid::Uuid::new_v4().as_u64_pair().0 % (config.retry_jitter_ms + 1)
+}
+
+fn retry_guidance(config: &Config, attempt: u32, retry_after_ms: u64) -> RetryGuidance {
+    RetryPolicy {
+        min_retry_ms: config.min_retry_ms,
+        max_delay_ms: config.retry_max_delay_ms,
+        give_up_attempts: config.retry_give_up_attempts,
     }
+    .guidance(attempt, retry_after_ms)
 }
 
 fn default_group_id() -> String {
-    "homelab-ip".to_string()
+    DEFAULT_GROUP_ID.to_string()
 }
 
 fn default_priority() -> String {
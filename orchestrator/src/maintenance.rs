@@ -0,0 +1,469 @@
+//! Planned maintenance windows loaded from the JSON file named by
+//! `DMBO_MAINTENANCE`. Each window starts whenever its cron spec matches and
+//! lasts `duration_minutes`; while it runs, matching groups and routes are
+//! paused (denied until the window ends) or reduced to a percentage of their
+//! limits. A background task tracks window boundaries once a minute and
+//! publishes `warning`, `started` and `ended` events to clients subscribed to
+//! `GET /events`.
+
+use crate::{
+    notifier::{self, Alert, Severity},
+    routes,
+    rules::{route_matches, wildcard_match, WEEKDAYS},
+    unix_ms, AppState, RequestTokenRequest,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, Arc, RwLock},
+    time::Duration,
+};
+use tokio::{sync::broadcast, time::interval};
+
+const MINUTE_MS: u64 = 60_000;
+const EVENT_BUFFER: usize = 64;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaintenanceFile {
+    /// Offset of the cron specs' local time from UTC.
+    #[serde(default)]
+    utc_offset_minutes: i32,
+    #[serde(default)]
+    windows: Vec<Window>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Window {
+    name: String,
+    /// `minute hour day-of-month month day-of-week`.
+    cron: String,
+    duration_minutes: u64,
+    /// Group ids (`*` wildcards); every group when empty.
+    #[serde(default)]
+    groups: Vec<String>,
+    /// Route patterns as in policy rules; every route when empty.
+    #[serde(default)]
+    routes: Vec<String>,
+    /// Share of the limits kept during the window; the window pauses
+    /// matching traffic entirely when absent.
+    #[serde(default)]
+    reduce_percent: Option<u64>,
+    #[serde(default = "default_warn_minutes")]
+    warn_minutes: u64,
+    #[serde(skip)]
+    spec: Option<Cron>,
+}
+
+/// One occurrence of a window, identified by its index and start minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Occurrence {
+    window: usize,
+    starts_unix_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct Status {
+    active: Vec<Occurrence>,
+    upcoming: Vec<Occurrence>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct MaintenanceEvent {
+    /// `warning`, `started`, `ended`, or `active` / `upcoming` in the
+    /// snapshot sent when a client subscribes.
+    kind: &'static str,
+    window: String,
+    starts_unix_ms: u64,
+    ends_unix_ms: u64,
+    /// `pause` or `reduce`.
+    action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reduce_percent: Option<u64>,
+    groups: Vec<String>,
+    routes: Vec<String>,
+}
+
+/// What an active window does to one request.
+pub(crate) enum Effect {
+    Paused { window: String, ends_unix_ms: u64 },
+    Reduced { percent: u64 },
+}
+
+pub(crate) struct Maintenance {
+    windows: Vec<Window>,
+    utc_offset_minutes: i32,
+    status: RwLock<Status>,
+    events: broadcast::Sender<MaintenanceEvent>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            windows: Vec::new(),
+            utc_offset_minutes: 0,
+            status: RwLock::new(Status::default()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl Maintenance {
+    /// Loads `DMBO_MAINTENANCE`. A missing or invalid file aborts startup.
+    pub(crate) fn load(path: Option<&str>) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+        let raw = std::fs::read_to_string(path)
+            .unwrap_or_else(|error| panic!("failed to read DMBO_MAINTENANCE {path}: {error}"));
+        let file: MaintenanceFile = serde_json::from_str(&raw)
+            .unwrap_or_else(|error| panic!("invalid DMBO_MAINTENANCE {path}: {error}"));
+        let mut windows = file.windows;
+        for window in &mut windows {
+            window.spec = Some(Cron::parse(&window.cron).unwrap_or_else(|error| {
+                panic!(
+                    "invalid DMBO_MAINTENANCE {path}: window {} cron {:?}: {error}",
+                    window.name, window.cron
+                )
+            }));
+            for route in &mut window.routes {
+                *route = routes::normalize_route(route).template;
+            }
+            if let Some(percent) = &mut window.reduce_percent {
+                *percent = (*percent).clamp(1, 100);
+            }
+        }
+        let maintenance = Self {
+            windows,
+            utc_offset_minutes: file.utc_offset_minutes,
+            ..Self::default()
+        };
+        maintenance.refresh(unix_ms());
+        maintenance
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    pub(crate) fn active_count(&self) -> usize {
+        self.status
+            .read()
+            .expect("maintenance status poisoned")
+            .active
+            .len()
+    }
+
+    /// The most restrictive active window matching `request`: a pause beats
+    /// any reduction, and the smallest reduction wins.
+    pub(crate) fn evaluate(&self, request: &RequestTokenRequest) -> Option<Effect> {
+        let status = self.status.read().expect("maintenance status poisoned");
+        let mut effect = None;
+        for occurrence in &status.active {
+            let window = &self.windows[occurrence.window];
+            let group_matches = window.groups.is_empty()
+                || window
+                    .groups
+                    .iter()
+                    .any(|group| wildcard_match(group, &request.group_id));
+            let route_matches = window.routes.is_empty()
+                || window
+                    .routes
+                    .iter()
+                    .any(|pattern| route_matches(pattern, &request.route));
+            if !group_matches || !route_matches {
+                continue;
+            }
+            match (window.reduce_percent, &effect) {
+                (None, _) => {
+                    return Some(Effect::Paused {
+                        window: window.name.clone(),
+                        ends_unix_ms: occurrence.ends_unix_ms(window),
+                    })
+                }
+                (Some(percent), Some(Effect::Reduced { percent: current }))
+                    if *current <= percent => {}
+                (Some(percent), _) => effect = Some(Effect::Reduced { percent }),
+            }
+        }
+        effect
+    }
+
+    /// Window transitions as they happen.
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<MaintenanceEvent> {
+        self.events.subscribe()
+    }
+
+    /// Current active and upcoming occurrences as snapshot events.
+    pub(crate) fn snapshot(&self) -> Vec<MaintenanceEvent> {
+        let status = self.status.read().expect("maintenance status poisoned");
+        status
+            .active
+            .iter()
+            .map(|occurrence| self.event("active", occurrence))
+            .chain(
+                status
+                    .upcoming
+                    .iter()
+                    .map(|occurrence| self.event("upcoming", occurrence)),
+            )
+            .collect()
+    }
+
+    /// Recomputes which windows are active or about to start and returns the
+    /// transitions since the previous refresh.
+    fn refresh(&self, now_ms: u64) -> Vec<MaintenanceEvent> {
+        let offset_ms = i64::from(self.utc_offset_minutes) * MINUTE_MS as i64;
+        let now_minute = now_ms / MINUTE_MS;
+        let matches = |spec: &Cron, minute: u64| {
+            spec.matches((minute * MINUTE_MS).saturating_add_signed(offset_ms))
+        };
+        let mut next = Status::default();
+        for (index, window) in self.windows.iter().enumerate() {
+            let Some(spec) = &window.spec else {
+                continue;
+            };
+            if let Some(start) = (0..window.duration_minutes)
+                .map(|ago| now_minute.saturating_sub(ago))
+                .find(|minute| matches(spec, *minute))
+            {
+                next.active.push(Occurrence {
+                    window: index,
+                    starts_unix_ms: start * MINUTE_MS,
+                });
+            }
+            if let Some(start) = (1..=window.warn_minutes)
+                .map(|ahead| now_minute + ahead)
+                .find(|minute| matches(spec, *minute))
+            {
+                next.upcoming.push(Occurrence {
+                    window: index,
+                    starts_unix_ms: start * MINUTE_MS,
+                });
+            }
+        }
+
+        let mut status = self.status.write().expect("maintenance status poisoned");
+        let was_active: HashSet<_> = status.active.iter().copied().collect();
+        let is_active: HashSet<_> = next.active.iter().copied().collect();
+        let was_upcoming: HashSet<_> = status.upcoming.iter().copied().collect();
+        let mut events: Vec<MaintenanceEvent> = status
+            .active
+            .iter()
+            .filter(|occurrence| !is_active.contains(occurrence))
+            .map(|occurrence| self.event("ended", occurrence))
+            .collect();
+        events.extend(
+            next.active
+                .iter()
+                .filter(|occurrence| !was_active.contains(occurrence))
+                .map(|occurrence| self.event("started", occurrence)),
+        );
+        events.extend(
+            next.upcoming
+                .iter()
+                .filter(|occurrence| !was_upcoming.contains(occurrence))
+                .map(|occurrence| self.event("warning", occurrence)),
+        );
+        *status = next;
+        events
+    }
+
+    fn event(&self, kind: &'static str, occurrence: &Occurrence) -> MaintenanceEvent {
+        let window = &self.windows[occurrence.window];
+        MaintenanceEvent {
+            kind,
+            window: window.name.clone(),
+            starts_unix_ms: occurrence.starts_unix_ms,
+            ends_unix_ms: occurrence.ends_unix_ms(window),
+            action: if window.reduce_percent.is_some() {
+                "reduce"
+            } else {
+                "pause"
+            },
+            reduce_percent: window.reduce_percent,
+            groups: window.groups.clone(),
+            routes: window.routes.clone(),
+        }
+    }
+}
+
+impl Occurrence {
+    fn ends_unix_ms(&self, window: &Window) -> u64 {
+        self.starts_unix_ms + window.duration_minutes * MINUTE_MS
+    }
+}
+
+/// Refreshes window state at every minute boundary and broadcasts the
+/// transitions. Runs only when `DMBO_MAINTENANCE` is set.
+pub(crate) async fn track_windows(state: Arc<AppState>) {
+    let mut ticker = interval(Duration::from_secs(1));
+    let mut last_minute = unix_ms() / MINUTE_MS;
+    loop {
+        ticker.tick().await;
+        let now = unix_ms();
+        if now / MINUTE_MS == last_minute {
+            continue;
+        }
+        last_minute = now / MINUTE_MS;
+        for event in state.maintenance.refresh(now) {
+            if event.kind == "started" {
+                state
+                    .metrics
+                    .maintenance_windows_started
+                    .fetch_add(1, Ordering::Relaxed);
+                if event.action == "pause" {
+                    notifier::notify(
+                        &state,
+                        Alert {
+                            kind: "traffic_paused",
+                            subject: event.window.clone(),
+                            severity: Severity::Warning,
+                            title: "Traffic paused".to_string(),
+                            description: format!(
+                                "Maintenance window `{}` is denying matching permits until <t:{}:t>.",
+                                event.window,
+                                event.ends_unix_ms / 1000
+                            ),
+                            fields: vec![
+                                ("Groups", scope_list(&event.groups)),
+                                ("Routes", scope_list(&event.routes)),
+                            ],
+                        },
+                    );
+                }
+            }
+            // Sending only fails when nobody is subscribed.
+            let _ = state.maintenance.events.send(event);
+        }
+    }
+}
+
+fn scope_list(patterns: &[String]) -> String {
+    if patterns.is_empty() {
+        "all".to_string()
+    } else {
+        patterns.join(", ")
+    }
+}
+
+fn default_warn_minutes() -> u64 {
+    15
+}
+
+/// Five-field cron spec. Fields accept `*`, numbers, `a-b` ranges, `/n`
+/// steps and comma lists; day-of-week also accepts `sun`..`sat` and `7` for
+/// Sunday. As in cron, when both day fields are restricted either may match.
+#[derive(Debug)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    day_of_month_any: bool,
+    day_of_week_any: bool,
+}
+
+impl Cron {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err("expected 5 fields".to_string());
+        };
+        let day_of_week = WEEKDAYS
+            .iter()
+            .enumerate()
+            .fold(day_of_week.to_ascii_lowercase(), |field, (index, name)| {
+                field.replace(name, &index.to_string())
+            });
+        let mut days_of_week = parse_field(&day_of_week, 0, 7)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days_of_month: parse_field(day_of_month, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            days_of_week,
+            day_of_month_any: day_of_month == "*",
+            day_of_week_any: day_of_week == "*",
+        })
+    }
+
+    fn matches(&self, local_ms: u64) -> bool {
+        let minute_of_day = local_ms % 86_400_000 / MINUTE_MS;
+        let days = local_ms / 86_400_000;
+        let (month, day) = month_and_day(days);
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 4) % 7;
+        let bit = |set: u64, value: u64| set & (1 << value) != 0;
+        let day_of_month = bit(self.days_of_month, day);
+        let day_of_week = bit(self.days_of_week, weekday);
+        let day_matches = match (self.day_of_month_any, self.day_of_week_any) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        bit(self.minutes, minute_of_day % 60)
+            && bit(self.hours, minute_of_day / 60)
+            && bit(self.months, month)
+            && day_matches
+    }
+}
+
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut set = 0_u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u64>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("bad step in {part:?}"))?,
+            ),
+            None => (part, 1),
+        };
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some((from, to)) = range.split_once('-') {
+            (parse_value(from)?, parse_value(to)?)
+        } else {
+            let value = parse_value(range)?;
+            (value, if step > 1 { max } else { value })
+        };
+        if from < min || to > max || from > to {
+            return Err(format!("{part:?} is outside {min}-{max}"));
+        }
+        for value in (from..=to).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn parse_value(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{value:?} is not a number"))
+}
+
+/// Month (1-12) and day of month for days since the Unix epoch.
+fn month_and_day(days: u64) -> (u64, u64) {
+    // Howard Hinnant's civil-from-days, restricted to dates after 1970.
+    let z = days + 719_468;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    (month, day)
+}
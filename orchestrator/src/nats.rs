@@ -0,0 +1,141 @@
+//! NATS request/reply transport (`DMBO_NATS_URL`, requires the `nats` build
+//! feature), for bot fleets that already talk NATS. Permit requests are
+//! taken on `{DMBO_NATS_SUBJECT}.request_token` and reports on
+//! `{DMBO_NATS_SUBJECT}.report_result`, with the same JSON bodies as the
+//! HTTP endpoints; the answer goes to the message's reply subject, so
+//! clients use plain NATS requests.
+//!
+//! Replicas subscribe in one queue group, so each message is handled by
+//! exactly one of them. Permit requests wait up to their `max_wait_ms` like
+//! HTTP ones; set the NATS request timeout above it.
+
+use crate::{
+    apply_path, decide_permit, prepare_request, record_report, validate_feature,
+    validation_failed_response, ws, AppState, FieldError, ReportResultRequest, RequestTokenRequest,
+    TraceContext,
+};
+use async_nats::{Client, ConnectOptions, Message};
+use axum::http::{HeaderMap, HeaderValue};
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use std::sync::{atomic::Ordering, Arc};
+
+const QUEUE_GROUP: &str = "dmbo-orchestrator";
+
+/// Report replies are small acks or problem bodies.
+const MAX_REPLY_BYTES: usize = 64 * 1024;
+
+/// Background task: serves both subjects until the connection is closed for
+/// good. The client reconnects on its own after transient losses.
+pub(crate) async fn serve(state: Arc<AppState>, url: String) {
+    let client = match ConnectOptions::new()
+        .retry_on_initial_connect()
+        .connect(url.as_str())
+        .await
+    {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("NATS transport disabled: {error}");
+            return;
+        }
+    };
+    let prefix = &state.config.nats_subject;
+    let subscribed = (
+        client
+            .queue_subscribe(format!("{prefix}.request_token"), QUEUE_GROUP.to_string())
+            .await,
+        client
+            .queue_subscribe(format!("{prefix}.report_result"), QUEUE_GROUP.to_string())
+            .await,
+    );
+    let (Ok(mut permits), Ok(mut reports)) = subscribed else {
+        eprintln!("NATS transport disabled: could not subscribe under {prefix}");
+        return;
+    };
+    loop {
+        tokio::select! {
+            Some(message) = permits.next() => {
+                tokio::spawn(request_token(state.clone(), client.clone(), message));
+            }
+            Some(message) = reports.next() => {
+                tokio::spawn(report_result(state.clone(), client.clone(), message));
+            }
+            else => break,
+        }
+    }
+}
+
+async fn request_token(state: Arc<AppState>, client: Client, message: Message) {
+    state
+        .metrics
+        .nats_permit_requests
+        .fetch_add(1, Ordering::Relaxed);
+    let trace = trace_context(&message);
+    let reply = match serde_json::from_slice::<RequestTokenRequest>(&message.payload) {
+        Ok(mut request) => {
+            let errors = prepare_request(&state.config, &mut request);
+            if errors.is_empty() {
+                let (response, _) = decide_permit(&state, &mut request, trace.as_ref()).await;
+                let mut reply = serde_json::to_value(&response).unwrap_or_default();
+                reply["request_id"] = Value::String(request.request_id);
+                reply
+            } else {
+                ws::invalid_frame(Some(&request.request_id), errors)
+            }
+        }
+        Err(error) => ws::invalid_frame(None, vec![body_error(error)]),
+    };
+    respond(&client, &message, &reply).await;
+}
+
+async fn report_result(state: Arc<AppState>, client: Client, message: Message) {
+    state.metrics.nats_reports.fetch_add(1, Ordering::Relaxed);
+    // The reply is the body `POST /report_result` would answer with.
+    let response = match serde_json::from_slice::<ReportResultRequest>(&message.payload) {
+        Ok(mut report) => match validate_feature(&state.config, report.feature.as_deref()) {
+            Some(error) => validation_failed_response(vec![error]),
+            None => {
+                if let Some(path) = report.path.take().filter(|path| !path.trim().is_empty()) {
+                    apply_path(&path, &mut report.route, &mut report.major_parameter);
+                }
+                record_report(&state, &report).await
+            }
+        },
+        Err(error) => validation_failed_response(vec![body_error(error)]),
+    };
+    let body = axum::body::to_bytes(response.into_body(), MAX_REPLY_BYTES)
+        .await
+        .unwrap_or_default();
+    let reply = serde_json::from_slice(&body).unwrap_or_else(|_| json!({ "ok": false }));
+    respond(&client, &message, &reply).await;
+}
+
+fn trace_context(message: &Message) -> Option<TraceContext> {
+    let received = message.headers.as_ref()?;
+    let mut headers = HeaderMap::new();
+    for name in ["traceparent", "tracestate"] {
+        if let Some(value) = received
+            .get(name)
+            .and_then(|value| HeaderValue::from_str(value.as_str()).ok())
+        {
+            headers.insert(name, value);
+        }
+    }
+    TraceContext::from_headers(&headers)
+}
+
+fn body_error(error: serde_json::Error) -> FieldError {
+    FieldError {
+        field: "body",
+        message: error.to_string(),
+    }
+}
+
+/// Answers on the message's reply subject; plain publishes get no answer.
+async fn respond(client: &Client, message: &Message, reply: &Value) {
+    if let Some(subject) = &message.reply {
+        let _ = client
+            .publish(subject.clone(), reply.to_string().into())
+            .await;
+    }
+}
@@ -0,0 +1,284 @@
+//! Ops alerts posted to a Discord webhook (`DMBO_ALERT_WEBHOOK_URL`). Guard
+//! trips, pausing maintenance windows, anomalies, sustained 429s and Redis
+//! outages are formatted as embeds. The alerts are rate limited themselves:
+//! the same alert for the same subject is sent at most once per
+//! `DMBO_ALERT_COOLDOWN_MS`, and at most `DMBO_ALERT_MAX_PER_MINUTE` alerts
+//! are sent per replica, well below Discord's webhook limit. Suppressed
+//! alerts are counted and mentioned in the next one that goes out.
+
+use crate::{unix_ms, AppState};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::interval;
+
+const PROBLEM_TYPE_NOT_FOUND: &str = "urn:dmbo:problem:not-found";
+const CHECK_INTERVAL_MS: u64 = 5_000;
+const MINUTE_MS: u64 = 60_000;
+const COLOR_CRITICAL: u32 = 0xE03E3E;
+const COLOR_WARNING: u32 = 0xF0B232;
+const COLOR_RESOLVED: u32 = 0x3BA55D;
+
+pub(crate) enum Severity {
+    Critical,
+    Warning,
+    Resolved,
+}
+
+pub(crate) struct Alert {
+    /// Stable alert name, e.g. `guard_trip`; with `subject` the cooldown key.
+    pub(crate) kind: &'static str,
+    pub(crate) subject: String,
+    pub(crate) severity: Severity,
+    pub(crate) title: String,
+    pub(crate) description: String,
+    pub(crate) fields: Vec<(&'static str, String)>,
+}
+
+#[derive(Default)]
+struct Limiter {
+    last_sent: HashMap<(&'static str, String), u64>,
+    minute: u64,
+    sent_this_minute: u64,
+    suppressed: u64,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Notifier {
+    limiter: Arc<Mutex<Limiter>>,
+}
+
+impl Notifier {
+    /// Whether `alert` may be sent now; also returns how many alerts were
+    /// suppressed since the last one sent.
+    fn admit(&self, alert: &Alert, cooldown_ms: u64, max_per_minute: u64) -> Option<u64> {
+        let now = unix_ms();
+        let mut limiter = self.limiter.lock().expect("notifier poisoned");
+        if limiter.minute != now / MINUTE_MS {
+            limiter.minute = now / MINUTE_MS;
+            limiter.sent_this_minute = 0;
+        }
+        let key = (alert.kind, alert.subject.clone());
+        let cooling = limiter
+            .last_sent
+            .get(&key)
+            .is_some_and(|sent| now.saturating_sub(*sent) < cooldown_ms);
+        if cooling || limiter.sent_this_minute >= max_per_minute {
+            limiter.suppressed += 1;
+            return None;
+        }
+        limiter
+            .last_sent
+            .retain(|_, sent| now.saturating_sub(*sent) < cooldown_ms);
+        limiter.last_sent.insert(key, now);
+        limiter.sent_this_minute += 1;
+        Some(std::mem::take(&mut limiter.suppressed))
+    }
+}
+
+/// Sends `alert` in the background when a webhook is configured and the
+/// alert limits allow it. Never blocks the caller.
+pub(crate) fn notify(state: &AppState, alert: Alert) {
+    let config = &state.config;
+    let Some(url) = config.alert_webhook_url.clone() else {
+        return;
+    };
+    let Some(suppressed) = state.notifier.admit(
+        &alert,
+        config.alert_cooldown_ms,
+        config.alert_max_per_minute,
+    ) else {
+        state
+            .metrics
+            .alerts_suppressed
+            .fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    let mut footer = format!("dmbo {}", config.instance_id);
+    if suppressed > 0 {
+        footer.push_str(&format!(" · {suppressed} earlier alerts suppressed"));
+    }
+    let color = match alert.severity {
+        Severity::Critical => COLOR_CRITICAL,
+        Severity::Warning => COLOR_WARNING,
+        Severity::Resolved => COLOR_RESOLVED,
+    };
+    let fields: Vec<_> = alert
+        .fields
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value, "inline": true }))
+        .collect();
+    let body = json!({
+        "username": "dmbo",
+        // Group ids and window names are operator input; never ping anyone.
+        "allowed_mentions": { "parse": [] },
+        "embeds": [{
+            "title": alert.title,
+            "description": alert.description,
+            "color": color,
+            "fields": fields,
+            "footer": { "text": footer },
+        }],
+    });
+    let http = state.http.clone();
+    let metrics = state.metrics.clone();
+    tokio::spawn(async move {
+        let sent = http
+            .post(&url)
+            .json(&body)
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success());
+        let counter = if sent {
+            &metrics.alerts_sent
+        } else {
+            &metrics.alerts_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+/// Watches for conditions no request path sees: Redis staying unreachable
+/// for `DMBO_ALERT_REDIS_OUTAGE_MS`, and reported 429s staying at or above
+/// `DMBO_ALERT_429_PER_MINUTE` for `DMBO_ALERT_429_MINUTES` minutes in a row.
+pub(crate) async fn watch(state: Arc<AppState>) {
+    let config = &state.config;
+    let mut ticker = interval(Duration::from_millis(CHECK_INTERVAL_MS));
+    let mut outage_since: Option<u64> = None;
+    let mut outage_alerted = false;
+    let mut minute = unix_ms() / MINUTE_MS;
+    let mut minute_start_429 = observed_429_total(&state);
+    let mut hot_minutes = 0_u64;
+    loop {
+        ticker.tick().await;
+        let now = unix_ms();
+
+        let reachable = config.memory_store
+            || match state.redis.get_multiplexed_async_connection().await {
+                Ok(mut conn) => redis::cmd("PING")
+                    .query_async::<_, String>(&mut conn)
+                    .await
+                    .is_ok(),
+                Err(_) => false,
+            };
+        match (reachable, outage_since) {
+            (false, None) => outage_since = Some(now),
+            (false, Some(since)) => {
+                if !outage_alerted && now - since >= config.alert_redis_outage_ms {
+                    outage_alerted = true;
+                    notify(
+                        &state,
+                        Alert {
+                            kind: "redis_outage",
+                            subject: String::new(),
+                            severity: Severity::Critical,
+                            title: "Redis unreachable".to_string(),
+                            description: format!(
+                                "Redis has been unreachable for {}s. Permits are failing \
+                                 with 503 until it recovers.",
+                                (now - since) / 1000
+                            ),
+                            fields: Vec::new(),
+                        },
+                    );
+                }
+            }
+            (true, Some(since)) => {
+                if outage_alerted {
+                    notify(
+                        &state,
+                        Alert {
+                            kind: "redis_recovered",
+                            subject: String::new(),
+                            severity: Severity::Resolved,
+                            title: "Redis reachable again".to_string(),
+                            description: format!(
+                                "Redis recovered after {}s.",
+                                (now - since) / 1000
+                            ),
+                            fields: Vec::new(),
+                        },
+                    );
+                }
+                outage_since = None;
+                outage_alerted = false;
+            }
+            (true, None) => {}
+        }
+
+        if now / MINUTE_MS != minute {
+            minute = now / MINUTE_MS;
+            let total = observed_429_total(&state);
+            let per_minute = total - minute_start_429;
+            minute_start_429 = total;
+            if per_minute >= config.alert_429_per_minute {
+                hot_minutes += 1;
+            } else {
+                hot_minutes = 0;
+            }
+            if hot_minutes >= config.alert_429_minutes {
+                notify(
+                    &state,
+                    Alert {
+                        kind: "sustained_429",
+                        subject: String::new(),
+                        severity: Severity::Warning,
+                        title: "Sustained Discord 429s".to_string(),
+                        description: format!(
+                            "{hot_minutes} consecutive minutes with at least {} reported 429s.",
+                            config.alert_429_per_minute
+                        ),
+                        fields: vec![("Last minute", per_minute.to_string())],
+                    },
+                );
+            }
+        }
+    }
+}
+
+fn observed_429_total(state: &AppState) -> u64 {
+    let metrics = &state.metrics;
+    [
+        &metrics.observed_429_global,
+        &metrics.observed_429_user,
+        &metrics.observed_429_shared,
+        &metrics.observed_429_unknown,
+    ]
+    .iter()
+    .map(|counter| counter.load(Ordering::Relaxed))
+    .sum()
+}
+
+/// `POST /admin/alerts/test`: sends a test alert, subject to the usual
+/// limits. Returns 404 when no webhook is configured.
+pub(crate) async fn test(State(state): State<Arc<AppState>>) -> Response {
+    if state.config.alert_webhook_url.is_none() {
+        return crate::problem_response(
+            StatusCode::NOT_FOUND,
+            PROBLEM_TYPE_NOT_FOUND,
+            "Alerting disabled",
+            "DMBO_ALERT_WEBHOOK_URL is not set".to_string(),
+            json!({}),
+        );
+    }
+    notify(
+        &state,
+        Alert {
+            kind: "test",
+            subject: String::new(),
+            severity: Severity::Resolved,
+            title: "Test alert".to_string(),
+            description: "Alerts from this orchestrator will be posted here.".to_string(),
+            fields: Vec::new(),
+        },
+    );
+    StatusCode::ACCEPTED.into_response()
+}
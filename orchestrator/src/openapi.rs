@@ -0,0 +1,140 @@
+//! OpenAPI document for the client API, served at `GET /openapi.json` so
+//! clients in other languages can generate their bindings from it.
+//!
+//! Request bodies and the permit decision are derived from the structs the
+//! handlers use. Responses the handlers build with `json!` are described by
+//! the schema-only structs below; keep them in step with the handlers.
+
+#![allow(dead_code)]
+
+use crate::{
+    jobs::{JobResult, JobSpec},
+    FieldError, RequestTokenResponse,
+};
+use axum::Json;
+use dmbo_core::Reason;
+use utoipa::{OpenApi, ToSchema};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "dmbo orchestrator",
+        description = "Discord rate-limit permits, reports and deferred jobs. \
+            `GET /events` (server-sent events) and `GET /permits/ws` (WebSocket) \
+            are described in docs/api-spec.md."
+    ),
+    servers(
+        (url = "/v1", description = "Current API version"),
+        (url = "/", description = "Unversioned aliases kept for older clients"),
+    ),
+    paths(
+        crate::healthz,
+        crate::policy,
+        crate::request_token,
+        crate::batch::request_tokens,
+        crate::identify::request_identify,
+        crate::report_result,
+        crate::leases::release_lease,
+        crate::leases::extend_lease,
+        crate::queue::cancel_request,
+        crate::jobs::submit_job,
+        crate::jobs::get_job,
+    ),
+    components(schemas(Reason))
+)]
+struct ApiDoc;
+
+/// `GET /openapi.json`.
+pub(crate) async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// RFC 9457 problem details, answered as `application/problem+json`.
+/// Endpoints add extension members such as `reason` and `retry_after_ms`.
+#[derive(ToSchema)]
+pub(crate) struct Problem {
+    /// `urn:dmbo:problem:*`.
+    #[schema(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    /// Set on validation failures.
+    errors: Option<Vec<FieldError>>,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct Health {
+    ok: bool,
+    /// `up` or `down`.
+    redis: String,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct CallbackAccepted {
+    request_id: String,
+    /// `queued`.
+    status: String,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct BatchResponse {
+    /// Items granted.
+    granted: u64,
+    /// One decision per item, in order.
+    results: Vec<RequestTokenResponse>,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct IdentifyResponse {
+    granted: bool,
+    /// `shard_id % max_concurrency`.
+    bucket: u64,
+    not_before_unix_ms: u64,
+    server_unix_ms: u64,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct ReportResponse {
+    ok: bool,
+    /// Reason given by a policy plugin that vetoed the report.
+    plugin_vetoed: Option<String>,
+    /// `unknown` or `mismatch` when the `lease_id` named no matching lease.
+    lease_mismatch: Option<String>,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct ExtendLeaseResponse {
+    lease_id: String,
+    expires_unix_ms: u64,
+    server_unix_ms: u64,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct CancelResponse {
+    ok: bool,
+    /// Waits ended on this instance.
+    cancelled: u64,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct JobAccepted {
+    job_id: String,
+    /// `queued`.
+    status: String,
+    status_url: String,
+}
+
+#[derive(ToSchema)]
+pub(crate) struct Job {
+    job_id: String,
+    /// `queued`, `running`, `succeeded` or `failed`.
+    status: String,
+    attempts: u32,
+    created_unix_ms: u64,
+    updated_unix_ms: u64,
+    spec: JobSpec,
+    result: Option<JobResult>,
+    /// `job_expired` or `upstream_unavailable`.
+    error: Option<String>,
+}
@@ -0,0 +1,332 @@
+//! Operator policy plugins. A plugin sees each permit request and report
+//! before the orchestrator acts on it and may veto it, or for permits adjust
+//! the priority and the limits it is admitted against. Two runtimes are
+//! supported and may be combined:
+//!
+//! - a Lua snippet (`DMBO_PLUGIN_LUA`) run as a script in the limiter's
+//!   Redis. It is trusted code: Redis' script sandbox keeps it off the
+//!   host, not away from the limiter's keys, which `redis.call` reaches;
+//! - a WASM module (`DMBO_PLUGIN_WASM`, requires the `wasm-plugins` build
+//!   feature) run in-process under wasmi with no imports and a fuel budget.
+//!
+//! Both receive the request as JSON and return a JSON verdict. A plugin that
+//! errors, traps or returns garbage is skipped: plugins fail open.
+
+use crate::{base_limits, unix_ms, AppState, Config, ReportResultRequest, RequestTokenRequest};
+use redis::Script;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::atomic::Ordering;
+
+/// Wraps the operator snippet so it runs in its own scope. The snippet must
+/// `return` a table of hook functions; `ARGV[1]` names the hook and
+/// `ARGV[2]` carries the JSON input.
+const LUA_WRAPPER: &str = r#"
+local hook = ARGV[1]
+local plugin = (function()
+--PLUGIN--
+end)()
+local fn = type(plugin) == 'table' and plugin[hook]
+if type(fn) ~= 'function' then
+  return '{}'
+end
+local verdict = fn(cjson.decode(ARGV[2]))
+if type(verdict) ~= 'table' then
+  return '{}'
+end
+return cjson.encode(verdict)
+"#;
+
+/// Returned by `on_permit`. Every field is optional; an empty verdict leaves
+/// the request untouched.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct PermitVerdict {
+    /// Operator-defined reason; any value denies the request.
+    #[serde(default)]
+    pub(crate) veto: Option<String>,
+    /// Retry hint for a veto; defaults to `DMBO_MIN_RETRY_MS`.
+    #[serde(default)]
+    pub(crate) retry_after_ms: Option<u64>,
+    #[serde(default)]
+    pub(crate) priority: Option<String>,
+    #[serde(default)]
+    pub(crate) global_rps: Option<u64>,
+    #[serde(default)]
+    pub(crate) route_rps: Option<u64>,
+}
+
+impl PermitVerdict {
+    /// Applies the priority and limit adjustments to `request`.
+    pub(crate) fn apply(&self, request: &mut RequestTokenRequest) {
+        if let Some(priority) = &self.priority {
+            request.priority = priority.clone();
+        }
+        if let Some(rps) = self.global_rps.filter(|rps| *rps > 0) {
+            request.global_rps_override = Some(rps);
+        }
+        if let Some(rps) = self.route_rps.filter(|rps| *rps > 0) {
+            request.route_rps_override = Some(rps);
+        }
+    }
+}
+
+/// Returned by `on_report`. A veto drops the report before it is counted.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ReportVerdict {
+    #[serde(default)]
+    pub(crate) veto: Option<String>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Plugins {
+    lua: Option<Script>,
+    #[cfg(feature = "wasm-plugins")]
+    wasm: Option<wasm::WasmPlugin>,
+}
+
+impl Plugins {
+    /// Loads the configured plugins. Unreadable or invalid plugins abort
+    /// startup rather than silently running without the operator's policy.
+    pub(crate) fn load(config: &Config) -> Self {
+        let lua = config.plugin_lua_path.as_ref().map(|path| {
+            let snippet = std::fs::read_to_string(path)
+                .unwrap_or_else(|error| panic!("failed to read DMBO_PLUGIN_LUA {path}: {error}"));
+            Script::new(&LUA_WRAPPER.replace("--PLUGIN--", &snippet))
+        });
+        #[cfg(feature = "wasm-plugins")]
+        let wasm = config.plugin_wasm_path.as_ref().map(|path| {
+            wasm::WasmPlugin::load(path, config.plugin_wasm_fuel)
+                .unwrap_or_else(|error| panic!("failed to load DMBO_PLUGIN_WASM {path}: {error}"))
+        });
+        #[cfg(not(feature = "wasm-plugins"))]
+        if config.plugin_wasm_path.is_some() {
+            panic!("DMBO_PLUGIN_WASM is set but the orchestrator was built without the wasm-plugins feature");
+        }
+        Self {
+            lua,
+            #[cfg(feature = "wasm-plugins")]
+            wasm,
+        }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        #[cfg(feature = "wasm-plugins")]
+        if self.wasm.is_some() {
+            return true;
+        }
+        self.lua.is_some()
+    }
+
+    /// Runs the `on_permit` hooks. With both runtimes configured the Lua
+    /// verdict is applied first and the WASM verdict overrides it field by
+    /// field; a veto from either wins.
+    pub(crate) async fn on_permit(
+        &self,
+        state: &AppState,
+        request: &RequestTokenRequest,
+    ) -> PermitVerdict {
+        if !self.enabled() {
+            return PermitVerdict::default();
+        }
+        let (global_rps, route_rps) =
+            base_limits(state, unix_ms(), Some(&request.discord_identity));
+        let input = json!({
+            "client_id": request.client_id,
+            "group_id": request.group_id,
+            "discord_identity": request.discord_identity,
+            "method": request.method,
+            "route": request.route,
+            "major_parameter": request.major_parameter,
+            "priority": request.priority,
+            "max_wait_ms": request.max_wait_ms,
+            "attempt": request.attempt,
+            "global_rps": request.global_rps_override.unwrap_or(global_rps),
+            "route_rps": request.route_rps_override.unwrap_or(route_rps),
+            "tags": request.tags,
+            "feature": request.feature,
+        });
+        let mut verdict = PermitVerdict::default();
+        for value in self.run(state, "on_permit", &input).await {
+            let Ok(next) = serde_json::from_value::<PermitVerdict>(value) else {
+                record_error(state);
+                continue;
+            };
+            verdict = PermitVerdict {
+                veto: verdict.veto.or(next.veto),
+                retry_after_ms: next.retry_after_ms.or(verdict.retry_after_ms),
+                priority: next.priority.or(verdict.priority),
+                global_rps: next.global_rps.or(verdict.global_rps),
+                route_rps: next.route_rps.or(verdict.route_rps),
+            };
+        }
+        verdict
+    }
+
+    pub(crate) async fn on_report(
+        &self,
+        state: &AppState,
+        report: &ReportResultRequest,
+    ) -> ReportVerdict {
+        if !self.enabled() {
+            return ReportVerdict::default();
+        }
+        let input = json!({
+            "client_id": report.client_id,
+            "group_id": report.group_id,
+            "discord_identity": report.discord_identity,
+            "method": report.method,
+            "route": report.route,
+            "major_parameter": report.major_parameter,
+            "status_code": report.status_code,
+            "x_ratelimit_bucket": report.x_ratelimit_bucket,
+            "x_ratelimit_scope": report.scope(),
+            "feature": report.feature,
+        });
+        let mut verdict = ReportVerdict::default();
+        for value in self.run(state, "on_report", &input).await {
+            match serde_json::from_value::<ReportVerdict>(value) {
+                Ok(next) => verdict.veto = verdict.veto.or(next.veto),
+                Err(_) => record_error(state),
+            }
+        }
+        verdict
+    }
+
+    /// Raw verdicts from each configured runtime, in evaluation order.
+    async fn run(&self, state: &AppState, hook: &str, input: &Value) -> Vec<Value> {
+        let mut verdicts = Vec::new();
+        if let Some(script) = &self.lua {
+            match run_lua(state, script, hook, input).await {
+                Ok(value) => verdicts.push(value),
+                Err(()) => record_error(state),
+            }
+        }
+        #[cfg(feature = "wasm-plugins")]
+        if let Some(plugin) = &self.wasm {
+            match plugin.call(hook, &input.to_string()) {
+                Ok(Some(value)) => verdicts.push(value),
+                Ok(None) => {}
+                Err(_) => record_error(state),
+            }
+        }
+        verdicts
+    }
+}
+
+async fn run_lua(
+    state: &AppState,
+    script: &Script,
+    hook: &str,
+    input: &Value,
+) -> Result<Value, ()> {
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| ())?;
+    let reply: String = script
+        .arg(hook)
+        .arg(input.to_string())
+        .invoke_async(&mut conn)
+        .await
+        .map_err(|_| ())?;
+    // cjson encodes an empty table as `{}`, but a table built with array
+    // entries would come back as a list; only objects are verdicts.
+    match serde_json::from_str(&reply) {
+        Ok(value @ Value::Object(_)) => Ok(value),
+        _ => Err(()),
+    }
+}
+
+fn record_error(state: &AppState) {
+    state.metrics.plugin_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+/// WASM plugin ABI: the module exports `memory`, `alloc(len: i32) -> i32`
+/// and any of `on_permit` / `on_report`, each `(ptr: i32, len: i32) -> i64`.
+/// The input JSON is written at the pointer returned by `alloc`; a hook
+/// returns `(ptr << 32) | len` locating its JSON verdict in memory, or `0`
+/// for no change. The module is instantiated fresh for every call with no
+/// host imports, so plugins cannot keep state or perform I/O.
+#[cfg(feature = "wasm-plugins")]
+mod wasm {
+    use serde_json::Value;
+    use std::sync::Arc;
+    use wasmi::{Config, Engine, Linker, Module, Store};
+
+    /// Longest verdict read back from a module; verdicts are a few fields.
+    const MAX_VERDICT_BYTES: usize = 64 * 1024;
+
+    #[derive(Clone)]
+    pub(crate) struct WasmPlugin {
+        engine: Engine,
+        module: Arc<Module>,
+        fuel: u64,
+    }
+
+    impl WasmPlugin {
+        pub(crate) fn load(path: &str, fuel: u64) -> Result<Self, String> {
+            let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+            let mut config = Config::default();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config);
+            let module = Module::new(&engine, &bytes[..]).map_err(|error| error.to_string())?;
+            Ok(Self {
+                engine,
+                module: Arc::new(module),
+                fuel,
+            })
+        }
+
+        /// Runs `hook` if the module exports it. Traps, fuel exhaustion and
+        /// out-of-bounds results are errors.
+        pub(crate) fn call(&self, hook: &str, input: &str) -> Result<Option<Value>, String> {
+            let mut store = Store::new(&self.engine, ());
+            store
+                .set_fuel(self.fuel)
+                .map_err(|error| error.to_string())?;
+            let instance = Linker::<()>::new(&self.engine)
+                .instantiate(&mut store, &self.module)
+                .and_then(|instance| instance.start(&mut store))
+                .map_err(|error| error.to_string())?;
+            let Ok(hook) = instance.get_typed_func::<(i32, i32), i64>(&store, hook) else {
+                return Ok(None);
+            };
+            let memory = instance
+                .get_memory(&store, "memory")
+                .ok_or("module does not export memory")?;
+            let alloc = instance
+                .get_typed_func::<i32, i32>(&store, "alloc")
+                .map_err(|error| error.to_string())?;
+
+            let len = i32::try_from(input.len()).map_err(|error| error.to_string())?;
+            let ptr = alloc
+                .call(&mut store, len)
+                .map_err(|error| error.to_string())?;
+            memory
+                .write(&mut store, ptr as u32 as usize, input.as_bytes())
+                .map_err(|error| error.to_string())?;
+            let packed = hook
+                .call(&mut store, (ptr, len))
+                .map_err(|error| error.to_string())? as u64;
+            if packed == 0 {
+                return Ok(None);
+            }
+            let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+            if out_len > MAX_VERDICT_BYTES {
+                return Err(format!(
+                    "verdict of {out_len} bytes is over {MAX_VERDICT_BYTES}"
+                ));
+            }
+            // Read in place: the length is the module's word, not an
+            // allocation to make before checking it against its memory.
+            let out = memory
+                .data(&store)
+                .get(out_ptr..out_ptr + out_len)
+                .ok_or("verdict out of bounds")?;
+            serde_json::from_slice(out)
+                .map(Some)
+                .map_err(|error| error.to_string())
+        }
+    }
+}
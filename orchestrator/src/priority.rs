@@ -0,0 +1,121 @@
+//! Weighted priority classes. A request's `priority` falls into one of
+//! three classes: `critical` (also `high`, which interaction traffic is
+//! raised to), `normal` (the default, and any value not listed here) and
+//! `background` (also `low` and `bulk`). Each class may fill only its share
+//! of the global and route limits (`DMBO_PRIORITY_SHARES`), so when buckets
+//! are tight background work is denied first and the capacity above its
+//! share stays free for interactive commands.
+//!
+//! Waiting requests age: every `DMBO_PRIORITY_AGING_MS` spent waiting raises
+//! the class a request is limited as by one step, so background work that
+//! has waited long enough competes as normal and then as critical traffic
+//! instead of starving under sustained interactive load.
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PriorityClass {
+    Critical,
+    Normal,
+    Background,
+}
+
+impl PriorityClass {
+    pub(crate) fn of(priority: &str) -> Self {
+        match priority.trim().to_ascii_lowercase().as_str() {
+            "critical" | "high" => Self::Critical,
+            "background" | "low" | "bulk" => Self::Background,
+            _ => Self::Normal,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Critical => "critical",
+            Self::Normal => "normal",
+            Self::Background => "background",
+        }
+    }
+
+    fn raised(self) -> Self {
+        match self {
+            Self::Background => Self::Normal,
+            Self::Normal | Self::Critical => Self::Critical,
+        }
+    }
+}
+
+/// The priority a request of `priority` is limited as after waiting
+/// `waited_ms`: one class higher per `aging_ms` (`0` disables aging).
+/// `None` while aging has not moved it to another class.
+pub(crate) fn aged(priority: &str, waited_ms: u64, aging_ms: u64) -> Option<&'static str> {
+    if aging_ms == 0 {
+        return None;
+    }
+    let class = PriorityClass::of(priority);
+    let mut aged = class;
+    for _ in 0..waited_ms / aging_ms {
+        aged = aged.raised();
+    }
+    (aged != class).then(|| aged.name())
+}
+
+/// Percent of each limit a class may fill.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct PriorityShares {
+    critical: u64,
+    normal: u64,
+    background: u64,
+}
+
+impl Default for PriorityShares {
+    fn default() -> Self {
+        Self {
+            critical: 100,
+            normal: 100,
+            background: 50,
+        }
+    }
+}
+
+impl PriorityShares {
+    /// Parses `class=percent` pairs separated by commas, e.g.
+    /// `normal=90,background=40`. Classes left out keep their default;
+    /// percentages must be 1 to 100.
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut shares = Self::default();
+        for pair in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (class, percent) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("{pair:?} is not class=percent"))?;
+            let class = class.trim().to_ascii_lowercase();
+            let percent = percent
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|percent| (1..=100).contains(percent))
+                .ok_or_else(|| format!("share for {class} must be 1 to 100"))?;
+            match class.as_str() {
+                "critical" => shares.critical = percent,
+                "normal" => shares.normal = percent,
+                "background" => shares.background = percent,
+                _ => return Err(format!("{class:?} is not critical, normal or background")),
+            }
+        }
+        Ok(shares)
+    }
+
+    /// The part of `limit` requests of `priority` may fill, never below 1.
+    pub(crate) fn scale(&self, priority: &str, limit: u64) -> u64 {
+        let percent = match PriorityClass::of(priority) {
+            PriorityClass::Critical => self.critical,
+            PriorityClass::Normal => self.normal,
+            PriorityClass::Background => self.background,
+        };
+        (limit * percent / 100).max(1)
+    }
+}
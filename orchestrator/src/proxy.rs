@@ -0,0 +1,359 @@
+//! Reverse-proxy mode. With `DMBO_PROXY` set, callers point their Discord
+//! library's API base at the orchestrator instead of discord.com: each call
+//! under `/api/` waits for its permit here, is forwarded to
+//! `DMBO_DISCORD_API_BASE`, reported, and answered with Discord's response.
+//!
+//! The caller's own `Authorization` header is forwarded untouched. The
+//! identity the call is limited under is `X-Dmbo-Identity`, or else the bot
+//! id part of a `Bot` token; other `X-Dmbo-*` headers fill in the rest of the
+//! permit request and are not forwarded. Rate limits and transient 5xx are
+//! retried here within `DMBO_PROXY_MAX_ATTEMPTS` and
+//! `DMBO_UPSTREAM_RETRY_BUDGET_MS`, as for jobs. Every response is reported
+//! by the proxy itself; callers never call `report_result`. GETs may be
+//! answered from the response cache (see `proxy_cache`) without a permit.
+
+use crate::{
+    denied_response, jobs, leases, prepare_request, problem_response, proxy_cache, record_report,
+    unix_ms, upstream, upstream::RetryBudget, validation_failed_response, AppState, FieldError,
+    Reason, RequestTokenRequest, RequestTokenResponse, TraceContext,
+    PROBLEM_TYPE_UPSTREAM_UNAVAILABLE,
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use serde_json::json;
+use std::{collections::HashMap, sync::atomic::Ordering, sync::Arc, time::Duration};
+use tokio::time::sleep;
+
+/// Headers that describe one connection rather than the request, plus those
+/// the proxy sets itself.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+/// `ANY /api/*path`: permits, forwards and reports one Discord call.
+pub(crate) async fn forward(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let trace = TraceContext::from_headers(&headers);
+    let mut response = process(&state, method, &uri, &headers, body, trace.as_ref()).await;
+    if let Some(trace) = &trace {
+        trace.propagate(response.headers_mut());
+    }
+    response
+}
+
+async fn process(
+    state: &Arc<AppState>,
+    method: Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: Bytes,
+    trace: Option<&TraceContext>,
+) -> Response {
+    let path = uri
+        .path_and_query()
+        .map_or_else(|| uri.path().to_string(), ToString::to_string);
+    let mut request = permit_request_for(headers, method.as_str(), &path, body.len());
+    if request.discord_identity.is_empty() {
+        return validation_failed_response(vec![FieldError {
+            field: "discord_identity",
+            message: "set X-Dmbo-Identity or send a Bot token".to_string(),
+        }]);
+    }
+    let field_errors = prepare_request(&state.config, &mut request);
+    if !field_errors.is_empty() {
+        return validation_failed_response(field_errors);
+    }
+    let cache_key = proxy_cache::key(state, &request, &path, headers);
+    if let Some(key) = &cache_key {
+        if let Some(response) = proxy_cache::lookup(state, key).await {
+            return response;
+        }
+    }
+    let url = format!(
+        "{}{}",
+        state.config.discord_api_base.trim_end_matches('/'),
+        dmbo_core::routes::request_path(&path)
+    );
+    let forwarded = forwarded_headers(headers);
+    let budget = RetryBudget::new(
+        state.config.proxy_max_attempts,
+        state.config.upstream_retry_budget_ms,
+    );
+    let mut attempts = 0;
+    loop {
+        let mut attempt = request.clone();
+        attempts += 1;
+        attempt.attempt = attempts;
+        let (decision, errored) = crate::decide_permit(state, &mut attempt, trace).await;
+        if !decision.granted {
+            // The proxy has no response of the leader's to share; it waits
+            // for the coalescing window to close and asks again.
+            if decision.reason == Reason::Coalesced {
+                attempts -= 1;
+                sleep(Duration::from_millis(
+                    decision
+                        .retry_after_ms
+                        .unwrap_or_default()
+                        .max(state.config.min_retry_ms),
+                ))
+                .await;
+                continue;
+            }
+            if errored {
+                return denied_response(state, decision, true);
+            }
+            return rate_limited_response(state, &decision);
+        }
+        sleep(Duration::from_millis(
+            decision.not_before_unix_ms.saturating_sub(unix_ms()),
+        ))
+        .await;
+
+        state
+            .metrics
+            .proxied_requests
+            .fetch_add(1, Ordering::Relaxed);
+        let sent = state
+            .http
+            .request(method.clone(), &url)
+            .headers(forwarded.clone())
+            .body(body.clone())
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await;
+        let upstream_response = match sent {
+            Ok(upstream_response) => upstream_response,
+            Err(_) => {
+                // No call reached Discord; hand the permit back.
+                if let (Some(lease_id), Ok(mut conn)) = (
+                    &decision.lease_id,
+                    state.redis.get_multiplexed_async_connection().await,
+                ) {
+                    let _ = leases::release(state, &mut conn, lease_id).await;
+                }
+                match upstream::retry_after(None, &HashMap::new(), state.config.min_retry_ms) {
+                    Some((cause, delay))
+                        if budget.spend(&state.metrics, attempts, cause, delay) =>
+                    {
+                        sleep(Duration::from_millis(delay)).await;
+                        continue;
+                    }
+                    _ => return upstream_unavailable_response(),
+                }
+            }
+        };
+
+        let status = upstream_response.status();
+        let response_headers = upstream_response.headers().clone();
+        let response_body = upstream_response.bytes().await.unwrap_or_default();
+        let result = learn(
+            state,
+            &attempt,
+            decision.lease_id.clone(),
+            status,
+            &response_headers,
+            &response_body,
+        )
+        .await;
+
+        if let Some((cause, delay)) = upstream::retry_after(
+            Some(result.status_code),
+            &result.headers,
+            state.config.min_retry_ms,
+        ) {
+            if budget.spend(&state.metrics, attempts, cause, delay) {
+                sleep(Duration::from_millis(delay)).await;
+                continue;
+            }
+        }
+
+        if let Some(key) = &cache_key {
+            proxy_cache::store(state, key, status, &response_headers, &response_body).await;
+        }
+        let mut response = Response::new(Body::from(response_body));
+        *response.status_mut() = status;
+        for (name, value) in &response_headers {
+            if !is_hop_by_hop(name) {
+                response.headers_mut().append(name, value.clone());
+            }
+        }
+        if cache_key.is_some() {
+            response
+                .headers_mut()
+                .insert("x-dmbo-cache", HeaderValue::from_static("miss"));
+        }
+        return response;
+    }
+}
+
+/// Feeds Discord's response to the limiter as the report a client would
+/// send, so bucket mappings, bucket state, 429 cooldowns and the
+/// invalid-request guardrail learn from every proxied call. Runs before the
+/// caller sees the response, so its next call is decided on the new state.
+async fn learn(
+    state: &Arc<AppState>,
+    request: &RequestTokenRequest,
+    lease_id: Option<String>,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> jobs::JobResult {
+    let result = jobs::JobResult {
+        status_code: status.as_u16(),
+        headers: rate_limit_headers(headers),
+        // Only a 429's body is read, for its exact `retry_after`.
+        body: if status == StatusCode::TOO_MANY_REQUESTS {
+            String::from_utf8_lossy(body).into_owned()
+        } else {
+            String::new()
+        },
+    };
+    let mut report = jobs::report_for(request, &result);
+    report.lease_id = lease_id;
+    record_report(state, &report).await;
+    result
+}
+
+/// The permit request for a proxied call, described by its `X-Dmbo-*`
+/// headers. Waits for the permit like a long poll.
+fn permit_request_for(
+    headers: &HeaderMap,
+    method: &str,
+    path: &str,
+    body_len: usize,
+) -> RequestTokenRequest {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    let is_upload = header("content-type")
+        .is_some_and(|content_type| content_type.starts_with("multipart/form-data"));
+    let mut request = RequestTokenRequest {
+        client_id: header("x-dmbo-client-id").unwrap_or_else(|| "proxy".to_string()),
+        discord_identity: header("x-dmbo-identity")
+            .or_else(|| bot_id(&header("authorization")?))
+            .unwrap_or_default(),
+        method: method.to_string(),
+        path: Some(path.to_string()),
+        // Replaced by the path's own major parameter where it has one.
+        major_parameter: "unknown".to_string(),
+        payload_bytes: is_upload.then_some(body_len as u64),
+        long_poll: true,
+        max_wait_ms: header("x-dmbo-max-wait-ms")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default(),
+        feature: header("x-dmbo-feature"),
+        ..Default::default()
+    };
+    if let Some(group_id) = header("x-dmbo-group-id") {
+        request.group_id = group_id;
+    }
+    if let Some(priority) = header("x-dmbo-priority") {
+        request.priority = priority;
+    }
+    request
+}
+
+/// Base64 as used in the first part of a token, with or without padding.
+const TOKEN_ID: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// The bot's user id, base64-encoded in the first part of a `Bot` token.
+/// Other token types need `X-Dmbo-Identity`.
+fn bot_id(authorization: &str) -> Option<String> {
+    let token = authorization.strip_prefix("Bot ")?.trim();
+    let (id, _) = token.split_once('.')?;
+    let id = String::from_utf8(TOKEN_ID.decode(id).ok()?).ok()?;
+    (!id.is_empty() && id.bytes().all(|b| b.is_ascii_digit())).then_some(id)
+}
+
+/// The caller's headers minus hop-by-hop ones, `Host` and the proxy's own
+/// `X-Dmbo-*` headers. Trace context passes through to Discord.
+fn forwarded_headers(headers: &HeaderMap) -> HeaderMap {
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            !is_hop_by_hop(name) && *name != header::HOST && !name.as_str().starts_with("x-dmbo-")
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+pub(crate) fn is_hop_by_hop(name: &HeaderName) -> bool {
+    HOP_BY_HOP.contains(&name.as_str())
+}
+
+/// The headers a report is built from, keyed by lower-case name.
+fn rate_limit_headers(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.as_str();
+            name.starts_with("x-ratelimit-") || name == "retry-after" || name == "content-type"
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// A denial in the shape of Discord's own 429, so the caller's library backs
+/// off as it would for Discord. `reason` names the orchestrator's reason.
+fn rate_limited_response(state: &AppState, decision: &RequestTokenResponse) -> Response {
+    let retry_after_ms = decision.retry_after_ms.unwrap_or(state.config.min_retry_ms);
+    let mut response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(json!({
+            "message": decision.reason_message,
+            "retry_after": retry_after_ms as f64 / 1000.0,
+            "global": false,
+            "reason": decision.reason,
+        })),
+    )
+        .into_response();
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(retry_after_ms.div_ceil(1000).max(1)),
+    );
+    response
+}
+
+fn upstream_unavailable_response() -> Response {
+    problem_response(
+        StatusCode::BAD_GATEWAY,
+        PROBLEM_TYPE_UPSTREAM_UNAVAILABLE,
+        "Discord unreachable",
+        Reason::UpstreamUnavailable.message().to_string(),
+        json!({
+            "reason": Reason::UpstreamUnavailable,
+            "reason_message": Reason::UpstreamUnavailable.message(),
+        }),
+    )
+}
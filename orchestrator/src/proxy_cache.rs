@@ -0,0 +1,130 @@
+//! Response cache for proxy mode. With `DMBO_PROXY_CACHE_TTL_MS` set, 200
+//! responses to proxied GETs are kept in Redis for that long and replayed to
+//! the same identity's identical GETs without a permit or a Discord call, so
+//! replicas and shards of one bot fetching the same guild or channel share
+//! one call. Callers never share entries: what a token may see differs, so
+//! entries are keyed by a hash of the `Authorization` header as well as the
+//! identity, and calls without one are not cached.
+
+use crate::{normalize_key_part, AppState, RequestTokenRequest};
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::Response,
+};
+use redis::AsyncCommands;
+use sha2::{Digest, Sha256};
+use std::{fmt::Write, sync::atomic::Ordering};
+
+/// Larger responses are forwarded but not cached.
+const MAX_CACHED_BODY_BYTES: usize = 1024 * 1024;
+
+/// The cache key of a proxied call, or `None` when it is not cacheable.
+/// Responses are stored as encoded, so the accepted encoding is part of it.
+pub(crate) fn key(
+    state: &AppState,
+    request: &RequestTokenRequest,
+    path: &str,
+    headers: &HeaderMap,
+) -> Option<String> {
+    if state.config.proxy_cache_ttl_ms == 0 || request.method != "GET" {
+        return None;
+    }
+    let authorization = headers
+        .get("authorization")
+        .map(|value| Sha256::digest(value.as_bytes()))?;
+    let authorization = authorization.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    });
+    let encoding = headers
+        .get("accept-encoding")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("identity");
+    Some(format!(
+        "rl:proxy_cache:{}:{authorization}:{}:{}",
+        normalize_key_part(&request.discord_identity),
+        normalize_key_part(encoding),
+        normalize_key_part(&dmbo_core::routes::request_path(path))
+    ))
+}
+
+/// The stored response for `key`, marked `X-Dmbo-Cache: hit`. Misses and
+/// Redis failures both return `None`.
+pub(crate) async fn lookup(state: &AppState, key: &str) -> Option<Response> {
+    let cached = async {
+        let mut conn = state.redis.get_multiplexed_async_connection().await.ok()?;
+        let (headers, body): (Option<String>, Option<Vec<u8>>) =
+            conn.hget(key, &["headers", "body"]).await.ok()?;
+        Some((headers?, body?))
+    }
+    .await;
+    let Some((headers, body)) = cached else {
+        state
+            .metrics
+            .proxy_cache_misses
+            .fetch_add(1, Ordering::Relaxed);
+        return None;
+    };
+    state
+        .metrics
+        .proxy_cache_hits
+        .fetch_add(1, Ordering::Relaxed);
+    let headers: Vec<(String, String)> = serde_json::from_str(&headers).unwrap_or_default();
+    let mut response = Response::new(Body::from(body));
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+        .headers_mut()
+        .insert("x-dmbo-cache", HeaderValue::from_static("hit"));
+    Some(response)
+}
+
+/// Stores Discord's response to a cacheable call when it was a 200. The
+/// rate-limit headers are dropped: they would be stale on replay.
+pub(crate) async fn store(
+    state: &AppState,
+    key: &str,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &Bytes,
+) {
+    if status != StatusCode::OK || body.len() > MAX_CACHED_BODY_BYTES {
+        return;
+    }
+    let kept: Vec<(&str, &str)> = headers
+        .iter()
+        .filter(|(name, _)| {
+            !name.as_str().starts_with("x-ratelimit-")
+                && *name != "set-cookie"
+                && !crate::proxy::is_hop_by_hop(name)
+        })
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return;
+    };
+    let stored: redis::RedisResult<()> = redis::pipe()
+        .atomic()
+        .hset_multiple(
+            key,
+            &[
+                ("headers", serde_json::to_vec(&kept).unwrap_or_default()),
+                ("body", body.to_vec()),
+            ],
+        )
+        .ignore()
+        .pexpire(key, state.config.proxy_cache_ttl_ms as i64)
+        .ignore()
+        .query_async(&mut conn)
+        .await;
+    if stored.is_ok() {
+        state
+            .metrics
+            .proxy_cache_stores
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,251 @@
+//! Optional event publishing to NATS (`DMBO_EVENTS_NATS_URL`, requires the
+//! `nats` build feature) and/or Kafka (`DMBO_EVENTS_KAFKA_BROKERS`, requires
+//! the `kafka` build feature). Every `request_token` decision is published to
+//! `<prefix>.decisions` and every reported 429 to `<prefix>.ratelimits` as a
+//! JSON object, so event pipelines can build their own analytics without
+//! polling the admin endpoints. Events pass through a bounded channel: a slow
+//! or unreachable broker drops events rather than delaying permits.
+
+use crate::{unix_ms, AppState, Config, ReportResultRequest, RequestTokenRequest};
+use serde_json::{json, Value};
+use std::sync::{atomic::Ordering, Arc};
+use tokio::sync::mpsc;
+
+/// Events buffered while the broker is slow before new ones are dropped.
+const CHANNEL_CAPACITY: usize = 10_000;
+
+#[derive(Clone, Copy)]
+enum Topic {
+    Decisions,
+    RateLimits,
+}
+
+impl Topic {
+    fn name(self, prefix: &str) -> String {
+        match self {
+            Topic::Decisions => format!("{prefix}.decisions"),
+            Topic::RateLimits => format!("{prefix}.ratelimits"),
+        }
+    }
+}
+
+#[cfg_attr(not(any(feature = "nats", feature = "kafka")), allow(dead_code))]
+struct Event {
+    topic: Topic,
+    /// Kafka message key; the Discord identity, so one bot's events stay
+    /// ordered within a partition.
+    #[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+    key: String,
+    payload: Vec<u8>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Publisher {
+    sender: Option<mpsc::Sender<Event>>,
+}
+
+pub(crate) struct PublisherTask {
+    receiver: mpsc::Receiver<Event>,
+}
+
+impl Publisher {
+    /// Creates the publisher when a broker is configured. Configuring a
+    /// broker whose feature was not built aborts startup.
+    pub(crate) fn start(config: &Config) -> (Self, Option<PublisherTask>) {
+        #[cfg(not(feature = "nats"))]
+        if config.events_nats_url.is_some() {
+            panic!("DMBO_EVENTS_NATS_URL is set but the orchestrator was built without the nats feature");
+        }
+        #[cfg(not(feature = "kafka"))]
+        if config.events_kafka_brokers.is_some() {
+            panic!("DMBO_EVENTS_KAFKA_BROKERS is set but the orchestrator was built without the kafka feature");
+        }
+        if config.events_nats_url.is_none() && config.events_kafka_brokers.is_none() {
+            return (Self::default(), None);
+        }
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        (
+            Self {
+                sender: Some(sender),
+            },
+            Some(PublisherTask { receiver }),
+        )
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    fn publish(&self, state: &AppState, topic: Topic, key: &str, mut event: Value) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        event["unix_ms"] = json!(unix_ms());
+        event["instance_id"] = json!(state.config.instance_id);
+        let event = Event {
+            topic,
+            key: key.to_string(),
+            payload: event.to_string().into_bytes(),
+        };
+        if sender.try_send(event).is_err() {
+            state.metrics.events_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Publishes a final `request_token` decision.
+    pub(crate) fn decision(
+        &self,
+        state: &AppState,
+        request: &RequestTokenRequest,
+        granted: bool,
+        reason: &str,
+        retry_after_ms: Option<u64>,
+        trace_id: Option<&str>,
+    ) {
+        if !self.enabled() {
+            return;
+        }
+        self.publish(
+            state,
+            Topic::Decisions,
+            &request.discord_identity,
+            json!({
+                "type": "decision",
+                "request_id": request.request_id,
+                "client_id": request.client_id,
+                "group_id": request.group_id,
+                "discord_identity": request.discord_identity,
+                "method": request.method,
+                "route": request.route,
+                "major_parameter": request.major_parameter,
+                "priority": request.priority,
+                "feature": request.feature,
+                "tags": request.tags,
+                "granted": granted,
+                "reason": reason,
+                "retry_after_ms": retry_after_ms,
+                "trace_id": trace_id,
+            }),
+        );
+    }
+
+    /// Publishes a reported Discord 429.
+    pub(crate) fn rate_limited(&self, state: &AppState, report: &ReportResultRequest) {
+        if !self.enabled() {
+            return;
+        }
+        self.publish(
+            state,
+            Topic::RateLimits,
+            &report.discord_identity,
+            json!({
+                "type": "rate_limited",
+                "request_id": report.request_id,
+                "client_id": report.client_id,
+                "group_id": report.group_id,
+                "discord_identity": report.discord_identity,
+                "method": report.method,
+                "route": report.route,
+                "major_parameter": report.major_parameter,
+                "feature": report.feature,
+                "scope": report.scope(),
+                "bucket": report.x_ratelimit_bucket,
+                "limit": report.x_ratelimit_limit,
+                "remaining": report.x_ratelimit_remaining,
+                "reset_after_s": report.x_ratelimit_reset_after_s,
+            }),
+        );
+    }
+}
+
+/// Connects to the configured brokers and forwards queued events until
+/// shutdown. Send failures are counted and the event is dropped.
+pub(crate) async fn run(state: Arc<AppState>, task: PublisherTask) {
+    let mut receiver = task.receiver;
+    let sinks = Sinks::connect(&state.config).await;
+    while let Some(event) = receiver.recv().await {
+        sinks
+            .send(
+                &state,
+                &event.topic.name(&state.config.events_prefix),
+                &event,
+            )
+            .await;
+    }
+}
+
+#[derive(Default)]
+struct Sinks {
+    #[cfg(feature = "nats")]
+    nats: Option<async_nats::Client>,
+    #[cfg(feature = "kafka")]
+    kafka: Option<rdkafka::producer::FutureProducer>,
+}
+
+impl Sinks {
+    #[cfg_attr(not(any(feature = "nats", feature = "kafka")), allow(unused_variables))]
+    async fn connect(config: &Config) -> Self {
+        #[allow(unused_mut)]
+        let mut sinks = Self::default();
+        #[cfg(feature = "nats")]
+        if let Some(url) = &config.events_nats_url {
+            match async_nats::ConnectOptions::new()
+                .retry_on_initial_connect()
+                .connect(url.as_str())
+                .await
+            {
+                Ok(client) => sinks.nats = Some(client),
+                Err(error) => eprintln!("NATS event publishing disabled: {error}"),
+            }
+        }
+        #[cfg(feature = "kafka")]
+        if let Some(brokers) = &config.events_kafka_brokers {
+            match rdkafka::ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .set("message.timeout.ms", "5000")
+                .create()
+            {
+                Ok(producer) => sinks.kafka = Some(producer),
+                Err(error) => eprintln!("Kafka event publishing disabled: {error}"),
+            }
+        }
+        sinks
+    }
+
+    #[cfg_attr(not(any(feature = "nats", feature = "kafka")), allow(unused_variables))]
+    async fn send(&self, state: &Arc<AppState>, topic: &str, event: &Event) {
+        #[cfg(feature = "nats")]
+        if let Some(client) = &self.nats {
+            let published = client
+                .publish(topic.to_string(), event.payload.clone().into())
+                .await;
+            count(state, published.is_ok());
+        }
+        #[cfg(feature = "kafka")]
+        if let Some(producer) = &self.kafka {
+            let record = rdkafka::producer::FutureRecord::to(topic)
+                .key(&event.key)
+                .payload(&event.payload);
+            // Enqueue without waiting for the broker's acknowledgement.
+            match producer.send_result(record) {
+                Ok(delivery) => {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        count(&state, matches!(delivery.await, Ok(Ok(_))));
+                    });
+                }
+                Err(_) => count(state, false),
+            }
+        }
+    }
+}
+
+#[cfg_attr(not(any(feature = "nats", feature = "kafka")), allow(dead_code))]
+fn count(state: &AppState, ok: bool) {
+    let counter = if ok {
+        &state.metrics.events_published
+    } else {
+        &state.metrics.events_failed
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,124 @@
+//! Redis pub/sub command transport, for clients that can reach Redis but not
+//! the orchestrator over HTTP. With `DMBO_PUBSUB_CHANNEL` set, every replica
+//! subscribes to that channel. Each message is a `request_token` body plus a
+//! `reply_channel`; the decision, carrying the request's `request_id`, is
+//! published to the reply channel once it is made, waiting up to
+//! `max_wait_ms` like an HTTP request.
+//!
+//! Every replica receives every message, so the first to claim the
+//! `request_id` in `rl:pubsub:claim:{request_id}` decides it and the others
+//! drop it. Requests without a `request_id` are rejected for that reason,
+//! once by every replica.
+
+use crate::{
+    decide_permit, normalize_key_part, prepare_request, ws, AppState, FieldError,
+    RequestTokenRequest,
+};
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::time::sleep;
+
+#[derive(Deserialize)]
+struct Command {
+    reply_channel: String,
+    #[serde(flatten)]
+    request: RequestTokenRequest,
+}
+
+/// Background task: decides the commands published on `channel`,
+/// resubscribing after connection losses.
+pub(crate) async fn serve(state: Arc<AppState>, channel: String) {
+    loop {
+        if let Ok(mut pubsub) = state.redis.get_async_pubsub().await {
+            if pubsub.subscribe(&channel).await.is_ok() {
+                let mut messages = pubsub.on_message();
+                while let Some(message) = messages.next().await {
+                    let Ok(payload) = message.get_payload::<String>() else {
+                        continue;
+                    };
+                    tokio::spawn(handle(state.clone(), payload));
+                }
+            }
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn handle(state: Arc<AppState>, payload: String) {
+    // Without a readable reply channel there is nobody to answer.
+    let Ok(Command {
+        reply_channel,
+        mut request,
+    }) = serde_json::from_str::<Command>(&payload)
+    else {
+        state.metrics.pubsub_invalid.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    if request.request_id.trim().is_empty() {
+        let errors = vec![FieldError {
+            field: "request_id",
+            message: "must not be empty".to_string(),
+        }];
+        reply_invalid(&state, &reply_channel, None, errors).await;
+        return;
+    }
+    if !claim(&state, &request.request_id).await {
+        return;
+    }
+    let errors = prepare_request(&state.config, &mut request);
+    if !errors.is_empty() {
+        reply_invalid(&state, &reply_channel, Some(&request.request_id), errors).await;
+        return;
+    }
+    state
+        .metrics
+        .pubsub_commands
+        .fetch_add(1, Ordering::Relaxed);
+    let (response, _) = decide_permit(&state, &mut request, None).await;
+    let mut reply = serde_json::to_value(&response).unwrap_or_default();
+    reply["request_id"] = Value::String(request.request_id);
+    publish(&state, &reply_channel, &reply).await;
+}
+
+/// Whether this replica is the one to decide `request_id`.
+async fn claim(state: &AppState, request_id: &str) -> bool {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return false;
+    };
+    let claimed: redis::RedisResult<Option<String>> = redis::cmd("SET")
+        .arg(format!(
+            "rl:pubsub:claim:{}",
+            normalize_key_part(request_id)
+        ))
+        .arg(&state.config.instance_id)
+        .arg("NX")
+        .arg("PX")
+        .arg(state.config.max_wait_cap_ms.saturating_add(5_000))
+        .query_async(&mut conn)
+        .await;
+    matches!(claimed, Ok(Some(_)))
+}
+
+async fn reply_invalid(
+    state: &AppState,
+    reply_channel: &str,
+    request_id: Option<&str>,
+    errors: Vec<FieldError>,
+) {
+    state.metrics.pubsub_invalid.fetch_add(1, Ordering::Relaxed);
+    publish(state, reply_channel, &ws::invalid_frame(request_id, errors)).await;
+}
+
+async fn publish(state: &AppState, reply_channel: &str, reply: &Value) {
+    if let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await {
+        let _ = conn
+            .publish::<_, _, i64>(reply_channel, reply.to_string())
+            .await;
+    }
+}
@@ -0,0 +1,258 @@
+//! Deadline-ordered queues for waiting `request_token` calls. A call that
+//! has to wait for its route bucket joins `rl:queue:{route_bucket}`, a
+//! sorted set that every replica shares, scored by the time the call gives
+//! up waiting (ties go to the earlier arrival). Only the head of the queue,
+//! the call closest to timing out, asks the limiter again. The others are
+//! woken when a waiter ahead of them leaves, on this instance or through
+//! the `rl:wakeups` channel, and check for their turn every
+//! `DMBO_QUEUE_POLL_MS` in case a wakeup was missed. A call arriving while
+//! calls due sooner are queued lines up behind them instead of racing them
+//! for freed capacity. Members whose deadline passed, e.g. because their
+//! replica died, are dropped.
+//! Critical-priority calls are scored in a band of their own below everyone
+//! else's, so they go ahead of every normal and background waiter; the
+//! number of calls that pass someone this way is counted as preemptions.
+//! Interaction responses keep their own deadline ordering and skip the
+//! queue.
+//!
+//! `POST /cancel_request` ends a wait early. Waiters on the instance that
+//! receives it stop at once; the `rl:cancel:{request_id}` marker it leaves
+//! stops queued waiters on other replicas at their next turn check.
+
+use crate::{
+    json_rejection_response, priority::PriorityClass, validation_failed_response, wakeups,
+    AppState, FieldError, RequestTokenRequest,
+};
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use dmbo_core::keys::bucket_id;
+use redis::{AsyncCommands, Script};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Score offset of the band normal and background calls queue in; critical
+/// calls are scored by their bare deadline and so sort first.
+const NON_CRITICAL_BAND: u64 = 10_000_000_000_000;
+
+/// Drops members of `KEYS[1]` whose deadline is not after `ARGV[1]` in
+/// either band, then returns `{ahead, passed}`: how many members are ahead
+/// of `ARGV[2]` (its rank when queued, else how many score no higher than
+/// its score `ARGV[3]`), and for a critical call how many non-critical
+/// members due no later than it are queued behind it. A cancelled call
+/// (`KEYS[2]` exists) leaves the queue and gets `{-1, 0}`.
+const TURN_LUA: &str = r#"
+local band = tonumber(ARGV[4])
+if redis.call('EXISTS', KEYS[2]) == 1 then
+  redis.call('ZREM', KEYS[1], ARGV[2])
+  return {-1, 0}
+end
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', ARGV[1])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], band, band + tonumber(ARGV[1]))
+local score = tonumber(ARGV[3])
+local ahead = redis.call('ZRANK', KEYS[1], ARGV[2])
+  or redis.call('ZCOUNT', KEYS[1], '-inf', ARGV[3])
+local passed = 0
+if score < band then
+  passed = redis.call('ZCOUNT', KEYS[1], band, band + score)
+end
+return {ahead, passed}
+"#;
+
+/// One call's place in its bucket's queue. Leaves the queue when dropped.
+pub(crate) struct QueueTicket {
+    redis: redis::Client,
+    instance_id: String,
+    route_bucket: String,
+    key: String,
+    cancel_key: String,
+    member: String,
+    score: u64,
+    joined: bool,
+    preempted: bool,
+    preemptions: Arc<AtomicU64>,
+}
+
+impl QueueTicket {
+    /// A ticket for `request`, which arrived at `arrived_ms` and gives up
+    /// waiting at `deadline_ms`. Critical requests queue ahead of the rest.
+    pub(crate) fn new(
+        state: &AppState,
+        request: &RequestTokenRequest,
+        arrived_ms: u64,
+        deadline_ms: u64,
+    ) -> Self {
+        let route_bucket = bucket_id(
+            &request.discord_identity,
+            &request.method,
+            &request.route,
+            &request.major_parameter,
+        );
+        Self {
+            redis: state.redis.clone(),
+            instance_id: state.config.instance_id.clone(),
+            key: format!("rl:queue:{route_bucket}"),
+            cancel_key: cancel_key(&request.request_id),
+            // Members sharing a deadline sort by arrival.
+            member: format!("{deadline_ms}:{arrived_ms}:{}", uuid::Uuid::new_v4()),
+            score: match PriorityClass::of(&request.priority) {
+                PriorityClass::Critical => deadline_ms,
+                PriorityClass::Normal | PriorityClass::Background => {
+                    NON_CRITICAL_BAND + deadline_ms
+                }
+            },
+            joined: false,
+            preempted: false,
+            preemptions: state.metrics.queue_preemptions.clone(),
+            route_bucket,
+        }
+    }
+
+    /// Calls queued ahead of this one; `Some(0)` means it may ask the
+    /// limiter and `None` that the call was cancelled. Redis failures let
+    /// the call through.
+    pub(crate) async fn ahead(&mut self, now_ms: u64) -> Option<u64> {
+        let Ok(mut conn) = self.redis.get_multiplexed_async_connection().await else {
+            return Some(0);
+        };
+        let (ahead, passed): (i64, u64) = Script::new(TURN_LUA)
+            .key(&self.key)
+            .key(&self.cancel_key)
+            .arg(now_ms)
+            .arg(&self.member)
+            .arg(self.score)
+            .arg(NON_CRITICAL_BAND)
+            .invoke_async(&mut conn)
+            .await
+            .unwrap_or((0, 0));
+        if passed > 0 && !self.preempted {
+            self.preempted = true;
+            self.preemptions.fetch_add(1, Ordering::Relaxed);
+        }
+        u64::try_from(ahead).ok()
+    }
+
+    /// Lines up by deadline; a no-op once queued. The queue outlives the
+    /// longest wait `max_wait_cap_ms` allows.
+    pub(crate) async fn join(&mut self, max_wait_cap_ms: u64) {
+        if self.joined {
+            return;
+        }
+        let Ok(mut conn) = self.redis.get_multiplexed_async_connection().await else {
+            return;
+        };
+        let joined: redis::RedisResult<()> = redis::pipe()
+            .atomic()
+            .zadd(&self.key, &self.member, self.score)
+            .ignore()
+            .pexpire(&self.key, max_wait_cap_ms as i64 + 1000)
+            .ignore()
+            .query_async(&mut conn)
+            .await;
+        self.joined = joined.is_ok();
+    }
+
+    /// Leaves the queue before returning, so the waiter woken next already
+    /// finds itself at the head. Waiters on other replicas are told too.
+    pub(crate) async fn leave(mut self) {
+        if !self.joined {
+            return;
+        }
+        self.joined = false;
+        if let Ok(mut conn) = self.redis.get_multiplexed_async_connection().await {
+            let _ = conn.zrem::<_, _, i64>(&self.key, &self.member).await;
+            wakeups::publish(&mut conn, &self.instance_id, &self.route_bucket).await;
+        }
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        if !self.joined {
+            return;
+        }
+        let redis = self.redis.clone();
+        let instance_id = std::mem::take(&mut self.instance_id);
+        let route_bucket = std::mem::take(&mut self.route_bucket);
+        let key = std::mem::take(&mut self.key);
+        let member = std::mem::take(&mut self.member);
+        tokio::spawn(async move {
+            if let Ok(mut conn) = redis.get_multiplexed_async_connection().await {
+                let _ = conn.zrem::<_, _, i64>(key, member).await;
+                wakeups::publish(&mut conn, &instance_id, &route_bucket).await;
+            }
+        });
+    }
+}
+
+fn cancel_key(request_id: &str) -> String {
+    format!("rl:cancel:{request_id}")
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct CancelRequest {
+    #[serde(default)]
+    request_id: String,
+}
+
+/// `POST /cancel_request`: ends the waits of `request_id` so their queue
+/// places go to the calls behind them. Reports how many were waiting here.
+#[utoipa::path(
+    post,
+    path = "/cancel_request",
+    request_body = CancelRequest,
+    responses(
+        (status = 200, description = "Waits cancelled", body = crate::openapi::CancelResponse),
+        (status = 400, description = "Invalid body", body = crate::openapi::Problem, content_type = "application/problem+json"),
+        (status = 503, description = "Backend unavailable", body = crate::openapi::Problem, content_type = "application/problem+json"),
+    )
+)]
+pub(crate) async fn cancel_request(
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<CancelRequest>, JsonRejection>,
+) -> Response {
+    let Json(request) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return json_rejection_response(&state, rejection),
+    };
+    if request.request_id.trim().is_empty() {
+        return validation_failed_response(vec![FieldError {
+            field: "request_id",
+            message: "must not be empty".to_string(),
+        }]);
+    }
+    let cancelled = state.waiters.cancel(&request.request_id);
+    if state.config.memory_store {
+        return (
+            StatusCode::OK,
+            Json(json!({ "ok": true, "cancelled": cancelled })),
+        )
+            .into_response();
+    }
+    // Only waits that can still be running need the marker.
+    let marked: redis::RedisResult<()> = async {
+        let mut conn = state.redis.get_multiplexed_async_connection().await?;
+        conn.pset_ex(
+            cancel_key(&request.request_id),
+            1_u8,
+            state.config.max_wait_cap_ms,
+        )
+        .await
+    }
+    .await;
+    if marked.is_err() && cancelled == 0 {
+        return crate::backend_unavailable_response(&state);
+    }
+    (
+        StatusCode::OK,
+        Json(json!({ "ok": true, "cancelled": cancelled })),
+    )
+        .into_response()
+}
@@ -0,0 +1,118 @@
+//! Daily request quotas. Operators who pay per request for services behind
+//! their bots can cap each identity (`DMBO_IDENTITY_DAILY_QUOTA`) and each
+//! group (`DMBO_GROUP_DAILY_QUOTA`) at a number of granted permits per UTC
+//! day, on top of the rate limits. Counters are keyed on the day, so they
+//! reset at midnight UTC. A permit takes its unit right before the
+//! per-second limiter and gives it back if that denies, like long-window
+//! limits.
+
+use crate::{normalize_key_part, AppState, RequestTokenRequest};
+use redis::{aio::MultiplexedConnection, Script};
+use std::sync::atomic::Ordering;
+
+const DAY_MS: u64 = 86_400_000;
+
+/// Counters outlive their day by this much, for permits denied around
+/// midnight that give their unit back.
+const GRACE_MS: u64 = 3_600_000;
+
+/// `KEYS` are day counters with caps `ARGV[2..]` in key order and lifetime
+/// `ARGV[1]` ms. Counts one more in each when all are under their cap.
+/// Returns `{1}`, or `{0, index}` naming the first full counter (1-based).
+const ACQUIRE_LUA: &str = r#"
+for i, key in ipairs(KEYS) do
+  if (tonumber(redis.call('GET', key)) or 0) >= tonumber(ARGV[i + 1]) then
+    return {0, i}
+  end
+end
+for _, key in ipairs(KEYS) do
+  redis.call('INCR', key)
+  redis.call('PEXPIRE', key, ARGV[1])
+end
+return {1}
+"#;
+
+/// Units counted for one permit attempt, given back if it is denied later.
+pub(crate) struct QuotaReservation {
+    keys: Vec<String>,
+}
+
+pub(crate) enum QuotaAcquired {
+    Reserved(QuotaReservation),
+    /// A quota is used up until midnight UTC.
+    Exhausted {
+        retry_after_ms: u64,
+    },
+}
+
+/// Milliseconds from `now_ms` to the next midnight UTC.
+fn until_midnight_ms(now_ms: u64) -> u64 {
+    DAY_MS - now_ms % DAY_MS
+}
+
+/// Counts the request against its identity's and group's quotas. Returns
+/// `None` when neither is configured.
+pub(crate) async fn acquire(
+    state: &AppState,
+    conn: &mut MultiplexedConnection,
+    request: &RequestTokenRequest,
+    now_ms: u64,
+) -> redis::RedisResult<Option<QuotaAcquired>> {
+    let config = &state.config;
+    let day = now_ms / DAY_MS;
+    let quotas = [
+        (
+            "identity",
+            &request.discord_identity,
+            config.identity_daily_quota,
+        ),
+        ("group", &request.group_id, config.group_daily_quota),
+    ];
+    let mut scopes = Vec::new();
+    let mut keys = Vec::new();
+    let mut caps = Vec::new();
+    for (scope, subject, cap) in quotas.into_iter().filter(|(_, _, cap)| *cap > 0) {
+        scopes.push(scope);
+        keys.push(format!(
+            "rl:quota:{scope}:{}:{day}",
+            normalize_key_part(subject)
+        ));
+        caps.push(cap);
+    }
+    if keys.is_empty() {
+        return Ok(None);
+    }
+    let script = Script::new(ACQUIRE_LUA);
+    let mut invocation = script.prepare_invoke();
+    for key in &keys {
+        invocation.key(key);
+    }
+    invocation.arg(until_midnight_ms(now_ms) + GRACE_MS);
+    for cap in &caps {
+        invocation.arg(cap);
+    }
+    let reply: Vec<u64> = invocation.invoke_async(conn).await?;
+    let [0, position] = reply.as_slice() else {
+        return Ok(Some(QuotaAcquired::Reserved(QuotaReservation { keys })));
+    };
+    let metrics = &state.metrics;
+    let counter = match scopes[(*position as usize).saturating_sub(1)] {
+        "identity" => &metrics.daily_quota_denials_identity,
+        _ => &metrics.daily_quota_denials_group,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    Ok(Some(QuotaAcquired::Exhausted {
+        retry_after_ms: until_midnight_ms(now_ms),
+    }))
+}
+
+impl QuotaReservation {
+    /// Gives the units back after the per-second limiter denied.
+    pub(crate) async fn release(self, conn: &mut MultiplexedConnection) {
+        let mut pipe = redis::pipe();
+        for key in &self.keys {
+            pipe.decr(key, 1).ignore();
+        }
+        let _ = pipe.query_async::<_, ()>(conn).await;
+    }
+}
@@ -0,0 +1,132 @@
+//! Reaction pacing. Discord spaces reaction adds and removes on a channel
+//! about 250 ms apart, finer than the per-second route counters can express,
+//! so bursts of reactions used to go out together and come back as 429s.
+//! Each reaction instead takes the next free slot on its channel's schedule
+//! (`DMBO_REACTION_INTERVAL_MS` apart) and is granted with
+//! `not_before_unix_ms` set to that slot. A request is denied only when its
+//! slot is further ahead than it is willing to wait, capped at
+//! `DMBO_REACTION_MAX_AHEAD_MS`. The slot is given back if a later check
+//! denies the permit, like long-window limits.
+
+use crate::{normalize_key_part, rules::route_matches, AppState, RequestTokenRequest};
+use redis::{aio::MultiplexedConnection, Script};
+use std::sync::atomic::Ordering;
+
+/// Adding or removing one reaction, all of a user's, or all of an emoji's.
+const REACTION_ROUTE: &str = "/channels/:channel_id/messages/:message_id/reactions/**";
+const REACTION_METHODS: [&str; 2] = ["PUT", "DELETE"];
+
+/// `KEYS[1]` holds the schedule's next free slot (unix ms). `ARGV`: now,
+/// interval ms, how far ahead a slot may be. Returns `{1, slot}`, or
+/// `{0, retry_after_ms}` until a slot is close enough.
+const ACQUIRE_LUA: &str = r#"
+local now = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local ahead = tonumber(ARGV[3])
+local slot = tonumber(redis.call('GET', KEYS[1])) or now
+if slot < now then slot = now end
+if slot - now > ahead then
+  return {0, slot - now - ahead}
+end
+redis.call('SET', KEYS[1], slot + interval, 'PX', slot + interval - now + 1000)
+return {1, slot}
+"#;
+
+/// Gives slot `ARGV[1]` back when no later reaction has taken the one after.
+const RELEASE_LUA: &str = r#"
+if tonumber(redis.call('GET', KEYS[1])) == tonumber(ARGV[1]) + tonumber(ARGV[2]) then
+  redis.call('INCRBY', KEYS[1], -tonumber(ARGV[2]))
+end
+return 1
+"#;
+
+/// A slot on a channel's schedule, given back if the permit is denied later.
+pub(crate) struct ReactionSlot {
+    key: String,
+    pub(crate) slot_unix_ms: u64,
+    interval_ms: u64,
+}
+
+pub(crate) enum ReactionAcquired {
+    Scheduled(ReactionSlot),
+    Exhausted { retry_after_ms: u64 },
+}
+
+/// Takes the next slot on the request's channel. Returns `None` for other
+/// routes or when pacing is off.
+pub(crate) async fn acquire(
+    state: &AppState,
+    conn: &mut MultiplexedConnection,
+    request: &RequestTokenRequest,
+    now_ms: u64,
+) -> redis::RedisResult<Option<ReactionAcquired>> {
+    let config = &state.config;
+    if config.reaction_interval_ms == 0
+        || request.major_parameter.trim().is_empty()
+        || !REACTION_METHODS.contains(&request.method.as_str())
+        || !route_matches(REACTION_ROUTE, &request.route)
+    {
+        return Ok(None);
+    }
+    let key = format!(
+        "rl:reactions:{}:{}",
+        normalize_key_part(&request.discord_identity),
+        normalize_key_part(&request.major_parameter)
+    );
+    let ahead_ms = config.reaction_max_ahead_ms.min(request.max_wait_ms);
+    let slot_unix_ms =
+        match schedule(conn, &key, now_ms, config.reaction_interval_ms, ahead_ms).await? {
+            Ok(slot_unix_ms) => slot_unix_ms,
+            Err(retry_after_ms) => {
+                state
+                    .metrics
+                    .reaction_pacing_denials
+                    .fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(ReactionAcquired::Exhausted { retry_after_ms }));
+            }
+        };
+    if slot_unix_ms > now_ms {
+        state
+            .metrics
+            .reaction_pacing_delays
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(Some(ReactionAcquired::Scheduled(ReactionSlot {
+        key,
+        slot_unix_ms,
+        interval_ms: config.reaction_interval_ms,
+    })))
+}
+
+/// Takes the next slot on the schedule at `key`, `interval_ms` after the
+/// last one taken. Returns the slot's start, or `Err(retry_after_ms)` when
+/// it lies more than `ahead_ms` from now.
+pub(crate) async fn schedule(
+    conn: &mut MultiplexedConnection,
+    key: &str,
+    now_ms: u64,
+    interval_ms: u64,
+    ahead_ms: u64,
+) -> redis::RedisResult<Result<u64, u64>> {
+    let (granted, value): (i64, i64) = Script::new(ACQUIRE_LUA)
+        .key(key)
+        .arg(now_ms)
+        .arg(interval_ms)
+        .arg(ahead_ms)
+        .invoke_async(conn)
+        .await?;
+    let value = value.max(0) as u64;
+    Ok(if granted == 1 { Ok(value) } else { Err(value) })
+}
+
+impl ReactionSlot {
+    /// Gives the slot back after a later check denied the permit.
+    pub(crate) async fn release(self, conn: &mut MultiplexedConnection) {
+        let _ = Script::new(RELEASE_LUA)
+            .key(&self.key)
+            .arg(self.slot_unix_ms)
+            .arg(self.interval_ms)
+            .invoke_async::<_, i64>(conn)
+            .await;
+    }
+}
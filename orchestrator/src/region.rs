@@ -0,0 +1,142 @@
+//! Multi-region mode. Each region runs against its own Redis and admits
+//! against a regional share of the configured budgets, so permits never wait
+//! on cross-region round trips. Every `DMBO_REGION_SYNC_MS` each region
+//! publishes its recent grant rate to a shared coordinator Redis and
+//! recomputes its share in proportion to demand. Between syncs the regions
+//! may over- or under-admit slightly.
+
+use crate::{unix_ms, AppState};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::interval;
+
+const COORDINATOR_KEY: &str = "rl:region:usage";
+/// Shares are stored as parts per million.
+const SHARE_SCALE: u64 = 1_000_000;
+/// Every live region keeps at least this fraction of an even split, so a
+/// quiet region can still admit its first requests after a burst elsewhere.
+const MIN_SHARE_OF_EVEN_SPLIT: f64 = 0.25;
+/// Regions that have not reported for this many sync intervals are ignored.
+const STALE_SYNC_INTERVALS: u64 = 3;
+
+#[derive(Clone)]
+pub(crate) struct RegionState {
+    share_ppm: Arc<AtomicU64>,
+    grants_since_sync: Arc<AtomicU64>,
+    live_regions: Arc<AtomicU64>,
+}
+
+impl Default for RegionState {
+    fn default() -> Self {
+        Self {
+            share_ppm: Arc::new(AtomicU64::new(SHARE_SCALE)),
+            grants_since_sync: Arc::new(AtomicU64::new(0)),
+            live_regions: Arc::new(AtomicU64::new(1)),
+        }
+    }
+}
+
+impl RegionState {
+    pub(crate) fn record_grant(&self) {
+        self.grants_since_sync.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// This region's fraction of the budgets, `1.0` outside multi-region mode.
+    pub(crate) fn share(&self) -> f64 {
+        self.share_ppm.load(Ordering::Relaxed) as f64 / SHARE_SCALE as f64
+    }
+
+    pub(crate) fn live_regions(&self) -> u64 {
+        self.live_regions.load(Ordering::Relaxed)
+    }
+
+    /// Scales a per-second limit to this region's share, never below 1.
+    pub(crate) fn scale(&self, limit: u64) -> u64 {
+        ((limit as f64 * self.share()).floor() as u64).max(1)
+    }
+}
+
+/// Publishes this region's grant rate and recomputes its share. Runs only
+/// when `DMBO_REGION` and `DMBO_COORDINATOR_REDIS_URL` are set; on
+/// coordinator errors the previous share is kept.
+pub(crate) async fn reconcile(state: Arc<AppState>, coordinator: redis::Client) {
+    let Some(region) = state.config.region.clone() else {
+        return;
+    };
+    let sync_ms = state.config.region_sync_ms;
+    let mut ticker = interval(Duration::from_millis(sync_ms));
+    let mut last_sync = unix_ms();
+    loop {
+        ticker.tick().await;
+        let now = unix_ms();
+        let grants = state.region.grants_since_sync.swap(0, Ordering::Relaxed);
+        let rate = grants as f64 * 1000.0 / now.saturating_sub(last_sync).max(1) as f64;
+        last_sync = now;
+
+        let reported: redis::RedisResult<HashMap<String, String>> = async {
+            let mut conn = coordinator.get_multiplexed_async_connection().await?;
+            let (_, usage): ((), HashMap<String, String>) = redis::pipe()
+                .atomic()
+                .hset(COORDINATOR_KEY, &region, format!("{rate:.3}:{now}"))
+                .hgetall(COORDINATOR_KEY)
+                .query_async(&mut conn)
+                .await?;
+            Ok(usage)
+        }
+        .await;
+        let Ok(reported) = reported else {
+            state
+                .metrics
+                .region_sync_failures
+                .fetch_add(1, Ordering::Relaxed);
+            continue;
+        };
+
+        let fresh_after = now.saturating_sub(sync_ms * STALE_SYNC_INTERVALS);
+        let rates: HashMap<String, f64> = reported
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let (rate, at) = value.split_once(':')?;
+                let at: u64 = at.parse().ok()?;
+                (at >= fresh_after).then_some((name, rate.parse().ok()?))
+            })
+            .collect();
+        let share = compute_share(&region, &rates);
+        state
+            .region
+            .share_ppm
+            .store((share * SHARE_SCALE as f64) as u64, Ordering::Relaxed);
+        state
+            .region
+            .live_regions
+            .store(rates.len().max(1) as u64, Ordering::Relaxed);
+    }
+}
+
+/// Demand-proportional share with a floor of `MIN_SHARE_OF_EVEN_SPLIT` of an
+/// even split, normalized so the shares of all live regions sum to one.
+fn compute_share(region: &str, rates: &HashMap<String, f64>) -> f64 {
+    let regions = rates.len().max(1) as f64;
+    let floor = MIN_SHARE_OF_EVEN_SPLIT / regions;
+    let total: f64 = rates.values().sum();
+    let raw = |rate: f64| {
+        if total > 0.0 {
+            (rate / total).max(floor)
+        } else {
+            1.0 / regions
+        }
+    };
+    let normalizer: f64 = rates.values().map(|rate| raw(*rate)).sum();
+    let mine = raw(rates.get(region).copied().unwrap_or_default());
+    if normalizer > 0.0 {
+        (mine / normalizer).clamp(0.0, 1.0)
+    } else {
+        1.0
+    }
+}
@@ -0,0 +1,220 @@
+//! Per-route limits loaded from the JSON file named by `DMBO_ROUTE_LIMITS`,
+//! for routes where one `DMBO_ROUTE_RPS` fits badly: reactions want fewer
+//! calls, bulk deletes a longer window. The first entry matching a request's
+//! method and route replaces the base route limit with its own `route_rps`
+//! calls per `window_ms`, and may cap the calls in flight on each resource
+//! with `concurrency`. A concurrency slot is held until the call's report
+//! arrives or its lease (`DMBO_ROUTE_CONCURRENCY_LEASE_MS`) runs out, and is
+//! reserved before the per-second limiter and given back if that denies,
+//! like long-window limits. `DMBO_ROUTE_MAX_INFLIGHT` caps every other
+//! route bucket the same way, so a slow endpoint is not stampeded while its
+//! rate counter still has room.
+
+use crate::{routes, rules::route_matches, RequestTokenRequest};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Script};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Longest window an entry may set; longer limits belong in
+/// `DMBO_LONG_LIMITS`.
+const MAX_WINDOW_MS: u64 = 60_000;
+
+/// `KEYS[1]` maps slot members to lease expiry. `ARGV`: now, slot cap, lease
+/// ms, member. Returns `{1, 0}`, or `{0, retry_after_ms}` until the oldest
+/// lease runs out.
+const ACQUIRE_LUA: &str = r#"
+local now = tonumber(ARGV[1])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[2]) then
+  local first = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+  return {0, math.max(1, tonumber(first[2]) - now)}
+end
+redis.call('ZADD', KEYS[1], now + tonumber(ARGV[3]), ARGV[4])
+redis.call('PEXPIRE', KEYS[1], tonumber(ARGV[3]))
+return {1, 0}
+"#;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RouteLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    /// Route pattern as in policy rules.
+    route: String,
+    /// Calls per window; the base route limit when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    route_rps: Option<u64>,
+    #[serde(default = "default_window_ms")]
+    window_ms: u64,
+    /// Calls in flight per identity, method, route and major parameter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    concurrency: Option<u64>,
+}
+
+fn default_window_ms() -> u64 {
+    1000
+}
+
+/// A concurrency slot taken for one permit attempt.
+pub(crate) struct Slot {
+    key: String,
+    member: String,
+}
+
+pub(crate) enum SlotAcquired {
+    Reserved(Slot),
+    Exhausted { retry_after_ms: u64 },
+}
+
+#[derive(Default)]
+pub(crate) struct RouteLimits {
+    limits: Vec<RouteLimit>,
+    lease_ms: u64,
+    /// Concurrency cap for buckets whose entry does not set one.
+    max_inflight: Option<u64>,
+    concurrency_denials: AtomicU64,
+}
+
+pub(crate) fn slot_key(route_bucket: &str) -> String {
+    format!("rl:concurrency:{route_bucket}")
+}
+
+impl RouteLimits {
+    /// Loads `DMBO_ROUTE_LIMITS`. A missing or invalid file aborts startup.
+    /// `max_inflight` of 0 leaves buckets without an entry uncapped.
+    pub(crate) fn load(path: Option<&str>, lease_ms: u64, max_inflight: u64) -> Self {
+        let max_inflight = (max_inflight > 0).then_some(max_inflight);
+        let Some(path) = path else {
+            return Self {
+                lease_ms,
+                max_inflight,
+                ..Self::default()
+            };
+        };
+        let raw = std::fs::read_to_string(path)
+            .unwrap_or_else(|error| panic!("failed to read DMBO_ROUTE_LIMITS {path}: {error}"));
+        let mut limits: Vec<RouteLimit> = serde_json::from_str(&raw)
+            .unwrap_or_else(|error| panic!("invalid DMBO_ROUTE_LIMITS {path}: {error}"));
+        for limit in &mut limits {
+            assert!(
+                limit.route_rps != Some(0) && limit.concurrency != Some(0),
+                "invalid DMBO_ROUTE_LIMITS {path}: {} needs route_rps and concurrency of at least 1",
+                limit.route
+            );
+            assert!(
+                (1..=MAX_WINDOW_MS).contains(&limit.window_ms),
+                "invalid DMBO_ROUTE_LIMITS {path}: {} needs window_ms from 1 to {MAX_WINDOW_MS}",
+                limit.route
+            );
+            limit.route = routes::normalize_route(&limit.route).template;
+            if let Some(method) = &mut limit.method {
+                *method = method.to_ascii_uppercase();
+            }
+        }
+        Self {
+            limits,
+            lease_ms,
+            max_inflight,
+            concurrency_denials: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether any route bucket can run out of concurrency slots.
+    pub(crate) fn caps_concurrency(&self) -> bool {
+        self.max_inflight.is_some() || self.limits.iter().any(|limit| limit.concurrency.is_some())
+    }
+
+    /// Configured limits, for `/admin/config`.
+    pub(crate) fn describe(&self) -> serde_json::Value {
+        serde_json::json!(self.limits)
+    }
+
+    /// Permits denied for want of a concurrency slot since startup.
+    pub(crate) fn concurrency_denials(&self) -> u64 {
+        self.concurrency_denials.load(Ordering::Relaxed)
+    }
+
+    fn find(&self, method: &str, route: &str) -> Option<&RouteLimit> {
+        self.limits.iter().find(|limit| {
+            limit
+                .method
+                .as_deref()
+                .is_none_or(|limit_method| limit_method == method)
+                && route_matches(&limit.route, route)
+        })
+    }
+
+    /// Calls allowed in flight per bucket: the matching entry's
+    /// `concurrency`, else `DMBO_ROUTE_MAX_INFLIGHT`.
+    fn concurrency(&self, method: &str, route: &str) -> Option<u64> {
+        self.find(method, route)
+            .and_then(|limit| limit.concurrency)
+            .or(self.max_inflight)
+    }
+
+    /// The route limit and its window for `request`, `base_rps` per second
+    /// unless an entry matches.
+    pub(crate) fn route_limit(&self, request: &RequestTokenRequest, base_rps: u64) -> (u64, u64) {
+        match self.find(&request.method, &request.route) {
+            Some(limit) => (limit.route_rps.unwrap_or(base_rps), limit.window_ms),
+            None => (base_rps, 1000),
+        }
+    }
+
+    /// Takes a concurrency slot on `route_bucket` when its route is capped.
+    /// Returns `None` when it is not.
+    pub(crate) async fn acquire(
+        &self,
+        conn: &mut MultiplexedConnection,
+        request: &RequestTokenRequest,
+        route_bucket: &str,
+        now_ms: u64,
+    ) -> redis::RedisResult<Option<SlotAcquired>> {
+        let Some(cap) = self.concurrency(&request.method, &request.route) else {
+            return Ok(None);
+        };
+        let key = slot_key(route_bucket);
+        let member = if request.request_id.is_empty() {
+            uuid::Uuid::new_v4().to_string()
+        } else {
+            request.request_id.clone()
+        };
+        let (granted, retry_after_ms): (i64, i64) = Script::new(ACQUIRE_LUA)
+            .key(&key)
+            .arg(now_ms)
+            .arg(cap)
+            .arg(self.lease_ms)
+            .arg(&member)
+            .invoke_async(conn)
+            .await?;
+        if granted == 1 {
+            return Ok(Some(SlotAcquired::Reserved(Slot { key, member })));
+        }
+        self.concurrency_denials.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(SlotAcquired::Exhausted {
+            retry_after_ms: retry_after_ms.max(0) as u64,
+        }))
+    }
+
+    /// Frees the slot held by a finished request, if its route has one.
+    pub(crate) async fn finish(
+        &self,
+        conn: &mut MultiplexedConnection,
+        request_id: &str,
+        method: &str,
+        route: &str,
+        route_bucket: &str,
+    ) -> redis::RedisResult<()> {
+        if self.concurrency(method, route).is_none() || request_id.is_empty() {
+            return Ok(());
+        }
+        conn.zrem(slot_key(route_bucket), request_id).await
+    }
+}
+
+impl Slot {
+    /// Gives the slot back after a later check denied the permit.
+    pub(crate) async fn release(self, conn: &mut MultiplexedConnection) {
+        let _ = conn.zrem::<_, _, i64>(&self.key, &self.member).await;
+    }
+}
@@ -0,0 +1,219 @@
+//! Declarative admission rules loaded from the JSON file named by
+//! `DMBO_RULES`. Each rule matches on request fields and UTC time and carries
+//! actions (deny, force priority, override limits, tag). Rules are evaluated
+//! in file order before the limiter: a matching `deny` stops evaluation,
+//! other actions accumulate with later rules overriding earlier ones.
+
+use crate::{routes, RequestTokenRequest};
+use serde::Deserialize;
+
+const DAY_MS: u64 = 86_400_000;
+const HOUR_MS: u64 = 3_600_000;
+pub(crate) const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    name: String,
+    #[serde(default, rename = "match")]
+    matcher: Matcher,
+    #[serde(default)]
+    action: Action,
+}
+
+/// All present fields must match. String fields accept `*` wildcards;
+/// `route` is matched segment by segment against the route template, where
+/// `*` is one segment and `**` any number of segments.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Matcher {
+    client_id: Option<String>,
+    group_id: Option<String>,
+    discord_identity: Option<String>,
+    method: Option<String>,
+    route: Option<String>,
+    /// `[from, to)` hours in UTC; wraps past midnight when `from > to`.
+    utc_hours: Option<(u8, u8)>,
+    /// Three-letter lowercase weekday names in UTC.
+    utc_days: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Action {
+    #[serde(default)]
+    deny: bool,
+    priority: Option<String>,
+    global_rps: Option<u64>,
+    route_rps: Option<u64>,
+    tag: Option<String>,
+}
+
+/// Combined effect of the rules matching one request.
+#[derive(Debug, Default)]
+pub(crate) struct RuleOutcome {
+    /// Name of the rule that denied the request.
+    pub(crate) denied_by: Option<String>,
+    priority: Option<String>,
+    global_rps: Option<u64>,
+    route_rps: Option<u64>,
+    tags: Vec<String>,
+}
+
+impl RuleOutcome {
+    pub(crate) fn apply(&self, request: &mut RequestTokenRequest) {
+        if let Some(priority) = &self.priority {
+            request.priority = priority.clone();
+        }
+        if let Some(rps) = self.global_rps {
+            request.global_rps_override = Some(rps);
+        }
+        if let Some(rps) = self.route_rps {
+            request.route_rps_override = Some(rps);
+        }
+        request.tags.extend(self.tags.iter().cloned());
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Loads `DMBO_RULES`. A missing or invalid file aborts startup rather
+    /// than silently admitting traffic the operator meant to restrict.
+    pub(crate) fn load(path: Option<&str>) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+        let raw = std::fs::read_to_string(path)
+            .unwrap_or_else(|error| panic!("failed to read DMBO_RULES {path}: {error}"));
+        let mut rules: Vec<Rule> = serde_json::from_str(&raw)
+            .unwrap_or_else(|error| panic!("invalid DMBO_RULES {path}: {error}"));
+        for rule in &mut rules {
+            if let Some(route) = &mut rule.matcher.route {
+                *route = routes::normalize_route(route).template;
+            }
+            if let Some(method) = &mut rule.matcher.method {
+                *method = method.to_ascii_uppercase();
+            }
+            if let Some((from, to)) = rule.matcher.utc_hours {
+                assert!(
+                    from < 24 && to <= 24,
+                    "invalid DMBO_RULES {path}: rule {} has utc_hours outside 0-24",
+                    rule.name
+                );
+            }
+            if let Some(days) = &rule.matcher.utc_days {
+                if let Some(day) = days.iter().find(|day| !WEEKDAYS.contains(&day.as_str())) {
+                    panic!(
+                        "invalid DMBO_RULES {path}: rule {} has unknown day {day}",
+                        rule.name
+                    );
+                }
+            }
+        }
+        Self { rules }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub(crate) fn evaluate(&self, request: &RequestTokenRequest, now_ms: u64) -> RuleOutcome {
+        let mut outcome = RuleOutcome::default();
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| rule.matcher.matches(request, now_ms))
+        {
+            let action = &rule.action;
+            if let Some(tag) = &action.tag {
+                outcome.tags.push(tag.clone());
+            }
+            if action.deny {
+                outcome.denied_by = Some(rule.name.clone());
+                break;
+            }
+            outcome.priority = action.priority.clone().or(outcome.priority);
+            outcome.global_rps = action.global_rps.or(outcome.global_rps);
+            outcome.route_rps = action.route_rps.or(outcome.route_rps);
+        }
+        outcome
+    }
+}
+
+impl Matcher {
+    fn matches(&self, request: &RequestTokenRequest, now_ms: u64) -> bool {
+        let field = |pattern: &Option<String>, value: &str| {
+            pattern
+                .as_deref()
+                .is_none_or(|pattern| wildcard_match(pattern, value))
+        };
+        if !field(&self.client_id, &request.client_id)
+            || !field(&self.group_id, &request.group_id)
+            || !field(&self.discord_identity, &request.discord_identity)
+            || !field(&self.method, &request.method)
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.route {
+            if !route_matches(pattern, &request.route) {
+                return false;
+            }
+        }
+        if let Some((from, to)) = self.utc_hours {
+            let hour = (now_ms % DAY_MS / HOUR_MS) as u8;
+            let within = if from <= to {
+                hour >= from && hour < to
+            } else {
+                hour >= from || hour < to
+            };
+            if !within {
+                return false;
+            }
+        }
+        if let Some(days) = &self.utc_days {
+            // 1970-01-01 was a Thursday.
+            let today = WEEKDAYS[((now_ms / DAY_MS + 4) % 7) as usize];
+            if !days.iter().any(|day| day == today) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// `*` matches any run of characters.
+pub(crate) fn wildcard_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => {
+            let Some(value) = value.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=value.len())
+                .filter(|index| value.is_char_boundary(*index))
+                .any(|index| wildcard_match(rest, &value[index..]))
+        }
+    }
+}
+
+/// Matches a route template against a normalized pattern where `*` is one
+/// segment and `**` any number of segments.
+pub(crate) fn route_matches(pattern: &str, route: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let route: Vec<&str> = route.split('/').filter(|s| !s.is_empty()).collect();
+    segments_match(&pattern, &route)
+}
+
+fn segments_match(pattern: &[&str], route: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => route.is_empty(),
+        Some((&"**", rest)) => (0..=route.len()).any(|skip| segments_match(rest, &route[skip..])),
+        Some((segment, rest)) => route.split_first().is_some_and(|(first, route_rest)| {
+            wildcard_match(segment, first) && segments_match(rest, route_rest)
+        }),
+    }
+}
@@ -0,0 +1,125 @@
+//! Time-of-day quota calendar loaded from the JSON file named by
+//! `DMBO_SCHEDULE`. Each window replaces `DMBO_GLOBAL_RPS` /
+//! `DMBO_ROUTE_RPS` while it is active; the first matching window wins and
+//! outside every window the configured limits apply. Windows are evaluated on
+//! every decision, so no restart or background task is needed at boundaries.
+
+use crate::rules::WEEKDAYS;
+use serde::{Deserialize, Serialize};
+
+const DAY_MS: u64 = 86_400_000;
+const MINUTE_MS: u64 = 60_000;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Schedule {
+    /// Offset of the calendar's local time from UTC, e.g. `-300` for UTC-5.
+    #[serde(default)]
+    utc_offset_minutes: i32,
+    #[serde(default)]
+    windows: Vec<Window>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Window {
+    pub(crate) name: String,
+    /// Local `HH:MM`, inclusive.
+    from: String,
+    /// Local `HH:MM`, exclusive; a window wraps past midnight when `to` is
+    /// not after `from`.
+    to: String,
+    /// Three-letter lowercase weekday names, matched on the day the window
+    /// starts; every day when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    days: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) global_rps: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) route_rps: Option<u64>,
+    #[serde(skip)]
+    from_minute: u32,
+    #[serde(skip)]
+    to_minute: u32,
+}
+
+impl Schedule {
+    /// Loads `DMBO_SCHEDULE`. A missing or invalid file aborts startup.
+    pub(crate) fn load(path: Option<&str>) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+        let raw = std::fs::read_to_string(path)
+            .unwrap_or_else(|error| panic!("failed to read DMBO_SCHEDULE {path}: {error}"));
+        let mut schedule: Schedule = serde_json::from_str(&raw)
+            .unwrap_or_else(|error| panic!("invalid DMBO_SCHEDULE {path}: {error}"));
+        for window in &mut schedule.windows {
+            let parse = |value: &str| {
+                parse_minute(value).unwrap_or_else(|| {
+                    panic!(
+                        "invalid DMBO_SCHEDULE {path}: window {} has time {value}, expected HH:MM",
+                        window.name
+                    )
+                })
+            };
+            window.from_minute = parse(&window.from);
+            window.to_minute = parse(&window.to);
+            if let Some(day) = window
+                .days
+                .iter()
+                .flatten()
+                .find(|day| !WEEKDAYS.contains(&day.as_str()))
+            {
+                panic!(
+                    "invalid DMBO_SCHEDULE {path}: window {} has unknown day {day}",
+                    window.name
+                );
+            }
+        }
+        schedule
+    }
+
+    pub(crate) fn windows(&self) -> &[Window] {
+        &self.windows
+    }
+
+    pub(crate) fn utc_offset_minutes(&self) -> i32 {
+        self.utc_offset_minutes
+    }
+
+    /// The window in force at `now_ms`, if any.
+    pub(crate) fn active(&self, now_ms: u64) -> Option<&Window> {
+        let local_ms =
+            now_ms.saturating_add_signed(i64::from(self.utc_offset_minutes) * MINUTE_MS as i64);
+        let minute = (local_ms % DAY_MS / MINUTE_MS) as u32;
+        // 1970-01-01 was a Thursday.
+        let day = ((local_ms / DAY_MS + 4) % 7) as usize;
+        self.windows
+            .iter()
+            .find(|window| window.contains(minute, day))
+    }
+}
+
+impl Window {
+    fn contains(&self, minute: u32, day: usize) -> bool {
+        let (within, start_day) = if self.from_minute < self.to_minute {
+            (minute >= self.from_minute && minute < self.to_minute, day)
+        } else if minute >= self.from_minute {
+            (true, day)
+        } else {
+            // Early-morning part of a window that began the previous day.
+            (minute < self.to_minute, (day + 6) % 7)
+        };
+        within
+            && self
+                .days
+                .as_ref()
+                .is_none_or(|days| days.iter().any(|name| name == WEEKDAYS[start_day]))
+    }
+}
+
+fn parse_minute(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
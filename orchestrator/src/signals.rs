@@ -0,0 +1,387 @@
+//! Guardrail and bucket state changes streamed on `GET /events`, so clients
+//! can pause their send loops before their next denial tells them to.
+//!
+//! A guardrail trip or a limiter denial for an exhausted bucket announces
+//! the guard or bucket as blocked until it is expected to clear, and once
+//! that time passes the instance that announced it announces it released
+//! (a guard extended by later invalid responses stays blocked). Every
+//! announcement is also published on the `rl:signals` channel, so
+//! subscribers of any replica see all of them, and a replica does not
+//! repeat what another one already announced.
+
+use crate::{maintenance::MaintenanceEvent, unix_ms, AppState, RequestTokenRequest};
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use dmbo_core::{keys::bucket_id, Reason};
+use futures_util::{Stream, StreamExt};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::broadcast,
+    time::{interval, sleep},
+};
+
+const CHANNEL: &str = "rl:signals";
+const EVENT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Signal {
+    /// `guardrail_activated`, `guardrail_released`, `bucket_exhausted` or
+    /// `bucket_replenished`.
+    kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    discord_identity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    route: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    major_parameter: Option<String>,
+    /// The denial reason that exhausted the bucket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// When the guard or bucket is expected to clear; only on activations
+    /// and exhaustions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    until_unix_ms: Option<u64>,
+    unix_ms: u64,
+}
+
+impl Signal {
+    /// SSE event name: `guardrail` or `bucket`.
+    fn event_name(&self) -> &'static str {
+        if self.kind.starts_with("guardrail") {
+            "guardrail"
+        } else {
+            "bucket"
+        }
+    }
+
+    /// The signal that ends this one.
+    fn released(&self, now_ms: u64) -> Signal {
+        let kind = if self.kind.starts_with("guardrail") {
+            "guardrail_released"
+        } else {
+            "bucket_replenished"
+        };
+        Signal {
+            kind: kind.to_string(),
+            reason: None,
+            until_unix_ms: None,
+            unix_ms: now_ms,
+            ..self.clone()
+        }
+    }
+
+    fn subject(&self) -> String {
+        match &self.group_id {
+            Some(group) => format!("guard:{group}"),
+            None => format!(
+                "bucket:{}",
+                bucket_id(
+                    self.discord_identity.as_deref().unwrap_or_default(),
+                    self.method.as_deref().unwrap_or_default(),
+                    self.route.as_deref().unwrap_or_default(),
+                    self.major_parameter.as_deref().unwrap_or_default(),
+                )
+            ),
+        }
+    }
+}
+
+/// A guard or bucket announced as blocked.
+struct Blocked {
+    signal: Signal,
+    until_unix_ms: u64,
+    /// Announced by this instance, which then also announces the release.
+    local: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    instance_id: String,
+    signal: Signal,
+}
+
+pub(crate) struct Signals {
+    events: broadcast::Sender<Signal>,
+    blocked: Mutex<HashMap<String, Blocked>>,
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self {
+            events: broadcast::channel(EVENT_BUFFER).0,
+            blocked: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Signals {
+    /// Records a block; returns whether it is new rather than an extension
+    /// of one already announced.
+    fn block(&self, signal: &Signal, until_unix_ms: u64, local: bool) -> bool {
+        let mut blocked = self.blocked.lock().expect("signal registry poisoned");
+        if let Some(entry) = blocked.get_mut(&signal.subject()) {
+            entry.until_unix_ms = entry.until_unix_ms.max(until_unix_ms);
+            return false;
+        }
+        blocked.insert(
+            signal.subject(),
+            Blocked {
+                signal: signal.clone(),
+                until_unix_ms,
+                local,
+            },
+        );
+        true
+    }
+
+    fn unblock(&self, subject: &str) -> Option<Blocked> {
+        self.blocked
+            .lock()
+            .expect("signal registry poisoned")
+            .remove(subject)
+    }
+
+    /// Current blocks, sent to a client when it subscribes.
+    fn snapshot(&self) -> Vec<Signal> {
+        self.blocked
+            .lock()
+            .expect("signal registry poisoned")
+            .values()
+            .map(|entry| Signal {
+                until_unix_ms: Some(entry.until_unix_ms),
+                ..entry.signal.clone()
+            })
+            .collect()
+    }
+
+    /// Removes the blocks due by `now_ms`; returns the local ones, whose
+    /// release this instance announces.
+    fn take_due(&self, now_ms: u64) -> Vec<Blocked> {
+        let mut blocked = self.blocked.lock().expect("signal registry poisoned");
+        let due: Vec<String> = blocked
+            .iter()
+            .filter(|(_, entry)| entry.until_unix_ms <= now_ms)
+            .map(|(subject, _)| subject.clone())
+            .collect();
+        due.into_iter()
+            .filter_map(|subject| blocked.remove(&subject))
+            .filter(|entry| entry.local)
+            .collect()
+    }
+}
+
+/// The guardrail of `group` is active until `until_unix_ms`.
+pub(crate) fn guardrail_activated(state: &AppState, group: &str, until_unix_ms: u64) {
+    let signal = Signal {
+        kind: "guardrail_activated".to_string(),
+        group_id: Some(group.to_string()),
+        discord_identity: None,
+        method: None,
+        route: None,
+        major_parameter: None,
+        reason: None,
+        until_unix_ms: Some(until_unix_ms),
+        unix_ms: unix_ms(),
+    };
+    if state.signals.block(&signal, until_unix_ms, true) {
+        emit(state, signal);
+    }
+}
+
+/// An operator lifted the guardrail of `group`.
+pub(crate) fn guardrail_released(state: &AppState, group: &str) {
+    let subject = format!("guard:{group}");
+    let released = match state.signals.unblock(&subject) {
+        Some(entry) => entry.signal.released(unix_ms()),
+        None => Signal {
+            kind: "guardrail_released".to_string(),
+            group_id: Some(group.to_string()),
+            discord_identity: None,
+            method: None,
+            route: None,
+            major_parameter: None,
+            reason: None,
+            until_unix_ms: None,
+            unix_ms: unix_ms(),
+        },
+    };
+    emit(state, released);
+}
+
+/// Notes a limiter denial; exhausted buckets are announced until the
+/// denial's retry hint runs out.
+pub(crate) fn observe_denial(
+    state: &AppState,
+    request: &RequestTokenRequest,
+    reason: Reason,
+    retry_after_ms: u64,
+) {
+    if !matches!(
+        reason,
+        Reason::RouteBucketExhausted | Reason::DiscordBucketExhausted
+    ) {
+        return;
+    }
+    let now = unix_ms();
+    let until_unix_ms = now.saturating_add(retry_after_ms);
+    let signal = Signal {
+        kind: "bucket_exhausted".to_string(),
+        group_id: None,
+        discord_identity: Some(request.discord_identity.clone()),
+        method: Some(request.method.clone()),
+        route: Some(request.route.clone()),
+        major_parameter: Some(request.major_parameter.clone()),
+        reason: Some(reason.code().to_string()),
+        until_unix_ms: Some(until_unix_ms),
+        unix_ms: now,
+    };
+    if state.signals.block(&signal, until_unix_ms, true) {
+        emit(state, signal);
+    }
+}
+
+/// Sends `signal` to this instance's subscribers and the other replicas.
+fn emit(state: &AppState, signal: Signal) {
+    state.metrics.signals_sent.fetch_add(1, Ordering::Relaxed);
+    // Sending only fails when nobody is subscribed.
+    let _ = state.signals.events.send(signal.clone());
+    if state.config.memory_store {
+        return;
+    }
+    let envelope = Envelope {
+        instance_id: state.config.instance_id.clone(),
+        signal,
+    };
+    let redis = state.redis.clone();
+    tokio::spawn(async move {
+        if let (Ok(mut conn), Ok(message)) = (
+            redis.get_multiplexed_async_connection().await,
+            serde_json::to_string(&envelope),
+        ) {
+            let _ = conn.publish::<_, _, i64>(CHANNEL, message).await;
+        }
+    });
+}
+
+/// Background task: announces the releases of local blocks once due.
+pub(crate) async fn release_due(state: Arc<AppState>) {
+    let mut ticker = interval(Duration::from_millis(250));
+    loop {
+        ticker.tick().await;
+        let now = unix_ms();
+        for entry in state.signals.take_due(now) {
+            // A guard re-armed by later invalid responses is still active.
+            if let Some(group) = &entry.signal.group_id {
+                if let Ok(Some(remaining_ms)) = state.store.guard_ttl_ms(group).await {
+                    state.signals.block(&entry.signal, now + remaining_ms, true);
+                    continue;
+                }
+            }
+            emit(&state, entry.signal.released(now));
+        }
+    }
+}
+
+/// Background task: forwards the other replicas' signals to this
+/// instance's subscribers, resubscribing after connection losses.
+pub(crate) async fn relay(state: Arc<AppState>) {
+    loop {
+        if let Ok(mut pubsub) = state.redis.get_async_pubsub().await {
+            if pubsub.subscribe(CHANNEL).await.is_ok() {
+                let mut messages = pubsub.on_message();
+                while let Some(message) = messages.next().await {
+                    let Ok(payload) = message.get_payload::<String>() else {
+                        continue;
+                    };
+                    let Ok(envelope) = serde_json::from_str::<Envelope>(&payload) else {
+                        continue;
+                    };
+                    if envelope.instance_id == state.config.instance_id {
+                        continue;
+                    }
+                    let signal = envelope.signal;
+                    match signal.until_unix_ms {
+                        Some(until_unix_ms) => {
+                            state.signals.block(&signal, until_unix_ms, false);
+                        }
+                        None => {
+                            state.signals.unblock(&signal.subject());
+                        }
+                    }
+                    let _ = state.signals.events.send(signal);
+                }
+            }
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Item of the `GET /events` stream.
+enum Item {
+    Maintenance(MaintenanceEvent),
+    Signal(Signal),
+}
+
+impl Item {
+    fn event(&self) -> Event {
+        match self {
+            Item::Maintenance(event) => Event::default().event("maintenance").json_data(event),
+            Item::Signal(signal) => Event::default()
+                .event(signal.event_name())
+                .json_data(signal),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// `GET /events`: server-sent maintenance, guardrail and bucket events.
+/// Subscribers first receive the current maintenance windows and blocked
+/// guards and buckets, then changes as they happen.
+pub(crate) async fn events(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let maintenance = state.maintenance.subscribe();
+    let signals = state.signals.events.subscribe();
+    let snapshot: Vec<Item> = state
+        .maintenance
+        .snapshot()
+        .into_iter()
+        .map(Item::Maintenance)
+        .chain(state.signals.snapshot().into_iter().map(Item::Signal))
+        .collect();
+    let stream = futures_util::stream::unfold(
+        (snapshot.into_iter(), maintenance, signals),
+        |(mut snapshot, mut maintenance, mut signals)| async move {
+            let item = match snapshot.next() {
+                Some(item) => item,
+                None => loop {
+                    let received = tokio::select! {
+                        event = maintenance.recv() => event.map(Item::Maintenance),
+                        signal = signals.recv() => signal.map(Item::Signal),
+                    };
+                    match received {
+                        Ok(item) => break item,
+                        // A slow subscriber skips what it missed.
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                },
+            };
+            Some((Ok(item.event()), (snapshot, maintenance, signals)))
+        },
+    );
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,349 @@
+//! Optional relational history in Postgres or SQLite (`DMBO_SQL_URL`,
+//! requires the `sql` build feature), for operators who need audit records
+//! and usage kept longer than suits Redis. Decision audit records and
+//! guardrail trips are queued and written in batches; each replica also
+//! copies the previous hour's per-caller usage rollup once the hour has been
+//! flushed. Rows older than `DMBO_SQL_AUDIT_RETENTION_DAYS` /
+//! `DMBO_SQL_USAGE_RETENTION_DAYS` are deleted hourly. A slow or unreachable
+//! database drops records rather than delaying permits.
+
+use crate::{AppState, Config};
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc;
+
+/// Records buffered while the database is slow before new ones are dropped.
+const CHANNEL_CAPACITY: usize = 10_000;
+
+#[cfg_attr(not(feature = "sql"), allow(dead_code))]
+enum Record {
+    /// `record_decision_audit` fields, in its order.
+    Audit(Vec<(&'static str, String)>),
+    GuardTrip {
+        unix_ms: u64,
+        group_id: String,
+        invalid_count: i64,
+    },
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct SqlSink {
+    sender: Option<mpsc::Sender<Record>>,
+}
+
+pub(crate) struct SqlTask {
+    #[cfg_attr(not(feature = "sql"), allow(dead_code))]
+    receiver: mpsc::Receiver<Record>,
+}
+
+impl SqlSink {
+    /// Creates the sink when `DMBO_SQL_URL` is set. Setting it on a build
+    /// without the `sql` feature aborts startup.
+    pub(crate) fn start(config: &Config) -> (Self, Option<SqlTask>) {
+        #[cfg(not(feature = "sql"))]
+        if config.sql_url.is_some() {
+            panic!("DMBO_SQL_URL is set but the orchestrator was built without the sql feature");
+        }
+        if config.sql_url.is_none() {
+            return (Self::default(), None);
+        }
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        (
+            Self {
+                sender: Some(sender),
+            },
+            Some(SqlTask { receiver }),
+        )
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    fn send(&self, state: &AppState, record: Record) {
+        if let Some(sender) = &self.sender {
+            if sender.try_send(record).is_err() {
+                state
+                    .metrics
+                    .sql_records_dropped
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub(crate) fn audit(&self, state: &AppState, fields: &[(&'static str, String)]) {
+        if self.enabled() {
+            self.send(state, Record::Audit(fields.to_vec()));
+        }
+    }
+
+    pub(crate) fn guard_trip(
+        &self,
+        state: &AppState,
+        unix_ms: u64,
+        group_id: &str,
+        invalid_count: i64,
+    ) {
+        if self.enabled() {
+            self.send(
+                state,
+                Record::GuardTrip {
+                    unix_ms,
+                    group_id: group_id.to_string(),
+                    invalid_count,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(not(feature = "sql"))]
+pub(crate) async fn run(_state: std::sync::Arc<AppState>, _task: SqlTask) {}
+
+#[cfg(feature = "sql")]
+pub(crate) use writer::run;
+
+#[cfg(feature = "sql")]
+mod writer {
+    use super::{Record, SqlTask};
+    use crate::{stats, unix_ms, AppState};
+    use sqlx::{any::AnyPoolOptions, AnyPool};
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        time::Duration,
+    };
+    use tokio::time::{interval, timeout};
+
+    const HOUR_MS: u64 = 3_600_000;
+    const DAY_MS: u64 = 86_400_000;
+    const BATCH_SIZE: usize = 500;
+    const BATCH_WAIT_MS: u64 = 1_000;
+    /// Usage rollups are copied this long after their hour ends, once every
+    /// replica has flushed into them.
+    const USAGE_COPY_DELAY_MS: u64 = 60_000;
+
+    /// Statements run at startup. Portable between Postgres and SQLite.
+    const SCHEMA: [&str; 6] = [
+        "CREATE TABLE IF NOT EXISTS dmbo_audit (
+            unix_ms BIGINT NOT NULL,
+            instance_id TEXT NOT NULL,
+            request_id TEXT NOT NULL,
+            client_id TEXT NOT NULL,
+            group_id TEXT NOT NULL,
+            discord_identity TEXT NOT NULL,
+            method TEXT NOT NULL,
+            route TEXT NOT NULL,
+            major_parameter TEXT NOT NULL,
+            granted BOOLEAN NOT NULL,
+            reason TEXT NOT NULL,
+            tags TEXT NOT NULL,
+            feature TEXT NOT NULL,
+            trace_id TEXT NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS dmbo_audit_unix_ms ON dmbo_audit (unix_ms)",
+        "CREATE TABLE IF NOT EXISTS dmbo_guard_trips (
+            unix_ms BIGINT NOT NULL,
+            instance_id TEXT NOT NULL,
+            group_id TEXT NOT NULL,
+            invalid_count BIGINT NOT NULL,
+            cooldown_ms BIGINT NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS dmbo_guard_trips_unix_ms ON dmbo_guard_trips (unix_ms)",
+        "CREATE TABLE IF NOT EXISTS dmbo_usage_hourly (
+            hour_start_unix_ms BIGINT NOT NULL,
+            client_id TEXT NOT NULL,
+            discord_identity TEXT NOT NULL,
+            grants BIGINT NOT NULL,
+            denials BIGINT NOT NULL,
+            errors BIGINT NOT NULL,
+            reports BIGINT NOT NULL,
+            observed_429 BIGINT NOT NULL,
+            invalid BIGINT NOT NULL,
+            wait_ms BIGINT NOT NULL,
+            PRIMARY KEY (hour_start_unix_ms, client_id, discord_identity)
+        )",
+        "CREATE INDEX IF NOT EXISTS dmbo_usage_hourly_caller
+            ON dmbo_usage_hourly (client_id, discord_identity)",
+    ];
+
+    const INSERT_AUDIT: &str = "INSERT INTO dmbo_audit (unix_ms, instance_id, request_id,
+        client_id, group_id, discord_identity, method, route, major_parameter, granted,
+        reason, tags, feature, trace_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)";
+    const INSERT_GUARD_TRIP: &str = "INSERT INTO dmbo_guard_trips (unix_ms, instance_id,
+        group_id, invalid_count, cooldown_ms) VALUES ($1, $2, $3, $4, $5)";
+    /// Rollups hold totals, so copying an hour again overwrites it.
+    const UPSERT_USAGE: &str = "INSERT INTO dmbo_usage_hourly (hour_start_unix_ms, client_id,
+        discord_identity, grants, denials, errors, reports, observed_429, invalid, wait_ms)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        ON CONFLICT (hour_start_unix_ms, client_id, discord_identity) DO UPDATE SET
+        grants = excluded.grants, denials = excluded.denials, errors = excluded.errors,
+        reports = excluded.reports, observed_429 = excluded.observed_429,
+        invalid = excluded.invalid, wait_ms = excluded.wait_ms";
+
+    /// Connects, creates the schema and writes queued records in batches of
+    /// up to `BATCH_SIZE`, alongside the hourly usage copy and retention.
+    pub(crate) async fn run(state: Arc<AppState>, task: SqlTask) {
+        let Some(url) = state.config.sql_url.clone() else {
+            return;
+        };
+        sqlx::any::install_default_drivers();
+        let pool = match connect(&url).await {
+            Ok(pool) => pool,
+            Err(error) => {
+                eprintln!("SQL persistence disabled: {error}");
+                return;
+            }
+        };
+        tokio::spawn(maintain(state.clone(), pool.clone()));
+
+        let mut receiver = task.receiver;
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while let Some(record) = receiver.recv().await {
+            batch.push(record);
+            while batch.len() < BATCH_SIZE {
+                match timeout(Duration::from_millis(BATCH_WAIT_MS), receiver.recv()).await {
+                    Ok(Some(record)) => batch.push(record),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            let written = batch.len() as u64;
+            let counter = if write_batch(&state, &pool, &batch).await.is_ok() {
+                &state.metrics.sql_records_written
+            } else {
+                &state.metrics.sql_records_failed
+            };
+            counter.fetch_add(written, Ordering::Relaxed);
+            batch.clear();
+        }
+    }
+
+    async fn connect(url: &str) -> Result<AnyPool, sqlx::Error> {
+        let pool = AnyPoolOptions::new()
+            .max_connections(4)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect(url)
+            .await?;
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        Ok(pool)
+    }
+
+    async fn write_batch(
+        state: &AppState,
+        pool: &AnyPool,
+        batch: &[Record],
+    ) -> Result<(), sqlx::Error> {
+        let instance_id = state.config.instance_id.as_str();
+        let mut tx = pool.begin().await?;
+        for record in batch {
+            match record {
+                Record::Audit(fields) => {
+                    let field = |name: &str| {
+                        fields
+                            .iter()
+                            .find(|(key, _)| *key == name)
+                            .map(|(_, value)| value.as_str())
+                            .unwrap_or_default()
+                    };
+                    sqlx::query(INSERT_AUDIT)
+                        .bind(field("unix_ms").parse::<i64>().unwrap_or_default())
+                        .bind(instance_id)
+                        .bind(field("request_id"))
+                        .bind(field("client_id"))
+                        .bind(field("group_id"))
+                        .bind(field("discord_identity"))
+                        .bind(field("method"))
+                        .bind(field("route"))
+                        .bind(field("major_parameter"))
+                        .bind(field("granted") == "true")
+                        .bind(field("reason"))
+                        .bind(field("tags"))
+                        .bind(field("feature"))
+                        .bind(field("trace_id"))
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                Record::GuardTrip {
+                    unix_ms,
+                    group_id,
+                    invalid_count,
+                } => {
+                    sqlx::query(INSERT_GUARD_TRIP)
+                        .bind(*unix_ms as i64)
+                        .bind(instance_id)
+                        .bind(group_id.as_str())
+                        .bind(*invalid_count)
+                        .bind(state.config.guardrail_cooldown_ms as i64)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+        }
+        tx.commit().await
+    }
+
+    /// Copies each finished hour's usage rollup and applies retention.
+    async fn maintain(state: Arc<AppState>, pool: AnyPool) {
+        let mut ticker = interval(Duration::from_millis(USAGE_COPY_DELAY_MS));
+        let mut copied_hour = 0;
+        let mut pruned_hour = 0;
+        loop {
+            ticker.tick().await;
+            let now = unix_ms();
+            let hour = now.saturating_sub(USAGE_COPY_DELAY_MS) / HOUR_MS * HOUR_MS;
+            let previous = hour.saturating_sub(HOUR_MS);
+            if copied_hour < previous && copy_usage(&state, &pool, previous).await.is_ok() {
+                copied_hour = previous;
+            }
+            if pruned_hour < hour && prune(&state, &pool, now).await.is_ok() {
+                pruned_hour = hour;
+            }
+        }
+    }
+
+    async fn copy_usage(state: &AppState, pool: &AnyPool, hour: u64) -> Result<(), sqlx::Error> {
+        let rows = stats::usage_rows(state, hour).await;
+        let mut tx = pool.begin().await?;
+        for row in &rows {
+            let text = |name: &str| row[name].as_str().unwrap_or_default().to_string();
+            let count = |name: &str| row[name].as_u64().unwrap_or_default() as i64;
+            sqlx::query(UPSERT_USAGE)
+                .bind(hour as i64)
+                .bind(text("client_id"))
+                .bind(text("discord_identity"))
+                .bind(count("grants"))
+                .bind(count("denials"))
+                .bind(count("errors"))
+                .bind(count("reports"))
+                .bind(count("observed_429"))
+                .bind(count("invalid"))
+                .bind(count("wait_ms"))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn prune(state: &AppState, pool: &AnyPool, now: u64) -> Result<(), sqlx::Error> {
+        let audit_cutoff = now.saturating_sub(state.config.sql_audit_retention_days * DAY_MS);
+        let usage_cutoff = now.saturating_sub(state.config.sql_usage_retention_days * DAY_MS);
+        for (statement, cutoff) in [
+            ("DELETE FROM dmbo_audit WHERE unix_ms < $1", audit_cutoff),
+            (
+                "DELETE FROM dmbo_guard_trips WHERE unix_ms < $1",
+                audit_cutoff,
+            ),
+            (
+                "DELETE FROM dmbo_usage_hourly WHERE hour_start_unix_ms < $1",
+                usage_cutoff,
+            ),
+        ] {
+            sqlx::query(statement)
+                .bind(cutoff as i64)
+                .execute(pool)
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,769 @@
+//! Historical usage rollups. Decisions and reports are counted in memory per
+//! minute and flushed every few seconds into per-minute and per-hour Redis
+//! hashes with `HINCRBY`, so every replica adds into the same rollups. Wait
+//! times are kept as fixed histogram buckets, which merge across replicas and
+//! across minutes; percentiles are derived from them when queried.
+//!
+//! Counters are also kept per caller (`client_id`, `discord_identity`) at hour
+//! resolution for `GET /admin/export/usage`, and, when `DMBO_FEATURES` is set,
+//! per feature tag and route for `GET /stats/features`.
+
+use crate::{
+    normalize_key_part, routes, unix_ms, validation_failed_response, AppState, FieldError,
+    ReportResultRequest, RequestTokenRequest,
+};
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::interval;
+
+const MINUTE_MS: u64 = 60_000;
+const HOUR_MS: u64 = 3_600_000;
+const FLUSH_INTERVAL_MS: u64 = 10_000;
+/// Upper bounds (ms) of the wait-time histogram buckets; waits above the last
+/// bound land in an overflow bucket.
+const WAIT_BUCKETS_MS: [u64; 14] = [
+    0, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10_000, 30_000, 60_000,
+];
+const COUNTERS: [&str; 6] = [
+    "grants",
+    "denials",
+    "errors",
+    "observed_429",
+    "invalid",
+    "reports",
+];
+
+/// Columns of the per-caller usage export, after the hour and caller columns.
+const USAGE_COLUMNS: [&str; 7] = [
+    "grants",
+    "denials",
+    "errors",
+    "reports",
+    "observed_429",
+    "invalid",
+    "wait_ms",
+];
+
+/// Feature name recorded for permits and reports that carried no `feature`.
+pub(crate) const UNTAGGED_FEATURE: &str = "untagged";
+const FEATURE_COUNTERS: [&str; 4] = ["grants", "denials", "reports", "observed_429"];
+
+#[derive(Default)]
+struct Window {
+    counters: HashMap<&'static str, u64>,
+    waits: [u64; WAIT_BUCKETS_MS.len() + 1],
+    /// Counters per (`client_id`, `discord_identity`).
+    by_caller: HashMap<(String, String), HashMap<&'static str, u64>>,
+    /// Counters per (feature, method, route template).
+    by_feature: HashMap<(String, String, String), HashMap<&'static str, u64>>,
+}
+
+impl Window {
+    fn add(&mut self, caller: (&str, &str), name: &'static str, count: u64) {
+        *self.counters.entry(name).or_default() += count;
+        let caller = (normalize_key_part(caller.0), normalize_key_part(caller.1));
+        *self
+            .by_caller
+            .entry(caller)
+            .or_default()
+            .entry(name)
+            .or_default() += count;
+    }
+}
+
+/// In-memory accumulator keyed by minute start (unix ms).
+#[derive(Clone, Default)]
+pub(crate) struct UsageStats {
+    windows: Arc<Mutex<BTreeMap<u64, Window>>>,
+    /// Whether per-feature counters are kept (`DMBO_FEATURES` is set).
+    track_features: bool,
+    /// Per-feature counters since startup, for `/metrics`.
+    feature_totals: Arc<Mutex<BTreeMap<String, HashMap<&'static str, u64>>>>,
+}
+
+pub(crate) enum Decision {
+    Granted,
+    Denied,
+    Errored,
+}
+
+impl UsageStats {
+    pub(crate) fn new(track_features: bool) -> Self {
+        Self {
+            track_features,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn record_decision(
+        &self,
+        request: &RequestTokenRequest,
+        decision: Decision,
+        waited_ms: u64,
+    ) {
+        let counter = match decision {
+            Decision::Granted => "grants",
+            Decision::Denied => "denials",
+            Decision::Errored => "errors",
+        };
+        let caller = (
+            request.client_id.as_str(),
+            request.discord_identity.as_str(),
+        );
+        // Backend errors say nothing about a feature's consumption.
+        let feature = (self.track_features && counter != "errors")
+            .then(|| feature_key(request.feature.as_deref(), &request.method, &request.route));
+        self.with_window(|window| {
+            window.add(caller, counter, 1);
+            if let Some(key) = &feature {
+                *window
+                    .by_feature
+                    .entry(key.clone())
+                    .or_default()
+                    .entry(counter)
+                    .or_default() += 1;
+            }
+            if waited_ms > 0 {
+                window.add(caller, "wait_ms", waited_ms);
+            }
+            let bucket = WAIT_BUCKETS_MS
+                .iter()
+                .position(|bound| waited_ms <= *bound)
+                .unwrap_or(WAIT_BUCKETS_MS.len());
+            window.waits[bucket] += 1;
+        });
+        if let Some((feature, _, _)) = feature {
+            let mut totals = self.feature_totals.lock().expect("usage stats poisoned");
+            *totals
+                .entry(feature)
+                .or_default()
+                .entry(counter)
+                .or_default() += 1;
+        }
+    }
+
+    pub(crate) fn record_report(&self, report: &ReportResultRequest, invalid: bool) {
+        let caller = (report.client_id.as_str(), report.discord_identity.as_str());
+        let feature = self.track_features.then(|| {
+            let route = routes::normalize_route(&report.route).template;
+            feature_key(report.feature.as_deref(), &report.method, &route)
+        });
+        self.with_window(|window| {
+            window.add(caller, "reports", 1);
+            if report.status_code == 429 {
+                window.add(caller, "observed_429", 1);
+            }
+            if invalid {
+                window.add(caller, "invalid", 1);
+            }
+            if let Some(key) = &feature {
+                let counters = window.by_feature.entry(key.clone()).or_default();
+                *counters.entry("reports").or_default() += 1;
+                if report.status_code == 429 {
+                    *counters.entry("observed_429").or_default() += 1;
+                }
+            }
+        });
+        if let Some((feature, _, _)) = feature {
+            let mut totals = self.feature_totals.lock().expect("usage stats poisoned");
+            let counters = totals.entry(feature).or_default();
+            *counters.entry("reports").or_default() += 1;
+            if report.status_code == 429 {
+                *counters.entry("observed_429").or_default() += 1;
+            }
+        }
+    }
+
+    /// Per-feature counter since startup; zero for features not yet seen.
+    pub(crate) fn feature_total(&self, feature: &str, name: &str) -> u64 {
+        self.feature_totals
+            .lock()
+            .expect("usage stats poisoned")
+            .get(feature)
+            .and_then(|counters| counters.get(name))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn with_window(&self, update: impl FnOnce(&mut Window)) {
+        let minute = unix_ms() / MINUTE_MS * MINUTE_MS;
+        let mut windows = self.windows.lock().expect("usage stats poisoned");
+        update(windows.entry(minute).or_default());
+    }
+
+    fn take(&self) -> BTreeMap<u64, Window> {
+        std::mem::take(&mut *self.windows.lock().expect("usage stats poisoned"))
+    }
+
+    /// Puts windows back after a failed flush so the counts are not lost.
+    fn restore(&self, windows: BTreeMap<u64, Window>) {
+        let mut current = self.windows.lock().expect("usage stats poisoned");
+        for (minute, window) in windows {
+            let entry = current.entry(minute).or_default();
+            for (name, count) in window.counters {
+                *entry.counters.entry(name).or_default() += count;
+            }
+            for (slot, count) in entry.waits.iter_mut().zip(window.waits) {
+                *slot += count;
+            }
+            for (caller, counters) in window.by_caller {
+                let slot = entry.by_caller.entry(caller).or_default();
+                for (name, count) in counters {
+                    *slot.entry(name).or_default() += count;
+                }
+            }
+            for (key, counters) in window.by_feature {
+                let slot = entry.by_feature.entry(key).or_default();
+                for (name, count) in counters {
+                    *slot.entry(name).or_default() += count;
+                }
+            }
+        }
+    }
+}
+
+fn feature_key(feature: Option<&str>, method: &str, route: &str) -> (String, String, String) {
+    (
+        feature.unwrap_or(UNTAGGED_FEATURE).to_string(),
+        method.to_string(),
+        route.to_string(),
+    )
+}
+
+fn rollup_key(resolution: &str, start_ms: u64) -> String {
+    format!("rl:stats:{resolution}:{start_ms}")
+}
+
+fn usage_key(hour_ms: u64) -> String {
+    format!("rl:stats:usage:{hour_ms}")
+}
+
+fn feature_usage_key(hour_ms: u64) -> String {
+    format!("rl:stats:features:{hour_ms}")
+}
+
+/// Flushes accumulated windows into the minute and hour rollups every
+/// `FLUSH_INTERVAL_MS`. Rollup keys expire after the configured retention.
+pub(crate) async fn flush_rollups(state: Arc<AppState>) {
+    let mut ticker = interval(Duration::from_millis(FLUSH_INTERVAL_MS));
+    loop {
+        ticker.tick().await;
+        let windows = state.usage.take();
+        if windows.is_empty() {
+            continue;
+        }
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (minute, window) in &windows {
+            let hour = minute / HOUR_MS * HOUR_MS;
+            for (key, retention_ms) in [
+                (
+                    rollup_key("minute", *minute),
+                    state.config.stats_minute_retention * MINUTE_MS,
+                ),
+                (
+                    rollup_key("hour", hour),
+                    state.config.stats_hour_retention * HOUR_MS,
+                ),
+            ] {
+                for (name, count) in &window.counters {
+                    pipe.hincr(&key, *name, *count).ignore();
+                }
+                for (index, count) in window.waits.iter().enumerate() {
+                    if *count > 0 {
+                        pipe.hincr(&key, format!("wait_bucket_{index}"), *count)
+                            .ignore();
+                    }
+                }
+                pipe.pexpire(&key, (retention_ms + HOUR_MS) as i64).ignore();
+            }
+            let usage = usage_key(hour);
+            for ((client_id, discord_identity), counters) in &window.by_caller {
+                for (name, count) in counters {
+                    pipe.hincr(
+                        &usage,
+                        format!("{name}:{client_id}:{discord_identity}"),
+                        *count,
+                    )
+                    .ignore();
+                }
+            }
+            if !window.by_caller.is_empty() {
+                pipe.pexpire(
+                    &usage,
+                    ((state.config.stats_hour_retention + 1) * HOUR_MS) as i64,
+                )
+                .ignore();
+            }
+            // Fields are `counter:feature:method:route`; the route goes last
+            // because templates contain colons.
+            let features = feature_usage_key(hour);
+            for ((feature, method, route), counters) in &window.by_feature {
+                for (name, count) in counters {
+                    pipe.hincr(
+                        &features,
+                        format!("{name}:{feature}:{method}:{route}"),
+                        *count,
+                    )
+                    .ignore();
+                }
+            }
+            if !window.by_feature.is_empty() {
+                pipe.pexpire(
+                    &features,
+                    ((state.config.stats_hour_retention + 1) * HOUR_MS) as i64,
+                )
+                .ignore();
+            }
+        }
+        let flushed: redis::RedisResult<()> =
+            match state.redis.get_multiplexed_async_connection().await {
+                Ok(mut conn) => pipe.query_async(&mut conn).await,
+                Err(error) => Err(error),
+            };
+        if flushed.is_err() {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+            state.usage.restore(windows);
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct HistoryQuery {
+    #[serde(default)]
+    resolution: Option<String>,
+    #[serde(default)]
+    from: Option<u64>,
+    #[serde(default)]
+    to: Option<u64>,
+}
+
+/// `GET /stats/history`: rollup points between `from` and `to` (unix ms).
+/// Defaults to the last hour of minutes or the last day of hours.
+pub(crate) async fn history(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let (resolution, step_ms, retention) = match query.resolution.as_deref() {
+        None | Some("minute") => ("minute", MINUTE_MS, state.config.stats_minute_retention),
+        Some("hour") => ("hour", HOUR_MS, state.config.stats_hour_retention),
+        Some(_) => {
+            return validation_failed_response(vec![FieldError {
+                field: "resolution",
+                message: "must be minute or hour".to_string(),
+            }])
+        }
+    };
+    let now = unix_ms();
+    let to = query.to.unwrap_or(now).min(now);
+    let default_span = if resolution == "minute" {
+        HOUR_MS
+    } else {
+        24 * HOUR_MS
+    };
+    let from = query
+        .from
+        .unwrap_or_else(|| to.saturating_sub(default_span))
+        .max(to.saturating_sub(retention * step_ms));
+    if from > to {
+        return validation_failed_response(vec![FieldError {
+            field: "from",
+            message: "must not be after `to`".to_string(),
+        }]);
+    }
+
+    let points = match load_points(&state, resolution, step_ms, from, to).await {
+        Ok(points) => points,
+        Err(()) => return crate::backend_unavailable_response(&state),
+    };
+    Json(json!({
+        "resolution": resolution,
+        "from_unix_ms": from,
+        "to_unix_ms": to,
+        "points": points,
+    }))
+    .into_response()
+}
+
+/// Reads every rollup bucket starting in `[from, to]` and returns one point
+/// per bucket that has data.
+pub(crate) async fn load_points(
+    state: &AppState,
+    resolution: &str,
+    step_ms: u64,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Value>, ()> {
+    let starts: Vec<u64> = (from / step_ms..=to / step_ms)
+        .map(|index| index * step_ms)
+        .collect();
+    let mut conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|_| ())?;
+    let mut pipe = redis::pipe();
+    for start in &starts {
+        pipe.hgetall(rollup_key(resolution, *start));
+    }
+    let rollups: Vec<HashMap<String, u64>> = pipe.query_async(&mut conn).await.map_err(|_| ())?;
+    Ok(starts
+        .into_iter()
+        .zip(rollups)
+        .filter(|(_, fields)| !fields.is_empty())
+        .map(|(start, fields)| point(start, &fields))
+        .collect())
+}
+
+fn point(start_unix_ms: u64, fields: &HashMap<String, u64>) -> Value {
+    let mut point = serde_json::Map::new();
+    point.insert("start_unix_ms".to_string(), json!(start_unix_ms));
+    for name in COUNTERS {
+        point.insert(
+            name.to_string(),
+            json!(fields.get(name).copied().unwrap_or_default()),
+        );
+    }
+    let waits: Vec<u64> = (0..=WAIT_BUCKETS_MS.len())
+        .map(|index| {
+            fields
+                .get(&format!("wait_bucket_{index}"))
+                .copied()
+                .unwrap_or_default()
+        })
+        .collect();
+    for (name, quantile) in [
+        ("wait_p50_ms", 0.50),
+        ("wait_p90_ms", 0.90),
+        ("wait_p99_ms", 0.99),
+    ] {
+        point.insert(name.to_string(), json!(percentile(&waits, quantile)));
+    }
+    Value::Object(point)
+}
+
+/// Upper bound of the histogram bucket containing the quantile, reported as
+/// the last bound for the overflow bucket; `None` when there were no samples.
+fn percentile(waits: &[u64], quantile: f64) -> Option<u64> {
+    let total: u64 = waits.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let rank = ((total as f64) * quantile).ceil().max(1.0) as u64;
+    let mut seen = 0;
+    let index = waits
+        .iter()
+        .position(|count| {
+            seen += count;
+            seen >= rank
+        })
+        .unwrap_or(WAIT_BUCKETS_MS.len());
+    WAIT_BUCKETS_MS
+        .get(index)
+        .or(WAIT_BUCKETS_MS.last())
+        .copied()
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct FeatureQuery {
+    #[serde(default)]
+    from: Option<u64>,
+    #[serde(default)]
+    to: Option<u64>,
+    #[serde(default)]
+    method: Option<String>,
+    /// Route template, concrete path or URL; normalized before matching.
+    #[serde(default)]
+    route: Option<String>,
+}
+
+/// `GET /stats/features`: per-feature totals between `from` and `to` (unix
+/// ms, default the last 24 hours) at hour resolution, optionally limited to
+/// one method and route. `grant_share` is the feature's fraction of all grants
+/// in the selection, untagged traffic included.
+pub(crate) async fn features(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeatureQuery>,
+) -> Response {
+    let now = unix_ms();
+    let to = query.to.unwrap_or(now).min(now);
+    let from = query
+        .from
+        .unwrap_or_else(|| to.saturating_sub(24 * HOUR_MS))
+        .max(to.saturating_sub(state.config.stats_hour_retention * HOUR_MS));
+    if from > to {
+        return validation_failed_response(vec![FieldError {
+            field: "from",
+            message: "must not be after `to`".to_string(),
+        }]);
+    }
+    let method = query
+        .method
+        .map(|method| method.trim().to_ascii_uppercase());
+    let route = query
+        .route
+        .map(|route| routes::normalize_route(&route).template);
+
+    let hours: Vec<u64> = (from / HOUR_MS..=to / HOUR_MS)
+        .map(|index| index * HOUR_MS)
+        .collect();
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        return crate::backend_unavailable_response(&state);
+    };
+    let mut pipe = redis::pipe();
+    for hour in &hours {
+        pipe.hgetall(feature_usage_key(*hour));
+    }
+    let Ok(rollups) = pipe
+        .query_async::<_, Vec<HashMap<String, u64>>>(&mut conn)
+        .await
+    else {
+        return crate::backend_unavailable_response(&state);
+    };
+
+    let mut totals: BTreeMap<String, HashMap<String, u64>> = BTreeMap::new();
+    for (field, count) in rollups.into_iter().flatten() {
+        let mut parts = field.splitn(4, ':');
+        let (Some(name), Some(feature), Some(field_method), Some(field_route)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        if method
+            .as_deref()
+            .is_some_and(|method| method != field_method)
+            || route.as_deref().is_some_and(|route| route != field_route)
+        {
+            continue;
+        }
+        *totals
+            .entry(feature.to_string())
+            .or_default()
+            .entry(name.to_string())
+            .or_default() += count;
+    }
+    let all_grants: u64 = totals
+        .values()
+        .filter_map(|counters| counters.get("grants"))
+        .sum();
+    let mut features: Vec<Value> = totals
+        .into_iter()
+        .map(|(feature, counters)| {
+            let mut row = serde_json::Map::new();
+            row.insert("feature".to_string(), json!(feature));
+            for name in FEATURE_COUNTERS {
+                row.insert(
+                    name.to_string(),
+                    json!(counters.get(name).copied().unwrap_or_default()),
+                );
+            }
+            let grants = counters.get("grants").copied().unwrap_or_default();
+            row.insert(
+                "grant_share".to_string(),
+                json!((all_grants > 0).then(|| grants as f64 / all_grants as f64)),
+            );
+            Value::Object(row)
+        })
+        .collect();
+    features.sort_by_key(|row| std::cmp::Reverse(row["grants"].as_u64().unwrap_or_default()));
+    Json(json!({
+        "from_unix_ms": from,
+        "to_unix_ms": to,
+        "method": method,
+        "route": route,
+        "features": features,
+    }))
+    .into_response()
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ExportQuery {
+    #[serde(default)]
+    from: Option<u64>,
+    #[serde(default)]
+    to: Option<u64>,
+    #[serde(default)]
+    format: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// `GET /admin/export/usage`: per-caller hourly usage between `from` and `to`
+/// (unix ms, default the last 24 hours) as CSV or a JSON array. The body is
+/// streamed one hour at a time so long ranges do not buffer in memory.
+pub(crate) async fn export_usage(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let format = match query.format.as_deref() {
+        None | Some("csv") => ExportFormat::Csv,
+        Some("json") => ExportFormat::Json,
+        Some(_) => {
+            return validation_failed_response(vec![FieldError {
+                field: "format",
+                message: "must be csv or json".to_string(),
+            }])
+        }
+    };
+    let now = unix_ms();
+    let to = query.to.unwrap_or(now).min(now);
+    let from = query
+        .from
+        .unwrap_or_else(|| to.saturating_sub(24 * HOUR_MS))
+        .max(to.saturating_sub(state.config.stats_hour_retention * HOUR_MS));
+    if from > to {
+        return validation_failed_response(vec![FieldError {
+            field: "from",
+            message: "must not be after `to`".to_string(),
+        }]);
+    }
+
+    let first_hour = from / HOUR_MS * HOUR_MS;
+    let last_hour = to / HOUR_MS * HOUR_MS;
+    let header = match format {
+        ExportFormat::Csv => format!(
+            "hour_start_unix_ms,client_id,discord_identity,{}\n",
+            USAGE_COLUMNS.join(",")
+        ),
+        ExportFormat::Json => "[".to_string(),
+    };
+    // State: (next hour to read, whether a JSON row has been written yet).
+    let rows =
+        futures_util::stream::unfold((Some(first_hour), false), move |(next, mut wrote_row)| {
+            let state = state.clone();
+            async move {
+                let hour = next?;
+                let mut chunk = String::new();
+                for row in usage_rows(&state, hour).await {
+                    match format {
+                        ExportFormat::Csv => {
+                            chunk.push_str(&csv_row(&row));
+                        }
+                        ExportFormat::Json => {
+                            if wrote_row {
+                                chunk.push(',');
+                            }
+                            chunk.push_str(&row.to_string());
+                            wrote_row = true;
+                        }
+                    }
+                }
+                let next = (hour < last_hour).then_some(hour + HOUR_MS);
+                if next.is_none() && format == ExportFormat::Json {
+                    chunk.push(']');
+                }
+                Some((Ok::<_, Infallible>(chunk), (next, wrote_row)))
+            }
+        });
+    let body = futures_util::StreamExt::chain(
+        futures_util::stream::once(async move { Ok::<_, Infallible>(header) }),
+        rows,
+    );
+    let content_type = match format {
+        ExportFormat::Csv => "text/csv; charset=utf-8",
+        ExportFormat::Json => "application/json",
+    };
+    (
+        [(header::CONTENT_TYPE, content_type)],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// Rows for one hour, sorted by caller. A Redis failure yields no rows for
+/// that hour rather than aborting a stream that has already started.
+pub(crate) async fn usage_rows(state: &AppState, hour: u64) -> Vec<Value> {
+    let Ok(mut conn) = state.redis.get_multiplexed_async_connection().await else {
+        state
+            .metrics
+            .redis_errors_total
+            .fetch_add(1, Ordering::Relaxed);
+        return Vec::new();
+    };
+    let fields: HashMap<String, u64> = match redis::cmd("HGETALL")
+        .arg(usage_key(hour))
+        .query_async(&mut conn)
+        .await
+    {
+        Ok(fields) => fields,
+        Err(_) => {
+            state
+                .metrics
+                .redis_errors_total
+                .fetch_add(1, Ordering::Relaxed);
+            return Vec::new();
+        }
+    };
+    let mut callers: BTreeMap<(String, String), HashMap<String, u64>> = BTreeMap::new();
+    for (field, count) in fields {
+        let mut parts = field.splitn(3, ':');
+        if let (Some(name), Some(client_id), Some(discord_identity)) =
+            (parts.next(), parts.next(), parts.next())
+        {
+            callers
+                .entry((client_id.to_string(), discord_identity.to_string()))
+                .or_default()
+                .insert(name.to_string(), count);
+        }
+    }
+    callers
+        .into_iter()
+        .map(|((client_id, discord_identity), counters)| {
+            let mut row = serde_json::Map::new();
+            row.insert("hour_start_unix_ms".to_string(), json!(hour));
+            row.insert("client_id".to_string(), json!(client_id));
+            row.insert("discord_identity".to_string(), json!(discord_identity));
+            for name in USAGE_COLUMNS {
+                row.insert(
+                    name.to_string(),
+                    json!(counters.get(name).copied().unwrap_or_default()),
+                );
+            }
+            Value::Object(row)
+        })
+        .collect()
+}
+
+fn csv_row(row: &Value) -> String {
+    let mut cells = Vec::with_capacity(USAGE_COLUMNS.len() + 3);
+    for name in ["hour_start_unix_ms", "client_id", "discord_identity"]
+        .into_iter()
+        .chain(USAGE_COLUMNS)
+    {
+        let cell = match row.get(name) {
+            Some(Value::String(text)) => csv_escape(text),
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+        cells.push(cell);
+    }
+    format!("{}\n", cells.join(","))
+}
+
+fn csv_escape(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,275 @@
+//! Optional StatsD exporter for monitoring stacks that ingest StatsD
+//! (Datadog, Telegraf) instead of scraping `/metrics`. Enabled by
+//! `DMBO_STATSD_ADDR`. Timings are sent as they happen through a bounded
+//! channel, so the permit path never waits on the socket and drops samples
+//! rather than queueing when the exporter falls behind. Counters and gauges
+//! are mirrored from the Prometheus metrics every `DMBO_STATSD_INTERVAL_MS`
+//! as deltas and current values.
+//!
+//! With `DMBO_STATSD_DOGSTATSD` (the default) labels are sent as DogStatsD
+//! tags together with `DMBO_STATSD_TAGS`; otherwise label values are
+//! appended to the metric name, e.g. `dmbo.request_token.granted`.
+
+use crate::{AppState, Config};
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, sync::mpsc, time::interval};
+
+/// Keeps datagrams under a typical Ethernet MTU after IP and UDP headers.
+const MAX_PACKET_BYTES: usize = 1432;
+/// Timing samples buffered between sends before new ones are dropped.
+const CHANNEL_CAPACITY: usize = 10_000;
+
+/// Prometheus label pairs for one mirrored series.
+type Labels = &'static [(&'static str, &'static str)];
+
+#[derive(Clone, Default)]
+pub(crate) struct Statsd {
+    timings: Option<mpsc::Sender<(&'static str, u64)>>,
+}
+
+impl Statsd {
+    /// Starts the exporter when `DMBO_STATSD_ADDR` is set. Must be called
+    /// from within the runtime.
+    pub(crate) fn start(config: &Config) -> (Self, Option<mpsc::Receiver<(&'static str, u64)>>) {
+        if config.statsd_addr.is_none() {
+            return (Self::default(), None);
+        }
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        (
+            Self {
+                timings: Some(sender),
+            },
+            Some(receiver),
+        )
+    }
+
+    /// Queues a timing sample; a no-op when the exporter is disabled.
+    pub(crate) fn timing(&self, name: &'static str, value_ms: u64) {
+        if let Some(timings) = &self.timings {
+            let _ = timings.try_send((name, value_ms));
+        }
+    }
+}
+
+struct Formatter {
+    prefix: String,
+    dogstatsd: bool,
+    global_tags: Vec<String>,
+}
+
+impl Formatter {
+    fn line(&self, name: &str, labels: &[(&str, &str)], value: u64, kind: &str) -> String {
+        if self.dogstatsd {
+            let tags: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{key}:{value}"))
+                .chain(self.global_tags.iter().cloned())
+                .collect();
+            if tags.is_empty() {
+                format!("{}{name}:{value}|{kind}", self.prefix)
+            } else {
+                format!("{}{name}:{value}|{kind}|#{}", self.prefix, tags.join(","))
+            }
+        } else {
+            let mut full = format!("{}{name}", self.prefix);
+            for (_, label) in labels {
+                full.push('.');
+                full.push_str(label);
+            }
+            format!("{full}:{value}|{kind}")
+        }
+    }
+}
+
+/// Sends queued timings as they arrive and mirrored counters and gauges every
+/// interval. Send errors are ignored: StatsD is best-effort by design.
+pub(crate) async fn export(state: Arc<AppState>, mut timings: mpsc::Receiver<(&'static str, u64)>) {
+    let Some(addr) = state.config.statsd_addr.clone() else {
+        return;
+    };
+    let socket = match UdpSocket::bind("0.0.0.0:0").await {
+        Ok(socket) => socket,
+        Err(error) => {
+            eprintln!("statsd exporter disabled: {error}");
+            return;
+        }
+    };
+    if let Err(error) = socket.connect(&addr).await {
+        eprintln!("statsd exporter disabled: cannot resolve {addr}: {error}");
+        return;
+    }
+    let formatter = Formatter {
+        prefix: state.config.statsd_prefix.clone(),
+        dogstatsd: state.config.statsd_dogstatsd,
+        global_tags: state.config.statsd_tags.clone(),
+    };
+    let mut ticker = interval(Duration::from_millis(state.config.statsd_interval_ms));
+    let mut previous: HashMap<String, u64> = HashMap::new();
+    let mut lines = Vec::new();
+    loop {
+        tokio::select! {
+            sample = timings.recv() => {
+                let Some((name, value)) = sample else { return };
+                lines.push(formatter.line(name, &[], value, "ms"));
+                // Drain whatever else is already queued into the same packets.
+                while let Ok((name, value)) = timings.try_recv() {
+                    lines.push(formatter.line(name, &[], value, "ms"));
+                }
+            }
+            _ = ticker.tick() => {
+                mirror_metrics(&state, &formatter, &mut previous, &mut lines);
+            }
+        }
+        send(&socket, &mut lines).await;
+    }
+}
+
+/// Appends counter deltas since the last tick and current gauge values.
+fn mirror_metrics(
+    state: &AppState,
+    formatter: &Formatter,
+    previous: &mut HashMap<String, u64>,
+    lines: &mut Vec<String>,
+) {
+    let metrics = &state.metrics;
+    let counters: [(&str, Labels, u64); 19] = [
+        (
+            "request_token",
+            &[("outcome", "granted")],
+            metrics.request_granted.load(Ordering::Relaxed),
+        ),
+        (
+            "request_token",
+            &[("outcome", "denied")],
+            metrics.request_denied.load(Ordering::Relaxed),
+        ),
+        (
+            "request_token",
+            &[("outcome", "error")],
+            metrics.request_error.load(Ordering::Relaxed),
+        ),
+        (
+            "observed_429",
+            &[("scope", "global")],
+            metrics.observed_429_global.load(Ordering::Relaxed),
+        ),
+        (
+            "observed_429",
+            &[("scope", "user")],
+            metrics.observed_429_user.load(Ordering::Relaxed),
+        ),
+        (
+            "observed_429",
+            &[("scope", "shared")],
+            metrics.observed_429_shared.load(Ordering::Relaxed),
+        ),
+        (
+            "observed_429",
+            &[("scope", "unknown")],
+            metrics.observed_429_unknown.load(Ordering::Relaxed),
+        ),
+        (
+            "invalid_requests",
+            &[("status", "401")],
+            metrics.invalid_401.load(Ordering::Relaxed),
+        ),
+        (
+            "invalid_requests",
+            &[("status", "403")],
+            metrics.invalid_403.load(Ordering::Relaxed),
+        ),
+        (
+            "invalid_requests",
+            &[("status", "429")],
+            metrics.invalid_429.load(Ordering::Relaxed),
+        ),
+        (
+            "redis_errors",
+            &[],
+            metrics.redis_errors_total.load(Ordering::Relaxed),
+        ),
+        (
+            "clock_skew_exceeded",
+            &[],
+            metrics.clock_skew_exceeded_total.load(Ordering::Relaxed),
+        ),
+        (
+            "jobs",
+            &[("outcome", "submitted")],
+            metrics.jobs_submitted.load(Ordering::Relaxed),
+        ),
+        (
+            "jobs",
+            &[("outcome", "succeeded")],
+            metrics.jobs_succeeded.load(Ordering::Relaxed),
+        ),
+        (
+            "jobs",
+            &[("outcome", "failed")],
+            metrics.jobs_failed.load(Ordering::Relaxed),
+        ),
+        (
+            "dlq_dead_lettered",
+            &[("kind", "report")],
+            metrics.dead_lettered_reports.load(Ordering::Relaxed),
+        ),
+        (
+            "dlq_dead_lettered",
+            &[("kind", "job")],
+            metrics.dead_lettered_jobs.load(Ordering::Relaxed),
+        ),
+        (
+            "rule_denials",
+            &[],
+            metrics.rule_denials.load(Ordering::Relaxed),
+        ),
+        (
+            "maintenance_denials",
+            &[],
+            metrics.maintenance_denials.load(Ordering::Relaxed),
+        ),
+    ];
+    for (name, labels, total) in counters {
+        let key = formatter.line(name, labels, 0, "c");
+        let last = previous.insert(key, total).unwrap_or_default();
+        let delta = total.saturating_sub(last);
+        if delta > 0 {
+            lines.push(formatter.line(name, labels, delta, "c"));
+        }
+    }
+    let gauges: [(&str, u64); 4] = [
+        ("queue_depth", metrics.queue_depth.load(Ordering::Relaxed)),
+        (
+            "inflight_requests",
+            metrics.inflight_requests.load(Ordering::Relaxed),
+        ),
+        ("dlq_deferred_reports", state.deferred_reports.len() as u64),
+        ("loans_active", state.loans.active_count() as u64),
+    ];
+    for (name, value) in gauges {
+        lines.push(formatter.line(name, &[], value, "g"));
+    }
+}
+
+/// Sends `lines` packed into as few datagrams as fit under
+/// `MAX_PACKET_BYTES`, then clears them.
+async fn send(socket: &UdpSocket, lines: &mut Vec<String>) {
+    let mut packet = String::new();
+    for line in lines.drain(..) {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_BYTES {
+            let _ = socket.send(packet.as_bytes()).await;
+            packet.clear();
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(&line);
+    }
+    if !packet.is_empty() {
+        let _ = socket.send(packet.as_bytes()).await;
+    }
+}
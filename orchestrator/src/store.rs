@@ -0,0 +1,544 @@
+//! Storage behind permit decisions. `PermitStore` covers what every permit
+//! and report goes through: the limiter evaluation that issues tokens, the
+//! invalid-response counter and the invalid-request guardrail, reported
+//! cooldowns, Cloudflare bans and idempotency records. Handlers reach them
+//! through `AppState::store` only, so another backend can stand in for
+//! Redis there. Service-only features (leases, quotas, long-window limits
+//! and the like) still keep their own state in Redis.
+//!
+//! `DMBO_STORE=memory` swaps in `MemoryStore`, which keeps that state in
+//! the process for single-instance deployments without Redis. `Config`
+//! refuses to start it alongside a service-only feature.
+
+use crate::{Config, PermitScriptReply};
+use dmbo_core::{
+    keys::{
+        self, global_cooldown_key, limit_key, route_cooldown_key, shared_cooldown_key,
+        sublimit_cooldown_key,
+    },
+    Algorithm, Backend, Evaluation, LimitInput, MemoryBackend, Reason, FIXED_WINDOW_TTL_MS,
+    INVALID_WINDOW_MS,
+};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Script};
+use std::{collections::HashMap, fmt, future::Future, pin::Pin, sync::Mutex};
+
+/// `KEYS[1]` counts the group's Cloudflare bans, `KEYS[2]` is its guard.
+/// `ARGV`: base cooldown ms, maximum cooldown ms, strike window ms. Never
+/// shortens a longer guard already in place. Returns `{strikes, cooldown_ms}`.
+const BAN_LUA: &str = r#"
+local strikes = redis.call('INCR', KEYS[1])
+redis.call('PEXPIRE', KEYS[1], ARGV[3])
+local cooldown = math.floor(math.min(tonumber(ARGV[2]), tonumber(ARGV[1]) * 2 ^ (strikes - 1)))
+if redis.call('PTTL', KEYS[2]) < cooldown then
+  redis.call('SET', KEYS[2], strikes, 'PX', cooldown)
+end
+return {strikes, cooldown}
+"#;
+
+/// Expired `MemoryStore` values are swept at most this often.
+const PRUNE_INTERVAL_MS: u64 = 1000;
+
+fn strikes_key(group: &str) -> String {
+    format!("rl:cloudflare:{group}")
+}
+
+pub(crate) type StoreFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, StoreError>> + Send + 'a>>;
+
+/// A store operation that failed; the permit path treats it like a Redis
+/// error.
+#[derive(Debug)]
+pub(crate) struct StoreError(String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<redis::RedisError> for StoreError {
+    fn from(error: redis::RedisError) -> Self {
+        Self(error.to_string())
+    }
+}
+
+impl From<StoreError> for redis::RedisError {
+    fn from(error: StoreError) -> Self {
+        (redis::ErrorKind::IoError, "permit store failed", error.0).into()
+    }
+}
+
+/// A counted invalid response that brought its group to the threshold.
+pub(crate) struct GuardTrip {
+    pub(crate) invalid_count: u64,
+    /// The guard was already up and has only been re-armed.
+    pub(crate) already_active: bool,
+}
+
+pub(crate) trait PermitStore: Send + Sync {
+    /// Checks the group's guard, then the identity's limit under `global`
+    /// and the bucket's under `route`, and records the grant if both have
+    /// room. A shadow run keeps separate counters and leaves learned Discord
+    /// bucket state untouched.
+    fn evaluate<'a>(
+        &'a self,
+        config: &'a Config,
+        global: Algorithm,
+        route: Algorithm,
+        input: &'a LimitInput,
+        shadow: bool,
+    ) -> StoreFuture<'a, PermitScriptReply>;
+
+    /// Counts an invalid response for the normalized `group` and, once the
+    /// count reaches `threshold`, (re)arms its guard for `cooldown_ms`.
+    fn record_invalid<'a>(
+        &'a self,
+        group: &'a str,
+        threshold: u64,
+        cooldown_ms: u64,
+    ) -> StoreFuture<'a, Option<GuardTrip>>;
+
+    /// Time left on the group's guard, `None` when it is not up.
+    fn guard_ttl_ms<'a>(&'a self, group: &'a str) -> StoreFuture<'a, Option<u64>>;
+
+    /// Lifts the group's guard and resets its invalid count. Returns whether
+    /// there was either to clear.
+    fn clear_guard<'a>(&'a self, group: &'a str) -> StoreFuture<'a, bool>;
+
+    /// Counts a Cloudflare ban for the group within `strike_window_ms` and
+    /// arms its guard for `base_ms` doubled per earlier strike, at most
+    /// `max_ms`. Returns the strike count and the cooldown applied.
+    fn record_ban<'a>(
+        &'a self,
+        group: &'a str,
+        base_ms: u64,
+        max_ms: u64,
+        strike_window_ms: u64,
+    ) -> StoreFuture<'a, (u64, u64)>;
+
+    /// Sets `key` to `value` unless it is already set, expiring after
+    /// `ttl_ms`. Returns the value already there, `None` once claimed.
+    fn claim<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+        ttl_ms: u64,
+    ) -> StoreFuture<'a, Option<String>>;
+
+    /// Sets `key` to `value` for `ttl_ms`, or deletes it when `value` is
+    /// `None`. Cooldown keys set here deny permits until they expire.
+    fn put<'a>(&'a self, key: &'a str, value: Option<String>, ttl_ms: u64) -> StoreFuture<'a, ()>;
+}
+
+/// The store shared by every replica: the `dmbo_core::scripts` run against
+/// the keys of `docs/redis-schema.md`.
+pub(crate) struct RedisStore {
+    client: redis::Client,
+    /// One multiplexed connection for every call, reopened after it fails.
+    conn: Mutex<Option<MultiplexedConnection>>,
+    limiter_script: Script,
+    incr_with_expire_script: Script,
+}
+
+impl RedisStore {
+    pub(crate) fn new(client: redis::Client) -> Self {
+        Self {
+            client,
+            conn: Mutex::new(None),
+            limiter_script: Script::new(dmbo_core::scripts::LIMITER),
+            incr_with_expire_script: Script::new(dmbo_core::scripts::INCR_WITH_EXPIRE),
+        }
+    }
+
+    async fn connection(&self) -> redis::RedisResult<MultiplexedConnection> {
+        if let Some(conn) = self.conn.lock().expect("store connection poisoned").clone() {
+            return Ok(conn);
+        }
+        let conn = self.client.get_multiplexed_async_connection().await?;
+        *self.conn.lock().expect("store connection poisoned") = Some(conn.clone());
+        Ok(conn)
+    }
+
+    /// Drops the shared connection when `result` shows it is gone.
+    fn checked<T>(&self, result: redis::RedisResult<T>) -> Result<T, StoreError> {
+        if let Err(error) = &result {
+            if error.is_io_error() || error.is_connection_dropped() {
+                *self.conn.lock().expect("store connection poisoned") = None;
+            }
+        }
+        Ok(result?)
+    }
+}
+
+impl PermitStore for RedisStore {
+    fn evaluate<'a>(
+        &'a self,
+        config: &'a Config,
+        global: Algorithm,
+        route: Algorithm,
+        input: &'a LimitInput,
+        shadow: bool,
+    ) -> StoreFuture<'a, PermitScriptReply> {
+        Box::pin(async move {
+            let mut conn = self.connection().await?;
+            let prefix = if shadow { "rl:shadow" } else { "rl" };
+            let identity = keys::normalize_key_part(&input.discord_identity);
+            let route_window_ms = input.route_window_ms.max(1);
+            let result = self
+                .limiter_script
+                .key(keys::guard_key(&input.group_id))
+                .key(limit_key(
+                    global,
+                    prefix,
+                    "global",
+                    &identity,
+                    input.now_ms,
+                    1000,
+                ))
+                .key(limit_key(
+                    route,
+                    prefix,
+                    "route",
+                    &input.bucket,
+                    input.now_ms,
+                    route_window_ms,
+                ))
+                .key(crate::anomaly::tighten_key(&input.discord_identity))
+                .key(crate::observed_key(&input.bucket))
+                .key(global_cooldown_key(&input.discord_identity))
+                .key(route_cooldown_key(&input.bucket))
+                .key(shared_cooldown_key(&input.major_parameter))
+                .key(sublimit_cooldown_key(&input.route_bucket))
+                .arg(input.global_limit as i64)
+                .arg(input.route_limit as i64)
+                .arg(FIXED_WINDOW_TTL_MS as i64)
+                .arg(config.min_retry_ms as i64)
+                .arg(if shadow { "0" } else { "1" })
+                .arg(input.now_ms as i64)
+                .arg(input.cost as i64)
+                .arg(if config.honor_discord_remaining {
+                    "1"
+                } else {
+                    "0"
+                })
+                .arg(input.burst_percent as i64)
+                .arg(global.name())
+                .arg(route.name())
+                .arg(route_window_ms as i64)
+                .arg(if input.global_exempt { "1" } else { "0" })
+                .arg(crate::retry_jitter_ms(config) as i64)
+                .invoke_async(&mut conn)
+                .await;
+            self.checked(result)
+        })
+    }
+
+    fn record_invalid<'a>(
+        &'a self,
+        group: &'a str,
+        threshold: u64,
+        cooldown_ms: u64,
+    ) -> StoreFuture<'a, Option<GuardTrip>> {
+        Box::pin(async move {
+            let mut conn = self.connection().await?;
+            let invalid_count: redis::RedisResult<i64> = self
+                .incr_with_expire_script
+                .key(keys::invalid_key(group))
+                .arg((INVALID_WINDOW_MS / 1000) as i64)
+                .invoke_async(&mut conn)
+                .await;
+            let invalid_count = self.checked(invalid_count)?.max(0) as u64;
+            if invalid_count < threshold {
+                return Ok(None);
+            }
+            let guard_key = keys::guard_key(group);
+            let armed: redis::RedisResult<(bool,)> = redis::pipe()
+                .exists(&guard_key)
+                .cmd("PSETEX")
+                .arg(&guard_key)
+                .arg(cooldown_ms as i64)
+                .arg(invalid_count)
+                .ignore()
+                .query_async(&mut conn)
+                .await;
+            let (already_active,) = self.checked(armed)?;
+            Ok(Some(GuardTrip {
+                invalid_count,
+                already_active,
+            }))
+        })
+    }
+
+    fn guard_ttl_ms<'a>(&'a self, group: &'a str) -> StoreFuture<'a, Option<u64>> {
+        Box::pin(async move {
+            let mut conn = self.connection().await?;
+            let ttl_ms: redis::RedisResult<i64> = conn.pttl(keys::guard_key(group)).await;
+            let ttl_ms = self.checked(ttl_ms)?;
+            Ok(u64::try_from(ttl_ms).ok().filter(|ttl_ms| *ttl_ms > 0))
+        })
+    }
+
+    fn clear_guard<'a>(&'a self, group: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let mut conn = self.connection().await?;
+            let deleted: redis::RedisResult<u64> = conn
+                .del(&[keys::guard_key(group), keys::invalid_key(group)])
+                .await;
+            Ok(self.checked(deleted)? > 0)
+        })
+    }
+
+    fn record_ban<'a>(
+        &'a self,
+        group: &'a str,
+        base_ms: u64,
+        max_ms: u64,
+        strike_window_ms: u64,
+    ) -> StoreFuture<'a, (u64, u64)> {
+        Box::pin(async move {
+            let mut conn = self.connection().await?;
+            let result: redis::RedisResult<(u64, u64)> = Script::new(BAN_LUA)
+                .key(strikes_key(group))
+                .key(keys::guard_key(group))
+                .arg(base_ms)
+                .arg(max_ms)
+                .arg(strike_window_ms)
+                .invoke_async(&mut conn)
+                .await;
+            self.checked(result)
+        })
+    }
+
+    fn claim<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+        ttl_ms: u64,
+    ) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let mut conn = self.connection().await?;
+            let claimed: redis::RedisResult<Option<String>> = redis::cmd("SET")
+                .arg(key)
+                .arg(value)
+                .arg("NX")
+                .arg("PX")
+                .arg(ttl_ms)
+                .query_async(&mut conn)
+                .await;
+            if self.checked(claimed)?.is_some() {
+                return Ok(None);
+            }
+            let existing: redis::RedisResult<Option<String>> = conn.get(key).await;
+            self.checked(existing)
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Option<String>, ttl_ms: u64) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let mut conn = self.connection().await?;
+            let result: redis::RedisResult<()> = match value {
+                Some(value) => conn.pset_ex(key, value, ttl_ms).await,
+                None => conn.del(key).await,
+            };
+            self.checked(result)
+        })
+    }
+}
+
+/// Limiter and guardrail state in this process, through the same arithmetic
+/// as the Redis scripts (`dmbo_core::MemoryBackend`). Nothing is shared with
+/// other replicas or survives a restart. Both limits run the route's
+/// algorithm, and learned Discord bucket state is not applied.
+#[derive(Default)]
+pub(crate) struct MemoryStore {
+    enforced: MemoryBackend,
+    /// The shadow algorithm's own counters.
+    shadow: MemoryBackend,
+    values: Mutex<MemoryValues>,
+}
+
+/// What `RedisStore` keeps in plain keys: cooldowns, ban strikes and
+/// idempotency records, each with its expiry.
+#[derive(Default)]
+struct MemoryValues {
+    entries: HashMap<String, (String, u64)>,
+    next_prune_ms: u64,
+}
+
+impl MemoryValues {
+    fn get(&self, key: &str, now: u64) -> Option<(&str, u64)> {
+        let (value, expires) = self.entries.get(key)?;
+        (*expires > now).then(|| (value.as_str(), expires - now))
+    }
+
+    fn set(&mut self, key: &str, value: String, ttl_ms: u64, now: u64) {
+        if now >= self.next_prune_ms {
+            self.next_prune_ms = now + PRUNE_INTERVAL_MS;
+            self.entries.retain(|_, (_, expires)| *expires > now);
+        }
+        self.entries.insert(key.to_string(), (value, now + ttl_ms));
+    }
+}
+
+impl MemoryStore {
+    fn values(&self) -> std::sync::MutexGuard<'_, MemoryValues> {
+        self.values.lock().expect("memory store poisoned")
+    }
+
+    /// The first cooldown that denies `input`, in the limiter script's
+    /// order, with its remaining time.
+    fn cooldown(&self, input: &LimitInput) -> Option<(Reason, u64)> {
+        let values = self.values();
+        let global = (!input.global_exempt).then(|| {
+            (
+                global_cooldown_key(&input.discord_identity),
+                Reason::GlobalCooldownActive,
+            )
+        });
+        global
+            .into_iter()
+            .chain([
+                (
+                    route_cooldown_key(&input.bucket),
+                    Reason::RouteCooldownActive,
+                ),
+                (
+                    shared_cooldown_key(&input.major_parameter),
+                    Reason::SharedCooldownActive,
+                ),
+                (
+                    sublimit_cooldown_key(&input.route_bucket),
+                    Reason::SublimitCooldownActive,
+                ),
+            ])
+            .find_map(|(key, reason)| {
+                let (_, remaining_ms) = values.get(&key, input.now_ms)?;
+                Some((reason, remaining_ms))
+            })
+    }
+}
+
+impl PermitStore for MemoryStore {
+    fn evaluate<'a>(
+        &'a self,
+        config: &'a Config,
+        _global: Algorithm,
+        route: Algorithm,
+        input: &'a LimitInput,
+        shadow: bool,
+    ) -> StoreFuture<'a, PermitScriptReply> {
+        Box::pin(async move {
+            // The guard comes first, as in the script; the backend checks it.
+            let guarded = self
+                .enforced
+                .guard_remaining_ms(&keys::normalize_key_part(&input.group_id), input.now_ms)
+                .is_some();
+            if let Some((reason, remaining_ms)) = self.cooldown(input).filter(|_| !guarded) {
+                return Ok(script_reply(&Evaluation {
+                    granted: false,
+                    retry_after_ms: remaining_ms.max(config.min_retry_ms),
+                    reason,
+                    route_limit: 0,
+                    route_remaining: 0,
+                }));
+            }
+            let backend = if shadow { &self.shadow } else { &self.enforced };
+            let Ok(evaluation) = backend.evaluate(route, input, config.min_retry_ms).await;
+            Ok(script_reply(&evaluation))
+        })
+    }
+
+    fn record_invalid<'a>(
+        &'a self,
+        group: &'a str,
+        threshold: u64,
+        cooldown_ms: u64,
+    ) -> StoreFuture<'a, Option<GuardTrip>> {
+        Box::pin(async move {
+            let (invalid_count, already_active) =
+                self.enforced
+                    .count_invalid(group, threshold, cooldown_ms, crate::unix_ms());
+            Ok(already_active.map(|already_active| GuardTrip {
+                invalid_count,
+                already_active,
+            }))
+        })
+    }
+
+    fn guard_ttl_ms<'a>(&'a self, group: &'a str) -> StoreFuture<'a, Option<u64>> {
+        Box::pin(async move { Ok(self.enforced.guard_remaining_ms(group, crate::unix_ms())) })
+    }
+
+    fn clear_guard<'a>(&'a self, group: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move { Ok(self.enforced.clear_guard(group, crate::unix_ms())) })
+    }
+
+    fn record_ban<'a>(
+        &'a self,
+        group: &'a str,
+        base_ms: u64,
+        max_ms: u64,
+        strike_window_ms: u64,
+    ) -> StoreFuture<'a, (u64, u64)> {
+        Box::pin(async move {
+            let now = crate::unix_ms();
+            let key = strikes_key(group);
+            let mut values = self.values();
+            let strikes = values
+                .get(&key, now)
+                .and_then(|(strikes, _)| strikes.parse::<u64>().ok())
+                .unwrap_or_default()
+                + 1;
+            values.set(&key, strikes.to_string(), strike_window_ms, now);
+            let doubling = 2_u64.saturating_pow((strikes - 1).min(63) as u32);
+            let cooldown_ms = base_ms.saturating_mul(doubling).min(max_ms);
+            self.enforced.extend_guard(group, cooldown_ms, now);
+            Ok((strikes, cooldown_ms))
+        })
+    }
+
+    fn claim<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+        ttl_ms: u64,
+    ) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let now = crate::unix_ms();
+            let mut values = self.values();
+            if let Some((existing, _)) = values.get(key, now) {
+                return Ok(Some(existing.to_string()));
+            }
+            values.set(key, value.to_string(), ttl_ms, now);
+            Ok(None)
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Option<String>, ttl_ms: u64) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let mut values = self.values();
+            match value {
+                Some(value) => values.set(key, value, ttl_ms, crate::unix_ms()),
+                None => {
+                    values.entries.remove(key);
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// An evaluation in the shape the limiter script replies with.
+fn script_reply(evaluation: &Evaluation) -> PermitScriptReply {
+    let known = |value: u64| if evaluation.granted { value as i64 } else { -1 };
+    (
+        i32::from(evaluation.granted),
+        evaluation.retry_after_ms as i64,
+        evaluation.reason.code().to_string(),
+        known(evaluation.route_limit),
+        known(evaluation.route_remaining),
+        -1,
+        -1,
+        -1,
+    )
+}
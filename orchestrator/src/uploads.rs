@@ -0,0 +1,198 @@
+//! Size-aware pacing for attachment uploads. Requests to upload routes may
+//! carry a `payload_bytes` hint; those draw from a per-identity byte budget
+//! (`DMBO_UPLOAD_BYTES_PER_INTERVAL` per `DMBO_UPLOAD_INTERVAL_MS`, paced like
+//! GCRA so a large file waits for the bytes ahead of it to drain), and uploads
+//! of at least `DMBO_LARGE_UPLOAD_BYTES` also take one of
+//! `DMBO_LARGE_UPLOAD_CONCURRENCY` slots until their report arrives or the
+//! slot's lease runs out. Both are reserved before the per-second limiter and
+//! given back if it denies, like long-window limits.
+
+use crate::{
+    normalize_key_part, rules::route_matches, AppState, FieldError, Reason, RequestTokenRequest,
+};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Script};
+use std::sync::atomic::Ordering;
+
+/// Routes that carry files: message sends and edits, webhook and interaction
+/// responses, forum posts, and sticker and emoji uploads.
+const UPLOAD_ROUTES: [&str; 9] = [
+    "/channels/:channel_id/messages",
+    "/channels/:channel_id/messages/:message_id",
+    "/channels/:channel_id/threads",
+    "/channels/:channel_id/attachments",
+    "/webhooks/:webhook_id/:webhook_token/**",
+    "/interactions/:interaction_id/:interaction_token/callback",
+    "/guilds/:guild_id/stickers",
+    "/guilds/:guild_id/emojis",
+    "/applications/:application_id/emojis",
+];
+
+/// `KEYS[1]` holds the byte budget's TAT and `KEYS[2]` the large-upload
+/// slots (member → lease expiry). `ARGV`: now, bytes, budget (0 disables),
+/// interval ms, large (1/0), slot cap, lease ms, slot member. Returns
+/// `{1, 0, tat_advance}` or `{0, retry_after_ms, 'bytes'|'concurrency'}`.
+const ACQUIRE_LUA: &str = r#"
+local now = tonumber(ARGV[1])
+local budget = tonumber(ARGV[3])
+local interval = tonumber(ARGV[4])
+local tat, next_tat = now, now
+if budget > 0 then
+  local cost = math.min(tonumber(ARGV[2]), budget)
+  tat = tonumber(redis.call('GET', KEYS[1])) or now
+  if tat < now then tat = now end
+  next_tat = tat + cost * interval / budget
+  if next_tat - now > interval then
+    return {0, math.ceil(next_tat - now - interval), 'bytes'}
+  end
+end
+if ARGV[5] == '1' then
+  redis.call('ZREMRANGEBYSCORE', KEYS[2], '-inf', now)
+  if redis.call('ZCARD', KEYS[2]) >= tonumber(ARGV[6]) then
+    local first = redis.call('ZRANGE', KEYS[2], 0, 0, 'WITHSCORES')
+    return {0, math.max(1, tonumber(first[2]) - now), 'concurrency'}
+  end
+  redis.call('ZADD', KEYS[2], now + tonumber(ARGV[7]), ARGV[8])
+  redis.call('PEXPIRE', KEYS[2], tonumber(ARGV[7]))
+end
+if budget > 0 then
+  redis.call('SET', KEYS[1], string.format('%.3f', next_tat), 'PX', math.ceil(next_tat - now) + interval)
+end
+return {1, 0, string.format('%.3f', next_tat - tat)}
+"#;
+
+/// Undoes an acquire: rewinds the TAT by `ARGV[1]` if it is still set and
+/// frees slot `ARGV[2]`.
+const RELEASE_LUA: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 1 then
+  redis.call('INCRBYFLOAT', KEYS[1], -tonumber(ARGV[1]))
+end
+if ARGV[2] ~= '' then redis.call('ZREM', KEYS[2], ARGV[2]) end
+return 1
+"#;
+
+/// What an upload took, given back if the permit is denied later.
+pub(crate) struct UploadReservation {
+    bytes_key: String,
+    slots_key: String,
+    tat_advance: String,
+    slot: String,
+}
+
+pub(crate) enum UploadAcquired {
+    Reserved(UploadReservation),
+    Exhausted { reason: Reason, retry_after_ms: u64 },
+}
+
+pub(crate) fn is_upload_route(route: &str) -> bool {
+    UPLOAD_ROUTES
+        .iter()
+        .any(|pattern| route_matches(pattern, route))
+}
+
+pub(crate) fn validate(request: &RequestTokenRequest) -> Option<FieldError> {
+    match request.payload_bytes {
+        Some(_) if !is_upload_route(&request.route) => Some(FieldError {
+            field: "payload_bytes",
+            message: "only accepted on attachment upload routes".to_string(),
+        }),
+        _ => None,
+    }
+}
+
+pub(crate) fn slots_key(discord_identity: &str) -> String {
+    format!("rl:uploads:slots:{}", normalize_key_part(discord_identity))
+}
+
+/// Reserves the request's bytes and, for a large upload, a slot. Returns
+/// `None` when the request carries no `payload_bytes` or pacing is off.
+pub(crate) async fn acquire(
+    state: &AppState,
+    conn: &mut MultiplexedConnection,
+    request: &RequestTokenRequest,
+    now_ms: u64,
+) -> redis::RedisResult<Option<UploadAcquired>> {
+    let config = &state.config;
+    let Some(bytes) = request.payload_bytes.filter(|bytes| *bytes > 0) else {
+        return Ok(None);
+    };
+    let large = config.large_upload_concurrency > 0 && bytes >= config.large_upload_bytes;
+    if config.upload_bytes_per_interval == 0 && !large {
+        return Ok(None);
+    }
+    let bytes_key = format!(
+        "rl:uploads:bytes:{}",
+        normalize_key_part(&request.discord_identity)
+    );
+    let slots_key = slots_key(&request.discord_identity);
+    let slot = if !large {
+        String::new()
+    } else if request.request_id.is_empty() {
+        uuid::Uuid::new_v4().to_string()
+    } else {
+        request.request_id.clone()
+    };
+    let (granted, retry_after_ms, detail): (i64, i64, String) = Script::new(ACQUIRE_LUA)
+        .key(&bytes_key)
+        .key(&slots_key)
+        .arg(now_ms)
+        .arg(bytes)
+        .arg(config.upload_bytes_per_interval)
+        .arg(config.upload_interval_ms)
+        .arg(if large { "1" } else { "0" })
+        .arg(config.large_upload_concurrency)
+        .arg(config.large_upload_lease_ms)
+        .arg(&slot)
+        .invoke_async(conn)
+        .await?;
+    let metrics = &state.metrics;
+    if granted == 1 {
+        metrics
+            .upload_bytes_granted
+            .fetch_add(bytes, Ordering::Relaxed);
+        return Ok(Some(UploadAcquired::Reserved(UploadReservation {
+            bytes_key,
+            slots_key,
+            tat_advance: detail,
+            slot,
+        })));
+    }
+    let reason = if detail == "concurrency" {
+        metrics
+            .upload_concurrency_denials
+            .fetch_add(1, Ordering::Relaxed);
+        Reason::UploadConcurrencyExhausted
+    } else {
+        metrics.upload_bytes_denials.fetch_add(1, Ordering::Relaxed);
+        Reason::UploadBytesExhausted
+    };
+    Ok(Some(UploadAcquired::Exhausted {
+        reason,
+        retry_after_ms: retry_after_ms.max(0) as u64,
+    }))
+}
+
+impl UploadReservation {
+    /// Gives the bytes and slot back after a later check denied the permit.
+    pub(crate) async fn release(self, conn: &mut MultiplexedConnection) {
+        let _ = Script::new(RELEASE_LUA)
+            .key(&self.bytes_key)
+            .key(&self.slots_key)
+            .arg(&self.tat_advance)
+            .arg(&self.slot)
+            .invoke_async::<_, i64>(conn)
+            .await;
+    }
+}
+
+/// Frees the large-upload slot held by a finished request, if any.
+pub(crate) async fn finish(
+    state: &AppState,
+    conn: &mut MultiplexedConnection,
+    discord_identity: &str,
+    request_id: &str,
+) -> redis::RedisResult<()> {
+    if state.config.large_upload_concurrency == 0 || request_id.is_empty() {
+        return Ok(());
+    }
+    conn.zrem(slots_key(discord_identity), request_id).await
+}
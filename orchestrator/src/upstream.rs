@@ -0,0 +1,80 @@
+//! Retries of Discord calls the orchestrator makes on a caller's behalf.
+//! Rate limits and transient server errors are retried after the delay Discord
+//! asks for, within an attempt and time budget, so the caller only sees the
+//! final outcome.
+
+use crate::{unix_ms, Metrics};
+use std::{collections::HashMap, sync::atomic::Ordering};
+
+/// Why an upstream call is worth retrying.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RetryCause {
+    RateLimited,
+    ServerError,
+    Network,
+}
+
+/// Whether a response (`None` when the call failed without one) should be
+/// retried, and after how long. `headers` are lower-cased.
+pub(crate) fn retry_after(
+    status_code: Option<u16>,
+    headers: &HashMap<String, String>,
+    min_retry_ms: u64,
+) -> Option<(RetryCause, u64)> {
+    let cause = match status_code {
+        None => return Some((RetryCause::Network, min_retry_ms)),
+        Some(429) => RetryCause::RateLimited,
+        Some(500 | 502 | 503 | 504) => RetryCause::ServerError,
+        Some(_) => return None,
+    };
+    let delay_ms = headers
+        .get("retry-after")
+        .or_else(|| headers.get("x-ratelimit-reset-after"))
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(|seconds| (seconds * 1000.0).ceil() as u64)
+        .unwrap_or(1000);
+    Some((cause, delay_ms.max(min_retry_ms)))
+}
+
+/// Attempts and time allowed for one logical call, the time counted from
+/// when the budget is created.
+pub(crate) struct RetryBudget {
+    max_attempts: u32,
+    deadline_unix_ms: u64,
+}
+
+impl RetryBudget {
+    pub(crate) fn new(max_attempts: u32, budget_ms: u64) -> Self {
+        Self {
+            max_attempts,
+            deadline_unix_ms: unix_ms().saturating_add(budget_ms),
+        }
+    }
+
+    /// Whether another attempt after `attempts` may follow a wait of
+    /// `delay_ms`. Records the retry, or the exhausted budget, in metrics.
+    pub(crate) fn spend(
+        &self,
+        metrics: &Metrics,
+        attempts: u32,
+        cause: RetryCause,
+        delay_ms: u64,
+    ) -> bool {
+        if attempts >= self.max_attempts
+            || unix_ms().saturating_add(delay_ms) > self.deadline_unix_ms
+        {
+            metrics
+                .upstream_retry_budget_exhausted
+                .fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let counter = match cause {
+            RetryCause::RateLimited => &metrics.upstream_retries_429,
+            RetryCause::ServerError => &metrics.upstream_retries_5xx,
+            RetryCause::Network => &metrics.upstream_retries_network,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
@@ -0,0 +1,199 @@
+//! In-process registry of `request_token` handlers that are currently waiting
+//! for capacity. Entries are removed when their guard drops, so the registry
+//! always reflects live waiters on this instance.
+//!
+//! Each waiter also carries a wakeup handle. A report that frees capacity on
+//! a route bucket wakes the bucket's waiter due soonest here, and a waiter
+//! that leaves hands the wakeup on to the next, so waiters on this instance
+//! don't have to poll Redis for capacity another call just returned.
+
+use crate::priority::PriorityClass;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct WaiterInfo {
+    pub(crate) id: u64,
+    pub(crate) request_id: String,
+    pub(crate) client_id: String,
+    pub(crate) group_id: String,
+    pub(crate) discord_identity: String,
+    pub(crate) method: String,
+    pub(crate) route: String,
+    pub(crate) major_parameter: String,
+    pub(crate) priority: String,
+    /// Waiting for a permit callback, which already holds a place.
+    #[serde(skip)]
+    pub(crate) callback: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) interaction_deadline_unix_ms: Option<u64>,
+    pub(crate) enqueued_unix_ms: u64,
+    pub(crate) deadline_unix_ms: u64,
+    #[serde(skip)]
+    pub(crate) route_bucket: String,
+    #[serde(skip)]
+    pub(crate) wakeup: Arc<Notify>,
+    #[serde(skip)]
+    pub(crate) cancelled: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Waiters {
+    next_id: Arc<AtomicU64>,
+    entries: Arc<Mutex<HashMap<u64, WaiterInfo>>>,
+    wakeups: Arc<AtomicU64>,
+    /// Permit callbacks accepted and not yet delivered.
+    callbacks: Arc<AtomicU64>,
+}
+
+impl Waiters {
+    /// Registers a waiter; the id field of `info` is assigned here.
+    pub(crate) fn register(&self, mut info: WaiterInfo) -> WaiterGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        info.id = id;
+        let wakeup = info.wakeup.clone();
+        let cancelled = info.cancelled.clone();
+        self.entries
+            .lock()
+            .expect("waiter registry poisoned")
+            .insert(id, info);
+        WaiterGuard {
+            waiters: self.clone(),
+            id,
+            wakeup,
+            cancelled,
+        }
+    }
+
+    /// Cancels and wakes every waiter of `request_id`; returns how many.
+    pub(crate) fn cancel(&self, request_id: &str) -> usize {
+        let entries = self.entries.lock().expect("waiter registry poisoned");
+        let mut cancelled = 0;
+        for waiter in entries
+            .values()
+            .filter(|waiter| waiter.request_id == request_id)
+        {
+            waiter.cancelled.store(true, Ordering::Relaxed);
+            waiter.wakeup.notify_one();
+            cancelled += 1;
+        }
+        cancelled
+    }
+
+    /// Wakes the waiter on `route_bucket` next in its queue's order, critical
+    /// ones first and then by deadline, if any. A wakeup
+    /// sent while it is busy asking the limiter is kept for its next sleep.
+    pub(crate) fn wake_next(&self, route_bucket: &str) {
+        let entries = self.entries.lock().expect("waiter registry poisoned");
+        let next = entries
+            .values()
+            .filter(|waiter| waiter.route_bucket == route_bucket)
+            .min_by_key(|waiter| {
+                (
+                    PriorityClass::of(&waiter.priority) != PriorityClass::Critical,
+                    waiter.deadline_unix_ms,
+                    waiter.id,
+                )
+            });
+        if let Some(waiter) = next {
+            waiter.wakeup.notify_one();
+            self.wakeups.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Wakeups sent since start.
+    pub(crate) fn wakeups(&self) -> u64 {
+        self.wakeups.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<WaiterInfo> {
+        self.entries
+            .lock()
+            .expect("waiter registry poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Live waiters plus pending permit callbacks, each counted once.
+    pub(crate) fn queued(&self) -> usize {
+        self.count(|waiter| !waiter.callback) + self.callbacks.load(Ordering::Relaxed) as usize
+    }
+
+    /// Holds a place for a permit callback until the slot drops.
+    pub(crate) fn hold_callback(&self) -> CallbackSlot {
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        CallbackSlot(self.callbacks.clone())
+    }
+
+    /// How many live waiters match `predicate`.
+    pub(crate) fn count(&self, predicate: impl Fn(&WaiterInfo) -> bool) -> usize {
+        self.entries
+            .lock()
+            .expect("waiter registry poisoned")
+            .values()
+            .filter(|waiter| predicate(waiter))
+            .count()
+    }
+
+    /// Whether any live waiter matches `predicate`.
+    pub(crate) fn any(&self, predicate: impl Fn(&WaiterInfo) -> bool) -> bool {
+        self.entries
+            .lock()
+            .expect("waiter registry poisoned")
+            .values()
+            .any(predicate)
+    }
+}
+
+pub(crate) struct CallbackSlot(Arc<AtomicU64>);
+
+impl Drop for CallbackSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) struct WaiterGuard {
+    waiters: Waiters,
+    id: u64,
+    wakeup: Arc<Notify>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl WaiterGuard {
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Whether `POST /cancel_request` named this waiter's request.
+    pub(crate) fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Resolves when capacity on the waiter's bucket may have freed up.
+    pub(crate) async fn woken(&self) {
+        self.wakeup.notified().await;
+    }
+}
+
+impl Drop for WaiterGuard {
+    fn drop(&mut self) {
+        let left = match self.waiters.entries.lock() {
+            Ok(mut entries) => entries.remove(&self.id),
+            Err(_) => None,
+        };
+        // Whatever this waiter leaves behind, granted or giving up, the
+        // next one in line may now fit.
+        if let Some(left) = left {
+            self.waiters.wake_next(&left.route_bucket);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Wakeups across replicas. A waiter is only woken in-process by calls on
+//! its own instance; when a queued call leaves its queue or a call frees
+//! capacity, the replica it ran on also publishes the route bucket on the
+//! `rl:wakeups` channel, and every other replica wakes its next waiter on
+//! that bucket. Long-poll waiters rely on these instead of checking for
+//! their turn every `DMBO_QUEUE_POLL_MS`. Pub/sub delivery is best effort,
+//! so they still recheck every `DMBO_LONG_POLL_RECHECK_MS`.
+
+use crate::AppState;
+use futures_util::StreamExt;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::time::sleep;
+
+const CHANNEL: &str = "rl:wakeups";
+
+#[derive(Serialize, Deserialize)]
+struct Wakeup<'a> {
+    instance_id: &'a str,
+    route_bucket: &'a str,
+}
+
+/// Capacity may have freed on `route_bucket`: wakes the next waiter here
+/// and tells the other replicas.
+pub(crate) fn announce(state: &AppState, route_bucket: &str) {
+    state.waiters.wake_next(route_bucket);
+    if state.config.memory_store {
+        return;
+    }
+    let redis = state.redis.clone();
+    let instance_id = state.config.instance_id.clone();
+    let route_bucket = route_bucket.to_string();
+    tokio::spawn(async move {
+        if let Ok(mut conn) = redis.get_multiplexed_async_connection().await {
+            publish(&mut conn, &instance_id, &route_bucket).await;
+        }
+    });
+}
+
+/// Tells the other replicas that a waiter on `route_bucket` may go next.
+pub(crate) async fn publish(
+    conn: &mut MultiplexedConnection,
+    instance_id: &str,
+    route_bucket: &str,
+) {
+    let wakeup = Wakeup {
+        instance_id,
+        route_bucket,
+    };
+    if let Ok(message) = serde_json::to_string(&wakeup) {
+        let _ = conn.publish::<_, _, i64>(CHANNEL, message).await;
+    }
+}
+
+/// Background task: wakes local waiters for the buckets other replicas
+/// announce, resubscribing after connection losses.
+pub(crate) async fn relay(state: Arc<AppState>) {
+    loop {
+        if let Ok(mut pubsub) = state.redis.get_async_pubsub().await {
+            if pubsub.subscribe(CHANNEL).await.is_ok() {
+                let mut messages = pubsub.on_message();
+                while let Some(message) = messages.next().await {
+                    let Ok(payload) = message.get_payload::<String>() else {
+                        continue;
+                    };
+                    let Ok(wakeup) = serde_json::from_str::<Wakeup>(&payload) else {
+                        continue;
+                    };
+                    if wakeup.instance_id == state.config.instance_id {
+                        continue;
+                    }
+                    state
+                        .metrics
+                        .relayed_wakeups
+                        .fetch_add(1, Ordering::Relaxed);
+                    state.waiters.wake_next(wakeup.route_bucket);
+                }
+            }
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,95 @@
+//! `GET /permits/ws`: a WebSocket over which a client streams permit
+//! requests and gets each decision pushed back as soon as it is made. Every
+//! text frame is a `request_token` body; requests are decided concurrently,
+//! waiting up to their `max_wait_ms` like HTTP ones, so grants arrive in the
+//! order capacity frees up rather than the order they were asked for. Each
+//! decision frame carries its request's `request_id`. Closing the socket
+//! abandons the requests still waiting.
+
+use crate::{decide_permit, prepare_request, AppState, FieldError, RequestTokenRequest};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use dmbo_core::Reason;
+use serde_json::{json, Value};
+use std::sync::{atomic::Ordering, Arc};
+use tokio::task::JoinSet;
+
+pub(crate) async fn permits_ws(
+    State(state): State<Arc<AppState>>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| serve(state, socket))
+}
+
+async fn serve(state: Arc<AppState>, mut socket: WebSocket) {
+    state.metrics.ws_connections.fetch_add(1, Ordering::Relaxed);
+    // Dropping the set when the socket closes aborts the waits still open,
+    // which leaves their queues and waiter registrations.
+    let mut pending = JoinSet::new();
+    loop {
+        tokio::select! {
+            frame = socket.recv() => match frame {
+                Some(Ok(Message::Text(text))) => match request_from(&state, &text) {
+                    Ok(mut request) => {
+                        let state = state.clone();
+                        pending.spawn(async move {
+                            let (response, _) = decide_permit(&state, &mut request, None).await;
+                            let mut frame = serde_json::to_value(&response).unwrap_or_default();
+                            frame["request_id"] = Value::String(request.request_id);
+                            frame
+                        });
+                    }
+                    Err(frame) => {
+                        if socket.send(Message::Text(frame.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by axum; binary frames carry nothing here.
+                Some(Ok(_)) => {}
+            },
+            Some(decided) = pending.join_next(), if !pending.is_empty() => {
+                let Ok(frame) = decided else { continue };
+                if socket.send(Message::Text(frame.to_string())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    state.metrics.ws_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Parses and readies one frame's request, or builds the frame rejecting it.
+fn request_from(state: &AppState, text: &str) -> Result<RequestTokenRequest, Value> {
+    let mut request: RequestTokenRequest = serde_json::from_str(text).map_err(|error| {
+        invalid_frame(
+            None,
+            vec![FieldError {
+                field: "body",
+                message: error.to_string(),
+            }],
+        )
+    })?;
+    let errors = prepare_request(&state.config, &mut request);
+    if errors.is_empty() {
+        Ok(request)
+    } else {
+        Err(invalid_frame(Some(&request.request_id), errors))
+    }
+}
+
+pub(crate) fn invalid_frame(request_id: Option<&str>, errors: Vec<FieldError>) -> Value {
+    json!({
+        "request_id": request_id,
+        "granted": false,
+        "reason": Reason::InvalidRequest,
+        "reason_message": Reason::InvalidRequest.message(),
+        "errors": errors,
+    })
+}